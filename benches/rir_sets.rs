@@ -0,0 +1,403 @@
+//! RIR ファイルの解析と集約の比較（時間とピークメモリ）
+//!
+//! - 旧方式: 割り当てを CIDR に分けて `BTreeSet<IpNet>` に積み、`IpNet::aggregate` で集約
+//! - 新方式: 割り当てを区間のまま積み、正規化する（`RirParser`）。CIDR へは書き出す時に変換
+//!
+//! 積と差も、旧方式（`BTreeSet<IpNet>` を集約してから範囲の列を掃引し、CIDR へ戻す）と
+//! 区間集合のまま求める新方式を比べる。
+//!
+//! `FIRE_SCOPE_RIR_DIR` に `fire-scope fetch --dir <DIR>` で取得したディレクトリを指定すると
+//! 実データで計測する。未指定なら RIR ファイルに似せた合成データを使う。
+//!
+//! ```text
+//! $ fire-scope fetch --dir ./rir
+//! $ FIRE_SCOPE_RIR_DIR=./rir cargo bench --bench rir_sets
+//! ```
+
+use fire_scope::interval::IpRangeSet;
+use fire_scope::ipv4_utils::{ipv4_summarize_range, parse_ipv4_range_to_cidrs};
+use fire_scope::parse::{CountryFilter, CountryMap, RirParser, aggregate_country_sets};
+use ipnet::{IpNet, Ipv6Net};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::{BTreeSet, HashMap};
+use std::hint::black_box;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// 確保中のバイト数とそのピークを数えるアロケータ
+struct CountingAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let now = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(now, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 5;
+
+/// 計測の結果（最短時間、開始時点からのピークの増分）
+struct Measured<T> {
+    value: T,
+    best: Duration,
+    peak: usize,
+}
+
+fn measure<T>(mut f: impl FnMut() -> T) -> Measured<T> {
+    let mut best = Duration::MAX;
+    let mut peak = 0;
+    let mut value = None;
+    for _ in 0..ROUNDS {
+        drop(value.take());
+        let base = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(base, Ordering::Relaxed);
+        let started = Instant::now();
+        let v = black_box(f());
+        best = best.min(started.elapsed());
+        peak = peak.max(PEAK.load(Ordering::Relaxed) - base);
+        value = Some(v);
+    }
+    Measured {
+        value: value.expect("ROUNDS > 0"),
+        best,
+        peak,
+    }
+}
+
+/// 旧方式の解析（単一スレッド）: 割り当てを CIDR に分けて国ごとの BTreeSet に積む
+fn parse_btreeset(texts: &[String]) -> HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)> {
+    let mut sets: HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)> = HashMap::new();
+    for line in texts.iter().flat_map(|t| t.lines()) {
+        if line.starts_with('#') || line.contains('*') || line.contains("reserved") {
+            continue;
+        }
+        let params: Vec<&str> = line.split('|').collect();
+        if params.len() < 7 {
+            continue;
+        }
+        let status = params[6].to_ascii_lowercase();
+        if status != "allocated" && status != "assigned" {
+            continue;
+        }
+        let entry = sets.entry(params[1].to_uppercase()).or_default();
+        match params[2] {
+            "ipv4" => {
+                if let Ok(nets) = parse_ipv4_range_to_cidrs(params[3], params[4]) {
+                    entry.0.extend(nets);
+                }
+            }
+            "ipv6" => {
+                if let Ok(net) = format!("{}/{}", params[3], params[4]).parse::<Ipv6Net>() {
+                    entry.1.insert(IpNet::V6(net));
+                }
+            }
+            _ => {}
+        }
+    }
+    sets
+}
+
+/// 国コード → (IPv4, IPv6) の集約済み CIDR
+type CidrMap = HashMap<String, (Vec<IpNet>, Vec<IpNet>)>;
+
+/// 旧方式の集約
+fn aggregate_btreeset(sets: HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)>) -> CidrMap {
+    sets.into_iter()
+        .map(|(cc, (v4, v6))| {
+            let v4: Vec<IpNet> = v4.into_iter().collect();
+            let v6: Vec<IpNet> = v6.into_iter().collect();
+            (cc, (IpNet::aggregate(&v4), IpNet::aggregate(&v6)))
+        })
+        .collect()
+}
+
+/// 新方式の解析と正規化（比較のため単一スレッドで解析する）
+fn parse_ranges(texts: &[String]) -> CountryMap {
+    let mut parser = RirParser::new(CountryFilter::All);
+    for line in texts.iter().flat_map(|t| t.lines()) {
+        parser.parse_line(line).expect("valid RIR line");
+    }
+    aggregate_country_sets(vec![parser.finish().expect("valid RIR text")])
+}
+
+/// 旧方式の積と差: 両方を集約して範囲の列にし、掃引した結果を最小 CIDR へ戻す
+fn sweep_btreeset(a: &BTreeSet<IpNet>, b: &BTreeSet<IpNet>) -> (BTreeSet<IpNet>, BTreeSet<IpNet>) {
+    let to_ranges = |set: &BTreeSet<IpNet>| {
+        let agg = IpNet::aggregate(&set.iter().copied().collect::<Vec<_>>());
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for net in agg {
+            match net {
+                IpNet::V4(n) => v4.push((
+                    u128::from(u32::from(n.network())),
+                    u128::from(u32::from(n.broadcast())),
+                )),
+                IpNet::V6(n) => v6.push((u128::from(n.network()), u128::from(n.broadcast()))),
+            }
+        }
+        v4.sort_unstable();
+        v6.sort_unstable();
+        [v4, v6]
+    };
+    let (a, b) = (to_ranges(a), to_ranges(b));
+    let mut inter = BTreeSet::new();
+    let mut diff = BTreeSet::new();
+    for (family, (a, b)) in a.iter().zip(&b).enumerate() {
+        let v4 = family == 0;
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            let (s, e) = (a[i].0.max(b[j].0), a[i].1.min(b[j].1));
+            if s <= e {
+                inter.extend(summarize(v4, s, e));
+            }
+            if a[i].1 < b[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        let mut j = 0;
+        for &(b_s, b_e) in a {
+            while j < b.len() && b[j].1 < b_s {
+                j += 1;
+            }
+            let mut cur = b_s;
+            let mut exhausted = false;
+            let mut k = j;
+            while k < b.len() && b[k].0 <= b_e {
+                let (x_s, x_e) = b[k];
+                if x_s > cur {
+                    diff.extend(summarize(v4, cur, x_s - 1));
+                }
+                if x_e >= b_e {
+                    exhausted = true;
+                    break;
+                }
+                cur = cur.max(x_e + 1);
+                k += 1;
+            }
+            if !exhausted {
+                diff.extend(summarize(v4, cur, b_e));
+            }
+        }
+    }
+    (inter, diff)
+}
+
+/// 範囲を最小 CIDR に分ける（旧方式の変換）
+fn summarize(v4: bool, start: u128, end: u128) -> Vec<IpNet> {
+    if v4 {
+        return ipv4_summarize_range(start as u64, end as u64);
+    }
+    let mut cidrs = Vec::new();
+    let mut cur = start;
+    loop {
+        let span = (end - cur).checked_add(1).map_or(128, u128::ilog2);
+        let bits = span.min(cur.trailing_zeros());
+        cidrs.push(IpNet::V6(
+            Ipv6Net::new(Ipv6Addr::from(cur), (128 - bits) as u8).expect("valid prefix"),
+        ));
+        let last = if bits == 128 {
+            u128::MAX
+        } else {
+            cur + ((1u128 << bits) - 1)
+        };
+        if last >= end {
+            break;
+        }
+        cur = last + 1;
+    }
+    cidrs
+}
+
+/// RIR の delegated ファイルに似せたデータ（割り当ての大きさと国の偏り、重複と隣接を含む）
+fn synthetic_rir_texts() -> Vec<String> {
+    let mut seed: u64 = 0x5eed_f1e5;
+    let mut rand = move |n: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % n
+    };
+    let countries: Vec<String> = (0..240u8)
+        .map(|i| {
+            format!(
+                "{}{}",
+                (b'A' + i / 26 % 26) as char,
+                (b'A' + i % 26) as char
+            )
+        })
+        .collect();
+    let sizes = [256u64, 512, 768, 1024, 1536, 2048, 4096, 8192, 16384, 65536];
+    let mut texts = Vec::new();
+    for rir in ["afrinic", "apnic", "arin", "lacnic", "ripencc"] {
+        let mut text = format!("2|{rir}|20250101|0|19700101|20250101|+0000\n");
+        let mut v4: u64 = u64::from(u32::from(Ipv4Addr::new(1, 0, 0, 0))) + rand(1 << 28);
+        for _ in 0..50_000 {
+            // 国は少数に偏らせる
+            let cc = &countries[(rand(240) * rand(240) / 240) as usize];
+            let size = sizes[rand(sizes.len() as u64) as usize];
+            let start = v4 - v4 % 256;
+            if start + size > u64::from(u32::MAX) {
+                break;
+            }
+            text.push_str(&format!(
+                "{rir}|{cc}|ipv4|{}|{size}|20100101|allocated\n",
+                Ipv4Addr::from(start as u32)
+            ));
+            v4 = start + size + rand(4) * 256;
+        }
+        let mut v6: u128 = 0x2001_0000u128 << 96 | u128::from(rand(1 << 20)) << 80;
+        for _ in 0..20_000 {
+            let cc = &countries[(rand(240) * rand(240) / 240) as usize];
+            let len = [29u8, 32, 32, 32, 36, 44, 48, 48][rand(8) as usize];
+            let block = 1u128 << (128 - len);
+            let start = v6.div_ceil(block) * block;
+            text.push_str(&format!(
+                "{rir}|{cc}|ipv6|{}|{len}|20100101|allocated\n",
+                Ipv6Addr::from(start)
+            ));
+            v6 = start + block * (1 + u128::from(rand(2)));
+        }
+        texts.push(text);
+    }
+    texts
+}
+
+fn load_texts() -> (Vec<String>, String) {
+    match std::env::var_os("FIRE_SCOPE_RIR_DIR") {
+        Some(dir) => {
+            let mut texts = Vec::new();
+            let mut entries: Vec<_> = std::fs::read_dir(&dir)
+                .expect("FIRE_SCOPE_RIR_DIR is readable")
+                .filter_map(Result::ok)
+                .map(|e| e.path())
+                .filter(|p| p.is_file())
+                .collect();
+            entries.sort();
+            for path in entries {
+                if let Ok(text) = std::fs::read_to_string(&path) {
+                    texts.push(text);
+                }
+            }
+            (texts, format!("RIR files in {}", dir.to_string_lossy()))
+        }
+        None => (synthetic_rir_texts(), "synthetic RIR-like data".to_string()),
+    }
+}
+
+fn mib(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn report(label: &str, best: Duration, peak: usize) {
+    println!(
+        "{label:<40} {:>10.1} ms {:>10.1} MiB",
+        best.as_secs_f64() * 1000.0,
+        mib(peak)
+    );
+}
+
+fn main() {
+    let (texts, source) = load_texts();
+    let lines: usize = texts.iter().map(|t| t.lines().count()).sum();
+    println!(
+        "{source}: {} files, {lines} lines (best of {ROUNDS})",
+        texts.len()
+    );
+    println!("{:<40} {:>13} {:>14}", "", "time", "peak heap");
+
+    // 解析のみ（集約前に保持する集合の大きさ）
+    let old_sets = measure(|| parse_btreeset(&texts));
+    report(
+        "parse: BTreeSet<IpNet> (before)",
+        old_sets.best,
+        old_sets.peak,
+    );
+    let new_sets = measure(|| {
+        let mut parser = RirParser::new(CountryFilter::All);
+        for line in texts.iter().flat_map(|t| t.lines()) {
+            parser.parse_line(line).expect("valid RIR line");
+        }
+        parser.finish().expect("valid RIR text")
+    });
+    report(
+        "parse: interval ranges (after)",
+        new_sets.best,
+        new_sets.peak,
+    );
+    drop(old_sets);
+    drop(new_sets);
+
+    // 解析から集約まで
+    let old = measure(|| aggregate_btreeset(parse_btreeset(&texts)));
+    report("parse + aggregate: BTreeSet (before)", old.best, old.peak);
+    let new = measure(|| parse_ranges(&texts));
+    report("parse + aggregate: intervals (after)", new.best, new.peak);
+
+    let mut old_map: Vec<_> = old.value.into_iter().collect();
+    let mut new_map: Vec<_> = new.value.into_iter().collect();
+    old_map.sort();
+    new_map.sort_by(|a, b| a.0.cmp(&b.0));
+    let converted: Vec<_> = new_map
+        .iter()
+        .map(|(cc, set)| (cc.clone(), (set.v4_nets(), set.v6_nets())))
+        .collect();
+    assert!(
+        old_map == converted,
+        "both pipelines must produce the same lists"
+    );
+
+    // 集合演算（すべての国の和と、最大の国との積と差）
+    let all_set = new_map
+        .iter()
+        .fold(IpRangeSet::new(), |acc, (_, set)| acc.union(set));
+    let largest_set = new_map
+        .iter()
+        .max_by_key(|(_, set)| set.cidr_len())
+        .map(|(_, set)| set.clone())
+        .unwrap_or_default();
+    let (all, largest) = (all_set.to_set(), largest_set.to_set());
+
+    let old_ops = measure(|| sweep_btreeset(&all, &largest));
+    report(
+        "intersection + difference: BTreeSet",
+        old_ops.best,
+        old_ops.peak,
+    );
+    let new_ops = measure(|| {
+        let inter = all_set.intersection(&largest_set);
+        let diff = all_set.difference(&largest_set);
+        (inter.to_set(), diff.to_set())
+    });
+    report(
+        "intersection + difference: intervals",
+        new_ops.best,
+        new_ops.peak,
+    );
+    assert!(
+        old_ops.value == new_ops.value,
+        "both set operations must produce the same lists"
+    );
+    println!(
+        "minimal CIDRs: {} in the union of all countries, {} in the largest country",
+        all.len(),
+        largest.len()
+    );
+}
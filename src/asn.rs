@@ -1,41 +1,95 @@
-use crate::common::{IpFamily, OutputFormat};
+use crate::asname::{AsNameOptions, annotate_output};
+use crate::common::IpFamily;
+use crate::cone::ConeOptions;
+use crate::constants::{MAX_JSON_DOWNLOAD_BYTES, RIPESTAT_BASE_URL};
 use crate::error::AppError;
-use crate::output::write_as_ip_list_to_file;
+use crate::fetch::{ApiPolicy, fetch_json_with_policy};
+use crate::interval::IpRangeSet;
+use crate::irr::{IrrOptions, IrrRoutes};
+use crate::mrt::{MrtOptions, MrtRoutes};
+use crate::output::{OutputOptions, write_as_ip_list_to_file};
+use crate::report::journal;
+use crate::stats::stats;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use ipnet::IpNet;
 use reqwest::Client;
 use serde_json::Value;
-use std::{collections::BTreeSet, str::FromStr, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::sync::Semaphore;
-use crate::constants::MAX_JSON_DOWNLOAD_BYTES;
-use crate::fetch::fetch_json_with_limit;
-use crate::common::debug_log;
+use tracing::{debug, info, instrument, warn};
 
 /// AS の発表プレフィックスを複数ソースから取得する（RIPEstat 優先、ARIN RDAP をフォールバック）
 /// RPKI検証なし
+#[instrument(skip_all, fields(asn = %as_number))]
 pub async fn get_prefixes_via_rdap(
     client: &Client,
     as_number: &str,
+    policy: &ApiPolicy,
 ) -> Result<(BTreeSet<IpNet>, BTreeSet<IpNet>), AppError> {
     // 1) RIPEstat announced-prefixes API
-    match fetch_ripe_stat_prefixes(client, as_number).await {
+    match fetch_ripe_stat_prefixes(client, as_number, policy).await {
         Ok(mut nets) => {
             // フォールバックとして ARIN も併合（失敗は無視）
-            if let Ok(mut arin) = fetch_arin_originas_prefixes(client, as_number).await {
+            if merges_arin_prefixes(policy)
+                && let Ok(mut arin) = fetch_arin_originas_prefixes(client, as_number, policy).await
+            {
                 nets.append(&mut arin);
             }
-            let (v4set, v6set) = dedup_and_partition(&nets);
-            return Ok((v4set, v6set));
+            let (v4set, v6set) = dedup_and_partition(as_number, &nets);
+            Ok((v4set, v6set))
         }
         Err(e) => {
-            debug_log(format!("RIPEstat fetch failed for AS{}: {}", as_number, e));
+            debug!(error = %e, "RIPEstat fetch failed, falling back to ARIN");
             // 2) ARIN OriginAS RDAP（米地域中心、非網羅）
-            let nets = fetch_arin_originas_prefixes(client, as_number).await?;
-            let (v4set, v6set) = dedup_and_partition(&nets);
-            return Ok((v4set, v6set));
+            let nets = fetch_arin_originas_prefixes(client, as_number, policy).await?;
+            let (v4set, v6set) = dedup_and_partition(as_number, &nets);
+            Ok((v4set, v6set))
         }
     }
 }
 
+/// RIPEstat の結果に ARIN OriginAS のプレフィックスを併合するか
+/// `--min-announced` 指定時は、広報時間を確かめられない ARIN の分で短期間の広報を戻さないよう併合しない
+pub fn merges_arin_prefixes(policy: &ApiPolicy) -> bool {
+    policy.min_announced.is_zero()
+}
+
+/// 読み込み済みの取得元
+#[derive(Clone)]
+enum Source {
+    Bgp(Arc<ApiPolicy>),
+    Irr(Arc<IrrRoutes>),
+    Mrt(Arc<MrtRoutes>),
+}
+
+impl Source {
+    /// AS のプレフィックスを取得する（集約済み）
+    async fn prefixes(
+        &self,
+        client: &Client,
+        as_number: &str,
+    ) -> Result<(BTreeSet<IpNet>, BTreeSet<IpNet>), AppError> {
+        let local = |as_number: &str| {
+            as_number
+                .parse::<u32>()
+                .map_err(|_| AppError::InvalidInput(format!("Invalid AS number: {}", as_number)))
+        };
+        let (v4, v6) = match self {
+            Source::Bgp(policy) => return get_prefixes_via_rdap(client, as_number, policy).await,
+            Source::Irr(irr) => irr.prefixes(local(as_number)?).await?,
+            Source::Mrt(mrt) => mrt.prefixes(local(as_number)?),
+        };
+        let nets: Vec<IpNet> = v4.into_iter().chain(v6).collect();
+        Ok(dedup_and_partition(as_number, &nets))
+    }
+}
+
 /// ARIN OriginAS RDAP 応答から CIDR を抽出
 fn extract_prefixes_from_arin(v: &Value) -> Result<Vec<IpNet>, AppError> {
     let mut nets = Vec::new();
@@ -63,37 +117,180 @@ fn extract_prefixes_from_arin(v: &Value) -> Result<Vec<IpNet>, AppError> {
     Ok(nets)
 }
 
+/// RIPEstat Data API の URL（`sourceapp` を付ける）
+/// 例: https://stat.ripe.net/data/announced-prefixes/data.json?resource=AS2497&sourceapp=fire-scope
+pub(crate) fn ripestat_url(endpoint: &str, resource: &str, policy: &ApiPolicy) -> String {
+    format!(
+        "{}/{}/data.json?resource={}&sourceapp={}",
+        RIPESTAT_BASE_URL, endpoint, resource, policy.sourceapp
+    )
+}
+
 /// RIPEstat: Announced Prefixes API から CIDR を抽出
-async fn fetch_ripe_stat_prefixes(client: &Client, as_number: &str) -> Result<Vec<IpNet>, AppError> {
-    // https://stat.ripe.net/data/announced-prefixes/data.json?resource=AS{asn}
-    let url = format!(
-        "https://stat.ripe.net/data/announced-prefixes/data.json?resource=AS{}",
-        as_number
-    );
-    let json: Value = fetch_json_with_limit(client, &url, MAX_JSON_DOWNLOAD_BYTES).await?;
+/// 問い合わせ期間と最小広報時間は JSON API の方針に従う
+pub(crate) async fn fetch_ripe_stat_prefixes(
+    client: &Client,
+    as_number: &str,
+    policy: &ApiPolicy,
+) -> Result<Vec<IpNet>, AppError> {
+    let mut url = ripestat_url("announced-prefixes", &format!("AS{}", as_number), policy);
+    if let Some(window) = policy.announced_window {
+        // 極端に長い期間は 1970年からとみなす
+        let start = chrono::Duration::from_std(window)
+            .ok()
+            .and_then(|w| Utc::now().checked_sub_signed(w))
+            .unwrap_or(DateTime::UNIX_EPOCH);
+        url.push_str(&format!("&starttime={}", start.format("%Y-%m-%dT%H:%M:%S")));
+    }
+    let json: Value = fetch_json_with_policy(client, &url, MAX_JSON_DOWNLOAD_BYTES, policy).await?;
+    Ok(extract_announced_prefixes(&json, policy.min_announced))
+}
+
+/// RIPEstat announced-prefixes 応答からプレフィックスを抽出する
+/// `min_announced` が 0 より大きい場合は、`timelines` の問い合わせ期間内の合計がそれに満たない
+/// （一時的なハイジャックや経路リークのような）プレフィックスを除く
+pub fn extract_announced_prefixes(json: &Value, min_announced: Duration) -> Vec<IpNet> {
+    let data = json.get("data");
+    let window = |key: &str| {
+        data.and_then(|d| d.get(key))
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse::<NaiveDateTime>().ok())
+    };
+    let (query_start, query_end) = (window("query_starttime"), window("query_endtime"));
+
     let mut nets = Vec::new();
-    if let Some(prefixes) = json.get("data").and_then(|d| d.get("prefixes")).and_then(|p| p.as_array()) {
-        for obj in prefixes {
-            if let Some(pfx) = obj.get("prefix").and_then(|v| v.as_str()) {
-                if let Ok(net) = IpNet::from_str(pfx) {
-                    nets.push(net);
+    let mut dropped = 0usize;
+    let prefixes = data
+        .and_then(|d| d.get("prefixes"))
+        .and_then(|p| p.as_array());
+    for obj in prefixes.into_iter().flatten() {
+        let Some(net) = obj
+            .get("prefix")
+            .and_then(|v| v.as_str())
+            .and_then(|p| IpNet::from_str(p).ok())
+        else {
+            continue;
+        };
+        if !min_announced.is_zero() {
+            let announced = announced_duration(obj.get("timelines"), query_start, query_end);
+            if announced < min_announced {
+                debug!(prefix = %net, announced_secs = announced.as_secs(), "Dropping briefly announced prefix");
+                dropped += 1;
+                continue;
+            }
+        }
+        nets.push(net);
+    }
+    if dropped > 0 {
+        info!(
+            dropped,
+            kept = nets.len(),
+            "Dropped prefixes below the minimum announced duration"
+        );
+    }
+    nets
+}
+
+/// `timelines` の区間を問い合わせ期間で切り詰め、重なりを除いて合計する
+fn announced_duration(
+    timelines: Option<&Value>,
+    query_start: Option<NaiveDateTime>,
+    query_end: Option<NaiveDateTime>,
+) -> Duration {
+    let time = |t: &Value, key: &str| t.get(key)?.as_str()?.parse::<NaiveDateTime>().ok();
+    let mut spans: Vec<(NaiveDateTime, NaiveDateTime)> = timelines
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|t| {
+            let mut start = time(t, "starttime")?;
+            let mut end = time(t, "endtime")?;
+            if let Some(qs) = query_start {
+                start = start.max(qs);
+            }
+            if let Some(qe) = query_end {
+                end = end.min(qe);
+            }
+            (end > start).then_some((start, end))
+        })
+        .collect();
+    spans.sort();
+
+    let mut total = chrono::Duration::zero();
+    let mut current: Option<(NaiveDateTime, NaiveDateTime)> = None;
+    for (start, end) in spans {
+        match &mut current {
+            Some((_, cur_end)) if start <= *cur_end => *cur_end = (*cur_end).max(end),
+            _ => {
+                if let Some((s, e)) = current {
+                    total += e - s;
                 }
+                current = Some((start, end));
             }
         }
     }
-    Ok(nets)
+    if let Some((s, e)) = current {
+        total += e - s;
+    }
+    total.to_std().unwrap_or(Duration::ZERO)
+}
+
+/// RIPEstat: Network Info API から、IPアドレスを含む経路プレフィックスと起源ASを取得
+pub async fn fetch_network_info(
+    client: &Client,
+    addr: IpAddr,
+    policy: &ApiPolicy,
+) -> Result<(Option<IpNet>, Vec<String>), AppError> {
+    let url = ripestat_url("network-info", &addr.to_string(), policy);
+    let json: Value = fetch_json_with_policy(client, &url, MAX_JSON_DOWNLOAD_BYTES, policy).await?;
+    let data = json.get("data");
+    let prefix = data
+        .and_then(|d| d.get("prefix"))
+        .and_then(|p| p.as_str())
+        .and_then(|p| IpNet::from_str(p).ok());
+    let asns = data
+        .and_then(|d| d.get("asns"))
+        .and_then(|a| a.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| match v {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((prefix, asns))
 }
 
 /// ARIN 独自 RDAP OriginAS ネットワーク API
-async fn fetch_arin_originas_prefixes(client: &Client, as_number: &str) -> Result<Vec<IpNet>, AppError> {
+async fn fetch_arin_originas_prefixes(
+    client: &Client,
+    as_number: &str,
+    policy: &ApiPolicy,
+) -> Result<Vec<IpNet>, AppError> {
     let base = "https://rdap.arin.net/registry";
     let url = format!("{base}/arin_originas0_networksbyoriginas/{as_number}");
-    let json: Value = fetch_json_with_limit(client, &url, MAX_JSON_DOWNLOAD_BYTES).await?;
+    let json: Value = fetch_json_with_policy(client, &url, MAX_JSON_DOWNLOAD_BYTES, policy).await?;
     extract_prefixes_from_arin(&json)
 }
 
 /// Vec<IpNet> → (IPv4, IPv6) 集合に分割し aggregate
-fn dedup_and_partition(nets: &[IpNet]) -> (BTreeSet<IpNet>, BTreeSet<IpNet>) {
+/// 集約前の重複を除いた数は統計（--stats）に記録する
+fn dedup_and_partition(as_number: &str, nets: &[IpNet]) -> (BTreeSet<IpNet>, BTreeSet<IpNet>) {
+    {
+        let mut collector = stats();
+        if collector.is_enabled() {
+            let distinct: BTreeSet<IpNet> = nets.iter().map(IpNet::trunc).collect();
+            let v4 = distinct
+                .iter()
+                .filter(|n| matches!(n, IpNet::V4(_)))
+                .count();
+            collector.record_source_prefixes("asn", as_number, IpFamily::V4, v4);
+            collector.record_source_prefixes("asn", as_number, IpFamily::V6, distinct.len() - v4);
+        }
+    }
     let agg = IpNet::aggregate(&nets.to_vec());
     let mut v4 = BTreeSet::new();
     let mut v6 = BTreeSet::new();
@@ -110,54 +307,278 @@ fn dedup_and_partition(nets: &[IpNet]) -> (BTreeSet<IpNet>, BTreeSet<IpNet>) {
     (v4, v6)
 }
 
+/// AS番号 → (IPv4, IPv6)
+pub type AsMap = HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)>;
+
+/// AS のプレフィックスの取得元
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AsSource {
+    /// BGP で広報されているプレフィックス（RIPEstat、ARIN RDAP をフォールバック）
+    #[default]
+    Bgp,
+    /// IRR の route / route6 オブジェクト（`origin:` が一致するもの）
+    Irr,
+    /// MRT の RIB ダンプの AS_PATH から求めた起源AS
+    Mrt,
+}
+
+impl FromStr for AsSource {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bgp" => Ok(AsSource::Bgp),
+            "irr" => Ok(AsSource::Irr),
+            "mrt" => Ok(AsSource::Mrt),
+            _ => Err("Invalid AS source. Valid options: 'bgp', 'irr' or 'mrt'"),
+        }
+    }
+}
+
+/// AS 問い合わせの設定
+#[derive(Debug, Clone)]
+pub struct AsLookupOptions {
+    /// 同時に問い合わせる AS の最大数（0 は 1 とみなす）
+    pub concurrency: usize,
+    /// 一部の AS が失敗しても成功分で続行する（既定は偽＝厳格）
+    pub continue_on_failure: bool,
+    /// プレフィックスの取得元
+    pub source: AsSource,
+    /// AS-SET 展開と IRR 取得元のダンプ / whois、深さ上限
+    pub irr: IrrOptions,
+    /// MRT 取得元のファイルとピア数のしきい値
+    pub mrt: MrtOptions,
+    /// カスタマーコーン展開の AS 関係データと上限
+    pub cone: ConeOptions,
+    /// 出力に付ける AS名の取得元
+    pub names: AsNameOptions,
+    /// JSON API（RIPEstat / ARIN RDAP）のリトライ・流量制限・sourceapp（同じ設定の問い合わせで共有する）
+    pub api: Arc<ApiPolicy>,
+}
+
+impl AsLookupOptions {
+    pub fn new(concurrency: usize, continue_on_failure: bool) -> Self {
+        let api = Arc::new(ApiPolicy::default());
+        Self {
+            concurrency,
+            continue_on_failure,
+            source: AsSource::default(),
+            irr: IrrOptions {
+                api: api.clone(),
+                ..IrrOptions::default()
+            },
+            mrt: MrtOptions::default(),
+            cone: ConeOptions::default(),
+            names: AsNameOptions::default(),
+            api,
+        }
+    }
+}
+
+/// AS 1件分の問い合わせ結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsOutcome {
+    /// 取得成功（IPv4/IPv6 のプレフィックス数）
+    Success { v4: usize, v6: usize },
+    /// 取得には成功したが発表プレフィックスが無い
+    Empty,
+    /// 取得失敗（理由）
+    Failed(String),
+}
+
+/// 複数 AS の問い合わせ結果（入力順）
+#[derive(Debug, Clone, Default)]
+pub struct AsReport {
+    pub outcomes: Vec<(String, AsOutcome)>,
+}
+
+impl AsReport {
+    /// 結果を1件追加する
+    pub fn push(&mut self, asn: &str, outcome: AsOutcome) {
+        self.outcomes.push((asn.to_string(), outcome));
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.count(|o| matches!(o, AsOutcome::Success { .. }))
+    }
+
+    pub fn empty(&self) -> usize {
+        self.count(|o| matches!(o, AsOutcome::Empty))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, AsOutcome::Failed(_)))
+    }
+
+    fn count(&self, pred: impl Fn(&AsOutcome) -> bool) -> usize {
+        self.outcomes.iter().filter(|(_, o)| pred(o)).count()
+    }
+
+    /// 人が読むための集計（1行目に件数、以降に AS ごとの結果）
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "AS lookup summary: {} succeeded, {} empty, {} failed\n",
+            self.succeeded(),
+            self.empty(),
+            self.failed()
+        );
+        for (asn, outcome) in &self.outcomes {
+            let line = match outcome {
+                AsOutcome::Success { v4, v6 } => format!("{} IPv4, {} IPv6 prefixes", v4, v6),
+                AsOutcome::Empty => "no announced prefixes".to_string(),
+                AsOutcome::Failed(reason) => format!("FAILED: {}", reason),
+            };
+            out.push_str(&format!("  AS{}: {}\n", asn, line));
+        }
+        out
+    }
+
+    /// 集計をログに出す（件数と AS ごとの結果は info、失敗は取得時に warn で出している）
+    pub fn log(&self) {
+        if self.outcomes.is_empty() {
+            return;
+        }
+        for (asn, outcome) in &self.outcomes {
+            match outcome {
+                AsOutcome::Success { v4, v6 } => {
+                    info!(asn = %asn, v4, v6, "AS prefixes fetched")
+                }
+                AsOutcome::Empty => info!(asn = %asn, "No announced prefixes"),
+                AsOutcome::Failed(_) => {}
+            }
+        }
+        info!(
+            succeeded = self.succeeded(),
+            empty = self.empty(),
+            failed = self.failed(),
+            "AS lookup summary"
+        );
+    }
+
+    /// 失敗の扱いを適用する
+    /// `continue_on_failure` が偽なら1件でも失敗した時点でエラー、
+    /// 真でも全件失敗ならエラー。
+    pub fn check(&self, continue_on_failure: bool) -> Result<(), AppError> {
+        let failed = self.failed();
+        if failed == 0 {
+            return Ok(());
+        }
+        if !continue_on_failure {
+            return Err(AppError::PartialData(format!(
+                "{} of {} AS lookups failed (use --continue-on-as-failure to proceed)",
+                failed,
+                self.outcomes.len()
+            )));
+        }
+        if failed == self.outcomes.len() {
+            return Err(AppError::Fetch("All AS lookups failed".into()));
+        }
+        Ok(())
+    }
+}
+
 /// 複数 AS を並列取得してファイル出力
+/// 集計をログに出し、失敗の扱い（厳格/続行）を適用してから書き出す
 pub async fn process_as_numbers(
     client: &Client,
     as_numbers: &[String],
-    output_format: OutputFormat,
-    concurrency: usize,
+    output: &OutputOptions,
+    opts: &AsLookupOptions,
 ) -> Result<(), AppError> {
-    let max_concurrent = if concurrency == 0 { 1 } else { concurrency };
+    let (map, report) = fetch_prefixes_for_many(client, as_numbers, opts).await?;
+    report.log();
+    report.check(opts.continue_on_failure)?;
+
+    let output = &annotate_output(client, output, &map, opts).await?;
+    for asn in as_numbers {
+        if let Some((v4, v6)) = map.get(asn) {
+            let set = IpRangeSet::from_nets(v4.iter().chain(v6));
+            write_ip_list(asn, IpFamily::V4, &set, output).await?;
+            write_ip_list(asn, IpFamily::V6, &set, output).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 複数 AS のプレフィックスを並列取得し、AS番号 → (IPv4, IPv6) のマップと結果の集計を返す
+/// 取得に失敗した AS はマップに含めない（失敗の扱いは呼び出し側で `AsReport::check` を使う）
+pub async fn fetch_prefixes_for_many(
+    client: &Client,
+    as_numbers: &[String],
+    opts: &AsLookupOptions,
+) -> Result<(AsMap, AsReport), AppError> {
+    let max_concurrent = if opts.concurrency == 0 {
+        1
+    } else {
+        opts.concurrency
+    };
     let semaphore = Arc::new(Semaphore::new(max_concurrent));
+    // IRR / MRT を使う場合、ファイルは対象の AS の分だけ1回読み込む
+    let origins: Vec<u32> = as_numbers.iter().filter_map(|a| a.parse().ok()).collect();
+    let source = match opts.source {
+        AsSource::Bgp => Source::Bgp(opts.api.clone()),
+        AsSource::Irr => Source::Irr(Arc::new(IrrRoutes::load(&opts.irr, &origins).await?)),
+        AsSource::Mrt => Source::Mrt(Arc::new(MrtRoutes::load(&opts.mrt, &origins).await?)),
+    };
 
     let handles = as_numbers
         .iter()
         .map(|asn| {
             let asn_cloned = asn.clone();
-            let fmt_c = output_format;
             let client_c = client.clone();
             let sem_c = semaphore.clone();
+            let source_c = source.clone();
             tokio::spawn(async move {
                 let _permit = sem_c.acquire_owned().await?;
-                match get_prefixes_via_rdap(&client_c, &asn_cloned).await {
-                    Ok((v4, v6)) => {
-                        write_ip_list(&asn_cloned, IpFamily::V4, &v4, fmt_c).await?;
-                        write_ip_list(&asn_cloned, IpFamily::V6, &v6, fmt_c).await?;
-                    }
-                    Err(e) => debug_log(format!("Error processing {}: {}", asn_cloned, e)),
-                };
-                Ok::<(), AppError>(())
+                let res = source_c.prefixes(&client_c, &asn_cloned).await;
+                Ok::<_, AppError>((asn_cloned, res))
             })
         })
         .collect::<Vec<_>>();
 
+    let mut map = HashMap::new();
+    let mut report = AsReport::default();
     for h in handles {
-        h.await??;
+        let (asn, res) = h.await??;
+        match res {
+            Ok((v4, v6)) => {
+                let outcome = if v4.is_empty() && v6.is_empty() {
+                    AsOutcome::Empty
+                } else {
+                    AsOutcome::Success {
+                        v4: v4.len(),
+                        v6: v6.len(),
+                    }
+                };
+                report.push(&asn, outcome);
+                journal().record_prefixes(&asn, v4.iter().chain(&v6));
+                map.insert(asn, (v4, v6));
+            }
+            Err(e) => {
+                warn!(asn = %asn, error = %e, "AS lookup failed");
+                journal().record_failure("asn", &asn, &e.to_string());
+                report.push(&asn, AsOutcome::Failed(e.to_string()));
+            }
+        }
     }
-    Ok(())
+    Ok((map, report))
 }
 
-/// ファイル書き出しヘルパ
-async fn write_ip_list(
+/// ファイル書き出しヘルパ（`ip_set` のうち `ip_family` の分を書き出す）
+pub(crate) async fn write_ip_list(
     as_number: &str,
     ip_family: IpFamily,
-    ip_set: &BTreeSet<IpNet>,
-    output_format: OutputFormat,
+    ip_set: &IpRangeSet,
+    output: &OutputOptions,
 ) -> Result<(), AppError> {
-    if ip_set.is_empty() {
-        debug_log(format!("No {} routes for {}", ip_family.as_str(), as_number));
+    let empty = match ip_family {
+        IpFamily::V4 => ip_set.v4.is_empty(),
+        IpFamily::V6 => ip_set.v6.is_empty(),
+    };
+    if empty {
+        debug!(asn = %as_number, family = ip_family.as_str(), "No routes");
     } else {
-        write_as_ip_list_to_file(as_number, ip_family, ip_set, output_format).await?;
+        write_as_ip_list_to_file(as_number, ip_family, ip_set, output).await?;
     }
     Ok(())
 }
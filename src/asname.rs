@@ -0,0 +1,375 @@
+//! AS名（`AS13335 CLOUDFLARENET`）による出力の注記（`--as-names`）
+//!
+//! コミットしたファイアウォールのリストを人が確認できるよう、ヘッダ、nft の各要素のコメント、
+//! 実行レポートに AS名を付ける。
+//! - `--as-names-file`: ローカルの AS名一覧（`AS13335 CLOUDFLARENET - Cloudflare, Inc., US` のような
+//!   1行1AS のテキスト。CIDR Report の asnames.txt など、gzip / bzip2 も可）
+//! - 指定しない場合は RIPEstat の as-overview に AS ごとに問い合わせる
+//!
+//! 名前は最初の語（ハンドル）だけを使い、コメントとして安全な文字に限る。
+
+use crate::asn::{AsLookupOptions, AsMap, ripestat_url};
+use crate::common::Asn;
+use crate::constants::MAX_JSON_DOWNLOAD_BYTES;
+use crate::error::AppError;
+use crate::fetch::{ApiPolicy, fetch_json_with_policy};
+use crate::irr::open_dump;
+use crate::output::OutputOptions;
+use crate::report::journal;
+use ipnet::IpNet;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+/// 1つの要素のコメントに並べる AS の上限
+const MAX_ORIGINS_PER_COMMENT: usize = 5;
+/// AS名の最大長
+const MAX_NAME_LEN: usize = 64;
+
+/// AS名の注記の設定
+#[derive(Debug, Clone, Default)]
+pub struct AsNameOptions {
+    /// 出力に AS名を付ける
+    pub enabled: bool,
+    /// ローカルの AS名一覧（指定しない場合は RIPEstat に問い合わせる）
+    pub file: Option<PathBuf>,
+}
+
+/// AS番号 → AS名
+pub type AsNames = HashMap<u32, String>;
+
+/// 名前の最初の語を、コメントに書いて安全な文字だけにする
+fn clean_name(s: &str) -> Option<String> {
+    let first = s.split_whitespace().next()?.trim_end_matches(',');
+    let name: String = first
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .take(MAX_NAME_LEN)
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+/// AS名一覧の1行（`AS13335 CLOUDFLARENET - ...` / `13335 CLOUDFLARENET, US`）
+pub fn parse_as_name_line(line: &str) -> Option<(u32, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (asn, rest) = line.split_once(char::is_whitespace)?;
+    let asn = asn.parse::<Asn>().ok()?;
+    Some((asn.0, clean_name(rest)?))
+}
+
+/// AS名一覧を読み、`names` に加える（`wanted` を指定した場合はその AS の分だけ）
+pub fn read_as_names<R: BufRead>(
+    reader: R,
+    wanted: Option<&HashSet<u32>>,
+    names: &mut AsNames,
+) -> Result<usize, AppError> {
+    let mut read = 0;
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let Some((asn, name)) = parse_as_name_line(&String::from_utf8_lossy(&line)) else {
+            continue;
+        };
+        read += 1;
+        if wanted.is_none_or(|w| w.contains(&asn)) {
+            names.insert(asn, name);
+        }
+    }
+    Ok(read)
+}
+
+/// AS名一覧のファイルを読む
+pub fn read_as_names_file(
+    path: &Path,
+    wanted: Option<&HashSet<u32>>,
+    names: &mut AsNames,
+) -> Result<usize, AppError> {
+    read_as_names(open_dump(path, "AS name file")?, wanted, names)
+}
+
+/// RIPEstat as-overview 応答の `holder`（`CLOUDFLARENET - Cloudflare, Inc.`）から名前を取り出す
+pub fn extract_as_overview_name(json: &Value) -> Option<String> {
+    json.get("data")
+        .and_then(|d| d.get("holder"))
+        .and_then(|h| h.as_str())
+        .and_then(clean_name)
+}
+
+async fn fetch_as_name(
+    client: &Client,
+    asn: u32,
+    policy: &ApiPolicy,
+) -> Result<Option<String>, AppError> {
+    let url = ripestat_url("as-overview", &format!("AS{}", asn), policy);
+    let json: Value = fetch_json_with_policy(client, &url, MAX_JSON_DOWNLOAD_BYTES, policy).await?;
+    Ok(extract_as_overview_name(&json))
+}
+
+/// AS名を読み込む（無効なら空）
+/// 取得できなかった AS は名前なしで続行し、実行レポートに記録する
+pub async fn load_as_names(
+    client: &Client,
+    asns: &[u32],
+    opts: &AsNameOptions,
+    concurrency: usize,
+    policy: &Arc<ApiPolicy>,
+) -> Result<AsNames, AppError> {
+    let mut names = AsNames::new();
+    if !opts.enabled || asns.is_empty() {
+        return Ok(names);
+    }
+    if let Some(path) = &opts.file {
+        let path = path.clone();
+        let wanted: HashSet<u32> = asns.iter().copied().collect();
+        names = tokio::task::spawn_blocking(move || {
+            let mut names = AsNames::new();
+            let lines = read_as_names_file(&path, Some(&wanted), &mut names)?;
+            debug!(path = %path.display(), lines, "Loaded AS name file");
+            Ok::<_, AppError>(names)
+        })
+        .await??;
+    } else {
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let handles = asns
+            .iter()
+            .map(|&asn| {
+                let client_c = client.clone();
+                let sem_c = semaphore.clone();
+                let policy_c = policy.clone();
+                tokio::spawn(async move {
+                    let _permit = sem_c.acquire_owned().await?;
+                    Ok::<_, AppError>((asn, fetch_as_name(&client_c, asn, &policy_c).await))
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            match h.await?? {
+                (asn, Ok(Some(name))) => {
+                    names.insert(asn, name);
+                }
+                (_, Ok(None)) => {}
+                (asn, Err(e)) => warn!(asn = %asn, error = %e, "AS name lookup failed"),
+            }
+        }
+    }
+    let missing = asns.iter().filter(|a| !names.contains_key(a)).count();
+    if missing > 0 {
+        debug!(missing, "AS numbers without a name");
+    }
+    journal().record_as_names(&names);
+    Ok(names)
+}
+
+/// 重複の出力の1要素の起源AS と国コード（実行レポート用）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttributedPrefix {
+    pub prefix: String,
+    pub origins: Vec<Asn>,
+    pub countries: Vec<String>,
+}
+
+/// 出力に付ける AS名と、プレフィックス → 起源AS（overlap では国コードも）の対応
+#[derive(Debug, Clone, Default)]
+pub struct AsAnnotations {
+    names: AsNames,
+    origins: BTreeMap<IpNet, BTreeSet<u32>>,
+    countries: BTreeMap<IpNet, BTreeSet<String>>,
+}
+
+impl AsAnnotations {
+    pub fn new(names: AsNames) -> Self {
+        Self {
+            names,
+            origins: BTreeMap::new(),
+            countries: BTreeMap::new(),
+        }
+    }
+
+    /// AS ごとのプレフィックス（`fetch_prefixes_for_many` の結果）から作る
+    pub fn from_as_map(names: AsNames, map: &AsMap) -> Self {
+        let mut annotations = Self::new(names);
+        for (asn, (v4, v6)) in map {
+            if let Ok(asn) = asn.parse::<u32>() {
+                annotations.add_prefixes(asn, v4.iter().chain(v6));
+            }
+        }
+        annotations
+    }
+
+    /// 読み込んだ AS名
+    pub fn names(&self) -> &AsNames {
+        &self.names
+    }
+
+    /// `asns` の AS のプレフィックスだけに絞る（名前はそのまま）
+    pub fn restricted_to(&self, asns: &[u32]) -> Self {
+        let wanted: HashSet<u32> = asns.iter().copied().collect();
+        let origins = self
+            .origins
+            .iter()
+            .filter_map(|(net, origins)| {
+                let kept: BTreeSet<u32> = origins
+                    .iter()
+                    .copied()
+                    .filter(|a| wanted.contains(a))
+                    .collect();
+                (!kept.is_empty()).then_some((*net, kept))
+            })
+            .collect();
+        Self {
+            names: self.names.clone(),
+            origins,
+            countries: self.countries.clone(),
+        }
+    }
+
+    pub fn add_prefixes<'a>(&mut self, asn: u32, nets: impl IntoIterator<Item = &'a IpNet>) {
+        for net in nets {
+            self.origins.entry(net.trunc()).or_default().insert(asn);
+        }
+    }
+
+    /// 国コードの割り当てを加える（overlap の出力で要素の国を示す）
+    pub fn add_country_prefixes<'a>(
+        &mut self,
+        country: &str,
+        nets: impl IntoIterator<Item = &'a IpNet>,
+    ) {
+        for net in nets {
+            self.countries
+                .entry(net.trunc())
+                .or_default()
+                .insert(country.to_uppercase());
+        }
+    }
+
+    /// 国コードの対応を持つ（overlap の注記）かどうか。TXT にも要素ごとのコメントを付ける
+    pub fn has_countries(&self) -> bool {
+        !self.countries.is_empty()
+    }
+
+    /// `AS13335 CLOUDFLARENET`（名前が無ければ `AS13335`）
+    pub fn label(&self, asn: u32) -> String {
+        match self.names.get(&asn) {
+            Some(name) => format!("AS{} {}", asn, name),
+            None => format!("AS{}", asn),
+        }
+    }
+
+    /// 要素と重なる（要素を含む、または要素に含まれる）プレフィックスの起源AS
+    pub fn origins_of(&self, net: &IpNet) -> BTreeSet<u32> {
+        overlapping(&self.origins, net)
+    }
+
+    /// 要素と重なる割り当ての国コード
+    pub fn countries_of(&self, net: &IpNet) -> BTreeSet<String> {
+        overlapping(&self.countries, net)
+    }
+
+    /// 要素に付けるコメント（`AS2497 IIJ, AS4713 OCN (JP)`。起源AS も国も分からなければ `None`）
+    pub fn element_comment(&self, net: &IpNet) -> Option<String> {
+        let origins = self.origins_of(net);
+        let countries = self.countries_of(net);
+        if origins.is_empty() && countries.is_empty() {
+            return None;
+        }
+        let mut labels: Vec<String> = origins
+            .iter()
+            .take(MAX_ORIGINS_PER_COMMENT)
+            .map(|&asn| self.label(asn))
+            .collect();
+        if origins.len() > MAX_ORIGINS_PER_COMMENT {
+            labels.push(format!("+{} more", origins.len() - MAX_ORIGINS_PER_COMMENT));
+        }
+        let mut comment = labels.join(", ");
+        if !countries.is_empty() {
+            let countries = countries.into_iter().collect::<Vec<_>>().join(", ");
+            comment = if comment.is_empty() {
+                countries
+            } else {
+                format!("{} ({})", comment, countries)
+            };
+        }
+        Some(comment)
+    }
+
+    /// 要素ごとの起源AS と国コード
+    pub fn attribute(&self, nets: &BTreeSet<IpNet>) -> Vec<AttributedPrefix> {
+        nets.iter()
+            .map(|net| AttributedPrefix {
+                prefix: net.to_string(),
+                origins: self.origins_of(net).into_iter().map(Asn).collect(),
+                countries: self.countries_of(net).into_iter().collect(),
+            })
+            .collect()
+    }
+
+    /// ヘッダに加える `# AS Name: AS13335 CLOUDFLARENET` の行（要素の起源AS のうち名前のあるもの）
+    pub fn header_lines(&self, nets: &BTreeSet<IpNet>) -> String {
+        let origins: BTreeSet<u32> = nets.iter().flat_map(|n| self.origins_of(n)).collect();
+        origins
+            .into_iter()
+            .filter(|asn| self.names.contains_key(asn))
+            .map(|asn| format!("# AS Name: {}\n", self.label(asn)))
+            .collect()
+    }
+}
+
+/// 要素と重なる（要素を含む、または要素に含まれる）プレフィックスの値を集める
+fn overlapping<T: Ord + Clone>(map: &BTreeMap<IpNet, BTreeSet<T>>, net: &IpNet) -> BTreeSet<T> {
+    let net = net.trunc();
+    let mut out = BTreeSet::new();
+    for len in 0..=net.prefix_len() {
+        if let Ok(sup) = IpNet::new(net.addr(), len)
+            && let Some(values) = map.get(&sup.trunc())
+        {
+            out.extend(values.iter().cloned());
+        }
+    }
+    for (_, values) in map.range(net..).take_while(|(p, _)| net.contains(*p)) {
+        out.extend(values.iter().cloned());
+    }
+    out
+}
+
+/// 有効なら、取得したプレフィックスの AS名を読み込んで注記を作る（無効なら `None`）
+pub async fn load_annotations(
+    client: &Client,
+    map: &AsMap,
+    lookup: &AsLookupOptions,
+) -> Result<Option<AsAnnotations>, AppError> {
+    if !lookup.names.enabled {
+        return Ok(None);
+    }
+    let mut asns: Vec<u32> = map.keys().filter_map(|a| a.parse().ok()).collect();
+    asns.sort_unstable();
+    let names = load_as_names(
+        client,
+        &asns,
+        &lookup.names,
+        lookup.concurrency,
+        &lookup.api,
+    )
+    .await?;
+    Ok(Some(AsAnnotations::from_as_map(names, map)))
+}
+
+/// 有効なら、注記付きの出力設定を返す
+pub async fn annotate_output(
+    client: &Client,
+    output: &OutputOptions,
+    map: &AsMap,
+    lookup: &AsLookupOptions,
+) -> Result<OutputOptions, AppError> {
+    let mut output = output.clone();
+    output.annotations = load_annotations(client, map, lookup).await?.map(Arc::new);
+    Ok(output)
+}
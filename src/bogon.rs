@@ -0,0 +1,178 @@
+//! 特殊用途アドレス（bogon）の除去
+//!
+//! IANA の特殊用途アドレスレジストリ（RFC 6890 とその更新）の範囲は、許可リストにも
+//! 拒否リストにも載せるべきではないが、RIR のデータや RIPEstat の広報にも含まれ得る。
+//! 組み込みの表（`SPECIAL_PURPOSE`）に加え、Team Cymru の fullbogons のような
+//! 1行1CIDR のファイル（`--bogon-file`）を読み込み、すべての出力から差し引く。
+
+use crate::error::AppError;
+use crate::interval::IpRangeSet;
+use crate::irr::open_dump;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::debug;
+
+/// 組み込みの特殊用途アドレス（CIDR, 種類）
+pub const SPECIAL_PURPOSE: &[(&str, &str)] = &[
+    ("0.0.0.0/8", "this network (RFC 791)"),
+    ("10.0.0.0/8", "private-use (RFC 1918)"),
+    ("100.64.0.0/10", "shared address space (RFC 6598)"),
+    ("127.0.0.0/8", "loopback (RFC 1122)"),
+    ("169.254.0.0/16", "link-local (RFC 3927)"),
+    ("172.16.0.0/12", "private-use (RFC 1918)"),
+    ("192.0.0.0/24", "IETF protocol assignments (RFC 6890)"),
+    ("192.0.2.0/24", "documentation (RFC 5737)"),
+    ("192.88.99.0/24", "6to4 relay anycast (RFC 7526)"),
+    ("192.168.0.0/16", "private-use (RFC 1918)"),
+    ("198.18.0.0/15", "benchmarking (RFC 2544)"),
+    ("198.51.100.0/24", "documentation (RFC 5737)"),
+    ("203.0.113.0/24", "documentation (RFC 5737)"),
+    ("224.0.0.0/4", "multicast (RFC 5771)"),
+    ("240.0.0.0/4", "reserved (RFC 1112)"),
+    ("::/128", "unspecified address (RFC 4291)"),
+    ("::1/128", "loopback (RFC 4291)"),
+    ("::ffff:0:0/96", "IPv4-mapped (RFC 4291)"),
+    (
+        "64:ff9b:1::/48",
+        "IPv4/IPv6 local-use translation (RFC 8215)",
+    ),
+    ("100::/64", "discard-only (RFC 6666)"),
+    ("2001::/32", "Teredo (RFC 4380)"),
+    ("2001:2::/48", "benchmarking (RFC 5180)"),
+    ("2001:10::/28", "ORCHID (RFC 4843)"),
+    ("2001:db8::/32", "documentation (RFC 3849)"),
+    ("2002::/16", "6to4 (RFC 3056)"),
+    ("3fff::/20", "documentation (RFC 9637)"),
+    ("5f00::/16", "SRv6 SIDs (RFC 9602)"),
+    ("fc00::/7", "unique-local (RFC 4193)"),
+    ("fe80::/10", "link-local unicast (RFC 4291)"),
+    ("fec0::/10", "site-local (RFC 3879)"),
+    ("ff00::/8", "multicast (RFC 4291)"),
+];
+
+/// 取り除いた1つの範囲
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BogonRemoval {
+    pub prefix: String,
+    /// 該当した範囲の種類（読み込んだファイルの場合はファイル名）
+    pub reason: String,
+}
+
+/// 差し引く bogon の表
+#[derive(Debug, Clone, Default)]
+pub struct BogonTable {
+    /// (範囲, 種類)。組み込みの表が先
+    entries: Vec<(IpNet, String)>,
+    /// 集約済みの範囲
+    nets: Vec<IpNet>,
+    /// 集約済みの範囲の区間集合
+    set: IpRangeSet,
+}
+
+impl BogonTable {
+    /// 組み込みの特殊用途アドレスの表
+    pub fn builtin() -> Self {
+        let entries: Vec<(IpNet, String)> = SPECIAL_PURPOSE
+            .iter()
+            .filter_map(|(cidr, reason)| Some((IpNet::from_str(cidr).ok()?, reason.to_string())))
+            .collect();
+        let mut table = Self {
+            entries,
+            ..Self::default()
+        };
+        table.rebuild();
+        table
+    }
+
+    /// 1行1CIDR のテキスト（`#` 以降はコメント）を読んで加え、読んだ数を返す
+    pub fn read<R: BufRead>(&mut self, reader: R, reason: &str) -> Result<usize, AppError> {
+        let mut read = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let net = IpNet::from_str(line).map_err(|e| {
+                AppError::ParseError(format!(
+                    "{} line {}: invalid CIDR '{}': {}",
+                    reason,
+                    i + 1,
+                    line,
+                    e
+                ))
+            })?;
+            self.entries.push((net.trunc(), reason.to_string()));
+            read += 1;
+        }
+        self.rebuild();
+        Ok(read)
+    }
+
+    /// bogon のファイル（plain / gzip / bzip2）を読んで加える
+    pub fn read_file(&mut self, path: &Path) -> Result<usize, AppError> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        self.read(open_dump(path, "bogon file")?, &name)
+    }
+
+    fn rebuild(&mut self) {
+        self.set = IpRangeSet::from_nets(self.entries.iter().map(|(net, _)| net));
+        self.nets = self
+            .set
+            .v4_nets()
+            .into_iter()
+            .chain(self.set.v6_nets())
+            .collect();
+    }
+
+    /// 集約済みの範囲（広げたりまとめたりする際に避ける範囲）
+    pub fn ranges(&self) -> &[IpNet] {
+        &self.nets
+    }
+
+    /// 集合から bogon を差し引き、取り除いた範囲とその種類を返す
+    pub fn remove_from(&self, set: &IpRangeSet) -> (IpRangeSet, Vec<BogonRemoval>) {
+        let removed = set.intersection(&self.set);
+        if removed.is_empty() {
+            return (set.clone(), Vec::new());
+        }
+        let removals = removed
+            .to_set()
+            .iter()
+            .map(|net| BogonRemoval {
+                prefix: net.to_string(),
+                reason: self.reason(net),
+            })
+            .collect();
+        (set.difference(&self.set), removals)
+    }
+
+    /// 範囲に重なる最初の表の項目の種類
+    fn reason(&self, net: &IpNet) -> String {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry.contains(net) || net.contains(entry))
+            .map(|(_, reason)| reason.clone())
+            .unwrap_or_else(|| "bogon".into())
+    }
+}
+
+/// 組み込みの表に `files` を加えた表を読み込む
+pub async fn load_bogons(files: &[PathBuf]) -> Result<BogonTable, AppError> {
+    let files = files.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut table = BogonTable::builtin();
+        for path in &files {
+            let lines = table.read_file(path)?;
+            debug!(path = %path.display(), lines, "Loaded bogon file");
+        }
+        Ok(table)
+    })
+    .await?
+}
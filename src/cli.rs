@@ -1,19 +1,32 @@
-use clap::Parser;
+use crate::asn::{AsLookupOptions, AsSource};
+use crate::asname::AsNameOptions;
+use crate::common::AsnRange;
+use crate::common_download::DownloadOptions;
+use crate::cone::{ConeOptions, DEFAULT_CONE_MAX_DEPTH, DEFAULT_CONE_MAX_SIZE};
+use crate::constants::{DEFAULT_API_RATE, DEFAULT_RIPESTAT_SOURCEAPP};
+use crate::country::normalize_country_code;
+use crate::error::AppError;
+use crate::fetch::ApiPolicy;
+use crate::irr::{DEFAULT_AS_SET_MAX_DEPTH, IrrOptions, Member, parse_member};
+use crate::mrt::{DEFAULT_MRT_MIN_PEERS, MrtOptions};
+use crate::rate_limit::RateLimiter;
+use crate::shape::ShapeOptions;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, Parser, Subcommand};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn parse_country_code(s: &str) -> Result<String, String> {
-    let upper = s.to_ascii_uppercase();
-    let valid = upper.chars().all(|c| c.is_ascii_alphabetic());
-    if !valid {
-        return Err("Country code must be alphabetic (A-Z)".into());
-    }
-    let len = upper.len();
-    if !(len == 2 || len == 3) {
-        return Err("Country code length must be 2 or 3".into());
-    }
-    Ok(upper)
+    normalize_country_code(s)
 }
 
+const LEGACY_HEADING: &str = "Legacy options (deprecated, use subcommands)";
+
 /// CLIの定義
+/// サブコマンド共通のオプションは `global = true` とし、サブコマンドの前後どちらにも書ける
 #[derive(Parser, Debug)]
 #[command(
     author,
@@ -21,27 +34,38 @@ fn parse_country_code(s: &str) -> Result<String, String> {
     about = "This tool can be used to obtain IP addresses by country or by AS number."
 )]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(
+        long = "config",
+        global = true,
+        required = false,
+        help = "Path to a TOML config file with [defaults] and [profiles.<name>].\nCommand-line options take precedence over [defaults]."
+    )]
+    pub config: Option<PathBuf>,
+
     #[arg(
         short = 'c',
         long = "country",
-        required_unless_present_any = ["as_numbers", "overlap"],
         required = false,
         num_args = 1..,
         value_parser = parse_country_code,
-        help = "Specify the country codes.\nExample: jp br us"
+        help_heading = LEGACY_HEADING,
+        help = "Specify the country codes (ISO 3166-1 alpha-2 or alpha-3).\nExample: jp br us"
     )]
     pub country_codes: Option<Vec<String>>,
 
     #[arg(
         short = 'a',
         long = "as-number",
-        required_unless_present_any = ["country_codes", "overlap"],
         required = false,
-        value_parser = clap::value_parser!(u32),
+        value_parser = clap::value_parser!(AsnRange),
         num_args = 1..,
-        help = "Specify AS numbers.\nExample: 0000 1234"
+        help_heading = LEGACY_HEADING,
+        help = "Specify AS numbers (plain, AS-prefixed, asdot or ranges).\nExample: 2497 AS13335 1.10 64512-64520"
     )]
-    pub as_numbers: Option<Vec<u32>>,
+    pub as_numbers: Option<Vec<AsnRange>>,
 
     #[arg(
         short = 'o',
@@ -49,6 +73,7 @@ pub struct Cli {
         help = "Write down the IP addresses of the overlapping country and AS numbers in a file of your choice.\nBoth the -c and -a arguments must be specified.",
         required = false,
         default_value = "false",
+        help_heading = LEGACY_HEADING,
         requires("country_codes"),
         requires("as_numbers")
     )]
@@ -57,6 +82,7 @@ pub struct Cli {
     #[arg(
         short = 'f',
         long = "format",
+        global = true,
         default_value = "txt",
         required = false,
         hide_default_value = true,
@@ -67,7 +93,8 @@ pub struct Cli {
 
     #[arg(
         long = "max-retries",
-        help = "Maximum HTTP retry attempts for downloads.",
+        global = true,
+        help = "Maximum HTTP retry attempts for downloads and API queries.",
         required = false,
         default_value_t = 6u32,
         value_parser = clap::value_parser!(u32)
@@ -76,6 +103,7 @@ pub struct Cli {
 
     #[arg(
         long = "max-backoff-sec",
+        global = true,
         help = "Cap for exponential backoff seconds per retry.",
         required = false,
         default_value_t = 16u64,
@@ -85,6 +113,7 @@ pub struct Cli {
 
     #[arg(
         long = "http-timeout-secs",
+        global = true,
         help = "HTTP request total timeout in seconds.",
         required = false,
         default_value_t = 20u64,
@@ -94,6 +123,7 @@ pub struct Cli {
 
     #[arg(
         long = "connect-timeout-secs",
+        global = true,
         help = "HTTP connect timeout in seconds.",
         required = false,
         default_value_t = 10u64,
@@ -104,6 +134,7 @@ pub struct Cli {
     #[arg(
         long = "concurrency",
         short = 'C',
+        global = true,
         help = "Max concurrent AS queries.",
         required = false,
         default_value_t = 5usize,
@@ -113,18 +144,899 @@ pub struct Cli {
 
     #[arg(
         long = "continue-on-partial",
+        global = true,
         help = "Continue with successfully downloaded RIR files even if some downloads fail.",
         required = false,
         default_value_t = false
     )]
     pub continue_on_partial: bool,
 
+    #[arg(
+        long = "continue-on-as-failure",
+        global = true,
+        help = "Continue with successfully fetched ASes even if some AS lookups fail.",
+        required = false,
+        default_value_t = false
+    )]
+    pub continue_on_as_failure: bool,
+
+    #[arg(
+        long = "api-rate-limit",
+        global = true,
+        value_name = "RPS",
+        help = "Max JSON API requests per second per host (RIPEstat, ARIN RDAP); 0 disables the limit.",
+        required = false,
+        default_value_t = DEFAULT_API_RATE,
+        value_parser = parse_rate
+    )]
+    pub api_rate_limit: f64,
+
+    #[arg(
+        long = "ripestat-sourceapp",
+        global = true,
+        value_name = "NAME",
+        help = "Identifier sent to RIPEstat as the sourceapp parameter.",
+        required = false,
+        default_value = DEFAULT_RIPESTAT_SOURCEAPP,
+        value_parser = parse_sourceapp
+    )]
+    pub ripestat_sourceapp: String,
+
+    #[arg(
+        long = "announced-window",
+        global = true,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Length of the RIPEstat announced-prefixes query window (e.g. 7d, 30d).\ndefault: RIPEstat's default of two weeks",
+        required = false
+    )]
+    pub announced_window: Option<Duration>,
+
+    #[arg(
+        long = "min-announced",
+        global = true,
+        value_name = "DURATION",
+        default_value = "0",
+        value_parser = parse_duration,
+        help = "Only use RIPEstat prefixes announced for at least this long within the query window (e.g. 12h, 3d); 0 disables the check.\nWhen set, ARIN OriginAS prefixes are not merged into a successful RIPEstat result (their announcement time is unknown).",
+        required = false
+    )]
+    pub min_announced: Duration,
+
+    #[arg(
+        long = "as-source",
+        global = true,
+        default_value = "bgp",
+        required = false,
+        value_parser = ["bgp", "irr", "mrt"],
+        help = "Where AS prefixes come from: 'bgp' (RIPEstat announced prefixes, ARIN RDAP fallback),\n'irr' (route/route6 objects from --irr-dump / --irr-whois) or 'mrt' (origin AS in --mrt-file RIB dumps).\nserve and lookup always use bgp."
+    )]
+    pub as_source: String,
+
+    #[arg(
+        long = "mrt-file",
+        global = true,
+        value_name = "FILE",
+        num_args = 1..,
+        help = "MRT TABLE_DUMP_V2 RIB dump (plain, .gz or .bz2, e.g. RouteViews rib.*.bz2) used by --as-source mrt (repeatable).",
+        required = false
+    )]
+    pub mrt_files: Vec<PathBuf>,
+
+    #[arg(
+        long = "mrt-min-peers",
+        global = true,
+        value_name = "N",
+        help = "Only use a prefix/origin pair seen by at least N collector peers (--as-source mrt).",
+        required = false,
+        default_value_t = DEFAULT_MRT_MIN_PEERS,
+        value_parser = clap::value_parser!(usize)
+    )]
+    pub mrt_min_peers: usize,
+
+    #[arg(
+        long = "irr-dump",
+        global = true,
+        value_name = "FILE",
+        num_args = 1..,
+        help = "IRR database dump in RPSL (plain or .gz, e.g. radb.db.gz) used for --as-set, --as-source irr and irr-compare (repeatable).",
+        required = false
+    )]
+    pub irr_dumps: Vec<PathBuf>,
+
+    #[arg(
+        long = "irr-whois",
+        global = true,
+        value_name = "HOST[:PORT]",
+        help = "IRR whois server queried for AS-SETs and route objects not found in the dumps (e.g. whois.radb.net).",
+        required = false
+    )]
+    pub irr_whois: Option<String>,
+
+    #[arg(
+        long = "as-set-max-depth",
+        global = true,
+        value_name = "N",
+        help = "Maximum AS-SET nesting depth to expand.",
+        required = false,
+        default_value_t = DEFAULT_AS_SET_MAX_DEPTH,
+        value_parser = clap::value_parser!(usize)
+    )]
+    pub as_set_max_depth: usize,
+
+    #[arg(
+        long = "customer-cone",
+        global = true,
+        help = "Expand each AS number into its customer cone from --as-rel-file before collecting prefixes.",
+        required = false,
+        default_value_t = false
+    )]
+    pub customer_cone: bool,
+
+    #[arg(
+        long = "as-rel-file",
+        global = true,
+        value_name = "FILE",
+        num_args = 1..,
+        help = "CAIDA AS relationship file (as-rel / as-rel2 or ppdc-ases; plain, .gz or .bz2) used by --customer-cone (repeatable).",
+        required = false
+    )]
+    pub as_rel_files: Vec<PathBuf>,
+
+    #[arg(
+        long = "cone-max-depth",
+        global = true,
+        value_name = "N",
+        help = "Maximum number of provider-to-customer hops followed from each AS (as-rel files only).",
+        required = false,
+        default_value_t = DEFAULT_CONE_MAX_DEPTH,
+        value_parser = clap::value_parser!(usize)
+    )]
+    pub cone_max_depth: usize,
+
+    #[arg(
+        long = "cone-max-size",
+        global = true,
+        value_name = "N",
+        help = "Maximum number of customer ASes taken from each cone (nearest first).",
+        required = false,
+        default_value_t = DEFAULT_CONE_MAX_SIZE,
+        value_parser = clap::value_parser!(usize)
+    )]
+    pub cone_max_size: usize,
+
+    #[arg(
+        long = "as-names",
+        global = true,
+        help = "Annotate AS outputs with AS names (header lines and a comment on each nft element), e.g. 'AS13335 CLOUDFLARENET'.",
+        required = false,
+        default_value_t = false
+    )]
+    pub as_names: bool,
+
+    #[arg(
+        long = "as-names-file",
+        global = true,
+        value_name = "FILE",
+        help = "Local AS name list ('AS13335 CLOUDFLARENET - ...' per line, e.g. asnames.txt) used by --as-names.\ndefault: query RIPEstat as-overview per AS",
+        required = false
+    )]
+    pub as_names_file: Option<PathBuf>,
+
+    #[arg(
+        long = "attribute-overlaps",
+        global = true,
+        help = "Attribute each element of overlap outputs to its origin AS(es) and country (a comment in txt and nft, and 'attribution' in the run report).",
+        required = false,
+        default_value_t = false
+    )]
+    pub attribute_overlaps: bool,
+
+    #[arg(
+        long = "rir-url",
+        global = true,
+        required = false,
+        num_args = 1..,
+        help = "Override the RIR delegated file URLs (e.g. a local mirror).\ndefault: the five RIR 'extended-latest' files"
+    )]
+    pub rir_urls: Option<Vec<String>>,
+
+    #[arg(
+        long = "output-dir",
+        global = true,
+        required = false,
+        help = "Directory to write output files into (created if missing).\ndefault: current directory"
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "exclude",
+        global = true,
+        required = false,
+        num_args = 1..,
+        help = "CIDRs to remove from every output.\nExample: 192.0.2.0/24 2001:db8::/32"
+    )]
+    pub exclude: Option<Vec<IpNet>>,
+
+    #[arg(
+        long = "post-hook",
+        global = true,
+        required = false,
+        value_name = "CMD",
+        help = "Shell command to run after outputs were written, only if any content changed (repeatable).\nChanged paths and counts are passed in FIRE_SCOPE_* environment variables."
+    )]
+    pub post_hooks: Vec<String>,
+
+    #[arg(
+        long = "metrics-file",
+        global = true,
+        required = false,
+        help = "Write Prometheus metrics (downloads, parse time, list sizes, last success) to this file after each run."
+    )]
+    pub metrics_file: Option<PathBuf>,
+
+    #[arg(
+        long = "report",
+        global = true,
+        required = false,
+        value_name = "FILE",
+        help = "Write a JSON run report (inputs, sources, failures, output files with SHA-256, exit code) to this file.\nIgnored by watch and serve."
+    )]
+    pub report: Option<PathBuf>,
+
+    #[arg(
+        long = "stats",
+        global = true,
+        required = false,
+        value_name = "FILE",
+        help = "Write per-list statistics (prefix counts before/after aggregation, addresses, IPv6 /48 and /64 counts, per-RIR shares, prefix-length histogram) as JSON to this file ('-' for stdout).\nIgnored by watch and serve."
+    )]
+    pub stats: Option<PathBuf>,
+
+    #[arg(
+        long = "max-shrink-percent",
+        global = true,
+        required = false,
+        value_name = "PCT",
+        value_parser = parse_percent,
+        help = "Refuse to overwrite a list whose address space would shrink by more than PCT percent (exit code 6)."
+    )]
+    pub max_shrink_percent: Option<f64>,
+
+    #[arg(
+        long = "keep-bogons",
+        global = true,
+        required = false,
+        default_value_t = false,
+        help = "Keep special-purpose ranges (RFC 6890: private, loopback, documentation, 6to4, Teredo, ...) in the outputs.\ndefault: remove them from every output"
+    )]
+    pub keep_bogons: bool,
+
+    #[arg(
+        long = "bogon-file",
+        global = true,
+        required = false,
+        num_args = 1..,
+        value_name = "FILE",
+        help = "Additional bogon lists (one CIDR per line, e.g. Team Cymru fullbogons-ipv4.txt / fullbogons-ipv6.txt) removed from every output."
+    )]
+    pub bogon_files: Vec<PathBuf>,
+
+    #[arg(
+        long = "drop-longer-than-v4",
+        global = true,
+        required = false,
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(0..=32),
+        help = "Drop IPv4 prefixes longer than /N from every output (after aggregation)."
+    )]
+    pub drop_longer_than_v4: Option<u8>,
+
+    #[arg(
+        long = "drop-longer-than-v6",
+        global = true,
+        required = false,
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(0..=128),
+        help = "Drop IPv6 prefixes longer than /N from every output (after aggregation)."
+    )]
+    pub drop_longer_than_v6: Option<u8>,
+
+    #[arg(
+        long = "widen-to-v4",
+        global = true,
+        required = false,
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(0..=32),
+        help = "Widen IPv4 prefixes longer than /N to their /N (adds addresses not in the source data)."
+    )]
+    pub widen_to_v4: Option<u8>,
+
+    #[arg(
+        long = "widen-to-v6",
+        global = true,
+        required = false,
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(0..=128),
+        help = "Widen IPv6 prefixes longer than /N to their /N (adds addresses not in the source data)."
+    )]
+    pub widen_to_v6: Option<u8>,
+
+    #[arg(
+        long = "max-entries",
+        global = true,
+        required = false,
+        value_name = "K",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Compress each list to at most K entries by merging neighbouring prefixes with the least added address space.\nThe added address space is printed and recorded in the run report."
+    )]
+    pub max_entries: Option<usize>,
+
+    #[arg(
+        long = "no-change-exit-code",
+        global = true,
+        required = false,
+        default_value_t = false,
+        help = "Exit with code 7 when outputs were written but none of them changed."
+    )]
+    pub no_change_exit_code: bool,
+
     #[arg(
         long = "debug",
         short = 'd',
-        help = "Enable verbose debug output to stderr.",
+        global = true,
+        help = "Enable verbose debug output to stderr (same as --log-level debug).",
         required = false,
         default_value_t = false
     )]
     pub debug: bool,
+
+    #[arg(
+        long = "log-level",
+        global = true,
+        default_value = "warn",
+        required = false,
+        value_parser = ["error", "warn", "info", "debug", "trace"],
+        help = "Minimum level of log messages written to stderr. RUST_LOG overrides this."
+    )]
+    pub log_level: String,
+
+    #[arg(
+        long = "log-format",
+        global = true,
+        default_value = "text",
+        required = false,
+        value_parser = ["text", "json"],
+        help = "Log output format: 'text' or 'json' (one JSON object per line)."
+    )]
+    pub log_format: String,
+}
+
+/// サブコマンド
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Write IPv4/IPv6 lists for country codes from the RIR delegated files.
+    Country(CountryArgs),
+    /// Write announced prefixes for AS numbers (RIPEstat, ARIN RDAP fallback).
+    Asn(AsnArgs),
+    /// Write the prefixes shared by the given countries and AS numbers.
+    Overlap(OverlapArgs),
+    /// Show the country allocation and origin AS for IP addresses.
+    Lookup(LookupArgs),
+    /// Compare IRR route objects with the prefixes seen in BGP (RIPEstat) per AS.
+    IrrCompare(IrrCompareArgs),
+    /// Download the RIR delegated files into a directory.
+    Fetch(FetchArgs),
+    /// Compare two generated list files (txt or nft) address by address.
+    Diff(DiffArgs),
+    /// Run named profiles from the config file (requires --config).
+    Run(RunArgs),
+    /// Keep running and regenerate config profiles on an interval (requires --config).
+    Watch(WatchArgs),
+    /// Serve lists over HTTP from in-memory data refreshed in the background.
+    Serve(ServeArgs),
+}
+
+/// `country` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct CountryArgs {
+    #[arg(
+        num_args = 0..,
+        value_parser = parse_country_code,
+        help = "Country codes (ISO 3166-1 alpha-2 or alpha-3). Falls back to [defaults] country.\nExample: jp br us"
+    )]
+    pub codes: Vec<String>,
+}
+
+/// `asn` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct AsnArgs {
+    #[arg(
+        num_args = 0..,
+        value_parser = clap::value_parser!(AsnRange),
+        help = "AS numbers (plain, AS-prefixed, asdot or ranges). Falls back to [defaults] as-number.\nExample: 2497 AS13335 64512-64520"
+    )]
+    pub as_numbers: Vec<AsnRange>,
+
+    #[arg(
+        long = "as-set",
+        num_args = 1..,
+        value_name = "AS-SET",
+        value_parser = parse_as_set_name,
+        help = "IRR AS-SETs to expand into member AS numbers (requires --irr-dump or --irr-whois).\nExample: AS-EXAMPLE AS2497:AS-CUSTOMERS"
+    )]
+    pub as_sets: Vec<String>,
+}
+
+/// `overlap` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct OverlapArgs {
+    #[arg(
+        short = 'c',
+        long = "country",
+        num_args = 1..,
+        value_parser = parse_country_code,
+        help = "Country codes. Falls back to [defaults] country.\nExample: jp kr"
+    )]
+    pub country_codes: Vec<String>,
+
+    #[arg(
+        short = 'a',
+        long = "as-number",
+        num_args = 1..,
+        value_parser = clap::value_parser!(AsnRange),
+        help = "AS numbers (plain, AS-prefixed, asdot or ranges). Falls back to [defaults] as-number.\nExample: 2497 AS4713"
+    )]
+    pub as_numbers: Vec<AsnRange>,
+
+    #[arg(
+        long = "as-set",
+        num_args = 1..,
+        value_name = "AS-SET",
+        value_parser = parse_as_set_name,
+        help = "IRR AS-SETs to expand into member AS numbers (requires --irr-dump or --irr-whois).\nExample: AS-EXAMPLE AS2497:AS-CUSTOMERS"
+    )]
+    pub as_sets: Vec<String>,
+
+    #[arg(
+        long = "per-pair",
+        default_value_t = false,
+        help = "Write one overlap_<CC>_<ASN> list per (country, AS number) pair instead of one merged list.\nAS-SETs and customer cones are split into their member AS numbers."
+    )]
+    pub per_pair: bool,
+
+    #[arg(
+        long = "matrix",
+        value_name = "FILE",
+        help = "Also write a CSV matrix of overlapping addresses per country and family (rows) and AS number and country (columns) to this file ('-' for stdout)."
+    )]
+    pub matrix: Option<PathBuf>,
+}
+
+/// `irr-compare` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct IrrCompareArgs {
+    #[arg(
+        num_args = 0..,
+        value_parser = clap::value_parser!(AsnRange),
+        help = "AS numbers (plain, AS-prefixed, asdot or ranges). Falls back to [defaults] as-number.\nExample: 2497 AS13335 64512-64520"
+    )]
+    pub as_numbers: Vec<AsnRange>,
+
+    #[arg(
+        long = "as-set",
+        num_args = 1..,
+        value_name = "AS-SET",
+        value_parser = parse_as_set_name,
+        help = "IRR AS-SETs to expand into member AS numbers.\nExample: AS-EXAMPLE AS2497:AS-CUSTOMERS"
+    )]
+    pub as_sets: Vec<String>,
+}
+
+/// `lookup` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct LookupArgs {
+    #[arg(required = true, num_args = 1.., help = "IP addresses to look up.")]
+    pub addresses: Vec<IpAddr>,
+
+    #[arg(
+        long = "no-asn",
+        default_value_t = false,
+        help = "Skip the origin AS lookup (RIPEstat) and only use the RIR files."
+    )]
+    pub no_asn: bool,
+
+    #[arg(
+        long = "no-country",
+        default_value_t = false,
+        help = "Skip the RIR download and only look up the origin AS."
+    )]
+    pub no_country: bool,
+}
+
+/// `fetch` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct FetchArgs {
+    #[arg(
+        long = "dir",
+        default_value = "rir",
+        help = "Directory to store the delegated files in."
+    )]
+    pub dir: PathBuf,
+}
+
+/// `diff` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct DiffArgs {
+    #[arg(help = "Old list file (txt or nft).")]
+    pub old: PathBuf,
+
+    #[arg(help = "New list file (txt or nft).")]
+    pub new: PathBuf,
+}
+
+/// `run` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct RunArgs {
+    #[arg(
+        required_unless_present = "all",
+        conflicts_with = "all",
+        help = "Profile names to run, in order."
+    )]
+    pub profiles: Vec<String>,
+
+    #[arg(
+        long = "all",
+        required = false,
+        default_value_t = false,
+        help = "Run every profile in the config file."
+    )]
+    pub all: bool,
+}
+
+/// `watch` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[arg(
+        long = "interval",
+        default_value = "6h",
+        value_parser = parse_duration,
+        help = "Time between refreshes (e.g. 900, 30m, 6h, 1d)."
+    )]
+    pub interval: Duration,
+
+    #[arg(
+        long = "jitter",
+        default_value = "5m",
+        value_parser = parse_duration,
+        help = "Random extra delay added to each interval (0 to disable)."
+    )]
+    pub jitter: Duration,
+}
+
+/// `serve` サブコマンドの引数
+#[derive(Args, Debug, Clone)]
+pub struct ServeArgs {
+    #[arg(
+        long = "listen",
+        default_value = "127.0.0.1:8080",
+        help = "Address and port to listen on."
+    )]
+    pub listen: SocketAddr,
+
+    #[arg(
+        long = "refresh",
+        default_value = "6h",
+        value_parser = parse_duration,
+        help = "Time between background refreshes of the RIR files and cached AS prefixes."
+    )]
+    pub refresh: Duration,
+}
+
+/// `900` / `30s` / `15m` / `6h` / `1d` 形式の期間をパースする（単位省略時は秒）
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (num, mult) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 3600),
+        Some((i, 'd')) => (&s[..i], 86_400),
+        _ => (s, 1),
+    };
+    let n: u64 = num
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration '{}' (examples: 900, 30m, 6h, 1d)", s))?;
+    n.checked_mul(mult)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too large", s))
+}
+
+/// AS-SET 名（`AS-FOO` / `AS2497:AS-FOO`）をパースし大文字にする
+pub fn parse_as_set_name(s: &str) -> Result<String, String> {
+    match parse_member(s) {
+        Some(Member::Set(name)) => Ok(name),
+        _ => Err(format!(
+            "'{}' is not an AS-SET name (expected AS-FOO or AS2497:AS-FOO)",
+            s
+        )),
+    }
+}
+
+/// 0 以上のリクエスト/秒をパースする
+pub fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v >= 0.0 => Ok(v),
+        _ => Err(format!(
+            "invalid rate '{}' (requests per second, 0 = unlimited)",
+            s
+        )),
+    }
+}
+
+/// RIPEstat の `sourceapp`（英数字と `.` `_` `-` のみ）
+pub fn parse_sourceapp(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if !s.is_empty()
+        && s.len() <= 64
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "invalid sourceapp '{}' (1-64 characters of A-Z, a-z, 0-9, '.', '_', '-')",
+            s
+        ))
+    }
+}
+
+/// 0〜100 の割合をパースする
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let v: f64 = s
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| format!("invalid percentage '{}'", s))?;
+    if (0.0..=100.0).contains(&v) {
+        Ok(v)
+    } else {
+        Err(format!("percentage '{}' must be between 0 and 100", s))
+    }
+}
+
+impl Command {
+    /// サブコマンド名
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Country(_) => "country",
+            Command::Asn(_) => "asn",
+            Command::Overlap(_) => "overlap",
+            Command::Lookup(_) => "lookup",
+            Command::IrrCompare(_) => "irr-compare",
+            Command::Fetch(_) => "fetch",
+            Command::Diff(_) => "diff",
+            Command::Run(_) => "run",
+            Command::Watch(_) => "watch",
+            Command::Serve(_) => "serve",
+        }
+    }
+}
+
+impl Cli {
+    /// 実行するサブコマンドを決定する
+    /// - サブコマンド指定時は、省略された国コード/AS番号を [defaults] の値で補う
+    /// - 旧形式のフラグ（-c / -a / -o）は対応するサブコマンドへ変換し、非推奨の警告を出す
+    pub fn resolve_command(&self) -> Result<Command, AppError> {
+        match &self.command {
+            Some(Command::Country(a)) => {
+                let codes = non_empty_or(&a.codes, &self.country_codes, "country")?;
+                Ok(Command::Country(CountryArgs { codes }))
+            }
+            Some(Command::Asn(a)) => {
+                // AS-SET だけの指定も可
+                let as_numbers = if a.as_sets.is_empty() {
+                    non_empty_or(&a.as_numbers, &self.as_numbers, "asn")?
+                } else {
+                    a.as_numbers.clone()
+                };
+                Ok(Command::Asn(AsnArgs {
+                    as_numbers,
+                    as_sets: a.as_sets.clone(),
+                }))
+            }
+            Some(Command::Overlap(a)) => {
+                let country_codes =
+                    non_empty_or(&a.country_codes, &self.country_codes, "overlap --country")?;
+                let as_numbers = if a.as_sets.is_empty() {
+                    non_empty_or(&a.as_numbers, &self.as_numbers, "overlap --as-number")?
+                } else {
+                    a.as_numbers.clone()
+                };
+                Ok(Command::Overlap(OverlapArgs {
+                    country_codes,
+                    as_numbers,
+                    as_sets: a.as_sets.clone(),
+                    per_pair: a.per_pair,
+                    matrix: a.matrix.clone(),
+                }))
+            }
+            Some(Command::IrrCompare(a)) => {
+                let as_numbers = if a.as_sets.is_empty() {
+                    non_empty_or(&a.as_numbers, &self.as_numbers, "irr-compare")?
+                } else {
+                    a.as_numbers.clone()
+                };
+                Ok(Command::IrrCompare(IrrCompareArgs {
+                    as_numbers,
+                    as_sets: a.as_sets.clone(),
+                }))
+            }
+            Some(other) => Ok(other.clone()),
+            None => self.resolve_legacy(),
+        }
+    }
+
+    /// 旧形式のフラグからサブコマンドを組み立てる（優先順: -o, -a, -c）
+    fn resolve_legacy(&self) -> Result<Command, AppError> {
+        let cmd = if self.overlap {
+            match (&self.country_codes, &self.as_numbers) {
+                (Some(c), Some(a)) => Command::Overlap(OverlapArgs {
+                    country_codes: c.clone(),
+                    as_numbers: a.clone(),
+                    as_sets: Vec::new(),
+                    per_pair: false,
+                    matrix: None,
+                }),
+                _ => {
+                    return Err(AppError::InvalidInput(
+                        "--overlap requires both --country and --as-number".into(),
+                    ));
+                }
+            }
+        } else if let Some(a) = &self.as_numbers {
+            Command::Asn(AsnArgs {
+                as_numbers: a.clone(),
+                as_sets: Vec::new(),
+            })
+        } else if let Some(c) = &self.country_codes {
+            Command::Country(CountryArgs { codes: c.clone() })
+        } else {
+            // 一覧は clap の定義から作り、サブコマンドの追加に追従させる
+            let names: Vec<String> = <Cli as clap::CommandFactory>::command()
+                .get_subcommands()
+                .map(|c| c.get_name().to_string())
+                .collect();
+            return Err(AppError::InvalidInput(format!(
+                "No command given. Use one of: {} (see --help)",
+                names.join(", ")
+            )));
+        };
+
+        tracing::warn!(
+            "Running without a subcommand (top-level -c/-a/-o) is deprecated; use `fire-scope {}` instead.",
+            match &cmd {
+                Command::Overlap(_) => "overlap -c <CC>... -a <ASN>...",
+                Command::Asn(_) => "asn <ASN>...",
+                _ => "country <CC>...",
+            }
+        );
+        Ok(cmd)
+    }
+
+    /// サブコマンドと旧形式の -c / -a / -o の併用を拒否する
+    /// （共通オプションはサブコマンドの前にも書けるため、clap の args_conflicts_with_subcommands は使わない）
+    pub fn reject_legacy_with_subcommand(matches: &ArgMatches) -> Result<(), AppError> {
+        let Some((name, _)) = matches.subcommand() else {
+            return Ok(());
+        };
+        for (id, flag) in [
+            ("country_codes", "-c"),
+            ("as_numbers", "-a"),
+            ("overlap", "-o"),
+        ] {
+            if matches!(matches.value_source(id), Some(ValueSource::CommandLine)) {
+                return Err(AppError::InvalidInput(format!(
+                    "{} cannot be combined with the '{}' subcommand",
+                    flag, name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// 実際に使うログレベル（`-d` は debug 相当）
+    pub fn log_level(&self) -> &str {
+        if self.debug && matches!(self.log_level.as_str(), "error" | "warn" | "info") {
+            "debug"
+        } else {
+            &self.log_level
+        }
+    }
+
+    /// RIRファイル取得の設定
+    pub fn download_options(&self) -> DownloadOptions {
+        let mut opts = DownloadOptions::new(
+            self.max_retries,
+            self.max_backoff_sec,
+            self.continue_on_partial,
+        );
+        if let Some(urls) = &self.rir_urls {
+            opts.rir_urls = urls.clone();
+        }
+        opts
+    }
+
+    /// JSON API の方針（リトライ、流量制限、sourceapp、広報期間）
+    pub fn api_policy(&self) -> ApiPolicy {
+        ApiPolicy {
+            retry_attempts: self.max_retries,
+            max_backoff_secs: self.max_backoff_sec,
+            limiter: RateLimiter::new(self.api_rate_limit, self.api_rate_limit.max(1.0)),
+            sourceapp: self.ripestat_sourceapp.clone(),
+            announced_window: self.announced_window,
+            min_announced: self.min_announced,
+        }
+    }
+
+    /// 書き出す前のプレフィックス長の調整と最大エントリ数
+    pub fn shape_options(&self) -> ShapeOptions {
+        ShapeOptions {
+            drop_longer_than_v4: self.drop_longer_than_v4,
+            drop_longer_than_v6: self.drop_longer_than_v6,
+            widen_to_v4: self.widen_to_v4,
+            widen_to_v6: self.widen_to_v6,
+            max_entries: self.max_entries,
+        }
+    }
+
+    /// AS問い合わせの設定
+    pub fn as_lookup_options(&self) -> AsLookupOptions {
+        let mut opts = AsLookupOptions::new(self.concurrency, self.continue_on_as_failure);
+        // 流量制限は IRR の whois とも共有する
+        opts.api = Arc::new(self.api_policy());
+        // 値は clap / 設定ファイルの読み込みで検証済み
+        opts.source = self.as_source.parse::<AsSource>().unwrap_or_default();
+        opts.irr = IrrOptions {
+            dumps: self.irr_dumps.clone(),
+            whois: self.irr_whois.clone(),
+            max_depth: self.as_set_max_depth,
+            api: opts.api.clone(),
+        };
+        opts.mrt = MrtOptions {
+            files: self.mrt_files.clone(),
+            min_peers: self.mrt_min_peers,
+        };
+        opts.cone = ConeOptions {
+            enabled: self.customer_cone,
+            files: self.as_rel_files.clone(),
+            max_depth: self.cone_max_depth,
+            max_size: self.cone_max_size,
+        };
+        opts.names = AsNameOptions {
+            enabled: self.as_names,
+            file: self.as_names_file.clone(),
+        };
+        opts
+    }
+}
+
+fn non_empty_or<T: Clone>(
+    given: &[T],
+    fallback: &Option<Vec<T>>,
+    what: &str,
+) -> Result<Vec<T>, AppError> {
+    if !given.is_empty() {
+        return Ok(given.to_vec());
+    }
+    match fallback {
+        Some(v) if !v.is_empty() => Ok(v.clone()),
+        _ => Err(AppError::InvalidInput(format!(
+            "{} requires at least one value (on the command line or in [defaults])",
+            what
+        ))),
+    }
 }
@@ -1,16 +1,23 @@
-use crate::asn::process_as_numbers;
-use crate::common::OutputFormat;
+use crate::asn::{AsLookupOptions, process_as_numbers};
+use crate::common::{Asn, AsnRange, expand_asns, warn_reserved_asns};
+use crate::cone::add_customer_cones;
 use crate::error::AppError;
+use crate::irr::resolve_as_numbers;
+use crate::output::OutputOptions;
 use reqwest::Client;
 
-/// ユーザー指定ASリストを受け取りRDAPで処理
+/// ユーザー指定ASリスト（AS-SET は展開する）を受け取りRDAPで処理
 pub async fn run_as_numbers(
     client: &Client,
-    as_numbers: &[u32],
-    output_format: OutputFormat,
-    concurrency: usize,
+    as_numbers: &[AsnRange],
+    as_sets: &[String],
+    output: &OutputOptions,
+    lookup: &AsLookupOptions,
 ) -> Result<(), AppError> {
+    let as_numbers = resolve_as_numbers(&expand_asns(as_numbers), as_sets, &lookup.irr).await?;
+    let as_numbers = add_customer_cones(&as_numbers, &lookup.cone).await?;
+    warn_reserved_asns(&as_numbers);
     // RDAPは純粋な数値のみを期待
-    let as_strings: Vec<String> = as_numbers.iter().map(|n| n.to_string()).collect();
-    process_as_numbers(client, &as_strings, output_format, concurrency).await
+    let as_strings: Vec<String> = as_numbers.iter().map(Asn::to_string).collect();
+    process_as_numbers(client, &as_strings, output, lookup).await
 }
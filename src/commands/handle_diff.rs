@@ -0,0 +1,25 @@
+use crate::diff::{diff_lists, read_list_file};
+use crate::error::AppError;
+use std::path::Path;
+
+/// 2つのリストファイルを比較し、追加 (+) / 削除 (-) されたCIDRを標準出力へ書き出す
+pub async fn run_diff(old: &Path, new: &Path) -> Result<(), AppError> {
+    let old_set = read_list_file(old).await?;
+    let new_set = read_list_file(new).await?;
+    let diff = diff_lists(&old_set, &new_set);
+
+    for net in &diff.removed {
+        println!("- {}", net);
+    }
+    for net in &diff.added {
+        println!("+ {}", net);
+    }
+    println!(
+        "# {} added, {} removed ({} -> {} entries)",
+        diff.added.len(),
+        diff.removed.len(),
+        old_set.len(),
+        new_set.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use crate::common_download::DownloadOptions;
+use crate::error::AppError;
+use crate::fetch::fetch_with_retry;
+use crate::output_common::atomic_write;
+use crate::report::journal;
+use futures::future::join_all;
+use reqwest::Client;
+use std::path::Path;
+
+/// RIRファイルをダウンロードし、URL末尾のファイル名で `dir` に保存する
+/// 1件でも失敗した場合はエラー（成功分は保存済み）
+pub async fn run_fetch(
+    client: &Client,
+    dir: &Path,
+    download: &DownloadOptions,
+) -> Result<(), AppError> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let results = join_all(download.rir_urls.iter().map(|url| async move {
+        let res =
+            fetch_with_retry(client, url, download.max_retries, download.max_backoff_sec).await;
+        (url.as_str(), res)
+    }))
+    .await;
+
+    let mut failed = Vec::new();
+    for (url, res) in results {
+        match res {
+            Ok(text) => {
+                let file_name = url.rsplit('/').next().unwrap_or("delegated");
+                let path = dir.join(file_name);
+                atomic_write(&path, text.as_bytes()).await?;
+                println!("{}", path.display());
+            }
+            Err(e) => {
+                tracing::warn!(url = %url, error = %e, "RIR download failed");
+                journal().record_failure("rir", url, &e.to_string());
+                failed.push(url);
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::PartialData(format!(
+            "Some RIR downloads failed: {}",
+            failed.join(", ")
+        )))
+    }
+}
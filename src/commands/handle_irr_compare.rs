@@ -0,0 +1,86 @@
+use crate::asn::{AsLookupOptions, AsOutcome, AsReport, fetch_ripe_stat_prefixes};
+use crate::common::{AsnRange, expand_asns, warn_reserved_asns};
+use crate::cone::add_customer_cones;
+use crate::error::AppError;
+use crate::irr::{IrrRoutes, compare_routes, resolve_as_numbers};
+use crate::report::journal;
+use ipnet::IpNet;
+use reqwest::Client;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// AS ごとに IRR の route / route6 と BGP（RIPEstat）の広報プレフィックスを比較し、
+/// 片方にしか無いプレフィックスを標準出力へ書き出す
+pub async fn run_irr_compare(
+    client: &Client,
+    as_numbers: &[AsnRange],
+    as_sets: &[String],
+    lookup: &AsLookupOptions,
+) -> Result<(), AppError> {
+    let as_numbers = resolve_as_numbers(&expand_asns(as_numbers), as_sets, &lookup.irr).await?;
+    let as_numbers = add_customer_cones(&as_numbers, &lookup.cone).await?;
+    warn_reserved_asns(&as_numbers);
+    let origins: Vec<u32> = as_numbers.iter().map(|a| a.0).collect();
+    let irr = Arc::new(IrrRoutes::load(&lookup.irr, &origins).await?);
+    let semaphore = Arc::new(Semaphore::new(lookup.concurrency.max(1)));
+
+    let handles = origins
+        .iter()
+        .map(|&asn| {
+            let client_c = client.clone();
+            let irr_c = irr.clone();
+            let sem_c = semaphore.clone();
+            let api_c = lookup.api.clone();
+            tokio::spawn(async move {
+                let _permit = sem_c.acquire_owned().await?;
+                let res = async {
+                    let (v4, v6) = irr_c.prefixes(asn).await?;
+                    let bgp = fetch_ripe_stat_prefixes(&client_c, &asn.to_string(), &api_c).await?;
+                    let irr_set: BTreeSet<IpNet> = v4.into_iter().chain(v6).collect();
+                    let bgp_set: BTreeSet<IpNet> = bgp.iter().map(|n| n.trunc()).collect();
+                    Ok::<_, AppError>(compare_routes(&irr_set, &bgp_set))
+                }
+                .await;
+                Ok::<_, AppError>((asn, res))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // 入力順に表示
+    let mut report = AsReport::default();
+    let (mut irr_only, mut bgp_only) = (0, 0);
+    for h in handles {
+        let (asn, res) = h.await??;
+        match res {
+            Ok(cmp) => {
+                print!("{}", cmp.report(asn));
+                irr_only += cmp.irr_only.len();
+                bgp_only += cmp.bgp_only.len();
+                let outcome = if cmp.irr == 0 && cmp.bgp == 0 {
+                    AsOutcome::Empty
+                } else {
+                    AsOutcome::Success {
+                        v4: cmp.irr,
+                        v6: cmp.bgp,
+                    }
+                };
+                report.push(&asn.to_string(), outcome);
+            }
+            Err(e) => {
+                warn!(asn = %asn, error = %e, "IRR/BGP comparison failed");
+                journal().record_failure("asn", &asn.to_string(), &e.to_string());
+                println!("AS{}: FAILED: {}", asn, e);
+                report.push(&asn.to_string(), AsOutcome::Failed(e.to_string()));
+            }
+        }
+    }
+    println!(
+        "# {} AS compared: {} IRR-only, {} BGP-only",
+        report.outcomes.len(),
+        irr_only,
+        bgp_only
+    );
+    report.check(lookup.continue_on_failure)
+}
@@ -0,0 +1,72 @@
+use crate::asn::fetch_network_info;
+use crate::common_download::{DownloadOptions, download_country_map};
+use crate::error::AppError;
+use crate::fetch::ApiPolicy;
+use crate::parse::{CountryFilter, CountryMap};
+use ipnet::IpNet;
+use reqwest::Client;
+use std::net::IpAddr;
+
+/// IPアドレスごとに、RIRファイル上の国コードとRIPEstat上の起源ASを表示する
+pub async fn run_lookup(
+    client: &Client,
+    addresses: &[IpAddr],
+    download: &DownloadOptions,
+    api: &ApiPolicy,
+    with_country: bool,
+    with_asn: bool,
+) -> Result<(), AppError> {
+    let country_map = if with_country {
+        // どの国の割り当てに含まれるか分からないため全体のマップが必要
+        Some(download_country_map(client, download, &CountryFilter::All).await?)
+    } else {
+        None
+    };
+
+    for addr in addresses {
+        let mut fields = vec![addr.to_string()];
+
+        if let Some(map) = &country_map {
+            fields.push(match lookup_country(map, *addr) {
+                Some((cc, net)) => format!("country={} ({})", cc, net),
+                None => "country=-".to_string(),
+            });
+        }
+
+        if with_asn {
+            fields.push(match fetch_network_info(client, *addr, api).await {
+                Ok((prefix, asns)) if !asns.is_empty() => format!(
+                    "asn={} ({})",
+                    asns.iter()
+                        .map(|a| format!("AS{}", a))
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    prefix.map(|p| p.to_string()).unwrap_or_else(|| "-".into())
+                ),
+                Ok(_) => "asn=-".to_string(),
+                Err(e) => format!("asn=error ({})", e),
+            });
+        }
+
+        println!("{}", fields.join("\t"));
+    }
+    Ok(())
+}
+
+/// 国コードマップからアドレスを含む割り当てを探す
+/// 返す CIDR は国ごとの区間を最小 CIDR に分けたうちアドレスを含むもの
+pub fn lookup_country(map: &CountryMap, addr: IpAddr) -> Option<(String, IpNet)> {
+    let mut best: Option<(String, IpNet)> = None;
+    for (cc, set) in map {
+        if let Some(net) = set.net_containing(addr) {
+            // より長いプレフィックスを優先（通常RIR割り当ては重ならない）
+            let better = best
+                .as_ref()
+                .is_none_or(|(_, b)| net.prefix_len() > b.prefix_len());
+            if better {
+                best = Some((cc.clone(), net));
+            }
+        }
+    }
+    best
+}
@@ -1,139 +1,331 @@
-use crate::cli::Cli;
-use crate::common::OutputFormat;
-use crate::common_download::download_all_rir_files;
+use crate::asn::{AsLookupOptions, AsMap, fetch_prefixes_for_many};
+use crate::asname::{AsAnnotations, load_annotations};
+use crate::cli::OverlapArgs;
+use crate::common::{Asn, IpFamily, expand_asns, warn_reserved_asns};
+use crate::common_download::{DownloadOptions, download_country_map};
+use crate::cone::add_customer_cones;
 use crate::error::AppError;
-use crate::output::write_overlap_to_file;
-use crate::overlap::find_overlaps;
-use crate::parse::parse_all_country_codes;
-use ipnet::IpNet;
+use crate::interval::IpRangeSet;
+use crate::irr::resolve_as_numbers;
+use crate::output::{OutputOptions, write_overlap_to_file};
+use crate::output_common::atomic_write;
+use crate::parse::{CountryFilter, CountryMap};
 use reqwest::Client;
-use std::collections::BTreeSet;
-use crate::common::debug_log;
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Semaphore;
+use tokio::io::AsyncWriteExt;
 
 /// overlapモードのメイン処理
+/// AS-SET は展開してから問い合わせ、出力名には AS-SET 名を使う
+/// `--per-pair` では (国コード, AS番号) の組ごとに書き出し、`--matrix` では組ごと（国同士を含む）のアドレス数の表も書き出す
+/// `--attribute-overlaps` では要素ごとに起源AS と国コードを付ける
 pub async fn run_overlap(
-    args: &Cli,
+    args: &OverlapArgs,
     client: &Client,
-    output_format: OutputFormat,
+    output: &OutputOptions,
+    download: &DownloadOptions,
+    lookup: &AsLookupOptions,
 ) -> Result<(), AppError> {
-    let (country_codes, as_numbers) = validate_args(args)?;
-    let (rir_texts_ok, failed_urls) =
-        download_all_rir_files(client, args.max_retries, args.max_backoff_sec).await?;
-    if !failed_urls.is_empty() {
-        debug_log(format!(
-            "The following RIR URLs failed to download: {:?}",
-            failed_urls
-        ));
-        if !args.continue_on_partial {
-            return Err(AppError::Other(
-                "Some RIR downloads failed (use --continue-on-partial to proceed)".into(),
-            ));
+    let OverlapArgs {
+        country_codes,
+        as_numbers,
+        as_sets,
+        per_pair,
+        matrix,
+    } = args;
+    let as_label = as_numbers
+        .iter()
+        .map(|n| n.to_string())
+        .chain(as_sets.iter().cloned())
+        .collect::<Vec<_>>()
+        .join("_");
+    let as_numbers = resolve_as_numbers(&expand_asns(as_numbers), as_sets, &lookup.irr).await?;
+    let as_numbers = add_customer_cones(&as_numbers, &lookup.cone).await?;
+    validate_args(country_codes, &as_numbers)?;
+    warn_reserved_asns(&as_numbers);
+    let country_map =
+        download_country_map(client, download, &CountryFilter::only(country_codes)).await?;
+    let as_strings: Vec<String> = as_numbers.iter().map(Asn::to_string).collect();
+    let as_map = collect_as_ips_no_rpki(client, &as_strings, lookup).await?;
+    let names = load_annotations(client, &as_map, lookup).await?;
+
+    if *per_pair || matrix.is_some() {
+        let countries: Vec<String> = country_codes.iter().map(|c| c.to_uppercase()).collect();
+        let pairs = pair_overlaps(&countries, &country_map, &as_strings, &as_map);
+        if *per_pair {
+            for pair in &pairs {
+                let pair_output = overlap_output(output, names.as_ref(), &pair.overlap);
+                write_overlap_to_file(&pair.country, &pair.asn, &pair.overlap.nets, &pair_output)
+                    .await?;
+            }
+        }
+        if let Some(path) = matrix {
+            let csv = render_overlap_matrix(&countries, &country_map, &as_strings, &pairs);
+            write_matrix(path, &csv).await?;
+        }
+        if *per_pair {
+            return Ok(());
         }
     }
-    if rir_texts_ok.is_empty() {
-        return Err(AppError::Other(
-            "No RIR files available to process".into(),
-        ));
-    }
-    let (country_ips_v4, country_ips_v6) =
-        collect_country_ips(&country_codes, &rir_texts_ok).await?;
-    let as_strings: Vec<String> = as_numbers.iter().map(|n| n.to_string()).collect();
-    let (as_ips_v4, as_ips_v6) =
-        collect_as_ips_no_rpki(client, &as_strings, args.concurrency).await?;
-    let overlap_nets = calculate_overlaps((country_ips_v4, country_ips_v6), (as_ips_v4, as_ips_v6));
+
+    let overlap = calculate_overlaps(
+        &country_sets(country_codes, &country_map),
+        &as_prefix_sets(&as_strings, &as_map),
+    );
     write_overlap_to_file(
         &country_codes.join("_").to_uppercase(),
-        &as_strings.join("_"),
-        &overlap_nets,
-        output_format,
+        &as_label,
+        &overlap.nets,
+        &overlap_output(output, names.as_ref(), &overlap),
     )
     .await?;
     Ok(())
 }
 
-/// 引数の検証
-/// --overlapオプションが指定されている場合、--countryと--as-numberの両方が必要
-fn validate_args(args: &Cli) -> Result<(Vec<String>, Vec<u32>), AppError> {
-    let country_codes = args.country_codes.clone().ok_or_else(|| {
-        AppError::InvalidInput("Error: --overlap requires --country <codes>".into())
-    })?;
-    let as_numbers = args.as_numbers.clone().ok_or_else(|| {
-        AppError::InvalidInput("Error: --overlap requires --as-number <numbers>".into())
-    })?;
-    Ok((country_codes, as_numbers))
+/// 重複と、その起源AS・国コードごとの内訳
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overlap {
+    pub nets: IpRangeSet,
+    /// AS番号 → その AS のプレフィックスと重なる部分
+    pub origins: BTreeMap<String, IpRangeSet>,
+    /// 国コード → その国の割り当てと重なる部分
+    pub countries: BTreeMap<String, IpRangeSet>,
+}
+
+/// 1組（国コード, AS番号）の重複
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairOverlap {
+    pub country: String,
+    pub asn: String,
+    pub overlap: Overlap,
+}
+
+/// (国コード, AS番号) の組ごとに重複を求める（国コード、AS番号の順）
+/// プレフィックスを取得できなかった AS は空の重複になる
+pub fn pair_overlaps(
+    country_codes: &[String],
+    country_map: &CountryMap,
+    as_numbers: &[String],
+    as_map: &AsMap,
+) -> Vec<PairOverlap> {
+    // 国・AS ごとの集合は一度だけ作り、組ごとには共通部分だけを求める
+    let countries = country_sets(country_codes, country_map);
+    let as_sets = as_prefix_sets(as_numbers, as_map);
+    let mut pairs = Vec::with_capacity(countries.len() * as_sets.len());
+    for country in &countries {
+        for as_set in &as_sets {
+            pairs.push(PairOverlap {
+                country: country.0.clone(),
+                asn: as_set.0.clone(),
+                overlap: calculate_overlaps(
+                    std::slice::from_ref(country),
+                    std::slice::from_ref(as_set),
+                ),
+            });
+        }
+    }
+    pairs
 }
 
-/// 国コードリストを1つずつparse_and_collect_ips()で取得
-/// 国コードは大文字に変換してから渡す
-async fn collect_country_ips(
+/// 組ごとの重複アドレス数の表（CSV）
+/// 行は国コードとファミリ、列は AS番号と国コード（国同士の重複。対角はその国の総数）
+pub fn render_overlap_matrix(
     country_codes: &[String],
-    rir_texts: &[String],
-) -> Result<(BTreeSet<IpNet>, BTreeSet<IpNet>), AppError> {
-    // 一度だけ全RIRテキストをパースし、国コード→(IPv4, IPv6)のマップを作成（CPU重）
-    let rir_texts_owned = rir_texts.to_owned();
-    let country_map = tokio::task::spawn_blocking(move || {
-        parse_all_country_codes(&rir_texts_owned)
-    })
-    .await??;
-
-    let mut c_v4 = BTreeSet::new();
-    let mut c_v6 = BTreeSet::new();
+    country_map: &CountryMap,
+    as_numbers: &[String],
+    pairs: &[PairOverlap],
+) -> String {
+    let countries: Vec<String> = country_codes.iter().map(|c| c.to_uppercase()).collect();
+    let country_sets: Vec<IpRangeSet> = countries
+        .iter()
+        .map(|c| country_map.get(c).cloned().unwrap_or_default())
+        .collect();
+    let mut csv = String::from("country,family");
+    for asn in as_numbers {
+        csv.push_str(&format!(",AS{}", asn));
+    }
+    for country in &countries {
+        csv.push_str(&format!(",{}", country));
+    }
+    csv.push('\n');
+    for (country, country_ips) in countries.iter().zip(&country_sets) {
+        for family in [IpFamily::V4, IpFamily::V6] {
+            csv.push_str(&format!("{},{}", country, family.as_str()));
+            for asn in as_numbers {
+                let count = pairs
+                    .iter()
+                    .find(|p| &p.country == country && &p.asn == asn)
+                    .map(|p| p.overlap.nets.only(family).address_count())
+                    .unwrap_or(0);
+                csv.push_str(&format!(",{}", count));
+            }
+            for other in &country_sets {
+                let count = match family {
+                    IpFamily::V4 => country_ips.v4.intersection(&other.v4).address_count(),
+                    IpFamily::V6 => country_ips.v6.intersection(&other.v6).address_count(),
+                };
+                csv.push_str(&format!(",{}", count));
+            }
+            csv.push('\n');
+        }
+    }
+    csv
+}
+
+/// 表をファイル（`-` なら標準出力）へ書き出す
+async fn write_matrix(path: &Path, csv: &str) -> Result<(), AppError> {
+    if path == Path::new("-") {
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(csv.as_bytes()).await?;
+        stdout.flush().await?;
+        Ok(())
+    } else {
+        atomic_write(path, csv.as_bytes()).await
+    }
+}
 
+/// 引数の検証
+/// overlapモードでは国コードとAS番号の両方が必要
+fn validate_args(country_codes: &[String], as_numbers: &[Asn]) -> Result<(), AppError> {
+    if country_codes.is_empty() {
+        return Err(AppError::InvalidInput(
+            "Error: overlap requires --country <codes>".into(),
+        ));
+    }
+    if as_numbers.is_empty() {
+        return Err(AppError::InvalidInput(
+            "Error: overlap requires --as-number <numbers>".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// パース済みの国コードマップから指定国のIPを合算する
+pub(crate) fn country_ips_from_map(
+    country_codes: &[String],
+    country_map: &CountryMap,
+) -> IpRangeSet {
+    let mut merged = IpRangeSet::new();
     for code in country_codes {
         let upper = code.to_uppercase();
-        if let Some((v4_vec, v6_vec)) = country_map.get(&upper) {
-            c_v4.extend(v4_vec.iter().copied());
-            c_v6.extend(v6_vec.iter().copied());
+        if let Some(set) = country_map.get(&upper) {
+            merged = merged.union(set);
         } else {
-            debug_log(format!("No IPs found for country code: {}", upper));
+            tracing::warn!(
+                country = %upper,
+                "No IPs found for country code (no allocated/assigned prefixes in the RIR data)"
+            );
         }
     }
-
-    Ok((c_v4, c_v6))
+    merged
 }
 
-/// AS番号リストを1つずつget_ips_for_as_once_no_rpki()で取得
+/// AS番号リストを並列で取得する
+/// 集計をログに出し、失敗の扱い（厳格/続行）を適用する
 async fn collect_as_ips_no_rpki(
     client: &Client,
     as_strings: &[String],
-    concurrency: usize,
-) -> Result<(BTreeSet<IpNet>, BTreeSet<IpNet>), AppError> {
-    let max_concurrent = if concurrency == 0 { 1 } else { concurrency };
-    let semaphore = Arc::new(Semaphore::new(max_concurrent));
-
-    let mut handles = Vec::with_capacity(as_strings.len());
-    for asn in as_strings.iter().cloned() {
-        let client_c = client.clone();
-        let sem_c = semaphore.clone();
-        handles.push(tokio::spawn(async move {
-            let _permit = sem_c.acquire_owned().await?;
-            crate::asn::get_prefixes_via_rdap(&client_c, &asn).await
-        }));
-    }
+    lookup: &AsLookupOptions,
+) -> Result<AsMap, AppError> {
+    let (map, report) = fetch_prefixes_for_many(client, as_strings, lookup).await?;
+    report.log();
+    report.check(lookup.continue_on_failure)?;
+    Ok(map)
+}
 
-    let mut a_v4 = BTreeSet::new();
-    let mut a_v6 = BTreeSet::new();
+/// 国コードごとの割り当て（大文字の国コードと集合）
+pub fn country_sets(
+    country_codes: &[String],
+    country_map: &CountryMap,
+) -> Vec<(String, IpRangeSet)> {
+    country_codes
+        .iter()
+        .map(|code| {
+            let upper = code.to_uppercase();
+            let set = country_ips_from_map(std::slice::from_ref(&upper), country_map);
+            (upper, set)
+        })
+        .collect()
+}
+
+/// AS ごとのプレフィックス（取得できなかった AS は空）
+pub fn as_prefix_sets(as_numbers: &[String], as_map: &AsMap) -> Vec<(String, IpRangeSet)> {
+    as_numbers
+        .iter()
+        .map(|asn| {
+            let set = as_map
+                .get(asn)
+                .map(|(v4, v6)| IpRangeSet::from_nets(v4.iter().chain(v6)))
+                .unwrap_or_default();
+            (asn.clone(), set)
+        })
+        .collect()
+}
 
-    for h in handles {
-        // JoinError は AppError に伝播
-        let res = h.await??;
-        let (v4set, v6set) = res;
-        a_v4.extend(v4set);
-        a_v6.extend(v6set);
+/// 重複の出力設定
+/// `--attribute-overlaps` なら要素ごとの起源AS と国コードを、そうでなければ AS名（`--as-names`）だけを付ける
+/// `names` は `--as-names` の注記
+pub(crate) fn overlap_output(
+    output: &OutputOptions,
+    names: Option<&AsAnnotations>,
+    overlap: &Overlap,
+) -> OutputOptions {
+    let annotations = if output.attribute_overlaps {
+        Some(overlap_annotations(names, overlap))
+    } else {
+        let asns: Vec<u32> = overlap
+            .origins
+            .keys()
+            .filter_map(|a| a.parse().ok())
+            .collect();
+        names.map(|n| n.restricted_to(&asns))
+    };
+    OutputOptions {
+        annotations: annotations.map(Arc::new),
+        ..output.clone()
     }
+}
 
-    Ok((a_v4, a_v6))
+/// 重複の内訳から、要素ごとの起源AS と国コードの注記を作る
+/// `names`（--as-names の注記）があればその AS名を使う
+pub fn overlap_annotations(names: Option<&AsAnnotations>, overlap: &Overlap) -> AsAnnotations {
+    let mut annotations = names
+        .map(|n| AsAnnotations::new(n.names().clone()))
+        .unwrap_or_default();
+    for (asn, set) in &overlap.origins {
+        if let Ok(asn) = asn.parse::<u32>() {
+            annotations.add_prefixes(asn, set.to_set().iter());
+        }
+    }
+    for (code, set) in &overlap.countries {
+        annotations.add_country_prefixes(code, set.to_set().iter());
+    }
+    annotations
 }
 
-/// 国コードとAS番号のIPリストを受け取り、重複部分を計算
-/// IPv4とIPv6の重複部分をそれぞれ計算し、結果を結合して返す
-fn calculate_overlaps(
-    (country_v4, country_v6): (BTreeSet<IpNet>, BTreeSet<IpNet>),
-    (as_v4, as_v6): (BTreeSet<IpNet>, BTreeSet<IpNet>),
-) -> BTreeSet<IpNet> {
-    let overlaps_v4 = find_overlaps(&country_v4, &as_v4);
-    let overlaps_v6 = find_overlaps(&country_v6, &as_v6);
-    overlaps_v4.into_iter().chain(overlaps_v6).collect()
+/// 国ごと・AS ごとの集合を受け取り、重複部分を起源AS と国コードの内訳付きで計算する
+/// 内訳には重複の無い AS・国を含めない
+pub fn calculate_overlaps(
+    countries: &[(String, IpRangeSet)],
+    as_sets: &[(String, IpRangeSet)],
+) -> Overlap {
+    let country_ips = countries
+        .iter()
+        .fold(IpRangeSet::new(), |acc, (_, set)| acc.union(set));
+    let mut overlap = Overlap::default();
+    for (asn, as_ips) in as_sets {
+        let shared = country_ips.intersection(as_ips);
+        if !shared.is_empty() {
+            overlap.nets = overlap.nets.union(&shared);
+            overlap.origins.insert(asn.clone(), shared);
+        }
+    }
+    for (code, set) in countries {
+        let shared = set.intersection(&overlap.nets);
+        if !shared.is_empty() {
+            overlap.countries.insert(code.clone(), shared);
+        }
+    }
+    overlap
 }
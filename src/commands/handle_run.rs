@@ -0,0 +1,235 @@
+use crate::asn::{AsLookupOptions, AsMap, fetch_prefixes_for_many, write_ip_list};
+use crate::asname::load_annotations;
+use crate::cli::{Cli, RunArgs};
+use crate::commands::handle_overlap::{
+    as_prefix_sets, calculate_overlaps, country_ips_from_map, country_sets, overlap_output,
+};
+use crate::common::{Asn, IpFamily, warn_reserved_asns};
+use crate::common_download::download_country_map;
+use crate::cone::{ConeOptions, expand_customer_cones};
+use crate::config::{Config, Job};
+use crate::error::AppError;
+use crate::hooks::run_hooks;
+use crate::interval::IpRangeSet;
+use crate::irr::{IrrOptions, expand_as_sets};
+use crate::output::{
+    OutputOptions, StagedWrites, WrittenFile, write_named_set_to_file, write_overlap_to_file,
+};
+use crate::parse::{CountryFilter, CountryMap};
+use crate::process::process_country_code_from_map;
+use reqwest::Client;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// 設定ファイルのプロファイルを実行する
+/// RIRファイルのダウンロード/パースとASの問い合わせは全ジョブで1回だけ行う
+/// 全ジョブで書き出したファイルを返す
+pub async fn run_profiles(
+    args: &Cli,
+    run_args: &RunArgs,
+    config: Option<&Config>,
+    client: &Client,
+    base_output: &OutputOptions,
+) -> Result<Vec<WrittenFile>, AppError> {
+    let config = config
+        .ok_or_else(|| AppError::InvalidInput("The run command requires --config <FILE>".into()))?;
+    let jobs = config.resolve_jobs(&run_args.profiles, run_args.all, base_output)?;
+
+    // 国コードを使うジョブがある場合のみRIRファイルを取得
+    let country_map = if jobs.iter().any(|j| !j.countries.is_empty()) {
+        // 選択したジョブの国だけを残す
+        let countries: Vec<&String> = jobs.iter().flat_map(|j| &j.countries).collect();
+        download_country_map(
+            client,
+            &args.download_options(),
+            &CountryFilter::only(&countries),
+        )
+        .await?
+    } else {
+        CountryMap::new()
+    };
+
+    let written = run_jobs(&jobs, &country_map, client, &args.as_lookup_options()).await?;
+    run_hooks(&args.post_hooks, &written, None).await?;
+    Ok(written)
+}
+
+/// パース済みの国コードマップを使って複数ジョブを実行する
+/// AS番号は全ジョブで重複排除して1回ずつ問い合わせ、集計を標準エラーへ表示する
+/// 厳格モードでは AS の失敗が1件でもあれば何も書き出さずにエラーを返す
+/// 全ジョブの出力を計算し終えてから書き出す（1つでも失敗すれば何も書き出さず、前回の出力を残す）
+/// 書き出した後にプロファイルごとのフックを実行し、全ジョブで書き出したファイルを返す
+/// フックが失敗しても残りのプロファイルのフックは実行し、最後に最初の失敗を返す
+pub async fn run_jobs(
+    jobs: &[Job],
+    country_map: &CountryMap,
+    client: &Client,
+    lookup: &AsLookupOptions,
+) -> Result<Vec<WrittenFile>, AppError> {
+    let jobs = &expand_job_as_sets(jobs, &lookup.irr).await?;
+    let jobs = &expand_job_cones(jobs, &lookup.cone).await?;
+
+    // 全ジョブのAS番号を重複排除して1回ずつ問い合わせ
+    let as_numbers: Vec<Asn> = jobs
+        .iter()
+        .flat_map(|j| j.all_as_numbers())
+        .collect::<BTreeSet<Asn>>()
+        .into_iter()
+        .collect();
+    warn_reserved_asns(&as_numbers);
+    let as_numbers: Vec<String> = as_numbers.iter().map(Asn::to_string).collect();
+    let (as_map, report) = fetch_prefixes_for_many(client, &as_numbers, lookup).await?;
+    report.log();
+    report.check(lookup.continue_on_failure)?;
+    let annotations = load_annotations(client, &as_map, lookup).await?;
+
+    let mut staged_jobs = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        let mut job = job.clone();
+        // AS名の注記はそのジョブの AS のプレフィックスに限る
+        if let Some(a) = &annotations
+            && !job.all_as_numbers().is_empty()
+        {
+            let asns: Vec<u32> = job.all_as_numbers().iter().map(|a| a.0).collect();
+            job.output.annotations = Some(Arc::new(a.restricted_to(&asns)));
+        }
+        let staged = StagedWrites::default();
+        job.output.staged = Some(staged.clone());
+        run_job(&job, country_map, &as_map).await?;
+        staged_jobs.push((job, staged));
+    }
+
+    let mut all_written = Vec::new();
+    let mut hook_error = None;
+    for (job, staged) in &staged_jobs {
+        staged.commit(&job.output.log).await?;
+        let written = job.output.log.take();
+        if let Err(e) = run_hooks(&job.post_hooks, &written, Some(&job.name)).await {
+            warn!(profile = %job.name, error = %e, "Profile hook failed");
+            hook_error.get_or_insert(e);
+        }
+        all_written.extend(written);
+    }
+    match hook_error {
+        Some(e) => Err(e),
+        None => Ok(all_written),
+    }
+}
+
+/// 各ジョブの AS-SET を展開し、メンバーを設定したジョブを返す（同じ AS-SET は1回だけ展開）
+async fn expand_job_as_sets(jobs: &[Job], irr: &IrrOptions) -> Result<Vec<Job>, AppError> {
+    let names: Vec<String> = jobs
+        .iter()
+        .flat_map(|j| j.as_sets.iter().cloned())
+        .collect();
+    let expanded = expand_as_sets(&names, irr).await?;
+    Ok(jobs
+        .iter()
+        .map(|job| {
+            let mut job = job.clone();
+            job.set_members = job
+                .as_sets
+                .iter()
+                .filter_map(|name| expanded.get(name))
+                .flat_map(|e| e.asns.iter().map(|&n| Asn(n)))
+                .collect();
+            job
+        })
+        .collect())
+}
+
+/// カスタマーコーンを使うジョブの AS番号（AS-SET のメンバーを含む）を展開し、
+/// コーンのメンバーを加えたジョブを返す（同じ AS は1回だけ展開）
+async fn expand_job_cones(jobs: &[Job], cone: &ConeOptions) -> Result<Vec<Job>, AppError> {
+    let enabled = |job: &Job| job.customer_cone.unwrap_or(cone.enabled);
+    let roots: Vec<Asn> = jobs
+        .iter()
+        .filter(|j| enabled(j))
+        .flat_map(|j| j.all_as_numbers())
+        .collect::<BTreeSet<Asn>>()
+        .into_iter()
+        .collect();
+    let opts = ConeOptions {
+        enabled: true,
+        ..cone.clone()
+    };
+    let cones = expand_customer_cones(&roots, &opts).await?;
+    Ok(jobs
+        .iter()
+        .map(|job| {
+            let mut job = job.clone();
+            if enabled(&job) {
+                let members: Vec<Asn> = job
+                    .all_as_numbers()
+                    .iter()
+                    .filter_map(|asn| cones.get(asn))
+                    .flat_map(|c| c.asns())
+                    .collect();
+                job.set_members.extend(members);
+            }
+            job
+        })
+        .collect())
+}
+
+/// 1ジョブ分の出力
+#[instrument(skip_all, fields(profile = %job.name))]
+pub async fn run_job(job: &Job, country_map: &CountryMap, as_map: &AsMap) -> Result<(), AppError> {
+    info!("Running profile");
+    let as_strings: Vec<String> = job.all_as_numbers().iter().map(Asn::to_string).collect();
+    let country_label = if job.countries.is_empty() {
+        "N/A".to_string()
+    } else {
+        job.countries.join("_")
+    };
+    let as_labels = job.as_labels();
+    let as_label = if as_labels.is_empty() {
+        "N/A".to_string()
+    } else {
+        as_labels.join("_")
+    };
+
+    if job.overlap {
+        let overlap = calculate_overlaps(
+            &country_sets(&job.countries, country_map),
+            &as_prefix_sets(&as_strings, as_map),
+        );
+        let output = &overlap_output(&job.output, job.output.annotations.as_deref(), &overlap);
+        return match &job.set_name {
+            Some(name) => {
+                write_named_set_to_file(name, &country_label, &as_label, &overlap.nets, output)
+                    .await
+            }
+            None => write_overlap_to_file(&country_label, &as_label, &overlap.nets, output).await,
+        };
+    }
+
+    if let Some(name) = &job.set_name {
+        let merged = country_ips_from_map(&job.countries, country_map)
+            .union(&merge_as_sets(&as_strings, as_map));
+        return write_named_set_to_file(name, &country_label, &as_label, &merged, &job.output)
+            .await;
+    }
+
+    for code in &job.countries {
+        process_country_code_from_map(code, country_map, &job.output).await?;
+    }
+    for asn in &as_strings {
+        if let Some((v4, v6)) = as_map.get(asn) {
+            let set = IpRangeSet::from_nets(v4.iter().chain(v6));
+            write_ip_list(asn, IpFamily::V4, &set, &job.output).await?;
+            write_ip_list(asn, IpFamily::V6, &set, &job.output).await?;
+        }
+    }
+    Ok(())
+}
+
+fn merge_as_sets(as_strings: &[String], as_map: &AsMap) -> IpRangeSet {
+    IpRangeSet::from_nets(
+        as_strings
+            .iter()
+            .filter_map(|asn| as_map.get(asn))
+            .flat_map(|(v4, v6)| v4.iter().chain(v6)),
+    )
+}
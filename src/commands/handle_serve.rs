@@ -0,0 +1,39 @@
+use crate::asn::AsLookupOptions;
+use crate::cli::{Cli, ServeArgs};
+use crate::error::AppError;
+use crate::output::OutputOptions;
+use crate::serve::{ServeState, serve};
+use reqwest::Client;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// HTTPでリストを配信し続ける（Ctrl-C で終了）
+/// RIRデータはバックグラウンドで取得するため、初回の取得が終わるまでは 503 を返す
+pub async fn run_serve(
+    args: &Cli,
+    serve_args: &ServeArgs,
+    client: &Client,
+    base_output: &OutputOptions,
+    lookup: &AsLookupOptions,
+) -> Result<(), AppError> {
+    let state = Arc::new(ServeState::new(
+        client.clone(),
+        args.download_options(),
+        base_output.clone(),
+        lookup.api.clone(),
+    ));
+    let listener = TcpListener::bind(serve_args.listen).await?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+
+    let refresher = Arc::clone(&state);
+    let refresh = serve_args.refresh;
+    tokio::select! {
+        _ = async move { refresher.refresh_loop(refresh).await } => Ok(()),
+        res = serve(listener, state) => res,
+        res = tokio::signal::ctrl_c() => {
+            res?;
+            eprintln!("Interrupted, exiting.");
+            Ok(())
+        }
+    }
+}
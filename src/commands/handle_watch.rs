@@ -0,0 +1,41 @@
+use crate::cli::{Cli, WatchArgs};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::output::OutputOptions;
+use crate::watch::{SystemClock, Watcher};
+use reqwest::Client;
+
+/// 設定ファイルのプロファイルを一定間隔で再生成し続ける（Ctrl-C で終了）
+pub async fn run_watch(
+    args: &Cli,
+    watch_args: &WatchArgs,
+    config: Option<&Config>,
+    client: &Client,
+    base_output: &OutputOptions,
+) -> Result<(), AppError> {
+    let config = config.ok_or_else(|| {
+        AppError::InvalidInput("The watch command requires --config <FILE>".into())
+    })?;
+    let jobs = config.resolve_jobs(&watch_args.run.profiles, watch_args.run.all, base_output)?;
+
+    let watcher = Watcher::new(
+        client.clone(),
+        jobs,
+        args.download_options(),
+        args.as_lookup_options(),
+        watch_args.interval,
+        watch_args.jitter,
+        SystemClock,
+    )
+    .with_post_hooks(args.post_hooks.clone())
+    .with_metrics_file(args.metrics_file.clone());
+
+    tokio::select! {
+        _ = watcher.run(None) => Ok(()),
+        res = tokio::signal::ctrl_c() => {
+            res?;
+            eprintln!("Interrupted, exiting.");
+            Ok(())
+        }
+    }
+}
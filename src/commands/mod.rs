@@ -1,3 +1,10 @@
 pub mod handle_as_numbers;
 pub mod handle_country_codes;
+pub mod handle_diff;
+pub mod handle_fetch;
+pub mod handle_irr_compare;
+pub mod handle_lookup;
 pub mod handle_overlap;
+pub mod handle_run;
+pub mod handle_serve;
+pub mod handle_watch;
@@ -1,26 +1,5 @@
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-static DEBUG_ENABLED: AtomicBool = AtomicBool::new(false);
-
-/// デバッグの有効/無効を設定
-pub fn set_debug(enabled: bool) {
-    DEBUG_ENABLED.store(enabled, Ordering::Relaxed);
-}
-
-/// デバッグが有効かどうか
-pub fn debug_enabled() -> bool {
-    DEBUG_ENABLED.load(Ordering::Relaxed)
-}
-
-/// デバッグ出力（stderr）
-pub fn debug_log(msg: impl AsRef<str>) {
-    if debug_enabled() {
-        eprintln!("[debug] {}", msg.as_ref());
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IpFamily {
     V4,
     V6,
@@ -39,11 +18,11 @@ impl FromStr for IpFamily {
 }
 
 impl IpFamily {
-    /// whoisのrouteキーを返す ("route:" / "route6:")
+    /// RPSL の経路オブジェクトのクラス名 ("route" / "route6")
     pub fn route_key(self) -> &'static str {
         match self {
-            IpFamily::V4 => "route:",
-            IpFamily::V6 => "route6:",
+            IpFamily::V4 => "route",
+            IpFamily::V6 => "route6",
         }
     }
 
@@ -54,10 +33,26 @@ impl IpFamily {
             IpFamily::V6 => "IPv6",
         }
     }
+
+    /// CIDR のファミリ
+    pub fn of(net: &ipnet::IpNet) -> Self {
+        match net {
+            ipnet::IpNet::V4(_) => IpFamily::V4,
+            ipnet::IpNet::V6(_) => IpFamily::V6,
+        }
+    }
+
+    /// アドレスのビット数
+    pub fn bits(self) -> u8 {
+        match self {
+            IpFamily::V4 => 32,
+            IpFamily::V6 => 128,
+        }
+    }
 }
 
 /// 出力形式を管理するためのenum
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum OutputFormat {
     Txt,
     Nft,
@@ -75,3 +70,201 @@ impl FromStr for OutputFormat {
         }
     }
 }
+
+/// AS番号（表示・ファイル名では数字のみ）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+#[serde(transparent)]
+pub struct Asn(pub u32);
+
+/// 1つの範囲指定で展開できる AS番号の上限（誤って巨大な範囲を問い合わせないため）
+pub const MAX_ASN_RANGE: u32 = 4096;
+
+impl FromStr for Asn {
+    type Err = String;
+
+    /// `13335` / `AS13335` / asdot の `1.10`（= 65546）/ `AS1.10`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let t = s.trim();
+        let digits = t
+            .strip_prefix("AS")
+            .or_else(|| t.strip_prefix("as"))
+            .or_else(|| t.strip_prefix("As"))
+            .unwrap_or(t);
+        let invalid = || format!("invalid AS number '{}' (examples: 13335, AS13335, 1.10)", s);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return Err(invalid());
+        }
+        let n = match digits.split_once('.') {
+            Some((hi, lo)) => {
+                let hi: u16 = hi.parse().map_err(|_| invalid())?;
+                let lo: u16 = lo.parse().map_err(|_| invalid())?;
+                (u32::from(hi) << 16) | u32::from(lo)
+            }
+            None => digits.parse().map_err(|_| invalid())?,
+        };
+        Ok(Asn(n))
+    }
+}
+
+impl std::fmt::Display for Asn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Asn {
+    /// 公開の経路表（RIPEstat）に現れない予約済み・プライベートの AS番号なら、その種別
+    pub fn reserved(self) -> Option<&'static str> {
+        match self.0 {
+            0 => Some("reserved (RFC 7607)"),
+            23456 => Some("AS_TRANS (RFC 6793)"),
+            64496..=64511 | 65536..=65551 => Some("for documentation (RFC 5398)"),
+            64512..=65534 | 4_200_000_000..=4_294_967_294 => Some("for private use (RFC 6996)"),
+            65535 | 4_294_967_295 => Some("reserved (RFC 7300)"),
+            65552..=131_071 => Some("reserved by IANA"),
+            _ => None,
+        }
+    }
+}
+
+/// コマンドラインや設定ファイルでの AS番号の指定（単一、または `64512-64520` のような範囲）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsnRange {
+    pub first: Asn,
+    pub last: Asn,
+}
+
+impl From<Asn> for AsnRange {
+    fn from(asn: Asn) -> Self {
+        Self {
+            first: asn,
+            last: asn,
+        }
+    }
+}
+
+impl FromStr for AsnRange {
+    type Err = String;
+
+    /// `13335` / `AS13335` / `1.10` / `64512-65534` / `AS64512-AS65534`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((first, last)) = s.split_once('-') else {
+            return Asn::from_str(s).map(Self::from);
+        };
+        let (first, last) = (Asn::from_str(first)?, Asn::from_str(last)?);
+        if first > last {
+            return Err(format!(
+                "invalid AS range '{}': start is greater than end",
+                s
+            ));
+        }
+        if last.0 - first.0 >= MAX_ASN_RANGE {
+            return Err(format!(
+                "AS range '{}' is too large (at most {} AS numbers)",
+                s, MAX_ASN_RANGE
+            ));
+        }
+        Ok(Self { first, last })
+    }
+}
+
+impl std::fmt::Display for AsnRange {
+    /// 単一なら `2497`、範囲なら `64512-64520`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AsnRange {
+    /// 設定ファイルでは数値（`2497`）と文字列（`"AS13335"`, `"64512-64520"`）のどちらも受け付ける
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u32),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(n) => Ok(Asn(n).into()),
+            Raw::Text(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl AsnRange {
+    pub fn iter(&self) -> impl Iterator<Item = Asn> {
+        (self.first.0..=self.last.0).map(Asn)
+    }
+}
+
+/// 範囲を展開した AS番号（指定順、重複なし）
+pub fn expand_asns(ranges: &[AsnRange]) -> Vec<Asn> {
+    let mut seen = std::collections::HashSet::new();
+    ranges
+        .iter()
+        .flat_map(AsnRange::iter)
+        .filter(|asn| seen.insert(*asn))
+        .collect()
+}
+
+/// 予約済み・プライベートの AS番号が含まれていれば、種別ごとの警告文
+pub fn reserved_asn_warnings(asns: &[Asn]) -> Vec<String> {
+    let mut by_kind: Vec<(&'static str, Vec<Asn>)> = Vec::new();
+    for asn in asns {
+        if let Some(kind) = asn.reserved() {
+            match by_kind.iter_mut().find(|(k, _)| *k == kind) {
+                Some((_, list)) => list.push(*asn),
+                None => by_kind.push((kind, vec![*asn])),
+            }
+        }
+    }
+    by_kind
+        .into_iter()
+        .map(|(kind, mut list)| {
+            list.sort();
+            format!(
+                "{} AS number(s) {} will not appear in RIPEstat: {}",
+                list.len(),
+                kind,
+                compact_asns(&list)
+            )
+        })
+        .collect()
+}
+
+/// 昇順の AS番号を `AS64512-AS64520, AS65000` のように連続範囲でまとめる（先頭5件まで）
+fn compact_asns(sorted: &[Asn]) -> String {
+    let mut ranges: Vec<(Asn, Asn)> = Vec::new();
+    for &asn in sorted {
+        match ranges.last_mut() {
+            Some((_, last)) if last.0.checked_add(1) == Some(asn.0) => *last = asn,
+            _ => ranges.push((asn, asn)),
+        }
+    }
+    let mut parts: Vec<String> = ranges
+        .iter()
+        .take(5)
+        .map(|(a, b)| {
+            if a == b {
+                format!("AS{}", a)
+            } else {
+                format!("AS{}-AS{}", a, b)
+            }
+        })
+        .collect();
+    if ranges.len() > 5 {
+        parts.push(format!("... ({} more)", ranges.len() - 5));
+    }
+    parts.join(", ")
+}
+
+/// 予約済み・プライベートの AS番号を警告する
+pub fn warn_reserved_asns(asns: &[Asn]) {
+    for message in reserved_asn_warnings(asns) {
+        tracing::warn!("{}", message);
+    }
+}
@@ -1,66 +1,100 @@
 use crate::constants::RIR_URLS;
 use crate::error::AppError;
-use crate::fetch::fetch_with_retry;
+use crate::fetch::fetch_parsed_with_retry;
+use crate::metrics::registry;
+use crate::parse::{CountryFilter, CountryMap, aggregate_country_sets};
+use crate::report::journal;
 use futures::future::join_all;
 use reqwest::Client;
-use crate::common::debug_log;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
 
-/// 共通のダウンロード関数。
-/// urlsに指定されたURLを並列で全てダウンロードし、
-/// 成功したもののテキストと失敗したURLのセットを返す。
-pub async fn download_files(
-    client: &Client,
-    urls: &[&'static str],
-    retry_attempts: u32,
-    max_backoff_secs: u64,
-) -> Result<(Vec<String>, Vec<String>), AppError> {
-    let mut handles = Vec::new();
-
-    // tokio::spawnでタスクを生成しながらfetch_with_retry()を呼び出す
-    for url in urls {
-        let url_owned = url.to_string();
-        let client_clone = client.clone();
+/// RIRファイル取得の設定
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// 取得するdelegatedファイルのURL（既定は `RIR_URLS`）
+    pub rir_urls: Vec<String>,
+    pub max_retries: u32,
+    pub max_backoff_sec: u64,
+    pub continue_on_partial: bool,
+}
 
-        let ra = retry_attempts;
-        let mx = max_backoff_secs;
-        handles.push(tokio::spawn(async move {
-            fetch_with_retry(&client_clone, &url_owned, ra, mx).await
-        }));
+impl DownloadOptions {
+    /// 既定のRIR URLを使う設定
+    pub fn new(max_retries: u32, max_backoff_sec: u64, continue_on_partial: bool) -> Self {
+        Self {
+            rir_urls: RIR_URLS.iter().map(|u| u.to_string()).collect(),
+            max_retries,
+            max_backoff_sec,
+            continue_on_partial,
+        }
     }
+}
 
-    let results = join_all(handles).await;
-    let mut success_texts = Vec::new();
-    let mut fail_urls = Vec::new();
+/// RIRファイルをダウンロードしながら解析し、部分失敗の扱いを適用した上で国コードのマップを返す。
+/// ファイルは文字列として保持せず、`filter` の国だけを集合に残す。
+/// `continue_on_partial` が偽なら1件でも失敗した時点でエラー、
+/// 真でも1件も取得できなければエラー。
+/// 解析に失敗したファイルがあれば、そのエラー（終了コードは解析エラー）を返す。
+pub async fn download_country_map(
+    client: &Client,
+    opts: &DownloadOptions,
+    filter: &CountryFilter,
+) -> Result<CountryMap, AppError> {
+    let handles: Vec<_> = opts
+        .rir_urls
+        .iter()
+        .map(|url| {
+            let url = url.clone();
+            let client = client.clone();
+            let filter = filter.clone();
+            let (retries, backoff) = (opts.max_retries, opts.max_backoff_sec);
+            tokio::spawn(async move {
+                fetch_parsed_with_retry(&client, &url, &filter, retries, backoff).await
+            })
+        })
+        .collect();
 
-    // タスク実行結果を集約
-    for (i, res) in results.into_iter().enumerate() {
-        match res {
-            // タスクは正常終了、内部のfetch処理も成功
-            Ok(Ok(text)) => {
-                success_texts.push(text);
-            }
-            // タスクは正常終了したが、内部のfetch処理がエラー
-            Ok(Err(e)) => {
-                debug_log(format!("HTTP fetch error: {} (url={})", e, urls[i]));
-                fail_urls.push(urls[i].to_string());
-            }
-            // タスク自体が失敗 (パニックなど)
-            Err(e) => {
-                debug_log(format!("Download task failed: {} (url={})", e, urls[i]));
-                fail_urls.push(urls[i].to_string());
+    let mut partials = Vec::new();
+    let mut parse_time = Duration::ZERO;
+    let mut failed_urls = Vec::new();
+    let mut parse_error = None;
+    for (url, res) in opts.rir_urls.iter().zip(join_all(handles).await) {
+        let error = match res {
+            Ok(Ok((sets, busy))) => {
+                partials.push(sets);
+                parse_time = parse_time.max(busy);
+                continue;
             }
+            Ok(Err(e)) => e,
+            Err(e) => e.into(),
+        };
+        warn!(url = %url, error = %error, "RIR download failed");
+        journal().record_failure("rir", url, &error.to_string());
+        failed_urls.push(url.clone());
+        if matches!(error, AppError::ParseError(_)) && parse_error.is_none() {
+            parse_error = Some(error);
         }
     }
 
-    Ok((success_texts, fail_urls))
-}
+    if !failed_urls.is_empty() {
+        debug!(?failed_urls, "Some RIR files failed to download");
+        if !opts.continue_on_partial {
+            return Err(parse_error.unwrap_or_else(|| {
+                AppError::PartialData(
+                    "Some RIR downloads failed (use --continue-on-partial to proceed)".into(),
+                )
+            }));
+        }
+    }
+    if partials.is_empty() {
+        return Err(parse_error
+            .unwrap_or_else(|| AppError::Fetch("No RIR files available to process".into())));
+    }
 
-/// RIRファイルのダウンロード関数。
-/// 成功テキストと失敗URLのタプルを返す。
-pub async fn download_all_rir_files(
-    client: &Client,
-    retry_attempts: u32,
-    max_backoff_secs: u64,
-) -> Result<(Vec<String>, Vec<String>), AppError> {
-    download_files(client, &RIR_URLS, retry_attempts, max_backoff_secs).await
+    // 集約は CPU を使うため blocking スレッドで行う
+    let started = Instant::now();
+    let map = tokio::task::spawn_blocking(move || aggregate_country_sets(partials)).await?;
+    registry().record_parse(parse_time + started.elapsed());
+    Ok(map)
 }
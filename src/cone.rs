@@ -0,0 +1,287 @@
+//! CAIDA の AS 関係データによるカスタマーコーン
+//!
+//! 「AS2497 を許可する」は多くの場合、その AS と下流の顧客 AS 全体を意味する。
+//! ローカルの CAIDA データから AS をカスタマーコーンへ展開してからプレフィックスを集める。
+//! - `as-rel` / `as-rel2`: `<provider>|<customer>|-1`（p2c）と `<peer>|<peer>|0`（p2p）。
+//!   serial-2 の4列目（取得元）は無視する。p2c の辺だけをたどる
+//! - `ppdc-ases`: `<AS> <AS> <customer>...`（1行に1つのコーン、AS 自身を含む）
+//!
+//! 形式は行ごとに `|` の有無で判別するため、同じ `--as-rel-file` にどちらも指定できる。
+//! 深さは as-rel の p2c をたどった段数で、ppdc-ases から求めたコーンには深さの制限を適用できない。
+
+use crate::common::Asn;
+use crate::error::AppError;
+use crate::irr::open_dump;
+use crate::report::journal;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// 既定の最大深さ（p2c の段数）
+pub const DEFAULT_CONE_MAX_DEPTH: usize = 8;
+/// 既定の1コーンあたりの最大 AS 数（起点の AS を除く）
+pub const DEFAULT_CONE_MAX_SIZE: usize = 1000;
+
+/// カスタマーコーン展開の設定
+#[derive(Debug, Clone)]
+pub struct ConeOptions {
+    /// 指定した AS をカスタマーコーンへ展開する
+    pub enabled: bool,
+    /// CAIDA の as-rel / ppdc-ases ファイル（plain / gzip / bzip2）
+    pub files: Vec<PathBuf>,
+    /// p2c をたどる最大の段数
+    pub max_depth: usize,
+    /// 1コーンあたりの最大 AS 数（近い段から採用する）
+    pub max_size: usize,
+}
+
+impl Default for ConeOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            files: Vec::new(),
+            max_depth: DEFAULT_CONE_MAX_DEPTH,
+            max_size: DEFAULT_CONE_MAX_SIZE,
+        }
+    }
+}
+
+/// 読み込んだ AS 関係データ
+#[derive(Debug, Default)]
+pub struct AsRelationships {
+    /// provider → customers（as-rel の p2c）
+    customers: HashMap<u32, Vec<u32>>,
+    /// AS → コーンの AS（ppdc-ases、AS 自身を除く）
+    cones: HashMap<u32, Vec<u32>>,
+}
+
+/// as-rel / ppdc-ases のテキストを読み、`data` に加える
+/// `roots` を指定した場合、ppdc-ases の行はその AS の分だけ保持する（p2c はすべて保持）
+pub fn read_as_relationships<R: BufRead>(
+    reader: R,
+    roots: Option<&HashSet<u32>>,
+    data: &mut AsRelationships,
+) -> Result<usize, AppError> {
+    let mut read = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.contains('|') {
+            let mut fields = line.split('|');
+            let (Some(a), Some(b), Some(rel)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            // p2p（0）や壊れた行は使わない
+            if rel.trim() != "-1" {
+                continue;
+            }
+            let (Ok(provider), Ok(customer)) = (a.trim().parse::<u32>(), b.trim().parse::<u32>())
+            else {
+                continue;
+            };
+            data.customers.entry(provider).or_default().push(customer);
+            read += 1;
+        } else {
+            let mut asns = line.split_whitespace().map(str::parse::<u32>);
+            let Some(Ok(root)) = asns.next() else {
+                continue;
+            };
+            if roots.is_some_and(|r| !r.contains(&root)) {
+                continue;
+            }
+            let members: Vec<u32> = asns.filter_map(Result::ok).filter(|&n| n != root).collect();
+            data.cones.entry(root).or_default().extend(members);
+            read += 1;
+        }
+    }
+    Ok(read)
+}
+
+/// as-rel / ppdc-ases のファイルを読む（gzip / bzip2 は内容から判別）
+pub fn read_as_relationships_file(
+    path: &Path,
+    roots: Option<&HashSet<u32>>,
+    data: &mut AsRelationships,
+) -> Result<usize, AppError> {
+    read_as_relationships(open_dump(path, "AS relationship file")?, roots, data)
+}
+
+/// コーンの1メンバー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConeMember {
+    pub asn: Asn,
+    /// 起点からの p2c の段数（ppdc-ases から求めた場合は `None`）
+    pub depth: Option<usize>,
+    /// この AS へたどった直上の provider（ppdc-ases から求めた場合は `None`）
+    pub via: Option<Asn>,
+}
+
+/// 1つの AS のカスタマーコーン
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomerCone {
+    pub root: Asn,
+    /// 起点を除くメンバー（近い段から、同じ段の中では昇順）
+    pub members: Vec<ConeMember>,
+    /// 深さの上限で打ち切った
+    pub depth_limited: bool,
+    /// 大きさの上限で打ち切った
+    pub size_limited: bool,
+}
+
+impl CustomerCone {
+    /// 起点とメンバーの AS番号
+    pub fn asns(&self) -> impl Iterator<Item = Asn> + '_ {
+        std::iter::once(self.root).chain(self.members.iter().map(|m| m.asn))
+    }
+
+    /// 人が読むための1行の集計
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "AS{} customer cone: {} customer AS(es)",
+            self.root,
+            self.members.len()
+        );
+        if let Some(depth) = self.members.iter().filter_map(|m| m.depth).max() {
+            out.push_str(&format!(", depth {}", depth));
+        }
+        if self.depth_limited {
+            out.push_str(", stopped at the depth limit");
+        }
+        if self.size_limited {
+            out.push_str(", truncated at the size limit");
+        }
+        out
+    }
+}
+
+impl AsRelationships {
+    /// ファイルを読み込む（ppdc-ases は `roots` の分だけ）
+    pub async fn load(opts: &ConeOptions, roots: &[Asn]) -> Result<Self, AppError> {
+        if opts.files.is_empty() {
+            return Err(AppError::InvalidInput(
+                "Customer cone expansion requires --as-rel-file <FILE>".into(),
+            ));
+        }
+        let files = opts.files.clone();
+        let wanted: HashSet<u32> = roots.iter().map(|a| a.0).collect();
+        tokio::task::spawn_blocking(move || {
+            let mut data = AsRelationships::default();
+            for path in &files {
+                let lines = read_as_relationships_file(path, Some(&wanted), &mut data)?;
+                debug!(path = %path.display(), lines, "Loaded AS relationship file");
+            }
+            Ok(data)
+        })
+        .await?
+    }
+
+    /// AS をカスタマーコーンへ展開する
+    /// as-rel に p2c があれば幅優先でたどり、無ければ ppdc-ases のコーンを使う
+    pub fn cone(&self, root: Asn, max_depth: usize, max_size: usize) -> CustomerCone {
+        let mut cone = CustomerCone {
+            root,
+            members: Vec::new(),
+            depth_limited: false,
+            size_limited: false,
+        };
+        if !self.customers.contains_key(&root.0)
+            && let Some(members) = self.cones.get(&root.0)
+        {
+            let mut members = members.clone();
+            members.sort_unstable();
+            members.dedup();
+            cone.size_limited = members.len() > max_size;
+            cone.members = members
+                .into_iter()
+                .take(max_size)
+                .map(|n| ConeMember {
+                    asn: Asn(n),
+                    depth: None,
+                    via: None,
+                })
+                .collect();
+            return cone;
+        }
+
+        let mut seen = HashSet::from([root.0]);
+        let mut queue = VecDeque::from([(root.0, 0usize)]);
+        while let Some((provider, depth)) = queue.pop_front() {
+            let Some(customers) = self.customers.get(&provider) else {
+                continue;
+            };
+            if depth >= max_depth {
+                if customers.iter().any(|c| !seen.contains(c)) {
+                    cone.depth_limited = true;
+                }
+                continue;
+            }
+            let mut customers = customers.clone();
+            customers.sort_unstable();
+            for customer in customers {
+                if !seen.insert(customer) {
+                    continue;
+                }
+                if cone.members.len() >= max_size {
+                    cone.size_limited = true;
+                    return cone;
+                }
+                cone.members.push(ConeMember {
+                    asn: Asn(customer),
+                    depth: Some(depth + 1),
+                    via: Some(Asn(provider)),
+                });
+                queue.push_back((customer, depth + 1));
+            }
+        }
+        cone
+    }
+}
+
+/// 有効なら各 AS をカスタマーコーンへ展開し、起点ごとのコーンを返す（無効なら空）
+/// コーンごとの集計をログに出し、実行レポートに記録する
+pub async fn expand_customer_cones(
+    roots: &[Asn],
+    opts: &ConeOptions,
+) -> Result<BTreeMap<Asn, CustomerCone>, AppError> {
+    let mut cones = BTreeMap::new();
+    if !opts.enabled || roots.is_empty() {
+        return Ok(cones);
+    }
+    let data = AsRelationships::load(opts, roots).await?;
+    for &root in roots {
+        if cones.contains_key(&root) {
+            continue;
+        }
+        let cone = data.cone(root, opts.max_depth, opts.max_size);
+        info!(
+            root = %cone.root,
+            members = cone.members.len(),
+            depth = cone.members.iter().filter_map(|m| m.depth).max(),
+            depth_limited = cone.depth_limited,
+            size_limited = cone.size_limited,
+            "{}",
+            cone.summary()
+        );
+        journal().record_cone(&cone);
+        cones.insert(root, cone);
+    }
+    Ok(cones)
+}
+
+/// 指定した AS を先に、続けて各コーンのメンバーを並べる（重複は除く）
+pub async fn add_customer_cones(asns: &[Asn], opts: &ConeOptions) -> Result<Vec<Asn>, AppError> {
+    let cones = expand_customer_cones(asns, opts).await?;
+    let mut seen = HashSet::new();
+    let mut all: Vec<Asn> = asns.iter().copied().filter(|n| seen.insert(*n)).collect();
+    for asn in asns {
+        if let Some(cone) = cones.get(asn) {
+            all.extend(cone.asns().filter(|n| seen.insert(*n)));
+        }
+    }
+    Ok(all)
+}
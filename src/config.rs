@@ -0,0 +1,358 @@
+//! 設定ファイル（TOML）の読み込み
+//!
+//! `[defaults]` には CLI の各オプションの既定値を、`[profiles.<name>]` には
+//! `fire-scope run <name>` で実行する名前付きジョブを記述する。
+//! キー名は CLI の長いオプション名と同じ（例: `max-retries`, `continue-on-partial`）。
+
+use crate::asn::AsSource;
+use crate::cli::{Cli, parse_as_set_name, parse_duration, parse_rate, parse_sourceapp};
+use crate::common::{Asn, AsnRange, OutputFormat};
+use crate::country::normalize_country_code;
+use crate::error::AppError;
+use crate::logging::LogFormat;
+use crate::output::OutputOptions;
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// 設定ファイル全体
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub defaults: Defaults,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// 名前付きジョブ
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    /// 取得元の国コード
+    pub country: Vec<String>,
+    /// 取得元のAS番号
+    pub as_number: Vec<AsnRange>,
+    /// 取得元の AS-SET（実行時に IRR で展開する）
+    pub as_set: Vec<String>,
+    /// AS番号をカスタマーコーンへ展開する（省略時は defaults / CLI の値）
+    pub customer_cone: Option<bool>,
+    /// 国コードとAS番号の重複部分のみを出力する
+    pub overlap: bool,
+    /// このジョブの出力から取り除くCIDR（defaults.exclude に追加される）
+    pub exclude: Vec<String>,
+    /// 出力形式（省略時は defaults / CLI の値）
+    pub format: Option<String>,
+    /// 出力ディレクトリ（省略時は defaults / CLI の値）
+    pub output_dir: Option<PathBuf>,
+    /// 指定時は全ソースを1つのセット `<set-name>_IPv4` / `<set-name>_IPv6` にまとめる
+    pub set_name: Option<String>,
+    /// このジョブの出力に変更があった場合に実行するコマンド
+    pub post_hook: Vec<String>,
+    /// プレフィックス長の調整と最大エントリ数（省略時は defaults / CLI の値）
+    pub drop_longer_than_v4: Option<u8>,
+    pub drop_longer_than_v6: Option<u8>,
+    pub widen_to_v4: Option<u8>,
+    pub widen_to_v6: Option<u8>,
+    pub max_entries: Option<usize>,
+}
+
+/// 検証済みで実行可能なジョブ
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub name: String,
+    pub countries: Vec<String>,
+    pub as_numbers: Vec<AsnRange>,
+    pub as_sets: Vec<String>,
+    /// `as_sets` を展開したメンバーとカスタマーコーンのメンバー（実行時に設定される）
+    pub set_members: Vec<Asn>,
+    /// カスタマーコーンへ展開するか（`None` は CLI / defaults の値）
+    pub customer_cone: Option<bool>,
+    pub overlap: bool,
+    pub set_name: Option<String>,
+    pub output: OutputOptions,
+    pub post_hooks: Vec<String>,
+}
+
+impl Job {
+    /// 問い合わせる AS番号（指定分と AS-SET のメンバー、重複なし）
+    pub fn all_as_numbers(&self) -> Vec<Asn> {
+        let mut seen = BTreeSet::new();
+        self.as_numbers
+            .iter()
+            .flat_map(AsnRange::iter)
+            .chain(self.set_members.iter().copied())
+            .filter(|n| seen.insert(*n))
+            .collect()
+    }
+
+    /// 出力ヘッダ・ファイル名用の AS ラベル（AS-SET は展開前の名前）
+    pub fn as_labels(&self) -> Vec<String> {
+        self.as_numbers
+            .iter()
+            .map(AsnRange::to_string)
+            .chain(self.as_sets.iter().cloned())
+            .collect()
+    }
+}
+
+impl Config {
+    /// ファイルから読み込む
+    pub fn load(path: &Path) -> Result<Self, AppError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            AppError::InvalidInput(format!("Cannot read config {}: {}", path.display(), e))
+        })?;
+        Self::from_toml_str(&text)
+    }
+
+    /// TOML文字列からパース
+    pub fn from_toml_str(text: &str) -> Result<Self, AppError> {
+        toml::from_str(text).map_err(|e| AppError::ParseError(format!("Config parse error: {e}")))
+    }
+
+    /// 実行するプロファイルを検証済みジョブへ変換
+    /// `all` が真なら全プロファイル（名前順）、偽なら `names` の順
+    pub fn resolve_jobs(
+        &self,
+        names: &[String],
+        all: bool,
+        base: &OutputOptions,
+    ) -> Result<Vec<Job>, AppError> {
+        let selected: Vec<(&String, &Profile)> = if all {
+            self.profiles.iter().collect()
+        } else {
+            names
+                .iter()
+                .map(|name| {
+                    self.profiles.get_key_value(name).ok_or_else(|| {
+                        AppError::InvalidInput(format!(
+                            "Unknown profile '{}' (available: {})",
+                            name,
+                            self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                        ))
+                    })
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        if selected.is_empty() {
+            return Err(AppError::InvalidInput(
+                "No profiles defined in the config file".into(),
+            ));
+        }
+
+        selected
+            .into_iter()
+            .map(|(name, profile)| profile.to_job(name, base))
+            .collect()
+    }
+}
+
+impl Profile {
+    fn to_job(&self, name: &str, base: &OutputOptions) -> Result<Job, AppError> {
+        let invalid = |msg: String| AppError::InvalidInput(format!("profile '{}': {}", name, msg));
+
+        let no_as = self.as_number.is_empty() && self.as_set.is_empty();
+        if self.country.is_empty() && no_as {
+            return Err(invalid(
+                "at least one of 'country', 'as-number' or 'as-set' is required".into(),
+            ));
+        }
+        if self.overlap && (self.country.is_empty() || no_as) {
+            return Err(invalid(
+                "'overlap' requires 'country' and 'as-number' or 'as-set'".into(),
+            ));
+        }
+        let as_sets = self
+            .as_set
+            .iter()
+            .map(|s| parse_as_set_name(s).map_err(&invalid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let countries = self
+            .country
+            .iter()
+            .map(|c| normalize_country_code(c).map_err(&invalid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut output = base.clone();
+        if let Some(fmt) = &self.format {
+            output.format = parse_format(fmt).map_err(&invalid)?;
+        }
+        if let Some(dir) = &self.output_dir {
+            output.dir = dir.clone();
+        }
+        output
+            .exclude
+            .extend(parse_cidrs(&self.exclude).map_err(&invalid)?);
+        let shape = &mut output.shape;
+        if let Some(v) = self.drop_longer_than_v4 {
+            shape.drop_longer_than_v4 = Some(check_prefix_len(v, 32).map_err(&invalid)?);
+        }
+        if let Some(v) = self.drop_longer_than_v6 {
+            shape.drop_longer_than_v6 = Some(check_prefix_len(v, 128).map_err(&invalid)?);
+        }
+        if let Some(v) = self.widen_to_v4 {
+            shape.widen_to_v4 = Some(check_prefix_len(v, 32).map_err(&invalid)?);
+        }
+        if let Some(v) = self.widen_to_v6 {
+            shape.widen_to_v6 = Some(check_prefix_len(v, 128).map_err(&invalid)?);
+        }
+        if let Some(v) = self.max_entries {
+            shape.max_entries = Some(check_max_entries(v).map_err(&invalid)?);
+        }
+
+        Ok(Job {
+            name: name.to_string(),
+            countries,
+            as_numbers: self.as_number.clone(),
+            as_sets,
+            set_members: Vec::new(),
+            customer_cone: self.customer_cone,
+            overlap: self.overlap,
+            set_name: self.set_name.clone(),
+            output,
+            post_hooks: self.post_hook.clone(),
+        })
+    }
+}
+
+/// `[defaults]` の表から `Defaults` と `Defaults::apply` を生成する
+/// 1行が「設定ファイルのキー: 型 => CLI のフィールド = |値, 検証エラー| 適用する値」。
+/// CLI 引数の ID はフィールド名そのものなので、CLI 側の改名はコンパイルエラーになる
+macro_rules! cli_defaults {
+    ($($key:ident: $ty:ty => $field:ident = |$v:pat_param, $invalid:pat_param| $apply:expr;)*) => {
+        /// CLI オプションの既定値（コマンドラインで明示された値が優先）
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+        pub struct Defaults {
+            $(pub $key: Option<$ty>,)*
+        }
+
+        impl Defaults {
+            /// (フィールド名, CLI 引数の ID) の一覧
+            pub const FIELDS: &'static [(&'static str, &'static str)] =
+                &[$((stringify!($key), stringify!($field)),)*];
+
+            /// コマンドラインで明示されていない項目に既定値を適用する
+            pub fn apply(&self, cli: &mut Cli, matches: &ArgMatches) -> Result<(), AppError> {
+                let invalid = |msg: String| AppError::InvalidInput(format!("config defaults: {}", msg));
+                $(
+                    if let Some($v) = &self.$key
+                        && !from_command_line(matches, stringify!($field))
+                    {
+                        let $invalid = &invalid;
+                        cli.$field = $apply;
+                    }
+                )*
+                Ok(())
+            }
+        }
+    };
+}
+
+cli_defaults! {
+    country: Vec<String> => country_codes = |v, invalid| Some(
+        v.iter()
+            .map(|c| normalize_country_code(c).map_err(invalid))
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+    as_number: Vec<AsnRange> => as_numbers = |v, _| Some(v.clone());
+    overlap: bool => overlap = |v, _| *v;
+    format: String => output_format = |v, invalid| {
+        parse_format(v).map_err(invalid)?;
+        v.to_ascii_lowercase()
+    };
+    max_retries: u32 => max_retries = |v, _| *v;
+    max_backoff_sec: u64 => max_backoff_sec = |v, _| *v;
+    http_timeout_secs: u64 => http_timeout_secs = |v, _| *v;
+    connect_timeout_secs: u64 => connect_timeout_secs = |v, _| *v;
+    concurrency: usize => concurrency = |v, _| *v;
+    continue_on_partial: bool => continue_on_partial = |v, _| *v;
+    continue_on_as_failure: bool => continue_on_as_failure = |v, _| *v;
+    api_rate_limit: f64 => api_rate_limit = |v, invalid| parse_rate(&v.to_string()).map_err(invalid)?;
+    ripestat_sourceapp: String => ripestat_sourceapp = |v, invalid| parse_sourceapp(v).map_err(invalid)?;
+    announced_window: String => announced_window = |v, invalid| Some(parse_duration(v).map_err(invalid)?);
+    min_announced: String => min_announced = |v, invalid| parse_duration(v).map_err(invalid)?;
+    as_source: String => as_source = |v, invalid| {
+        AsSource::from_str(v).map_err(|e| invalid(format!("invalid as-source '{}': {}", v, e)))?;
+        v.to_ascii_lowercase()
+    };
+    mrt_file: Vec<PathBuf> => mrt_files = |v, _| v.clone();
+    mrt_min_peers: usize => mrt_min_peers = |v, _| *v;
+    irr_dump: Vec<PathBuf> => irr_dumps = |v, _| v.clone();
+    irr_whois: String => irr_whois = |v, _| Some(v.clone());
+    as_set_max_depth: usize => as_set_max_depth = |v, _| *v;
+    customer_cone: bool => customer_cone = |v, _| *v;
+    as_rel_file: Vec<PathBuf> => as_rel_files = |v, _| v.clone();
+    cone_max_depth: usize => cone_max_depth = |v, _| *v;
+    cone_max_size: usize => cone_max_size = |v, _| *v;
+    as_names: bool => as_names = |v, _| *v;
+    as_names_file: PathBuf => as_names_file = |v, _| Some(v.clone());
+    attribute_overlaps: bool => attribute_overlaps = |v, _| *v;
+    debug: bool => debug = |v, _| *v;
+    output_dir: PathBuf => output_dir = |v, _| Some(v.clone());
+    exclude: Vec<String> => exclude = |v, invalid| Some(parse_cidrs(v).map_err(invalid)?);
+    rir_url: Vec<String> => rir_urls = |v, _| Some(v.clone());
+    post_hook: Vec<String> => post_hooks = |v, _| v.clone();
+    metrics_file: PathBuf => metrics_file = |v, _| Some(v.clone());
+    report: PathBuf => report = |v, _| Some(v.clone());
+    stats: PathBuf => stats = |v, _| Some(v.clone());
+    max_shrink_percent: f64 => max_shrink_percent = |v, invalid| {
+        if !(0.0..=100.0).contains(v) {
+            return Err(invalid(format!("max-shrink-percent {} must be between 0 and 100", v)));
+        }
+        Some(*v)
+    };
+    keep_bogons: bool => keep_bogons = |v, _| *v;
+    bogon_file: Vec<PathBuf> => bogon_files = |v, _| v.clone();
+    drop_longer_than_v4: u8 => drop_longer_than_v4 = |v, invalid| Some(check_prefix_len(*v, 32).map_err(invalid)?);
+    drop_longer_than_v6: u8 => drop_longer_than_v6 = |v, invalid| Some(check_prefix_len(*v, 128).map_err(invalid)?);
+    widen_to_v4: u8 => widen_to_v4 = |v, invalid| Some(check_prefix_len(*v, 32).map_err(invalid)?);
+    widen_to_v6: u8 => widen_to_v6 = |v, invalid| Some(check_prefix_len(*v, 128).map_err(invalid)?);
+    max_entries: usize => max_entries = |v, invalid| Some(check_max_entries(*v).map_err(invalid)?);
+    no_change_exit_code: bool => no_change_exit_code = |v, _| *v;
+    log_level: String => log_level = |v, invalid| {
+        let v = v.to_ascii_lowercase();
+        if !["error", "warn", "info", "debug", "trace"].contains(&v.as_str()) {
+            return Err(invalid(format!("invalid log-level '{}'", v)));
+        }
+        v
+    };
+    log_format: String => log_format = |v, invalid| {
+        LogFormat::from_str(v).map_err(|e| invalid(format!("invalid log-format '{}': {}", v, e)))?;
+        v.to_ascii_lowercase()
+    };
+}
+
+fn from_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), Some(ValueSource::CommandLine))
+}
+
+fn parse_format(s: &str) -> Result<OutputFormat, String> {
+    OutputFormat::from_str(s).map_err(|e| format!("invalid format '{}': {}", s, e))
+}
+
+fn check_prefix_len(v: u8, max: u8) -> Result<u8, String> {
+    if v <= max {
+        Ok(v)
+    } else {
+        Err(format!("prefix length {} must be between 0 and {}", v, max))
+    }
+}
+
+fn check_max_entries(v: usize) -> Result<usize, String> {
+    if v >= 1 {
+        Ok(v)
+    } else {
+        Err("max-entries must be at least 1".into())
+    }
+}
+
+fn parse_cidrs(list: &[String]) -> Result<Vec<IpNet>, String> {
+    list.iter()
+        .map(|s| IpNet::from_str(s.trim()).map_err(|e| format!("invalid CIDR '{}': {}", s, e)))
+        .collect()
+}
@@ -1,4 +1,4 @@
-/// 定数の共通化
+//! 定数の共通化
 
 pub const RIR_URLS: &[&str] = &[
     "https://ftp.afrinic.net/pub/stats/afrinic/delegated-afrinic-extended-latest",
@@ -13,3 +13,15 @@ pub const MAX_RIR_DOWNLOAD_BYTES: u64 = 32 * 1024 * 1024;
 
 /// JSON API 応答の最大サイズ上限 (バイト)。防御的に 8 MiB
 pub const MAX_JSON_DOWNLOAD_BYTES: u64 = 8 * 1024 * 1024;
+
+/// JSON API へのホストごとの既定の流量（リクエスト/秒）
+pub const DEFAULT_API_RATE: f64 = 4.0;
+
+/// Retry-After で待つ最大秒数
+pub const MAX_RETRY_AFTER_SECS: u64 = 120;
+
+/// RIPEstat Data API の基底URL
+pub const RIPESTAT_BASE_URL: &str = "https://stat.ripe.net/data";
+
+/// RIPEstat に送る既定の `sourceapp`
+pub const DEFAULT_RIPESTAT_SOURCEAPP: &str = "fire-scope";
@@ -0,0 +1,66 @@
+//! 生成済みリストファイル（txt / nft）の比較
+
+use crate::error::AppError;
+use crate::overlap::subtract_nets;
+use ipnet::IpNet;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// 2つのリストの差分（アドレス単位）
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ListDiff {
+    /// new にのみ含まれる範囲
+    pub added: BTreeSet<IpNet>,
+    /// old にのみ含まれる範囲
+    pub removed: BTreeSet<IpNet>,
+}
+
+impl ListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// txt / nft 形式のリスト本文からCIDRを取り出す
+/// - `#` 以降はコメントとして無視
+/// - `define X = {` や `}` などCIDRとして解釈できない語は無視
+/// - プレフィックス長の無いアドレスはホストルート（/32, /128）として扱う
+pub fn parse_list_text(text: &str) -> BTreeSet<IpNet> {
+    let mut set = BTreeSet::new();
+    for line in text.lines() {
+        let body = line.split('#').next().unwrap_or("");
+        for token in body.split(|c: char| c.is_whitespace() || c == ',') {
+            let token = token.trim();
+            if token.is_empty() {
+                continue;
+            }
+            if let Ok(net) = IpNet::from_str(token) {
+                set.insert(net.trunc());
+            } else if let Ok(addr) = IpAddr::from_str(token) {
+                set.insert(IpNet::from(addr));
+            }
+        }
+    }
+    set
+}
+
+/// リストファイルを読み込む
+pub async fn read_list_file(path: &Path) -> Result<BTreeSet<IpNet>, AppError> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| AppError::InvalidInput(format!("Cannot read {}: {}", path.display(), e)))?;
+    Ok(parse_list_text(&text))
+}
+
+/// old → new の差分をアドレス単位で計算する
+/// 集約の仕方が異なるだけのリストは差分なしになる
+pub fn diff_lists(old: &BTreeSet<IpNet>, new: &BTreeSet<IpNet>) -> ListDiff {
+    let old_vec: Vec<IpNet> = old.iter().copied().collect();
+    let new_vec: Vec<IpNet> = new.iter().copied().collect();
+    ListDiff {
+        added: subtract_nets(new, &old_vec),
+        removed: subtract_nets(old, &new_vec),
+    }
+}
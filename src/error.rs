@@ -25,6 +25,22 @@ pub enum AppError {
     #[error("Invalid input: {0}")]
     InvalidInput(String),
 
+    // リトライしても取得できなかった、または1件も取得できなかった
+    #[error("Download failed: {0}")]
+    Fetch(String),
+
+    // 一部のデータソースが失敗し、厳格モードのため中断した
+    #[error("Partial data: {0}")]
+    PartialData(String),
+
+    // 出力の縮小が安全しきい値を超えたため書き出さなかった
+    #[error("Safety threshold exceeded: {0}")]
+    SafetyThreshold(String),
+
+    // 出力に変化が無かった（`--no-change-exit-code` 指定時のみ）
+    #[error("No output changed")]
+    NoChange,
+
     // その他、文字列メッセージだけを格納した汎用エラー
     #[error("{0}")]
     Other(String),
@@ -41,7 +57,47 @@ pub enum AppError {
     #[error("Address parse error: {0}")]
     AddrParseError(#[from] AddrParseError),
 
+    // 出力更新後のフックコマンドが失敗した
+    #[error("Post-update hook failed: {0}")]
+    Hook(String),
+
     // 文字列 → 数値パース失敗
     #[error("Integer parse error: {0}")]
     ParseIntError(#[from] ParseIntError),
 }
+
+/// 終了コード（README に記載）
+pub mod exit_code {
+    pub const SUCCESS: u8 = 0;
+    /// 分類されないエラー（I/O、フックの失敗など）
+    pub const FAILURE: u8 = 1;
+    /// 不正な入力（clap の使い方エラーと同じ値）
+    pub const INVALID_INPUT: u8 = 2;
+    pub const NETWORK: u8 = 3;
+    pub const PARSE: u8 = 4;
+    pub const PARTIAL_DATA: u8 = 5;
+    pub const SAFETY_THRESHOLD: u8 = 6;
+    pub const NO_CHANGE: u8 = 7;
+}
+
+impl AppError {
+    /// このエラーで終了する場合の終了コード
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::InvalidInput(_) => exit_code::INVALID_INPUT,
+            AppError::Network(_) | AppError::Fetch(_) => exit_code::NETWORK,
+            AppError::ParseError(_)
+            | AppError::Utf8(_)
+            | AppError::AddrParseError(_)
+            | AppError::ParseIntError(_) => exit_code::PARSE,
+            AppError::PartialData(_) => exit_code::PARTIAL_DATA,
+            AppError::SafetyThreshold(_) => exit_code::SAFETY_THRESHOLD,
+            AppError::NoChange => exit_code::NO_CHANGE,
+            AppError::Io(_)
+            | AppError::Other(_)
+            | AppError::SemaphoreError(_)
+            | AppError::JoinError(_)
+            | AppError::Hook(_) => exit_code::FAILURE,
+        }
+    }
+}
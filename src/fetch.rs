@@ -1,11 +1,17 @@
-use crate::constants::MAX_RIR_DOWNLOAD_BYTES;
+use crate::constants::{
+    DEFAULT_API_RATE, DEFAULT_RIPESTAT_SOURCEAPP, MAX_RETRY_AFTER_SECS, MAX_RIR_DOWNLOAD_BYTES,
+};
 use crate::error::AppError;
+use crate::metrics::registry;
+use crate::parse::{CountryFilter, CountrySets, RirParser};
+use crate::rate_limit::RateLimiter;
+use crate::report::journal;
 use futures::StreamExt;
 use rand::Rng;
 use reqwest::Client;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
-use crate::common::debug_log;
+use tracing::{debug, instrument};
 
 /// ボディをストリーミングで読み込みつつ、サイズ上限を強制してStringへ変換
 async fn read_body_with_limit_to_string(
@@ -20,7 +26,7 @@ async fn read_body_with_limit_to_string(
         let chunk = chunk?; // reqwest::Error -> AppError::Network via ? 上位で変換
         total = total.saturating_add(chunk.len() as u64);
         if total > max_bytes {
-            return Err(AppError::Other(format!(
+            return Err(AppError::Fetch(format!(
                 "Response too large ({} bytes > {} bytes)",
                 total, max_bytes
             )));
@@ -35,20 +41,21 @@ async fn read_body_with_limit_to_string(
 async fn fetch_once(client: &Client, url: &str) -> Result<String, AppError> {
     let resp = client.get(url).send().await?.error_for_status()?; // 非2xxを明示的にエラー化
 
-    if let Some(len) = resp.content_length() {
-        if len > MAX_RIR_DOWNLOAD_BYTES {
-            return Err(AppError::Other(format!(
-                "Response too large ({} bytes > {} bytes): {}",
-                len, MAX_RIR_DOWNLOAD_BYTES, url
-            )));
-        }
+    if let Some(len) = resp.content_length()
+        && len > MAX_RIR_DOWNLOAD_BYTES
+    {
+        return Err(AppError::Fetch(format!(
+            "Response too large ({} bytes > {} bytes): {}",
+            len, MAX_RIR_DOWNLOAD_BYTES, url
+        )));
     }
     // Content-Length が無い場合にも備えて、常にストリーミングで上限制御
     read_body_with_limit_to_string(resp, MAX_RIR_DOWNLOAD_BYTES).await
 }
 
 /// HTTP GETによるデータ取得をリトライ+指数バックオフ付きで行う
-/// 失敗時はAppError::Other(...)を返す
+/// 失敗時はAppError::Fetch(...)を返す
+#[instrument(skip_all, fields(url = %url))]
 pub async fn fetch_with_retry(
     client: &Client,
     url: &str,
@@ -57,17 +64,15 @@ pub async fn fetch_with_retry(
 ) -> Result<String, AppError> {
     let attempts = retry_attempts.max(1);
     for i in 0..attempts {
+        let started = Instant::now();
         match fetch_once(client, url).await {
             Ok(text) => {
+                registry().record_download(url, started.elapsed(), Some(text.len() as u64), i);
+                journal().record_source(url, true);
                 return Ok(text);
             }
             Err(e) => {
-                debug_log(format!(
-                    "fetch attempt {}/{} failed: {}",
-                    i + 1,
-                    attempts,
-                    e
-                ));
+                debug!(attempt = i + 1, attempts, error = %e, "Fetch attempt failed");
                 // 最終試行後はスリープせずに即エラー復帰
                 if i + 1 < attempts {
                     let sleep_duration = calc_exponential_backoff_duration(i, max_backoff_secs);
@@ -78,12 +83,235 @@ pub async fn fetch_with_retry(
     }
 
     // リトライ失敗
-    Err(AppError::Other(format!(
+    registry().record_download_failure(url, attempts - 1);
+    journal().record_source(url, false);
+    Err(AppError::Fetch(format!(
         "Failed to fetch data from {} after {} attempts",
         url, attempts
     )))
 }
 
+/// 解析スレッドへ渡すチャンクの最大数（ダウンロードが解析より速い場合に溜める量）
+const PARSE_CHANNEL_CHUNKS: usize = 16;
+
+/// 解析済みの RIR ファイル
+#[derive(Debug)]
+pub struct ParsedRir {
+    /// `filter` の国だけの部分結果
+    pub sets: CountrySets,
+    /// 次回の条件付きGETに使う検証子
+    pub validators: Validators,
+    /// 解析にかかった時間（ダウンロードの待ち時間を除く）
+    pub parse_time: Duration,
+}
+
+/// 条件付きGETの検証子（前回応答の ETag / Last-Modified）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// 条件付きGETの結果
+#[derive(Debug)]
+pub enum Conditional {
+    /// 304 Not Modified（手元の解析結果をそのまま使える）
+    NotModified,
+    /// 新しい本文の解析結果と、次回用の検証子
+    Modified(ParsedRir),
+}
+
+/// RIR ファイル1回分の試行結果
+enum RirAttempt {
+    Done(Conditional, Option<u64>),
+    /// 再試行してよい失敗（接続失敗、HTTP エラー、サイズ超過）
+    Retry(AppError),
+    /// 再試行しても変わらない失敗（本文の解析エラー）
+    Fail(AppError),
+}
+
+/// 本文をダウンロードしながら RIR のパーサへ渡す
+/// 本文全体は保持しない。サイズ上限は `fetch_once` と同じ
+async fn fetch_parsed_once(
+    client: &Client,
+    url: &str,
+    filter: &CountryFilter,
+    validators: &Validators,
+) -> RirAttempt {
+    let mut req = client.get(url);
+    if let Some(etag) = &validators.etag {
+        req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    if let Some(lm) = &validators.last_modified {
+        req = req.header(reqwest::header::IF_MODIFIED_SINCE, lm);
+    }
+    let resp = match req.send().await {
+        Ok(resp) => resp,
+        Err(e) => return RirAttempt::Retry(e.into()),
+    };
+    // 304 は本文が無いのでサイズは前回値のまま
+    if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return RirAttempt::Done(Conditional::NotModified, None);
+    }
+    let resp = match resp.error_for_status() {
+        Ok(resp) => resp,
+        Err(e) => return RirAttempt::Retry(e.into()),
+    };
+    if let Some(len) = resp.content_length()
+        && len > MAX_RIR_DOWNLOAD_BYTES
+    {
+        return RirAttempt::Retry(AppError::Fetch(format!(
+            "Response too large ({} bytes > {} bytes): {}",
+            len, MAX_RIR_DOWNLOAD_BYTES, url
+        )));
+    }
+    let header_str = |name: reqwest::header::HeaderName| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+    };
+    let validators = Validators {
+        etag: header_str(reqwest::header::ETAG),
+        last_modified: header_str(reqwest::header::LAST_MODIFIED),
+    };
+
+    // 解析は CPU を使うため blocking スレッドで行い、チャンクは有界チャネルで渡す
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(PARSE_CHANNEL_CHUNKS);
+    let mut parser = RirParser::new(filter.clone());
+    let parse_task = tokio::task::spawn_blocking(move || {
+        let mut busy = Duration::ZERO;
+        while let Some(chunk) = rx.blocking_recv() {
+            let started = Instant::now();
+            let fed = parser.feed(&chunk);
+            busy += started.elapsed();
+            fed?;
+        }
+        let started = Instant::now();
+        let sets = parser.finish()?;
+        Ok::<_, AppError>((sets, busy + started.elapsed()))
+    });
+
+    let mut total: u64 = 0;
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return RirAttempt::Retry(e.into()),
+        };
+        total = total.saturating_add(chunk.len() as u64);
+        if total > MAX_RIR_DOWNLOAD_BYTES {
+            return RirAttempt::Retry(AppError::Fetch(format!(
+                "Response too large ({} bytes > {} bytes)",
+                total, MAX_RIR_DOWNLOAD_BYTES
+            )));
+        }
+        // 解析が失敗して受け手がいなければ、その結果を返す
+        if tx.send(chunk.to_vec()).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+    match parse_task.await {
+        Ok(Ok((sets, parse_time))) => RirAttempt::Done(
+            Conditional::Modified(ParsedRir {
+                sets,
+                validators,
+                parse_time,
+            }),
+            Some(total),
+        ),
+        Ok(Err(e)) => RirAttempt::Fail(parse_error_for(url, e)),
+        Err(e) => RirAttempt::Fail(e.into()),
+    }
+}
+
+/// 解析エラーに URL を付ける（終了コードが変わらないよう種類は保つ）
+fn parse_error_for(url: &str, e: AppError) -> AppError {
+    match e {
+        AppError::ParseError(msg) => AppError::ParseError(format!("{}: {}", url, msg)),
+        AppError::Utf8(e) => AppError::ParseError(format!("{}: {}", url, e)),
+        other => other,
+    }
+}
+
+/// RIR ファイルをダウンロードしながら解析する（`fetch_with_retry` のストリーミング版）
+/// 前回の検証子を送り、変更が無ければ本文をダウンロードせず `NotModified` を返す
+/// 失敗した試行の途中の結果は捨て、次の試行は最初から解析し直す
+/// 本文の解析エラーは再試行せず、そのまま返す
+#[instrument(skip_all, fields(url = %url))]
+pub async fn fetch_parsed_conditional(
+    client: &Client,
+    url: &str,
+    filter: &CountryFilter,
+    validators: &Validators,
+    retry_attempts: u32,
+    max_backoff_secs: u64,
+) -> Result<Conditional, AppError> {
+    let attempts = retry_attempts.max(1);
+    let mut last_error = None;
+    for i in 0..attempts {
+        let started = Instant::now();
+        match fetch_parsed_once(client, url, filter, validators).await {
+            RirAttempt::Done(res, bytes) => {
+                registry().record_download(url, started.elapsed(), bytes, i);
+                journal().record_source(url, true);
+                return Ok(res);
+            }
+            RirAttempt::Fail(e) => {
+                registry().record_download_failure(url, i);
+                journal().record_source(url, false);
+                return Err(e);
+            }
+            RirAttempt::Retry(e) => {
+                debug!(attempt = i + 1, attempts, error = %e, "Fetch attempt failed");
+                if i + 1 < attempts {
+                    sleep(calc_exponential_backoff_duration(i, max_backoff_secs)).await;
+                }
+                last_error = Some(e);
+            }
+        }
+    }
+
+    registry().record_download_failure(url, attempts - 1);
+    journal().record_source(url, false);
+    Err(AppError::Fetch(format!(
+        "Failed to fetch data from {} after {} attempts: {}",
+        url,
+        attempts,
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    )))
+}
+
+/// RIR ファイルをダウンロードしながら解析する（条件なし）
+/// 戻り値は部分結果と解析にかかった時間
+pub async fn fetch_parsed_with_retry(
+    client: &Client,
+    url: &str,
+    filter: &CountryFilter,
+    retry_attempts: u32,
+    max_backoff_secs: u64,
+) -> Result<(CountrySets, Duration), AppError> {
+    let validators = Validators::default();
+    match fetch_parsed_conditional(
+        client,
+        url,
+        filter,
+        &validators,
+        retry_attempts,
+        max_backoff_secs,
+    )
+    .await?
+    {
+        Conditional::Modified(parsed) => Ok((parsed.sets, parsed.parse_time)),
+        // 検証子を送っていないので通常は返らない
+        Conditional::NotModified => Err(AppError::Fetch(format!(
+            "Unexpected 304 Not Modified from {}",
+            url
+        ))),
+    }
+}
+
 /// 指数バックオフのスリープ時間を計算するヘルパー関数
 fn calc_exponential_backoff_duration(retry_count: u32, max_backoff_secs: u64) -> Duration {
     // Full Jitter
@@ -96,26 +324,158 @@ fn calc_exponential_backoff_duration(retry_count: u32, max_backoff_secs: u64) ->
     Duration::from_secs_f64(wait_secs)
 }
 
-/// JSONをサイズ上限制御の上で取得してパース
-pub async fn fetch_json_with_limit<T: serde::de::DeserializeOwned>(
+/// JSON API 問い合わせの方針（リトライ、流量制限、RIPEstat の `sourceapp` と問い合わせ期間）
+#[derive(Debug)]
+pub struct ApiPolicy {
+    pub retry_attempts: u32,
+    pub max_backoff_secs: u64,
+    pub limiter: RateLimiter,
+    /// RIPEstat Data API に付ける `sourceapp`（利用者の識別用）
+    pub sourceapp: String,
+    /// RIPEstat announced-prefixes の問い合わせ期間（`None` は RIPEstat の既定の2週間）
+    pub announced_window: Option<Duration>,
+    /// 問い合わせ期間内にこの時間以上広報されていたプレフィックスだけを使う（0 は制限なし）
+    pub min_announced: Duration,
+}
+
+impl Default for ApiPolicy {
+    fn default() -> Self {
+        Self {
+            retry_attempts: 3,
+            max_backoff_secs: 16,
+            limiter: RateLimiter::new(DEFAULT_API_RATE, DEFAULT_API_RATE),
+            sourceapp: DEFAULT_RIPESTAT_SOURCEAPP.to_string(),
+            announced_window: None,
+            min_announced: Duration::ZERO,
+        }
+    }
+}
+
+/// JSON API 1回分の試行結果
+enum JsonAttempt<T> {
+    Done(T),
+    /// 再試行してよい失敗（接続失敗、5xx、429）。Retry-After があれば待ち時間
+    Retry(AppError, Option<Duration>),
+    /// 再試行しても変わらない失敗（404 などの 4xx、サイズ超過、JSON 不正）
+    Fail(AppError),
+}
+
+/// JSONをサイズ上限制御の上で取得してパース（`policy` に従ってリトライ・流量制限）
+/// 429 / 503 の Retry-After はホスト単位で守り（上限 `MAX_RETRY_AFTER_SECS`）、
+/// それ以外の再試行可能な失敗は指数バックオフで待つ
+#[instrument(skip_all, fields(url = %url))]
+pub async fn fetch_json_with_policy<T: serde::de::DeserializeOwned>(
     client: &Client,
     url: &str,
     max_bytes: u64,
+    policy: &ApiPolicy,
 ) -> Result<T, AppError> {
-    let resp = client.get(url).send().await?.error_for_status()?;
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_default();
+    let attempts = policy.retry_attempts.max(1);
+    let mut last_error = None;
 
-    if let Some(len) = resp.content_length() {
-        if len > max_bytes {
-            return Err(AppError::Other(format!(
-                "JSON response too large ({} bytes > {} bytes): {}",
-                len, max_bytes, url
-            )));
-        }
+    for i in 0..attempts {
+        policy.limiter.acquire(&host).await;
+        let err = match fetch_json_once(client, url, max_bytes).await {
+            JsonAttempt::Done(value) => {
+                journal().record_source(url, true);
+                return Ok(value);
+            }
+            JsonAttempt::Fail(e) => {
+                journal().record_source(url, false);
+                return Err(e);
+            }
+            JsonAttempt::Retry(e, retry_after) => {
+                debug!(attempt = i + 1, attempts, error = %e, ?retry_after, "JSON fetch attempt failed");
+                if i + 1 < attempts {
+                    match retry_after {
+                        // 次の acquire が待つ（他のタスクも同じホストでは待つ）
+                        Some(wait) if !policy.limiter.is_unlimited() => {
+                            policy.limiter.defer(&host, wait)
+                        }
+                        Some(wait) => sleep(wait).await,
+                        None => {
+                            sleep(calc_exponential_backoff_duration(
+                                i,
+                                policy.max_backoff_secs,
+                            ))
+                            .await
+                        }
+                    }
+                }
+                e
+            }
+        };
+        last_error = Some(err);
+    }
+
+    journal().record_source(url, false);
+    Err(AppError::Fetch(format!(
+        "Failed to fetch data from {} after {} attempts: {}",
+        url,
+        attempts,
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    )))
+}
+
+async fn fetch_json_once<T: serde::de::DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    max_bytes: u64,
+) -> JsonAttempt<T> {
+    let resp = match client.get(url).send().await {
+        Ok(resp) => resp,
+        Err(e) => return JsonAttempt::Retry(e.into(), None),
+    };
+    let status = resp.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let err = AppError::Fetch(format!("HTTP {} from {}", status, url));
+        return JsonAttempt::Retry(err, retry_after);
     }
+    let resp = match resp.error_for_status() {
+        Ok(resp) => resp,
+        Err(e) => return JsonAttempt::Fail(e.into()),
+    };
 
-    // ボディを上限制御で読み込む
-    let text = read_body_with_limit_to_string(resp, max_bytes).await?;
-    let value = serde_json::from_str::<T>(&text)
-        .map_err(|e| AppError::ParseError(format!("JSON parse error: {e}")))?;
-    Ok(value)
+    if let Some(len) = resp.content_length()
+        && len > max_bytes
+    {
+        return JsonAttempt::Fail(AppError::Fetch(format!(
+            "JSON response too large ({} bytes > {} bytes): {}",
+            len, max_bytes, url
+        )));
+    }
+
+    // ボディを上限制御で読み込む（途中の切断は再試行）
+    let text = match read_body_with_limit_to_string(resp, max_bytes).await {
+        Ok(text) => text,
+        Err(e @ AppError::Network(_)) => return JsonAttempt::Retry(e, None),
+        Err(e) => return JsonAttempt::Fail(e),
+    };
+    match serde_json::from_str::<T>(&text) {
+        Ok(value) => JsonAttempt::Done(value),
+        Err(e) => JsonAttempt::Fail(AppError::ParseError(format!("JSON parse error: {e}"))),
+    }
+}
+
+/// Retry-After ヘッダ（秒数または HTTP-date）を待ち時間に変換する（上限 `MAX_RETRY_AFTER_SECS`）
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let secs = match value.parse::<u64>() {
+        Ok(secs) => secs,
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            let secs = (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).num_seconds();
+            secs.max(0) as u64
+        }
+    };
+    Some(Duration::from_secs(secs.min(MAX_RETRY_AFTER_SECS)))
 }
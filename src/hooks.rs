@@ -0,0 +1,85 @@
+//! 出力更新後のフックコマンド
+//!
+//! 書き出したファイルのいずれかの内容が変わった場合のみ、設定されたコマンドを
+//! 順番にシェル経由で実行する（例: `nft -f ...`, `ipset restore < ...`）。
+//! コマンドには次の環境変数を渡す。
+//! - `FIRE_SCOPE_CHANGED_FILES`: 変更されたファイルのパス（改行区切り）
+//! - `FIRE_SCOPE_CHANGED_COUNT`: 変更されたファイル数
+//! - `FIRE_SCOPE_WRITTEN_COUNT`: 書き出したファイル数（未変更を含む）
+//! - `FIRE_SCOPE_PREFIX_COUNT`: 変更されたファイルのCIDR数の合計
+//! - `FIRE_SCOPE_PROFILE`: プロファイル名（プロファイルのフックのみ）
+
+use crate::error::AppError;
+use crate::output::WrittenFile;
+use tokio::process::Command;
+use tracing::{debug, info};
+
+/// フックを実行する
+/// 変更がなければ何もしない。失敗したコマンドがあればそこで中断してエラーを返す
+pub async fn run_hooks(
+    hooks: &[String],
+    written: &[WrittenFile],
+    profile: Option<&str>,
+) -> Result<(), AppError> {
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    let changed: Vec<&WrittenFile> = written.iter().filter(|f| f.changed).collect();
+    if changed.is_empty() {
+        debug!(
+            written = written.len(),
+            hooks = hooks.len(),
+            "No output changed, skipping hooks"
+        );
+        return Ok(());
+    }
+
+    let changed_files = changed
+        .iter()
+        .map(|f| f.path.display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let prefix_count: usize = changed.iter().map(|f| f.entries).sum();
+
+    for hook in hooks {
+        info!(hook = %hook, changed = changed.len(), "Running post-update hook");
+        let mut cmd = shell_command(hook);
+        cmd.env("FIRE_SCOPE_CHANGED_FILES", &changed_files)
+            .env("FIRE_SCOPE_CHANGED_COUNT", changed.len().to_string())
+            .env("FIRE_SCOPE_WRITTEN_COUNT", written.len().to_string())
+            .env("FIRE_SCOPE_PREFIX_COUNT", prefix_count.to_string());
+        if let Some(name) = profile {
+            cmd.env("FIRE_SCOPE_PROFILE", name);
+        }
+
+        let status = cmd
+            .status()
+            .await
+            .map_err(|e| AppError::Hook(format!("`{}` could not be started: {}", hook, e)))?;
+        if !status.success() {
+            let code = status
+                .code()
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "signal".into());
+            return Err(AppError::Hook(format!(
+                "`{}` exited with status {}",
+                hook, code
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell_command(hook: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(hook);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell_command(hook: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(hook);
+    cmd
+}
@@ -0,0 +1,440 @@
+//! 区間（開始・終了アドレスの組）による IP アドレス集合
+//!
+//! `BTreeSet<IpNet>` の代わりに、ソート済みで互いに重ならず隣接もしない閉区間の列として持つ。
+//! - RIR の割り当て（開始アドレスと個数）は CIDR に分けずにそのまま区間として積み、最後に1度だけ正規化する
+//! - 和・積・差は区間列の線形マージで求める
+//! - CIDR へは書き出す直前に変換する（区間を覆う最小の CIDR 列。`IpNet::aggregate` と同じ結果）
+
+use crate::common::IpFamily;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// 区間の端点（IPv4 は u32、IPv6 は u128）
+pub trait Bound: Copy + Ord + Debug {
+    /// アドレスのビット数
+    const BITS: u32;
+    const MAX: Self;
+
+    fn next(self) -> Self;
+    fn prev(self) -> Self;
+    /// 末尾の0ビットの数
+    fn trailing_zeros(self) -> u32;
+    /// 下位 `bits` ビットがすべて1の値（`bits` は `BITS` 以下）
+    fn low_mask(bits: u32) -> Self;
+    fn sub(self, other: Self) -> Self;
+    fn add(self, other: Self) -> Self;
+    fn ilog2(self) -> u32;
+    fn to_u128(self) -> u128;
+}
+
+macro_rules! impl_bound {
+    ($t:ty) => {
+        impl Bound for $t {
+            const BITS: u32 = <$t>::BITS;
+            const MAX: Self = <$t>::MAX;
+
+            fn next(self) -> Self {
+                self + 1
+            }
+            fn prev(self) -> Self {
+                self - 1
+            }
+            fn trailing_zeros(self) -> u32 {
+                <$t>::trailing_zeros(self)
+            }
+            fn low_mask(bits: u32) -> Self {
+                if bits >= Self::BITS {
+                    Self::MAX
+                } else {
+                    (1 << bits) - 1
+                }
+            }
+            fn sub(self, other: Self) -> Self {
+                self - other
+            }
+            fn add(self, other: Self) -> Self {
+                self + other
+            }
+            fn ilog2(self) -> u32 {
+                <$t>::ilog2(self)
+            }
+            fn to_u128(self) -> u128 {
+                self as u128
+            }
+        }
+    };
+}
+
+impl_bound!(u32);
+impl_bound!(u128);
+
+/// ソート済み・非重複・非隣接の閉区間の列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ranges<T> {
+    ranges: Vec<(T, T)>,
+}
+
+impl<T: Bound> Default for Ranges<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Bound> Ranges<T> {
+    pub fn new() -> Self {
+        Self { ranges: Vec::new() }
+    }
+
+    /// 任意の順序・重なりの区間から作る（`start > end` の区間は無視する）
+    pub fn from_unsorted(mut ranges: Vec<(T, T)>) -> Self {
+        ranges.retain(|(s, e)| s <= e);
+        ranges.sort_unstable();
+        let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+        for (s, e) in ranges {
+            match merged.last_mut() {
+                // 重なる、または隣接する区間はまとめる
+                Some(last) if last.1 == T::MAX || s <= last.1.next() => {
+                    last.1 = last.1.max(e);
+                }
+                _ => merged.push((s, e)),
+            }
+        }
+        merged.shrink_to_fit();
+        Self { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(T, T)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// 区間の数
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// 和集合
+    pub fn union(&self, other: &Self) -> Self {
+        if other.is_empty() {
+            return self.clone();
+        }
+        if self.is_empty() {
+            return other.clone();
+        }
+        let mut all = Vec::with_capacity(self.len() + other.len());
+        all.extend_from_slice(&self.ranges);
+        all.extend_from_slice(&other.ranges);
+        Self::from_unsorted(all)
+    }
+
+    /// 積集合
+    pub fn intersection(&self, other: &Self) -> Self {
+        let (a, b) = (&self.ranges, &other.ranges);
+        let mut out = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            let s = a[i].0.max(b[j].0);
+            let e = a[i].1.min(b[j].1);
+            if s <= e {
+                out.push((s, e));
+            }
+            if a[i].1 < b[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        // 入力が正規化済みなので、結果も重ならず隣接しない
+        Self { ranges: out }
+    }
+
+    /// 差集合（`self` から `other` を除く）
+    pub fn difference(&self, other: &Self) -> Self {
+        let exclude = &other.ranges;
+        let mut out = Vec::new();
+        let mut j = 0;
+        for &(b_s, b_e) in &self.ranges {
+            // 手前で終わる除外区間は読み飛ばす
+            while j < exclude.len() && exclude[j].1 < b_s {
+                j += 1;
+            }
+            let mut cur = b_s;
+            let mut exhausted = false;
+            let mut k = j;
+            while k < exclude.len() && exclude[k].0 <= b_e {
+                let (x_s, x_e) = exclude[k];
+                if x_s > cur {
+                    out.push((cur, x_s.prev()));
+                }
+                if x_e >= b_e {
+                    exhausted = true;
+                    break;
+                }
+                cur = cur.max(x_e.next());
+                k += 1;
+            }
+            if !exhausted {
+                out.push((cur, b_e));
+            }
+        }
+        Self { ranges: out }
+    }
+
+    /// 区間 `start..=end` と重なる部分があるか
+    pub fn overlaps(&self, start: T, end: T) -> bool {
+        // end 以下で始まる最後の区間が start 以降まで続いていれば重なる
+        let idx = self.ranges.partition_point(|(s, _)| *s <= end);
+        idx > 0 && self.ranges[idx - 1].1 >= start
+    }
+
+    /// アドレス数（上限で飽和）
+    pub fn address_count(&self) -> u128 {
+        self.ranges
+            .iter()
+            .map(|&(s, e)| e.sub(s).to_u128().saturating_add(1))
+            .fold(0, u128::saturating_add)
+    }
+
+    /// 区間を覆う最小の CIDR 列（開始アドレス, プレフィックス長）
+    pub fn cidrs(&self) -> Vec<(T, u8)> {
+        let mut out = Vec::new();
+        for &(s, e) in &self.ranges {
+            push_cidrs(s, e, &mut out);
+        }
+        out
+    }
+
+    /// `cidrs()` の要素数（列は作らない）
+    pub fn cidr_len(&self) -> usize {
+        self.ranges.iter().map(|&(s, e)| count_cidrs(s, e)).sum()
+    }
+
+    /// アドレスを含む区間の中で、そのアドレスを含む CIDR
+    pub fn cidr_containing(&self, addr: T) -> Option<(T, u8)> {
+        let idx = self.ranges.partition_point(|(s, _)| *s <= addr);
+        let (s, e) = *self.ranges.get(idx.checked_sub(1)?)?;
+        if e < addr {
+            return None;
+        }
+        let mut cidrs = Vec::new();
+        push_cidrs(s, e, &mut cidrs);
+        cidrs
+            .into_iter()
+            .find(|&(start, len)| addr <= start.add(T::low_mask(T::BITS - len as u32)))
+    }
+}
+
+/// 閉区間 `s..=e` を覆う最小の CIDR を順に渡す
+fn for_each_cidr<T: Bound>(s: T, e: T, mut f: impl FnMut(T, u8)) {
+    let mut cur = s;
+    loop {
+        let diff = e.sub(cur);
+        // 残りのアドレス数（diff + 1）に収まるビット数
+        let span = if diff == T::MAX {
+            T::BITS
+        } else {
+            diff.next().ilog2()
+        };
+        let bits = span.min(cur.trailing_zeros());
+        f(cur, (T::BITS - bits) as u8);
+        let last = cur.add(T::low_mask(bits));
+        if last >= e {
+            break;
+        }
+        cur = last.next();
+    }
+}
+
+fn push_cidrs<T: Bound>(s: T, e: T, out: &mut Vec<(T, u8)>) {
+    for_each_cidr(s, e, |start, len| out.push((start, len)));
+}
+
+fn count_cidrs<T: Bound>(s: T, e: T) -> usize {
+    let mut n = 0;
+    for_each_cidr(s, e, |_, _| n += 1);
+    n
+}
+
+fn distinct_cidrs<T: Bound>(ranges: &[(T, T)]) -> usize {
+    let mut cidrs = Vec::with_capacity(ranges.len());
+    for &(s, e) in ranges {
+        push_cidrs(s, e, &mut cidrs);
+    }
+    cidrs.sort_unstable();
+    cidrs.dedup();
+    cidrs.len()
+}
+
+/// IPv4 と IPv6 の区間集合
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpRangeSet {
+    pub v4: Ranges<u32>,
+    pub v6: Ranges<u128>,
+}
+
+impl IpRangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_nets<'a>(nets: impl IntoIterator<Item = &'a IpNet>) -> Self {
+        let mut builder = IpRangeBuilder::new();
+        for net in nets {
+            builder.push_net(net);
+        }
+        builder.build()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            v4: self.v4.union(&other.v4),
+            v6: self.v6.union(&other.v6),
+        }
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self {
+            v4: self.v4.intersection(&other.v4),
+            v6: self.v6.intersection(&other.v6),
+        }
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        Self {
+            v4: self.v4.difference(&other.v4),
+            v6: self.v6.difference(&other.v6),
+        }
+    }
+
+    /// CIDR と重なる部分があるか
+    pub fn overlaps_net(&self, net: &IpNet) -> bool {
+        match net {
+            IpNet::V4(n) => self
+                .v4
+                .overlaps(u32::from(n.network()), u32::from(n.broadcast())),
+            IpNet::V6(n) => self
+                .v6
+                .overlaps(u128::from(n.network()), u128::from(n.broadcast())),
+        }
+    }
+
+    /// 一方のファミリだけの集合
+    pub fn only(&self, family: IpFamily) -> Self {
+        match family {
+            IpFamily::V4 => Self {
+                v4: self.v4.clone(),
+                v6: Ranges::new(),
+            },
+            IpFamily::V6 => Self {
+                v4: Ranges::new(),
+                v6: self.v6.clone(),
+            },
+        }
+    }
+
+    /// アドレス数（上限で飽和）
+    pub fn address_count(&self) -> u128 {
+        self.v4
+            .address_count()
+            .saturating_add(self.v6.address_count())
+    }
+
+    /// 最小 CIDR に変換したときの数
+    pub fn cidr_len(&self) -> usize {
+        self.v4.cidr_len() + self.v6.cidr_len()
+    }
+
+    /// アドレスを含む最小 CIDR（区間を CIDR に分けたうちの1つ）
+    pub fn net_containing(&self, addr: IpAddr) -> Option<IpNet> {
+        match addr {
+            IpAddr::V4(a) => {
+                let (start, len) = self.v4.cidr_containing(u32::from(a))?;
+                Ipv4Net::new(Ipv4Addr::from(start), len).ok().map(IpNet::V4)
+            }
+            IpAddr::V6(a) => {
+                let (start, len) = self.v6.cidr_containing(u128::from(a))?;
+                Ipv6Net::new(Ipv6Addr::from(start), len).ok().map(IpNet::V6)
+            }
+        }
+    }
+
+    /// IPv4 の最小 CIDR 列
+    pub fn v4_nets(&self) -> Vec<IpNet> {
+        self.v4
+            .cidrs()
+            .into_iter()
+            .filter_map(|(start, len)| Ipv4Net::new(Ipv4Addr::from(start), len).ok())
+            .map(IpNet::V4)
+            .collect()
+    }
+
+    /// IPv6 の最小 CIDR 列
+    pub fn v6_nets(&self) -> Vec<IpNet> {
+        self.v6
+            .cidrs()
+            .into_iter()
+            .filter_map(|(start, len)| Ipv6Net::new(Ipv6Addr::from(start), len).ok())
+            .map(IpNet::V6)
+            .collect()
+    }
+
+    /// 最小 CIDR の集合（IPv4 が先）
+    pub fn to_set(&self) -> BTreeSet<IpNet> {
+        self.v4_nets().into_iter().chain(self.v6_nets()).collect()
+    }
+}
+
+/// 区間を正規化せずに積み、最後にまとめて `IpRangeSet` にする
+#[derive(Debug, Clone, Default)]
+pub struct IpRangeBuilder {
+    v4: Vec<(u32, u32)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl IpRangeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_net(&mut self, net: &IpNet) {
+        match net {
+            IpNet::V4(n) => self.push_v4(u32::from(n.network()), u32::from(n.broadcast())),
+            IpNet::V6(n) => self.push_v6(u128::from(n.network()), u128::from(n.broadcast())),
+        }
+    }
+
+    pub fn push_v4(&mut self, start: u32, end: u32) {
+        self.v4.push((start, end));
+    }
+
+    pub fn push_v6(&mut self, start: u128, end: u128) {
+        self.v6.push((start, end));
+    }
+
+    /// 別のビルダーの区間を加える
+    pub fn append(&mut self, other: &mut IpRangeBuilder) {
+        self.v4.append(&mut other.v4);
+        self.v6.append(&mut other.v6);
+    }
+
+    /// 各区間を CIDR に分けたときの、重複を除いた CIDR の数（IPv4, IPv6）
+    pub fn distinct_cidr_len(&self) -> (usize, usize) {
+        (distinct_cidrs(&self.v4), distinct_cidrs(&self.v6))
+    }
+
+    pub fn build(self) -> IpRangeSet {
+        IpRangeSet {
+            v4: Ranges::from_unsorted(self.v4),
+            v6: Ranges::from_unsorted(self.v6),
+        }
+    }
+}
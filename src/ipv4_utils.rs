@@ -74,6 +74,12 @@ pub fn ipv4_summarize_range(start: u64, end: u64) -> Vec<IpNet> {
 
 /// RIR拡張フォーマットのIPv4行（start, value）をCIDR列へ展開
 pub fn parse_ipv4_range_to_cidrs(start_str: &str, value_str: &str) -> Result<Vec<IpNet>, AppError> {
+    let (start, end) = parse_ipv4_range(start_str, value_str)?;
+    Ok(ipv4_summarize_range(start as u64, end as u64))
+}
+
+/// RIR拡張フォーマットのIPv4行（start, value）を閉区間 [start, end] へ変換
+pub fn parse_ipv4_range(start_str: &str, value_str: &str) -> Result<(u32, u32), AppError> {
     let start_addr = start_str.parse::<Ipv4Addr>()?;
     let width_u64 = value_str.parse::<u64>()?;
 
@@ -93,5 +99,5 @@ pub fn parse_ipv4_range_to_cidrs(start_str: &str, value_str: &str) -> Result<Vec
         ));
     }
 
-    Ok(ipv4_summarize_range(start_num, end_num_u64))
+    Ok((start_num as u32, end_num_u64 as u32))
 }
@@ -0,0 +1,623 @@
+//! IRR（RPSL）のデータ
+//!
+//! - AS-SET 展開: `as-set` オブジェクトの `members` / `mp-members` を再帰的にたどり、
+//!   メンバーの AS番号を集める。同じ AS-SET を2度展開しないことで循環を検出し、
+//!   深さの上限を超えた AS-SET は展開しない。`mbrs-by-ref`（aut-num 側の `member-of`）は扱わない。
+//! - 経路オブジェクト: `route` / `route6` を `origin:` ごとに集め、AS のプレフィックスの
+//!   取得元（`--as-source irr`）や BGP との比較（`irr-compare`）に使う。
+//!
+//! 取得元は IRR データベースのダンプ（`radb.db.gz` などの RPSL テキスト、gzip / bzip2 可）と、
+//! 任意で IRR の whois サーバ（TCP 43）。ダンプに無いものだけを whois に問い合わせる。
+
+use crate::common::{Asn, IpFamily};
+use crate::error::AppError;
+use crate::fetch::ApiPolicy;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// 既定の展開の深さ上限
+pub const DEFAULT_AS_SET_MAX_DEPTH: usize = 8;
+
+/// whois の応答サイズ上限（バイト）
+const MAX_WHOIS_RESPONSE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// whois の接続・応答のタイムアウト
+const WHOIS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// AS-SET 展開の設定
+#[derive(Debug, Clone)]
+pub struct IrrOptions {
+    /// RPSL ダンプファイル（同名の AS-SET はメンバーを合算）
+    pub dumps: Vec<PathBuf>,
+    /// IRR whois サーバ（`host` または `host:port`）。ダンプに無い AS-SET を問い合わせる
+    pub whois: Option<String>,
+    /// 展開の深さ上限（指定した AS-SET 自身が 0）
+    pub max_depth: usize,
+    /// whois の問い合わせに使う流量制限（JSON API と同じホストごとの制限を共有する）
+    pub api: Arc<ApiPolicy>,
+}
+
+impl Default for IrrOptions {
+    fn default() -> Self {
+        Self {
+            dumps: Vec::new(),
+            whois: None,
+            max_depth: DEFAULT_AS_SET_MAX_DEPTH,
+            api: Arc::new(ApiPolicy::default()),
+        }
+    }
+}
+
+/// `members` の1要素
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Member {
+    Asn(u32),
+    Set(String),
+}
+
+/// `members` の1要素を解釈する（`AS2497`, `AS1.10`（asdot）, `AS-FOO`, `AS2497:AS-FOO`）
+/// AS番号でも AS-SET 名でもないもの（`ANY` など）は `None`
+pub fn parse_member(token: &str) -> Option<Member> {
+    let upper = token.trim().to_ascii_uppercase();
+    if upper.is_empty() {
+        return None;
+    }
+    if let Some(num) = upper.strip_prefix("AS")
+        && !num.is_empty()
+        && num.chars().all(|c| c.is_ascii_digit() || c == '.')
+    {
+        return num.parse::<Asn>().ok().map(|asn| Member::Asn(asn.0));
+    }
+    // 階層名は構成要素のどれかが AS- で始まれば AS-SET
+    if upper.split(':').any(|part| part.starts_with("AS-")) {
+        return Some(Member::Set(upper));
+    }
+    None
+}
+
+/// 起源AS → (IPv4, IPv6) の route / route6 プレフィックス
+pub type RouteMap = HashMap<u32, (BTreeSet<IpNet>, BTreeSet<IpNet>)>;
+
+/// RPSL テキストをオブジェクト単位で読み、クラス（先頭の属性名）が `classes` のいずれかなら
+/// 属性（小文字の属性名, 値）の一覧を `f` に渡す
+/// 継続行は値に空白で連結し、`#` 以降のコメントは除く
+fn read_rpsl_objects<R: BufRead>(
+    reader: R,
+    classes: &[&str],
+    mut f: impl FnMut(&[(String, String)]),
+) -> Result<(), AppError> {
+    let mut attrs: Vec<(String, String)> = Vec::new();
+    // 対象外のクラスのオブジェクトは空行まで読み飛ばす
+    let mut skipping = false;
+
+    for line in reader.split(b'\n') {
+        let line = line?;
+        // ダンプには UTF-8 以外の文字も含まれるため、失われても問題ない範囲で変換
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r');
+
+        if line.trim().is_empty() {
+            if !attrs.is_empty() {
+                f(&attrs);
+                attrs.clear();
+            }
+            skipping = false;
+            continue;
+        }
+        if skipping || line.starts_with('#') || line.starts_with('%') {
+            continue;
+        }
+
+        if line.starts_with([' ', '\t', '+']) {
+            // 継続行
+            if let Some((_, value)) = attrs.last_mut() {
+                value.push(' ');
+                value.push_str(strip_comment(&line[1..]));
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        if attrs.is_empty() && !classes.contains(&key.as_str()) {
+            skipping = true;
+            continue;
+        }
+        attrs.push((key, strip_comment(value).to_string()));
+    }
+    if !attrs.is_empty() {
+        f(&attrs);
+    }
+    Ok(())
+}
+
+fn strip_comment(value: &str) -> &str {
+    value.split('#').next().unwrap_or("")
+}
+
+/// RPSL テキストから `as-set` オブジェクトを読み、名前 → メンバー（生の文字列）を追加する
+/// 同名のオブジェクトが複数あればメンバーを合算する
+pub fn read_rpsl_as_sets<R: BufRead>(
+    reader: R,
+    sets: &mut HashMap<String, Vec<String>>,
+) -> Result<(), AppError> {
+    read_rpsl_objects(reader, &["as-set"], |attrs| {
+        let name = attrs[0].1.trim().to_ascii_uppercase();
+        let members = sets.entry(name).or_default();
+        for (key, value) in &attrs[1..] {
+            if key == "members" || key == "mp-members" {
+                members.extend(
+                    value
+                        .split([',', ' ', '\t'])
+                        .filter(|t| !t.is_empty())
+                        .map(|t| t.to_ascii_uppercase()),
+                );
+            }
+        }
+    })
+}
+
+/// RPSL テキストから `route` / `route6` オブジェクトを読み、`origin:` ごとにプレフィックスを追加する
+/// `origins` を指定した場合はその AS のものだけを集める（大きなダンプでのメモリ節約）
+pub fn read_rpsl_routes<R: BufRead>(
+    reader: R,
+    origins: Option<&HashSet<u32>>,
+    routes: &mut RouteMap,
+) -> Result<(), AppError> {
+    let classes = [IpFamily::V4.route_key(), IpFamily::V6.route_key()];
+    read_rpsl_objects(reader, &classes, |attrs| {
+        let family = if attrs[0].0 == IpFamily::V4.route_key() {
+            IpFamily::V4
+        } else {
+            IpFamily::V6
+        };
+        let Ok(net) = IpNet::from_str(attrs[0].1.trim()) else {
+            debug!(route = %attrs[0].1.trim(), "Ignoring malformed route object");
+            return;
+        };
+        if matches!(net, IpNet::V4(_)) != (family == IpFamily::V4) {
+            debug!(route = %net, "Ignoring route object of the wrong address family");
+            return;
+        }
+        let origin = attrs[1..]
+            .iter()
+            .find(|(key, _)| key == "origin")
+            .and_then(|(_, value)| parse_member(value));
+        let Some(Member::Asn(origin)) = origin else {
+            return;
+        };
+        if origins.is_some_and(|wanted| !wanted.contains(&origin)) {
+            return;
+        }
+        let (v4, v6) = routes.entry(origin).or_default();
+        match family {
+            IpFamily::V4 => v4.insert(net.trunc()),
+            IpFamily::V6 => v6.insert(net.trunc()),
+        };
+    })
+}
+
+/// ダンプファイルを開く（先頭が gzip / bzip2 のマジックなら展開しながら読む）
+/// `what` はエラーメッセージ用の種類（`IRR dump` など）
+pub(crate) fn open_dump(path: &Path, what: &str) -> Result<Box<dyn BufRead + Send>, AppError> {
+    let open = || {
+        File::open(path).map_err(|e| {
+            AppError::InvalidInput(format!("Cannot read {} {}: {}", what, path.display(), e))
+        })
+    };
+    let mut magic = [0u8; 3];
+    let magic = match open()?.read_exact(&mut magic) {
+        Ok(()) => magic,
+        Err(_) => [0u8; 3],
+    };
+    let file = open()?;
+    Ok(match magic {
+        [0x1f, 0x8b, _] => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        [b'B', b'Z', b'h'] => Box::new(BufReader::new(MultiBzDecoder::new(file))),
+        _ => Box::new(BufReader::new(file)),
+    })
+}
+
+/// RPSL ダンプファイルから AS-SET を読む
+pub fn read_rpsl_dump(
+    path: &Path,
+    sets: &mut HashMap<String, Vec<String>>,
+) -> Result<(), AppError> {
+    read_rpsl_as_sets(open_dump(path, "IRR dump")?, sets)
+}
+
+/// RPSL ダンプファイルから route / route6 を読む
+pub fn read_route_dump(
+    path: &Path,
+    origins: Option<&HashSet<u32>>,
+    routes: &mut RouteMap,
+) -> Result<(), AppError> {
+    read_rpsl_routes(open_dump(path, "IRR dump")?, origins, routes)
+}
+
+/// AS-SET 1件分の展開結果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AsSetExpansion {
+    pub name: String,
+    pub asns: BTreeSet<u32>,
+    /// 展開した AS-SET の数（自身を含む）
+    pub sets: usize,
+    /// 循環していたメンバー（`親 -> 子`）
+    pub loops: Vec<String>,
+    /// どの取得元にも無かった AS-SET
+    pub missing: Vec<String>,
+    /// 深さ上限のため展開しなかった AS-SET
+    pub truncated: Vec<String>,
+}
+
+impl AsSetExpansion {
+    /// 1行の集計
+    pub fn summary(&self) -> String {
+        let mut line = format!(
+            "{}: {} AS numbers from {} AS-SETs",
+            self.name,
+            self.asns.len(),
+            self.sets
+        );
+        for (label, items) in [
+            ("loops", &self.loops),
+            ("missing", &self.missing),
+            ("depth limit", &self.truncated),
+        ] {
+            if !items.is_empty() {
+                line.push_str(&format!("; {}: {}", label, items.join(", ")));
+            }
+        }
+        line
+    }
+}
+
+/// ダンプと whois から AS-SET を引いて展開する
+/// whois の応答は実行中キャッシュする
+#[derive(Debug, Default)]
+pub struct AsSetResolver {
+    sets: HashMap<String, Vec<String>>,
+    whois: Option<String>,
+    max_depth: usize,
+    whois_cache: HashMap<String, Option<Vec<String>>>,
+    api: Arc<ApiPolicy>,
+}
+
+impl AsSetResolver {
+    /// ダンプファイルを読み込む（大きいファイルもあるためブロッキングスレッドで読む）
+    pub async fn load(opts: &IrrOptions) -> Result<Self, AppError> {
+        let dumps = opts.dumps.clone();
+        let sets = tokio::task::spawn_blocking(move || {
+            let mut sets = HashMap::new();
+            for path in &dumps {
+                read_rpsl_dump(path, &mut sets)?;
+                debug!(path = %path.display(), sets = sets.len(), "Loaded IRR dump");
+            }
+            Ok::<_, AppError>(sets)
+        })
+        .await??;
+        Ok(Self::from_sets(sets, opts))
+    }
+
+    /// 読み込み済みの AS-SET から作る
+    pub fn from_sets(sets: HashMap<String, Vec<String>>, opts: &IrrOptions) -> Self {
+        Self {
+            sets,
+            whois: opts.whois.clone(),
+            max_depth: opts.max_depth,
+            whois_cache: HashMap::new(),
+            api: opts.api.clone(),
+        }
+    }
+
+    /// AS-SET のメンバー（生の文字列）。どこにも無ければ `None`
+    async fn members(&mut self, name: &str) -> Result<Option<Vec<String>>, AppError> {
+        if let Some(members) = self.sets.get(name) {
+            return Ok(Some(members.clone()));
+        }
+        let Some(server) = self.whois.clone() else {
+            return Ok(None);
+        };
+        if let Some(cached) = self.whois_cache.get(name) {
+            return Ok(cached.clone());
+        }
+        let text = whois_query(&server, name, &self.api).await?;
+        let mut found = HashMap::new();
+        read_rpsl_as_sets(text.as_bytes(), &mut found)?;
+        let members = found.remove(name);
+        self.whois_cache.insert(name.to_string(), members.clone());
+        Ok(members)
+    }
+
+    /// AS-SET を再帰的に展開する
+    /// 指定した AS-SET 自体が見つからない場合はエラー
+    pub async fn expand(&mut self, root: &str) -> Result<AsSetExpansion, AppError> {
+        let root = root.trim().to_ascii_uppercase();
+        let mut result = AsSetExpansion {
+            name: root.clone(),
+            ..AsSetExpansion::default()
+        };
+        let mut visited: BTreeSet<String> = BTreeSet::new();
+        // (AS-SET 名, 深さ, 根からの経路)
+        let mut stack: Vec<(String, usize, Vec<String>)> = vec![(root.clone(), 0, Vec::new())];
+
+        while let Some((name, depth, path)) = stack.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let Some(members) = self.members(&name).await? else {
+                if name == root {
+                    return Err(AppError::InvalidInput(format!(
+                        "AS-SET {} was not found in the IRR sources",
+                        root
+                    )));
+                }
+                warn!(as_set = %name, "AS-SET not found in the IRR sources");
+                result.missing.push(name);
+                continue;
+            };
+            result.sets += 1;
+
+            let mut child_path = path.clone();
+            child_path.push(name.clone());
+            for token in &members {
+                match parse_member(token) {
+                    Some(Member::Asn(asn)) => {
+                        result.asns.insert(asn);
+                    }
+                    Some(Member::Set(child)) => {
+                        if child_path.contains(&child) {
+                            warn!(as_set = %name, member = %child, "AS-SET loop detected");
+                            result.loops.push(format!("{} -> {}", name, child));
+                        } else if visited.contains(&child) {
+                            // 別経路で展開済み
+                        } else if depth + 1 > self.max_depth {
+                            warn!(as_set = %child, max_depth = self.max_depth, "AS-SET depth limit reached");
+                            if !result.truncated.contains(&child) {
+                                result.truncated.push(child);
+                            }
+                        } else {
+                            stack.push((child, depth + 1, child_path.clone()));
+                        }
+                    }
+                    None => debug!(as_set = %name, member = %token, "Ignoring AS-SET member"),
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// IRR whois サーバへ1件問い合わせ（`AS-FOO`, `-i origin AS2497` など）、応答（RPSL テキスト）を返す
+async fn whois_query(server: &str, query: &str, policy: &ApiPolicy) -> Result<String, AppError> {
+    let addr = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:43", server)
+    };
+    let host = addr
+        .rsplit_once(':')
+        .map(|(h, _)| h)
+        .unwrap_or(&addr)
+        .to_string();
+    policy.limiter.acquire(&host).await;
+
+    let query = async {
+        let mut stream = TcpStream::connect(&addr).await?;
+        stream
+            .write_all(format!("{}\r\n", query).as_bytes())
+            .await?;
+        let mut buf = Vec::new();
+        (&mut stream)
+            .take(MAX_WHOIS_RESPONSE_BYTES + 1)
+            .read_to_end(&mut buf)
+            .await?;
+        Ok::<_, std::io::Error>(buf)
+    };
+    let buf = tokio::time::timeout(WHOIS_TIMEOUT, query)
+        .await
+        .map_err(|_| AppError::Fetch(format!("whois query to {} timed out", addr)))?
+        .map_err(|e| AppError::Fetch(format!("whois query to {} failed: {}", addr, e)))?;
+    if buf.len() as u64 > MAX_WHOIS_RESPONSE_BYTES {
+        return Err(AppError::Fetch(format!(
+            "whois response from {} is too large (> {} bytes)",
+            addr, MAX_WHOIS_RESPONSE_BYTES
+        )));
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// 複数の AS-SET を展開する（同じ名前は1回だけ）
+/// AS-SET ごとの件数をログに出す（ループ、見つからない AS-SET、深さ上限は展開中に warn で出している）
+pub async fn expand_as_sets(
+    names: &[String],
+    opts: &IrrOptions,
+) -> Result<BTreeMap<String, AsSetExpansion>, AppError> {
+    let mut expanded = BTreeMap::new();
+    if names.is_empty() {
+        return Ok(expanded);
+    }
+    if opts.dumps.is_empty() && opts.whois.is_none() {
+        return Err(AppError::InvalidInput(
+            "AS-SET expansion requires --irr-dump <FILE> or --irr-whois <HOST>".into(),
+        ));
+    }
+
+    let mut resolver = AsSetResolver::load(opts).await?;
+    for name in names {
+        let name = name.trim().to_ascii_uppercase();
+        if expanded.contains_key(&name) {
+            continue;
+        }
+        let expansion = resolver.expand(&name).await?;
+        info!(
+            as_set = %expansion.name,
+            asns = expansion.asns.len(),
+            sets = expansion.sets,
+            loops = expansion.loops.len(),
+            missing = expansion.missing.len(),
+            depth_limited = expansion.truncated.len(),
+            "AS-SET expanded"
+        );
+        expanded.insert(name, expansion);
+    }
+    Ok(expanded)
+}
+
+/// AS番号と AS-SET を合わせた AS番号の一覧
+/// 指定した AS番号を指定順で先に、続けて AS-SET のメンバーを昇順で並べる（重複は除く）
+pub async fn resolve_as_numbers(
+    as_numbers: &[Asn],
+    as_sets: &[String],
+    opts: &IrrOptions,
+) -> Result<Vec<Asn>, AppError> {
+    if as_sets.is_empty() {
+        return Ok(as_numbers.to_vec());
+    }
+    let expanded = expand_as_sets(as_sets, opts).await?;
+    let mut seen = BTreeSet::new();
+    let mut all: Vec<Asn> = as_numbers
+        .iter()
+        .copied()
+        .filter(|n| seen.insert(*n))
+        .collect();
+    for expansion in expanded.values() {
+        all.extend(
+            expansion
+                .asns
+                .iter()
+                .map(|&n| Asn(n))
+                .filter(|n| seen.insert(*n)),
+        );
+    }
+    Ok(all)
+}
+
+/// IRR の route / route6 から起源AS ごとのプレフィックスを引く
+/// ダンプに1件も無い AS は whois（`-i origin`）に問い合わせる
+#[derive(Debug, Default)]
+pub struct IrrRoutes {
+    routes: RouteMap,
+    whois: Option<String>,
+    api: Arc<ApiPolicy>,
+}
+
+impl IrrRoutes {
+    /// `origins` の AS の route / route6 をダンプから読み込む
+    pub async fn load(opts: &IrrOptions, origins: &[u32]) -> Result<Self, AppError> {
+        if opts.dumps.is_empty() && opts.whois.is_none() {
+            return Err(AppError::InvalidInput(
+                "The IRR source requires --irr-dump <FILE> or --irr-whois <HOST>".into(),
+            ));
+        }
+        let dumps = opts.dumps.clone();
+        let wanted: HashSet<u32> = origins.iter().copied().collect();
+        let routes = tokio::task::spawn_blocking(move || {
+            let mut routes = RouteMap::new();
+            for path in &dumps {
+                read_route_dump(path, Some(&wanted), &mut routes)?;
+                debug!(path = %path.display(), origins = routes.len(), "Loaded IRR routes");
+            }
+            Ok::<_, AppError>(routes)
+        })
+        .await??;
+        Ok(Self::from_routes(routes, opts))
+    }
+
+    /// 読み込み済みの route / route6 から作る（ダンプは読まない）
+    pub fn from_routes(routes: RouteMap, opts: &IrrOptions) -> Self {
+        Self {
+            routes,
+            whois: opts.whois.clone(),
+            api: opts.api.clone(),
+        }
+    }
+
+    /// AS の route / route6 プレフィックス（集約しない）
+    pub async fn prefixes(&self, asn: u32) -> Result<(BTreeSet<IpNet>, BTreeSet<IpNet>), AppError> {
+        if let Some(found) = self.routes.get(&asn) {
+            return Ok(found.clone());
+        }
+        let Some(server) = &self.whois else {
+            return Ok((BTreeSet::new(), BTreeSet::new()));
+        };
+        let text = whois_query(server, &format!("-i origin AS{}", asn), &self.api).await?;
+        let mut found = RouteMap::new();
+        read_rpsl_routes(text.as_bytes(), Some(&HashSet::from([asn])), &mut found)?;
+        Ok(found.remove(&asn).unwrap_or_default())
+    }
+}
+
+/// IRR と BGP のプレフィックスの比較結果（プレフィックス単位の完全一致）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteComparison {
+    /// IRR の route / route6 の数
+    pub irr: usize,
+    /// BGP で見えているプレフィックスの数
+    pub bgp: usize,
+    /// 両方にあるプレフィックスの数
+    pub both: usize,
+    /// IRR に登録があるが BGP で見えていないもの
+    pub irr_only: Vec<IpNet>,
+    /// BGP で見えているが route オブジェクトが無いもの（覆う route があればそれも）
+    pub bgp_only: Vec<(IpNet, Option<IpNet>)>,
+}
+
+/// IRR の route と BGP の広報プレフィックスを比較する
+pub fn compare_routes(irr: &BTreeSet<IpNet>, bgp: &BTreeSet<IpNet>) -> RouteComparison {
+    let irr_only = irr.difference(bgp).copied().collect();
+    let bgp_only = bgp
+        .difference(irr)
+        .map(|net| {
+            // 最も長い（近い）覆う route
+            let covering = irr
+                .iter()
+                .filter(|r| r.prefix_len() < net.prefix_len() && r.contains(net))
+                .max_by_key(|r| r.prefix_len())
+                .copied();
+            (*net, covering)
+        })
+        .collect();
+    RouteComparison {
+        irr: irr.len(),
+        bgp: bgp.len(),
+        both: irr.intersection(bgp).count(),
+        irr_only,
+        bgp_only,
+    }
+}
+
+impl RouteComparison {
+    /// AS 1件分の表示（1行目に件数、以降に差分を1行ずつ）
+    pub fn report(&self, asn: u32) -> String {
+        let mut out = format!(
+            "AS{}: {} in IRR, {} in BGP, {} in both\n",
+            asn, self.irr, self.bgp, self.both
+        );
+        for net in &self.irr_only {
+            out.push_str(&format!("  irr-only {}\n", net));
+        }
+        for (net, covering) in &self.bgp_only {
+            match covering {
+                Some(route) => out.push_str(&format!(
+                    "  bgp-only {} (covered by route {})\n",
+                    net, route
+                )),
+                None => out.push_str(&format!("  bgp-only {}\n", net)),
+            }
+        }
+        out
+    }
+}
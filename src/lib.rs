@@ -1,15 +1,33 @@
 pub mod asn;
+pub mod asname;
+pub mod bogon;
 pub mod cli;
 pub mod commands;
 pub mod common;
 pub mod common_download;
+pub mod cone;
+pub mod config;
 pub mod constants;
+pub mod country;
+pub mod diff;
 pub mod error;
 pub mod fetch;
+pub mod hooks;
+pub mod interval;
 pub mod ipv4_utils;
+pub mod irr;
+pub mod logging;
+pub mod metrics;
+pub mod mrt;
 pub mod output;
 pub mod output_common;
 pub mod overlap;
 pub mod parse;
 pub mod process;
+pub mod rate_limit;
+pub mod report;
 // pub mod rpki;
+pub mod serve;
+pub mod shape;
+pub mod stats;
+pub mod watch;
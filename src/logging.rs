@@ -0,0 +1,54 @@
+//! ログ出力（tracing）の初期化
+//!
+//! 既定では警告以上を stderr へ出力する。`--log-level` / `-d` で詳細度を、
+//! `--log-format json` でログ基盤向けの1行1JSON形式を選べる。
+//! 環境変数 `RUST_LOG` が設定されていればそちらを優先する。
+
+use crate::error::AppError;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// ログの出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("Invalid log format. Valid options: 'text' or 'json'"),
+        }
+    }
+}
+
+/// 本クレートは `level`、依存クレートは警告以上に絞ったフィルタ
+pub fn log_filter(level: &str) -> Result<EnvFilter, AppError> {
+    if let Ok(env) = std::env::var(EnvFilter::DEFAULT_ENV)
+        && !env.is_empty()
+    {
+        return EnvFilter::try_new(&env)
+            .map_err(|e| AppError::InvalidInput(format!("Invalid RUST_LOG '{}': {}", env, e)));
+    }
+    EnvFilter::try_new(format!("warn,fire_scope={}", level))
+        .map_err(|e| AppError::InvalidInput(format!("Invalid log level '{}': {}", level, e)))
+}
+
+/// グローバルなサブスクライバを設定する（2回目以降の呼び出しは無視される）
+pub fn init_logging(level: &str, format: LogFormat) -> Result<(), AppError> {
+    let filter = log_filter(level)?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    // 既に設定済みの場合（テストなど）はそのまま
+    let _ = match format {
+        LogFormat::Text => builder.with_target(false).try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    };
+    Ok(())
+}
@@ -1,18 +1,96 @@
-use clap::Parser;
-use fire_scope::cli::Cli;
-use fire_scope::common::{set_debug, OutputFormat};
+use chrono::Local;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use fire_scope::bogon::load_bogons;
+use fire_scope::cli::{Cli, Command};
+use fire_scope::common::OutputFormat;
+use fire_scope::config::Config;
 use fire_scope::error::AppError;
+use fire_scope::logging::{LogFormat, init_logging};
+use fire_scope::metrics::{registry, write_metrics_file};
+use fire_scope::output::OutputOptions;
+use fire_scope::report::{RunReport, journal};
+use fire_scope::stats::{StatsReport, stats};
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<(), AppError> {
-    let args = Cli::parse();
-    set_debug(args.debug);
-    run(args).await
+async fn main() -> ExitCode {
+    let started_at = Local::now();
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let prepared =
+        setup(&mut args, &matches).and_then(|config| Ok((config, args.resolve_command()?)));
+    // 常駐モードは1回の実行という区切りが無いためレポートと統計を記録しない
+    let resident = matches!(prepared, Ok((_, Command::Watch(_) | Command::Serve(_))));
+    let reporting = args.report.is_some() && !resident;
+    if reporting {
+        journal().enable();
+    }
+    let collecting_stats = args.stats.is_some() && !resident;
+    if collecting_stats {
+        stats().enable();
+    }
+
+    let (command, result) = match prepared {
+        Ok((config, command)) => {
+            let result = run(&args, command.clone(), config).await;
+            (Some(command), result)
+        }
+        Err(e) => (None, Err(e)),
+    };
+
+    if reporting && let Some(path) = &args.report {
+        let report = RunReport::finish(started_at, command.as_ref(), &result);
+        if let Err(e) = report.write(path).await {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to write run report");
+        }
+    }
+    if collecting_stats
+        && command.is_some()
+        && let Some(path) = &args.stats
+        && let Err(e) = StatsReport::finish().write(path).await
+    {
+        tracing::warn!(path = %path.display(), error = %e, "Failed to write stats");
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e @ AppError::NoChange) => {
+            eprintln!("{}", e);
+            ExitCode::from(e.exit_code())
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
 }
 
-async fn run(args: Cli) -> Result<(), AppError> {
+/// 旧形式フラグの検査、設定ファイルの適用、ログの初期化
+fn setup(args: &mut Cli, matches: &ArgMatches) -> Result<Option<Config>, AppError> {
+    Cli::reject_legacy_with_subcommand(matches)?;
+
+    // 設定ファイルの既定値を、コマンドラインで明示されていない項目に適用
+    let config = match &args.config {
+        Some(path) => Some(Config::load(path)?),
+        None => None,
+    };
+    if let Some(cfg) = &config {
+        cfg.defaults.apply(args, matches)?;
+    }
+
+    init_logging(
+        args.log_level(),
+        LogFormat::from_str(&args.log_format)
+            .map_err(|e| AppError::InvalidInput(format!("Invalid --log-format: {}", e)))?,
+    )?;
+    Ok(config)
+}
+
+async fn run(args: &Cli, command: Command, config: Option<Config>) -> Result<(), AppError> {
     // HTTPクライアント（タイムアウト付き）
     let client = reqwest::ClientBuilder::new()
         .timeout(Duration::from_secs(args.http_timeout_secs))
@@ -34,40 +112,145 @@ async fn run(args: Cli) -> Result<(), AppError> {
         }
     };
 
-    if args.overlap {
-        // Overlap mode
-        fire_scope::commands::handle_overlap::run_overlap(&args, &client, format_enum).await?;
-        return Ok(());
+    let mut output = OutputOptions::new(format_enum);
+    if let Some(dir) = &args.output_dir {
+        output.dir = dir.clone();
     }
-
-    if let Some(as_list) = &args.as_numbers {
-        // AS番号指定時
-        fire_scope::commands::handle_as_numbers::run_as_numbers(
-            &client,
-            as_list,
-            format_enum,
-            args.concurrency,
-        )
-        .await?;
-        return Ok(());
+    output.exclude = args.exclude.clone().unwrap_or_default();
+    output.max_shrink_percent = args.max_shrink_percent;
+    output.shape = args.shape_options();
+    output.attribute_overlaps = args.attribute_overlaps;
+    if !args.keep_bogons {
+        output.bogons = Some(Arc::new(load_bogons(&args.bogon_files).await?));
+    } else if !args.bogon_files.is_empty() {
+        tracing::warn!("--bogon-file is ignored with --keep-bogons");
     }
 
-    if let Some(country_codes) = &args.country_codes {
-        // 国コード指定時
-        fire_scope::commands::handle_country_codes::run_country_codes(
-            country_codes,
+    let download = args.download_options();
+    let lookup = args.as_lookup_options();
+
+    // run / watch はプロファイルごとにフックを実行するため、ここでは対象外
+    let runs_global_hooks = matches!(
+        command,
+        Command::Country(_) | Command::Asn(_) | Command::Overlap(_)
+    );
+    // 変化なしの終了コードの対象（出力を書き出す1回限りのコマンド）
+    let writes_outputs = runs_global_hooks || matches!(command, Command::Run(_));
+
+    let result = match command {
+        Command::Country(a) => {
+            // 国コード指定時
+            fire_scope::commands::handle_country_codes::run_country_codes(
+                &a.codes, &client, &output, &download,
+            )
+            .await
+            .map(|()| output.log.take())
+        }
+        Command::Asn(a) => {
+            // AS番号指定時
+            fire_scope::commands::handle_as_numbers::run_as_numbers(
+                &client,
+                &a.as_numbers,
+                &a.as_sets,
+                &output,
+                &lookup,
+            )
+            .await
+            .map(|()| output.log.take())
+        }
+        Command::Overlap(a) => {
+            // Overlap mode
+            fire_scope::commands::handle_overlap::run_overlap(
+                &a, &client, &output, &download, &lookup,
+            )
+            .await
+            .map(|()| output.log.take())
+        }
+        Command::Lookup(a) => fire_scope::commands::handle_lookup::run_lookup(
             &client,
-            format_enum,
-            args.continue_on_partial,
-            args.max_retries,
-            args.max_backoff_sec,
+            &a.addresses,
+            &download,
+            &lookup.api,
+            !a.no_country,
+            !a.no_asn,
         )
-        .await?;
-        return Ok(());
-    }
+        .await
+        .map(|()| Vec::new()),
+        Command::IrrCompare(a) => {
+            // IRR の route と BGP の広報プレフィックスを比較
+            fire_scope::commands::handle_irr_compare::run_irr_compare(
+                &client,
+                &a.as_numbers,
+                &a.as_sets,
+                &lookup,
+            )
+            .await
+            .map(|()| Vec::new())
+        }
+        Command::Fetch(a) => {
+            fire_scope::commands::handle_fetch::run_fetch(&client, &a.dir, &download)
+                .await
+                .map(|()| Vec::new())
+        }
+        Command::Diff(a) => fire_scope::commands::handle_diff::run_diff(&a.old, &a.new)
+            .await
+            .map(|()| Vec::new()),
+        Command::Run(a) => {
+            // 設定ファイルのプロファイルを実行
+            fire_scope::commands::handle_run::run_profiles(
+                args,
+                &a,
+                config.as_ref(),
+                &client,
+                &output,
+            )
+            .await
+        }
+        Command::Watch(a) => {
+            // 常駐して一定間隔でプロファイルを再生成
+            fire_scope::commands::handle_watch::run_watch(
+                args,
+                &a,
+                config.as_ref(),
+                &client,
+                &output,
+            )
+            .await
+            .map(|()| Vec::new())
+        }
+        Command::Serve(a) => {
+            // HTTPでリストを配信
+            fire_scope::commands::handle_serve::run_serve(args, &a, &client, &output, &lookup)
+                .await
+                .map(|()| Vec::new())
+        }
+    };
+    let result = match result {
+        Ok(written) if runs_global_hooks => {
+            fire_scope::hooks::run_hooks(&args.post_hooks, &written, None)
+                .await
+                .map(|()| written)
+        }
+        other => other,
+    };
+    // 指定時のみ、どのファイルも変化しなければ専用の終了コードにする
+    let result = match result {
+        Ok(written)
+            if args.no_change_exit_code && writes_outputs && !written.iter().any(|f| f.changed) =>
+        {
+            Err(AppError::NoChange)
+        }
+        other => other.map(|_| ()),
+    };
 
-    eprintln!("Please specify --country or --as-number. Use --help for usage.");
-    Err(AppError::InvalidInput(
-        "Either --country or --as-number must be specified".into(),
-    ))
+    // 失敗した実行でもダウンロードの失敗などを記録するため、結果に関わらず書き出す
+    if let Some(path) = &args.metrics_file {
+        if matches!(result, Ok(()) | Err(AppError::NoChange)) {
+            registry().record_run_success();
+        }
+        if let Err(e) = write_metrics_file(path).await {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to write metrics");
+        }
+    }
+    result
 }
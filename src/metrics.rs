@@ -0,0 +1,298 @@
+//! Prometheus テキスト形式のメトリクス
+//!
+//! プロセス全体で1つのレジストリに記録し、`--metrics-file` への書き出しや
+//! serve モードの `/metrics` で公開する。
+//! `*_last_success_timestamp_seconds` は既存のメトリクスファイルから引き継ぐため、
+//! 実行が失敗しても最後に成功した時刻は残る（古くなったリストの検知用）。
+
+use crate::common::IpFamily;
+use crate::error::AppError;
+use crate::output_common::{address_count, atomic_write};
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DOWNLOAD_LAST_SUCCESS: &str = "fire_scope_download_last_success_timestamp_seconds";
+const LIST_LAST_SUCCESS: &str = "fire_scope_list_last_success_timestamp_seconds";
+const RUN_LAST_SUCCESS: &str = "fire_scope_run_last_success_timestamp_seconds";
+const LAST_SUCCESS_METRICS: [&str; 3] =
+    [DOWNLOAD_LAST_SUCCESS, LIST_LAST_SUCCESS, RUN_LAST_SUCCESS];
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// プロセス全体のレジストリ
+pub fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// URLごとのダウンロード統計
+#[derive(Debug, Clone, Default)]
+struct DownloadStats {
+    duration_seconds: f64,
+    bytes: u64,
+    retries: u64,
+    failures: u64,
+}
+
+/// 出力リストごとの統計
+#[derive(Debug, Clone, Default)]
+struct ListStats {
+    prefixes: usize,
+    addresses: f64,
+}
+
+/// メトリクスの保持と描画
+#[derive(Debug, Default)]
+pub struct Registry {
+    downloads: BTreeMap<String, DownloadStats>,
+    parse_seconds: Option<f64>,
+    lists: BTreeMap<String, ListStats>,
+    // メトリクス名 → ラベル文字列 → UNIX時刻
+    last_success: BTreeMap<&'static str, BTreeMap<String, f64>>,
+}
+
+impl Registry {
+    pub const fn new() -> Self {
+        Self {
+            downloads: BTreeMap::new(),
+            parse_seconds: None,
+            lists: BTreeMap::new(),
+            last_success: BTreeMap::new(),
+        }
+    }
+
+    /// 成功したダウンロード（`retries` は成功までに失敗した回数、`bytes` は本文が無い 304 では `None`）
+    pub fn record_download(
+        &mut self,
+        url: &str,
+        duration: Duration,
+        bytes: Option<u64>,
+        retries: u32,
+    ) {
+        let labels = format!("url=\"{}\"", escape(url));
+        let stats = self.downloads.entry(labels.clone()).or_default();
+        stats.duration_seconds = duration.as_secs_f64();
+        if let Some(b) = bytes {
+            stats.bytes = b;
+        }
+        stats.retries += u64::from(retries);
+        self.set_last_success(DOWNLOAD_LAST_SUCCESS, labels);
+    }
+
+    /// 全試行が失敗したダウンロード
+    pub fn record_download_failure(&mut self, url: &str, retries: u32) {
+        let labels = format!("url=\"{}\"", escape(url));
+        let stats = self.downloads.entry(labels).or_default();
+        stats.retries += u64::from(retries);
+        stats.failures += 1;
+    }
+
+    /// RIRファイルのパース時間
+    pub fn record_parse(&mut self, duration: Duration) {
+        self.parse_seconds = Some(duration.as_secs_f64());
+    }
+
+    /// 書き出したリストのプレフィックス数とアドレス数
+    /// `kind` は country / asn / overlap / set
+    pub fn record_list(
+        &mut self,
+        kind: &str,
+        name: &str,
+        family: IpFamily,
+        nets: &BTreeSet<IpNet>,
+    ) {
+        let labels = format!(
+            "kind=\"{}\",name=\"{}\",family=\"{}\"",
+            escape(kind),
+            escape(name),
+            family.as_str()
+        );
+        self.lists.insert(
+            labels.clone(),
+            ListStats {
+                prefixes: nets.len(),
+                addresses: address_count(nets),
+            },
+        );
+        self.set_last_success(LIST_LAST_SUCCESS, labels);
+    }
+
+    /// 実行（watch/serve ではリフレッシュ）全体の成功
+    pub fn record_run_success(&mut self) {
+        self.set_last_success(RUN_LAST_SUCCESS, String::new());
+    }
+
+    fn set_last_success(&mut self, metric: &'static str, labels: String) {
+        self.last_success
+            .entry(metric)
+            .or_default()
+            .insert(labels, unix_now());
+    }
+
+    /// 以前のメトリクスファイルから成功時刻を引き継ぐ（今回記録済みのものは上書きしない）
+    pub fn carry_over_last_success(&mut self, previous: &str) {
+        for line in previous.lines() {
+            let Some(metric) = LAST_SUCCESS_METRICS.iter().find(|m| line.starts_with(**m)) else {
+                continue;
+            };
+            let rest = &line[metric.len()..];
+            let (labels, value) = match rest.strip_prefix('{') {
+                Some(r) => match r.rsplit_once("} ") {
+                    Some((labels, value)) => (labels, value),
+                    None => continue,
+                },
+                None => match rest.strip_prefix(' ') {
+                    Some(value) => ("", value),
+                    None => continue,
+                },
+            };
+            if let Ok(ts) = value.trim().parse::<f64>() {
+                self.last_success
+                    .entry(metric)
+                    .or_default()
+                    .entry(labels.to_string())
+                    .or_insert(ts);
+            }
+        }
+    }
+
+    /// Prometheus テキスト形式で描画
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let dl = |f: fn(&DownloadStats) -> String| {
+            self.downloads
+                .iter()
+                .map(move |(labels, s)| (labels.clone(), f(s)))
+                .collect::<Vec<_>>()
+        };
+        write_family(
+            &mut out,
+            "fire_scope_download_duration_seconds",
+            "gauge",
+            "Duration of the last successful download per URL.",
+            dl(|s| s.duration_seconds.to_string()),
+        );
+        write_family(
+            &mut out,
+            "fire_scope_download_bytes",
+            "gauge",
+            "Body size of the last successful download per URL.",
+            dl(|s| s.bytes.to_string()),
+        );
+        write_family(
+            &mut out,
+            "fire_scope_download_retries_total",
+            "counter",
+            "Failed attempts that were retried per URL.",
+            dl(|s| s.retries.to_string()),
+        );
+        write_family(
+            &mut out,
+            "fire_scope_download_failures_total",
+            "counter",
+            "Downloads that failed after all retries per URL.",
+            dl(|s| s.failures.to_string()),
+        );
+        write_family(
+            &mut out,
+            "fire_scope_parse_duration_seconds",
+            "gauge",
+            "Duration of the last RIR parse.",
+            self.parse_seconds
+                .map(|s| vec![(String::new(), s.to_string())])
+                .unwrap_or_default(),
+        );
+        write_family(
+            &mut out,
+            "fire_scope_list_prefixes",
+            "gauge",
+            "Prefixes in the last written list.",
+            self.lists
+                .iter()
+                .map(|(l, s)| (l.clone(), s.prefixes.to_string()))
+                .collect(),
+        );
+        write_family(
+            &mut out,
+            "fire_scope_list_addresses",
+            "gauge",
+            "Addresses covered by the last written list.",
+            self.lists
+                .iter()
+                .map(|(l, s)| (l.clone(), s.addresses.to_string()))
+                .collect(),
+        );
+        for (metric, help) in [
+            (
+                DOWNLOAD_LAST_SUCCESS,
+                "Time of the last successful download per URL.",
+            ),
+            (LIST_LAST_SUCCESS, "Time the list was last written."),
+            (
+                RUN_LAST_SUCCESS,
+                "Time of the last successful run or refresh.",
+            ),
+        ] {
+            let samples = self
+                .last_success
+                .get(metric)
+                .map(|m| {
+                    m.iter()
+                        .map(|(l, ts)| (l.clone(), ts.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            write_family(&mut out, metric, "gauge", help, samples);
+        }
+        out
+    }
+}
+
+/// メトリクスファイルを書き出す（既存ファイルの成功時刻を引き継ぐ）
+pub async fn write_metrics_file(path: &Path) -> Result<(), AppError> {
+    let previous = tokio::fs::read_to_string(path).await.unwrap_or_default();
+    let text = {
+        let mut reg = registry();
+        reg.carry_over_last_success(&previous);
+        reg.render()
+    };
+    atomic_write(path, text.as_bytes()).await
+}
+
+fn write_family(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: Vec<(String, String)>,
+) {
+    if samples.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+/// ラベル値のエスケープ
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as f64)
+        .unwrap_or(0.0)
+}
@@ -0,0 +1,327 @@
+//! MRT（RFC 6396）TABLE_DUMP_V2 の RIB ダンプ
+//!
+//! RouteViews の `rib.*.bz2` や RIPE RIS の `bview.*.gz` を読み、各経路の AS_PATH の末尾から
+//! プレフィックス → 起源AS を求める。ファイルだけで `-a` の問い合わせに答えられるため、
+//! オフラインで再現可能な結果になる（`--as-source mrt`）。
+//! - 末尾のセグメントが AS_SET の場合は、集合の各 AS をいずれも起源とみなす
+//! - AS_CONFED_* のセグメントは起源の判定に使わない
+//! - 起源ごとに、その経路を見ているピアの数を数え、`--mrt-min-peers` 未満のものは捨てる
+//! - ユニキャストの RIB（ADD-PATH（RFC 8050）を含む）のみを扱う
+
+use crate::error::AppError;
+use crate::irr::{RouteMap, open_dump};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{ErrorKind, Read};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// 既定の最小ピア数（1 = 1つのピアからでも見えていれば採用）
+pub const DEFAULT_MRT_MIN_PEERS: usize = 1;
+
+/// 1レコードの大きさの上限（壊れたファイルで巨大な確保をしないため）
+const MAX_RECORD_BYTES: usize = 16 * 1024 * 1024;
+
+const TABLE_DUMP_V2: u16 = 13;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_FLAG_EXTENDED_LENGTH: u8 = 0x10;
+
+const AS_SET: u8 = 1;
+const AS_SEQUENCE: u8 = 2;
+
+/// MRT 取得元の設定
+#[derive(Debug, Clone)]
+pub struct MrtOptions {
+    /// TABLE_DUMP_V2 の RIB ファイル（plain / gzip / bzip2）
+    pub files: Vec<PathBuf>,
+    /// 起源として採用するのに必要な、経路を見ているピアの最小数
+    pub min_peers: usize,
+}
+
+impl Default for MrtOptions {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            min_peers: DEFAULT_MRT_MIN_PEERS,
+        }
+    }
+}
+
+/// (プレフィックス, 起源AS) → その経路を見ているピアの数
+pub type OriginCounts = HashMap<(IpNet, u32), usize>;
+
+/// MRT のストリームを読み、RIB の経路を `counts` に加える
+/// `origins` を指定した場合はその AS を起源とする経路だけを数える
+/// 戻り値は読んだ RIB レコード（プレフィックス）の数
+pub fn read_mrt<R: Read>(
+    mut reader: R,
+    origins: Option<&HashSet<u32>>,
+    counts: &mut OriginCounts,
+) -> Result<usize, AppError> {
+    let mut ribs = 0;
+    let mut header = [0u8; 12];
+    let mut body = Vec::new();
+    loop {
+        match read_full(&mut reader, &mut header)? {
+            0 => break,
+            12 => {}
+            _ => return Err(truncated("header")),
+        }
+        let mrt_type = u16::from_be_bytes([header[4], header[5]]);
+        let subtype = u16::from_be_bytes([header[6], header[7]]);
+        let len = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        if len > MAX_RECORD_BYTES {
+            return Err(AppError::ParseError(format!(
+                "MRT record of {} bytes exceeds the limit of {} bytes",
+                len, MAX_RECORD_BYTES
+            )));
+        }
+        body.resize(len, 0);
+        if read_full(&mut reader, &mut body)? != len {
+            return Err(truncated("record"));
+        }
+        if mrt_type != TABLE_DUMP_V2 {
+            continue;
+        }
+        let (v6, add_path) = match subtype {
+            RIB_IPV4_UNICAST => (false, false),
+            RIB_IPV6_UNICAST => (true, false),
+            RIB_IPV4_UNICAST_ADDPATH => (false, true),
+            RIB_IPV6_UNICAST_ADDPATH => (true, true),
+            // PEER_INDEX_TABLE（ピアはインデックスで区別できれば足りる）、マルチキャスト、RIB_GENERIC
+            _ => continue,
+        };
+        read_rib(&body, v6, add_path, origins, counts)?;
+        ribs += 1;
+    }
+    Ok(ribs)
+}
+
+/// EOF まで、または `buf` が埋まるまで読む
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, AppError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+fn truncated(what: &str) -> AppError {
+    AppError::ParseError(format!("Truncated MRT {}", what))
+}
+
+/// バイト列を先頭から読むためのカーソル
+struct Cursor<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], AppError> {
+        if self.buf.len() < n {
+            return Err(truncated("RIB entry"));
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, AppError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, AppError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, AppError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// RIB_IPV4_UNICAST / RIB_IPV6_UNICAST（ADD-PATH 含む）の1レコード
+fn read_rib(
+    body: &[u8],
+    v6: bool,
+    add_path: bool,
+    origins: Option<&HashSet<u32>>,
+    counts: &mut OriginCounts,
+) -> Result<(), AppError> {
+    let mut cur = Cursor { buf: body };
+    let _sequence = cur.u32()?;
+    let prefix = read_prefix(&mut cur, v6)?;
+    let entries = cur.u16()?;
+
+    // ADD-PATH では同じピアが複数の経路を持ちうるため、ピアと起源の組で数える
+    let mut seen: HashSet<(u16, u32)> = HashSet::new();
+    for _ in 0..entries {
+        let peer = cur.u16()?;
+        let _originated = cur.u32()?;
+        if add_path {
+            let _path_id = cur.u32()?;
+        }
+        let attr_len = cur.u16()? as usize;
+        let attrs = cur.take(attr_len)?;
+        let Some(as_path) = find_as_path(attrs)? else {
+            continue;
+        };
+        for origin in origins_from_as_path(as_path)? {
+            if origins.is_none_or(|wanted| wanted.contains(&origin)) && seen.insert((peer, origin))
+            {
+                *counts.entry((prefix, origin)).or_insert(0) += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// プレフィックス長と、必要なバイト数だけのアドレス
+fn read_prefix(cur: &mut Cursor, v6: bool) -> Result<IpNet, AppError> {
+    let len = cur.u8()?;
+    let max = if v6 { 128 } else { 32 };
+    if len > max {
+        return Err(AppError::ParseError(format!(
+            "Invalid MRT prefix length {}",
+            len
+        )));
+    }
+    let bytes = cur.take(usize::from(len).div_ceil(8))?;
+    let net = if v6 {
+        let mut addr = [0u8; 16];
+        addr[..bytes.len()].copy_from_slice(bytes);
+        IpNet::V6(
+            Ipv6Net::new(Ipv6Addr::from(addr), len)
+                .map_err(|e| AppError::ParseError(e.to_string()))?,
+        )
+    } else {
+        let mut addr = [0u8; 4];
+        addr[..bytes.len()].copy_from_slice(bytes);
+        IpNet::V4(
+            Ipv4Net::new(Ipv4Addr::from(addr), len)
+                .map_err(|e| AppError::ParseError(e.to_string()))?,
+        )
+    };
+    Ok(net.trunc())
+}
+
+/// BGP パス属性から AS_PATH の値を探す
+fn find_as_path(attrs: &[u8]) -> Result<Option<&[u8]>, AppError> {
+    let mut cur = Cursor { buf: attrs };
+    while !cur.buf.is_empty() {
+        let flags = cur.u8()?;
+        let code = cur.u8()?;
+        let len = if flags & ATTR_FLAG_EXTENDED_LENGTH != 0 {
+            cur.u16()? as usize
+        } else {
+            cur.u8()? as usize
+        };
+        let value = cur.take(len)?;
+        if code == ATTR_AS_PATH {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// AS_PATH（TABLE_DUMP_V2 では常に4バイトAS）の起源AS
+/// 末尾の AS_SEQUENCE ならその最後の AS、AS_SET なら集合の全 AS
+/// AS_CONFED_SEQUENCE / AS_CONFED_SET は読み飛ばす
+pub fn origins_from_as_path(as_path: &[u8]) -> Result<Vec<u32>, AppError> {
+    let mut cur = Cursor { buf: as_path };
+    let mut last: Option<(u8, Vec<u32>)> = None;
+    while !cur.buf.is_empty() {
+        let seg_type = cur.u8()?;
+        let count = cur.u8()?;
+        let mut asns = Vec::with_capacity(usize::from(count));
+        for _ in 0..count {
+            asns.push(cur.u32()?);
+        }
+        if matches!(seg_type, AS_SET | AS_SEQUENCE) && !asns.is_empty() {
+            last = Some((seg_type, asns));
+        }
+    }
+    Ok(match last {
+        Some((AS_SEQUENCE, asns)) => asns.last().copied().into_iter().collect(),
+        Some((_, mut asns)) => {
+            asns.sort_unstable();
+            asns.dedup();
+            asns
+        }
+        None => Vec::new(),
+    })
+}
+
+/// MRT ファイルを読む（gzip / bzip2 は展開しながら）
+pub fn read_mrt_file(
+    path: &Path,
+    origins: Option<&HashSet<u32>>,
+    counts: &mut OriginCounts,
+) -> Result<usize, AppError> {
+    read_mrt(open_dump(path, "MRT file")?, origins, counts).map_err(|e| match e {
+        AppError::ParseError(msg) => AppError::ParseError(format!("{}: {}", path.display(), msg)),
+        other => other,
+    })
+}
+
+/// MRT から求めた起源AS ごとのプレフィックス
+#[derive(Debug, Default)]
+pub struct MrtRoutes {
+    routes: RouteMap,
+}
+
+impl MrtRoutes {
+    /// `origins` の AS を起源とする経路を MRT ファイルから読み込む
+    /// 複数ファイル（複数コレクタ）のピア数は合算する
+    pub async fn load(opts: &MrtOptions, origins: &[u32]) -> Result<Self, AppError> {
+        if opts.files.is_empty() {
+            return Err(AppError::InvalidInput(
+                "The MRT source requires --mrt-file <FILE>".into(),
+            ));
+        }
+        let files = opts.files.clone();
+        let wanted: HashSet<u32> = origins.iter().copied().collect();
+        let counts = tokio::task::spawn_blocking(move || {
+            let mut counts = OriginCounts::new();
+            for path in &files {
+                let ribs = read_mrt_file(path, Some(&wanted), &mut counts)?;
+                debug!(path = %path.display(), ribs, "Loaded MRT RIB dump");
+            }
+            Ok::<_, AppError>(counts)
+        })
+        .await??;
+        Ok(Self::from_counts(&counts, opts.min_peers))
+    }
+
+    /// ピア数のしきい値を適用して作る
+    pub fn from_counts(counts: &OriginCounts, min_peers: usize) -> Self {
+        let mut routes = RouteMap::new();
+        for (&(net, origin), &peers) in counts {
+            if peers < min_peers {
+                continue;
+            }
+            let (v4, v6) = routes.entry(origin).or_default();
+            match net {
+                IpNet::V4(_) => v4.insert(net),
+                IpNet::V6(_) => v6.insert(net),
+            };
+        }
+        Self { routes }
+    }
+
+    /// AS を起源とするプレフィックス（集約しない）
+    pub fn prefixes(&self, asn: u32) -> (BTreeSet<IpNet>, BTreeSet<IpNet>) {
+        self.routes.get(&asn).cloned().unwrap_or_default()
+    }
+}
@@ -1,46 +1,350 @@
+use crate::asname::AsAnnotations;
+use crate::bogon::{BogonRemoval, BogonTable};
 use crate::common::{IpFamily, OutputFormat};
+use crate::diff::parse_list_text;
 use crate::error::AppError;
-use crate::output_common::{make_header, sanitize_identifier, write_list_nft, write_list_txt};
+use crate::interval::IpRangeSet;
+use crate::metrics::registry;
+use crate::output_common::{
+    address_count, atomic_write, extend_header, make_header, nft_define_name,
+    render_list_nft_with_comments, render_list_txt, render_list_txt_with_comments,
+    same_content_ignoring_timestamp, sanitize_identifier,
+};
+use crate::report::journal;
+use crate::shape::{ShapeOptions, ShapeReport, shape_nets};
+use crate::stats::record_list_stats;
 use chrono::Local;
 use ipnet::IpNet;
 use std::collections::BTreeSet;
-use crate::common::debug_log;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+
+/// 書き出した1ファイル分の記録
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrittenFile {
+    pub path: PathBuf,
+    /// 前回の内容から変化したか（生成日時の行は比較しない）
+    pub changed: bool,
+    /// 書き出したCIDRの数
+    pub entries: usize,
+}
+
+/// 書き出したファイルの記録（`OutputOptions` の clone 間で共有される）
+#[derive(Debug, Clone, Default)]
+pub struct WriteLog(Arc<Mutex<Vec<WrittenFile>>>);
+
+impl WriteLog {
+    fn record(&self, file: WrittenFile) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(file);
+    }
+
+    /// これまでの記録を取り出して空にする
+    pub fn take(&self) -> Vec<WrittenFile> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// 書き出し待ちの1ファイル
+#[derive(Debug)]
+struct PendingWrite {
+    path: PathBuf,
+    content: String,
+    changed: bool,
+    entries: usize,
+}
+
+impl PendingWrite {
+    async fn commit(self, log: &WriteLog) -> Result<(), AppError> {
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        // 常に上書き（原子的に安全な書き込み）
+        atomic_write(&self.path, self.content.as_bytes()).await?;
+        journal().record_output(
+            &self.path,
+            self.changed,
+            self.entries,
+            self.content.as_bytes(),
+        );
+        log.record(WrittenFile {
+            path: self.path,
+            changed: self.changed,
+            entries: self.entries,
+        });
+        Ok(())
+    }
+}
+
+/// 書き出しを保留したファイル（`OutputOptions::staged` の clone 間で共有される）
+/// 複数のリストを計算し終えてからまとめて書き出すために使う
+#[derive(Debug, Clone, Default)]
+pub struct StagedWrites(Arc<Mutex<Vec<PendingWrite>>>);
+
+impl StagedWrites {
+    fn push(&self, pending: PendingWrite) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(pending);
+    }
+
+    /// 保留中のファイルの数
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 保留中のファイルをすべて書き出し、`log` に記録する
+    pub async fn commit(&self, log: &WriteLog) -> Result<(), AppError> {
+        let pending = std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()));
+        for file in pending {
+            file.commit(log).await?;
+        }
+        Ok(())
+    }
+}
+
+/// 出力先と書き出し前の後処理の設定
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// 出力形式
+    pub format: OutputFormat,
+    /// 出力ディレクトリ（無ければ作成する）
+    pub dir: PathBuf,
+    /// すべての出力から取り除くCIDR
+    pub exclude: Vec<IpNet>,
+    /// 書き出したファイルの記録（フック実行の判定に使う）
+    pub log: WriteLog,
+    /// 既存ファイルからのアドレス空間の縮小率の上限（%）。超える場合は書き出さずにエラー
+    pub max_shrink_percent: Option<f64>,
+    /// AS名の注記（`--as-names`、AS のプレフィックスを含む出力のみ）
+    pub annotations: Option<Arc<AsAnnotations>>,
+    /// 重複の出力の要素ごとに起源AS と国コードを付ける（`--attribute-overlaps`）
+    pub attribute_overlaps: bool,
+    /// プレフィックス長の調整と最大エントリ数
+    pub shape: ShapeOptions,
+    /// すべての出力から取り除く特殊用途アドレス（`None` なら取り除かない）
+    pub bogons: Option<Arc<BogonTable>>,
+    /// 指定時は書き出さずに保留する（`StagedWrites::commit` で書き出す）
+    pub staged: Option<StagedWrites>,
+}
+
+impl OutputOptions {
+    /// カレントディレクトリへ除外なしで書き出す設定
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            dir: PathBuf::from("."),
+            exclude: Vec::new(),
+            log: WriteLog::default(),
+            max_shrink_percent: None,
+            annotations: None,
+            attribute_overlaps: false,
+            shape: ShapeOptions::default(),
+            bogons: None,
+            staged: None,
+        }
+    }
+
+    /// 出力ディレクトリ配下のパスを返す
+    pub fn path_for(&self, file_name: &str) -> PathBuf {
+        self.dir.join(file_name)
+    }
+
+    /// 書き出し前に除外CIDRと bogon を差し引き、CIDR に変換してプレフィックス長とエントリ数を調整する
+    pub fn prepare(&self, set: &IpRangeSet) -> BTreeSet<IpNet> {
+        self.prepare_with_details(set).0
+    }
+
+    fn prepare_with_details(
+        &self,
+        set: &IpRangeSet,
+    ) -> (BTreeSet<IpNet>, Vec<BogonRemoval>, Vec<ShapeReport>) {
+        let set = if self.exclude.is_empty() {
+            set.clone()
+        } else {
+            set.difference(&IpRangeSet::from_nets(&self.exclude))
+        };
+        // 広げたりまとめたりしても、除外CIDR と bogon の範囲は含めない
+        let mut avoid = self.exclude.clone();
+        let (set, removed) = match &self.bogons {
+            Some(bogons) => {
+                avoid.extend_from_slice(bogons.ranges());
+                bogons.remove_from(&set)
+            }
+            None => (set, Vec::new()),
+        };
+        let (shaped, reports) = shape_nets(&set.to_set(), &self.shape, &avoid);
+        (shaped, removed, reports)
+    }
+
+    /// `prepare` と同じ処理をし、取り除いた bogon と調整の結果をログと実行レポートに残す
+    fn prepare_reported(&self, list: &str, set: &IpRangeSet) -> BTreeSet<IpNet> {
+        let (shaped, removed, reports) = self.prepare_with_details(set);
+        if !removed.is_empty() {
+            warn!(
+                list,
+                removed = removed.len(),
+                "Removed bogon ranges: {}",
+                removal_summary(&removed)
+            );
+            journal().record_bogons(list, removed);
+        }
+        for report in reports.iter().filter(|r| r.changed()) {
+            info!(
+                list,
+                family = report.family,
+                entries_before = report.entries_before,
+                entries_after = report.entries_after,
+                dropped = report.dropped,
+                widened = report.widened,
+                not_widened = report.not_widened,
+                removed_addresses = report.removed_addresses,
+                added_addresses = report.added_addresses,
+                "{}",
+                report.summary(list)
+            );
+        }
+        journal().record_shaping(list, reports);
+        shaped
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.format {
+            OutputFormat::Txt => "txt",
+            OutputFormat::Nft => "nft",
+        }
+    }
+
+    async fn write(
+        &self,
+        file_stem: &str,
+        ipnets: &BTreeSet<IpNet>,
+        header: &str,
+    ) -> Result<(), AppError> {
+        let path = self.path_for(&format!("{}.{}", file_stem, self.extension()));
+        let annotations = self.annotations.as_deref();
+        let header = match annotations {
+            Some(a) => extend_header(header, &a.header_lines(ipnets)),
+            None => header.to_string(),
+        };
+        let content = match self.format {
+            // TXT への要素ごとのコメントは overlap の注記（国コード付き）のみ
+            OutputFormat::Txt => match annotations.filter(|a| a.has_countries()) {
+                Some(a) => {
+                    render_list_txt_with_comments(ipnets, &header, |net| a.element_comment(net))
+                }
+                None => render_list_txt(ipnets, &header),
+            },
+            OutputFormat::Nft => {
+                render_list_nft_with_comments(nft_define_name(&path), ipnets, &header, |net| {
+                    annotations.and_then(|a| a.element_comment(net))
+                })
+            }
+        };
+
+        // 既存ファイルと比較して変更の有無を記録（読めない場合は変更ありとみなす）
+        let old = tokio::fs::read_to_string(&path).await.ok();
+        let changed = match &old {
+            Some(old) => !same_content_ignoring_timestamp(old, &content),
+            None => true,
+        };
+        if changed && let (Some(limit), Some(old)) = (self.max_shrink_percent, &old) {
+            check_shrink(&path, old, ipnets, limit)?;
+        }
+
+        let pending = PendingWrite {
+            path,
+            content,
+            changed,
+            entries: ipnets.len(),
+        };
+        match &self.staged {
+            Some(staged) => {
+                staged.push(pending);
+                Ok(())
+            }
+            None => pending.commit(&self.log).await,
+        }
+    }
+}
+
+/// 取り除いた bogon の一覧（先頭の数件）
+fn removal_summary(removed: &[BogonRemoval]) -> String {
+    const SHOWN: usize = 5;
+    let mut out = removed
+        .iter()
+        .take(SHOWN)
+        .map(|r| format!("{} {}", r.prefix, r.reason))
+        .collect::<Vec<_>>()
+        .join("; ");
+    if removed.len() > SHOWN {
+        out.push_str(&format!("; +{} more", removed.len() - SHOWN));
+    }
+    out
+}
+
+/// 既存ファイルからのアドレス空間の縮小率が上限以内か確認する
+/// 既存ファイルが空（またはCIDRを含まない）なら確認しない
+fn check_shrink(
+    path: &Path,
+    old: &str,
+    new: &BTreeSet<IpNet>,
+    limit_percent: f64,
+) -> Result<(), AppError> {
+    let before = address_count(&parse_list_text(old));
+    if before == 0.0 {
+        return Ok(());
+    }
+    let after = address_count(new);
+    let shrink = (before - after) / before * 100.0;
+    if shrink > limit_percent {
+        return Err(AppError::SafetyThreshold(format!(
+            "{}: address space would shrink by {:.1}% (limit {}%), keeping the previous file",
+            path.display(),
+            shrink,
+            limit_percent
+        )));
+    }
+    Ok(())
+}
 
 /// IPv4/IPv6リストをファイルに書き出す
 /// 国コード用
 pub async fn write_ip_lists_to_files(
     country_code: &str,
-    ipv4_list: &BTreeSet<IpNet>,
-    ipv6_list: &BTreeSet<IpNet>,
-    format_enum: OutputFormat,
+    set: &IpRangeSet,
+    output: &OutputOptions,
 ) -> Result<(), AppError> {
     let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
     let safe_code = sanitize_identifier(&country_code.to_uppercase());
+    // 国コードのリストには AS名を付けない
+    let output = &OutputOptions {
+        annotations: None,
+        ..output.clone()
+    };
 
-    match format_enum {
-        OutputFormat::Txt => {
-            // IPv4
-            let ipv4_file = format!("IPv4_{}.txt", safe_code);
-            let header_v4 = make_header(&now_str, &safe_code, "N/A");
-            write_list_txt(&ipv4_file, ipv4_list, &header_v4).await?;
-
-            // IPv6
-            let ipv6_file = format!("IPv6_{}.txt", safe_code);
-            let header_v6 = make_header(&now_str, &safe_code, "N/A");
-            write_list_txt(&ipv6_file, ipv6_list, &header_v6).await?;
-        }
-        OutputFormat::Nft => {
-            // IPv4
-            let ipv4_file = format!("IPv4_{}.nft", safe_code);
-            let header_v4 = make_header(&now_str, &safe_code, "N/A");
-            write_list_nft(&ipv4_file, ipv4_list, &header_v4).await?;
-
-            // IPv6
-            let ipv6_file = format!("IPv6_{}.nft", safe_code);
-            let header_v6 = make_header(&now_str, &safe_code, "N/A");
-            write_list_nft(&ipv6_file, ipv6_list, &header_v6).await?;
-        }
-    }
+    // IPv4
+    let header_v4 = make_header(&now_str, &safe_code, "N/A");
+    let v4 = output.prepare_reported(&format!("IPv4_{}", safe_code), &set.only(IpFamily::V4));
+    output
+        .write(&format!("IPv4_{}", safe_code), &v4, &header_v4)
+        .await?;
+    registry().record_list("country", &safe_code, IpFamily::V4, &v4);
+    record_list_stats("country", &safe_code, IpFamily::V4, set.v4.cidr_len(), &v4);
+
+    // IPv6
+    let header_v6 = make_header(&now_str, &safe_code, "N/A");
+    let v6 = output.prepare_reported(&format!("IPv6_{}", safe_code), &set.only(IpFamily::V6));
+    output
+        .write(&format!("IPv6_{}", safe_code), &v6, &header_v6)
+        .await?;
+    registry().record_list("country", &safe_code, IpFamily::V6, &v6);
+    record_list_stats("country", &safe_code, IpFamily::V6, set.v6.cidr_len(), &v6);
     Ok(())
 }
 
@@ -49,25 +353,25 @@ pub async fn write_ip_lists_to_files(
 pub async fn write_as_ip_list_to_file(
     as_number: &str,
     family: IpFamily,
-    ipnets: &BTreeSet<IpNet>,
-    format_enum: OutputFormat,
+    set: &IpRangeSet,
+    output: &OutputOptions,
 ) -> Result<(), AppError> {
     let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
     let safe_as = sanitize_identifier(as_number);
     let header = make_header(&now_str, "N/A", &safe_as);
 
-    match format_enum {
-        OutputFormat::Txt => {
-            let file_name = format!("AS_{}_{}.txt", safe_as, family.as_str());
-            write_list_txt(&file_name, ipnets, &header).await?;
-            debug_log(format!("Wrote TXT for AS_{} {}", safe_as, family.as_str()));
-        }
-        OutputFormat::Nft => {
-            let file_name = format!("AS_{}_{}.nft", safe_as, family.as_str());
-            write_list_nft(&file_name, ipnets, &header).await?;
-            debug_log(format!("Wrote NFT for AS_{} {}", safe_as, family.as_str()));
-        }
-    }
+    let file_stem = format!("AS_{}_{}", safe_as, family.as_str());
+    let set = set.only(family);
+    let prepared = output.prepare_reported(&file_stem, &set);
+    output.write(&file_stem, &prepared, &header).await?;
+    registry().record_list("asn", &safe_as, family, &prepared);
+    record_list_stats("asn", &safe_as, family, set.cidr_len(), &prepared);
+    debug!(
+        asn = %safe_as,
+        family = family.as_str(),
+        format = output.extension(),
+        "Wrote AS list"
+    );
     Ok(())
 }
 
@@ -75,63 +379,100 @@ pub async fn write_as_ip_list_to_file(
 pub async fn write_overlap_to_file(
     country_code: &str,
     as_number: &str,
-    overlaps: &BTreeSet<IpNet>,
-    format_enum: OutputFormat,
+    overlaps: &IpRangeSet,
+    output: &OutputOptions,
 ) -> Result<(), AppError> {
-    use chrono::Local;
     let safe_cc = sanitize_identifier(country_code);
     let safe_as = sanitize_identifier(as_number);
+    let file_prefix = format!("overlap_{}_{}", safe_cc, safe_as);
+    write_split_set(
+        "overlap",
+        &file_prefix,
+        &safe_cc,
+        &safe_as,
+        overlaps,
+        output,
+    )
+    .await
+}
 
-    let overlaps_v4: BTreeSet<IpNet> = overlaps
+/// 複数ソースをまとめた名前付きセットを書き出す（設定ファイルのプロファイル用）
+pub async fn write_named_set_to_file(
+    set_name: &str,
+    country_label: &str,
+    as_label: &str,
+    nets: &IpRangeSet,
+    output: &OutputOptions,
+) -> Result<(), AppError> {
+    let safe_name = sanitize_identifier(set_name);
+    write_split_set("set", &safe_name, country_label, as_label, nets, output).await
+}
+
+/// IPv4/IPv6混在の集合をファミリ別に `<prefix>_IPv4` / `<prefix>_IPv6` へ書き出す
+/// 空のファミリは書き出さない
+/// `kind` はメトリクスのラベル（overlap / set）
+async fn write_split_set(
+    kind: &str,
+    file_prefix: &str,
+    country_label: &str,
+    as_label: &str,
+    nets: &IpRangeSet,
+    output: &OutputOptions,
+) -> Result<(), AppError> {
+    let prepared = output.prepare_reported(file_prefix, nets);
+    if let Some(a) = output.annotations.as_deref().filter(|a| a.has_countries()) {
+        journal().record_attribution(file_prefix, a.attribute(&prepared));
+    }
+
+    let nets_v4: BTreeSet<IpNet> = prepared
         .iter()
         .cloned()
         .filter(|net| matches!(net, IpNet::V4(_)))
         .collect();
 
-    let overlaps_v6: BTreeSet<IpNet> = overlaps
+    let nets_v6: BTreeSet<IpNet> = prepared
         .iter()
         .cloned()
         .filter(|net| matches!(net, IpNet::V6(_)))
         .collect();
 
-    if overlaps_v4.is_empty() && overlaps_v6.is_empty() {
-        debug_log(format!(
-            "No overlap found for country={} and AS={}",
-            country_code, as_number
-        ));
+    if nets_v4.is_empty() && nets_v6.is_empty() {
+        debug!(
+            set = %file_prefix,
+            country = %country_label,
+            asn = %as_label,
+            "No prefixes to write"
+        );
         return Ok(());
     }
 
-    match format_enum {
-        OutputFormat::Txt => {
-            if !overlaps_v4.is_empty() {
-                let filename_v4 = format!("overlap_{}_{}_IPv4.txt", safe_cc, safe_as);
-                let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-                let header_v4 = make_header(&now_str, &safe_cc, &safe_as);
-                write_list_txt(&filename_v4, &overlaps_v4, &header_v4).await?;
-            }
-            if !overlaps_v6.is_empty() {
-                let filename_v6 = format!("overlap_{}_{}_IPv6.txt", safe_cc, safe_as);
-                let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-                let header_v6 = make_header(&now_str, &safe_cc, &safe_as);
-                write_list_txt(&filename_v6, &overlaps_v6, &header_v6).await?;
-            }
-        }
-        OutputFormat::Nft => {
-            if !overlaps_v4.is_empty() {
-                let filename_v4 = format!("overlap_{}_{}_IPv4.nft", safe_cc, safe_as);
-                let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-                let header_v4 = make_header(&now_str, &safe_cc, &safe_as);
-                write_list_nft(&filename_v4, &overlaps_v4, &header_v4).await?;
-            }
-            if !overlaps_v6.is_empty() {
-                let filename_v6 = format!("overlap_{}_{}_IPv6.nft", safe_cc, safe_as);
-                let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-                let header_v6 = make_header(&now_str, &safe_cc, &safe_as);
-                write_list_nft(&filename_v6, &overlaps_v6, &header_v6).await?;
-            }
-        }
+    let now_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let header = make_header(&now_str, country_label, as_label);
+    if !nets_v4.is_empty() {
+        output
+            .write(&format!("{}_IPv4", file_prefix), &nets_v4, &header)
+            .await?;
+        registry().record_list(kind, file_prefix, IpFamily::V4, &nets_v4);
+        record_list_stats(
+            kind,
+            file_prefix,
+            IpFamily::V4,
+            nets.v4.cidr_len(),
+            &nets_v4,
+        );
+    }
+    if !nets_v6.is_empty() {
+        output
+            .write(&format!("{}_IPv6", file_prefix), &nets_v6, &header)
+            .await?;
+        registry().record_list(kind, file_prefix, IpFamily::V6, &nets_v6);
+        record_list_stats(
+            kind,
+            file_prefix,
+            IpFamily::V6,
+            nets.v6.cidr_len(),
+            &nets_v6,
+        );
     }
-
     Ok(())
 }
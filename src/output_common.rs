@@ -1,6 +1,10 @@
 use crate::error::AppError;
+use crate::interval::IpRangeSet;
 use ipnet::IpNet;
-use std::{collections::BTreeSet, path::{Path, PathBuf}};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
 use tokio::fs::{self, OpenOptions};
 use tokio::io::AsyncWriteExt;
 
@@ -22,7 +26,11 @@ pub fn sanitize_identifier(input: &str) -> String {
     // 先頭末尾のアンダースコア除去
     let s = s.trim_matches('_').to_string();
     let s = if s.len() > 64 { s[..64].to_string() } else { s };
-    if s.is_empty() { "UNKNOWN".to_string() } else { s }
+    if s.is_empty() {
+        "UNKNOWN".to_string()
+    } else {
+        s
+    }
 }
 
 /// 汎用ヘッダー生成
@@ -33,48 +41,103 @@ pub fn make_header(now_str: &str, country_code: &str, as_number: &str) -> String
     )
 }
 
-pub async fn write_list_txt<P: AsRef<Path>>(
-    path: P,
-    ipnets: &BTreeSet<IpNet>,
-    header: &str,
-) -> Result<(), AppError> {
+/// TXT形式の本文を生成（1行1CIDR）
+pub fn render_list_txt(ipnets: &BTreeSet<IpNet>, header: &str) -> String {
     let body = ipnets
         .iter()
         .map(|net| net.to_string())
         .collect::<Vec<_>>()
         .join("\n");
 
-    let content = format!("{}{}\n", header, body);
+    format!("{}{}\n", header, body)
+}
 
-    // 常に上書き（原子的に安全な書き込み）
-    atomic_write(path.as_ref(), content.as_bytes()).await?;
+/// TXT形式の本文を生成し、要素ごとに行末コメント（`# ...`）を付ける
+pub fn render_list_txt_with_comments(
+    ipnets: &BTreeSet<IpNet>,
+    header: &str,
+    comment: impl Fn(&IpNet) -> Option<String>,
+) -> String {
+    let mut content = String::from(header);
+    for net in ipnets {
+        match comment(net) {
+            Some(c) => content.push_str(&format!("{} # {}\n", net, c)),
+            None => content.push_str(&format!("{}\n", net)),
+        }
+    }
+    content
+}
 
-    Ok(())
+/// ヘッダの末尾（空行の前）に行を加える
+pub fn extend_header(header: &str, lines: &str) -> String {
+    if lines.is_empty() {
+        return header.to_string();
+    }
+    format!(
+        "{}\n{}\n\n",
+        header.trim_end_matches('\n'),
+        lines.trim_end_matches('\n')
+    )
 }
 
-pub async fn write_list_nft<P: AsRef<Path>>(
-    path: P,
+/// nftables の `define` 形式の本文を生成
+pub fn render_list_nft(define_name: &str, ipnets: &BTreeSet<IpNet>, header: &str) -> String {
+    render_list_nft_with_comments(define_name, ipnets, header, |_| None)
+}
+
+/// nftables の `define` 形式の本文を生成し、要素ごとに行末コメント（`# ...`）を付ける
+pub fn render_list_nft_with_comments(
+    define_name: &str,
     ipnets: &BTreeSet<IpNet>,
     header: &str,
-) -> Result<(), AppError> {
-    let file_path = path.as_ref();
-    let define_name_raw = file_path
-        .file_stem()
-        .and_then(|os| os.to_str())
-        .unwrap_or("unknown_define");
-    let define_name = sanitize_identifier(define_name_raw);
+    comment: impl Fn(&IpNet) -> Option<String>,
+) -> String {
+    let define_name = sanitize_identifier(define_name);
 
     let mut content = String::new();
     content.push_str(header);
     content.push_str(&format!("define {} = {{\n", define_name));
 
-    if !ipnets.is_empty() {
-        let lines: Vec<String> = ipnets.iter().map(|n| format!("    {}", n)).collect();
-        content.push_str(&lines.join(",\n"));
-        content.push('\n');
+    let count = ipnets.len();
+    for (i, net) in ipnets.iter().enumerate() {
+        let sep = if i + 1 < count { "," } else { "" };
+        match comment(net) {
+            Some(c) => content.push_str(&format!("    {}{} # {}\n", net, sep, c)),
+            None => content.push_str(&format!("    {}{}\n", net, sep)),
+        }
     }
 
     content.push_str("}\n");
+    content
+}
+
+/// nft の define 名（ファイル名のstem）
+pub fn nft_define_name(path: &Path) -> &str {
+    path.file_stem()
+        .and_then(|os| os.to_str())
+        .unwrap_or("unknown_define")
+}
+
+pub async fn write_list_txt<P: AsRef<Path>>(
+    path: P,
+    ipnets: &BTreeSet<IpNet>,
+    header: &str,
+) -> Result<(), AppError> {
+    let content = render_list_txt(ipnets, header);
+
+    // 常に上書き（原子的に安全な書き込み）
+    atomic_write(path.as_ref(), content.as_bytes()).await?;
+
+    Ok(())
+}
+
+pub async fn write_list_nft<P: AsRef<Path>>(
+    path: P,
+    ipnets: &BTreeSet<IpNet>,
+    header: &str,
+) -> Result<(), AppError> {
+    let file_path = path.as_ref();
+    let content = render_list_nft(nft_define_name(file_path), ipnets, header);
 
     // 常に上書き（原子的に安全な書き込み）
     atomic_write(file_path, content.as_bytes()).await?;
@@ -82,8 +145,31 @@ pub async fn write_list_nft<P: AsRef<Path>>(
     Ok(())
 }
 
+/// 集合がカバーするアドレス数（重複は無い前提、IPv6 のため浮動小数）
+pub fn address_count(nets: &BTreeSet<IpNet>) -> f64 {
+    nets.iter()
+        .map(|n| 2f64.powi(i32::from(n.max_prefix_len() - n.prefix_len())))
+        .sum()
+}
+
+/// 集合がカバーするアドレス数の正確な値（重なる部分は1度だけ数える、上限で飽和）
+pub fn exact_address_count(nets: &BTreeSet<IpNet>) -> u128 {
+    IpRangeSet::from_nets(nets).address_count()
+}
+
+/// 生成日時の行を除いて内容が同じかどうか（ヘッダの時刻だけの違いは変更とみなさない）
+pub fn same_content_ignoring_timestamp(old: &str, new: &str) -> bool {
+    let strip = |s: &str| {
+        s.lines()
+            .filter(|l| !l.starts_with("# Generated at:"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    strip(old) == strip(new)
+}
+
 /// 一時ファイルに書いてから原子的に`rename`で置換する安全な書き込み
-async fn atomic_write(path: &Path, content: &[u8]) -> Result<(), AppError> {
+pub(crate) async fn atomic_write(path: &Path, content: &[u8]) -> Result<(), AppError> {
     let dir = path.parent().unwrap_or_else(|| Path::new("."));
     let mut tmp_path = PathBuf::from(dir);
     let fname = path
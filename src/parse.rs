@@ -1,8 +1,19 @@
+use crate::common::IpFamily;
 use crate::error::AppError;
+use crate::interval::{IpRangeBuilder, IpRangeSet};
+use crate::metrics::registry;
+use crate::stats::stats;
 use ipnet::{IpNet, Ipv6Net};
-use std::collections::{BTreeSet, HashMap};
 use rayon::prelude::*;
-use rayon::join;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Deref;
+use std::time::Instant;
+
+/// 国コード → 正規化済みの区間集合（CIDR へは書き出す時に変換する）
+pub type CountryMap = HashMap<String, IpRangeSet>;
+
+/// 国コード → RIR の割り当ての区間（集約前）
+pub type CountrySets = HashMap<String, IpRangeBuilder>;
 
 pub fn parse_ip_lines(
     text: &str,
@@ -54,96 +65,230 @@ fn parse_ip_params(params: &[&str]) -> Result<Vec<IpNet>, AppError> {
 }
 
 fn parse_ipv6_range(start_str: &str, value_str: &str) -> Result<Vec<IpNet>, AppError> {
+    Ok(vec![IpNet::V6(parse_ipv6_net(start_str, value_str)?)])
+}
+
+fn parse_ipv6_net(start_str: &str, value_str: &str) -> Result<Ipv6Net, AppError> {
     let cidr = format!("{}/{}", start_str, value_str);
-    let net = cidr
-        .parse::<Ipv6Net>()
-        .map_err(|e| AppError::ParseError(format!("Ipv6Net parse error: {e}")))?;
-    Ok(vec![IpNet::V6(net)])
+    cidr.parse::<Ipv6Net>()
+        .map_err(|e| AppError::ParseError(format!("Ipv6Net parse error: {e}")))
 }
 
-pub fn parse_all_country_codes(
-    rir_texts: &[String],
-) -> Result<HashMap<String, (Vec<IpNet>, Vec<IpNet>)>, AppError> {
-    // RIRファイル単位のパースをrayonで並列化し、結果を順次マージ
-    let partials: Vec<Result<HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)>, AppError>> =
-        rir_texts
-            .par_iter()
-            .map(|text| parse_one_rir_text_to_sets(text))
-            .collect();
-
-    let mut country_sets: HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)> = HashMap::new();
-    for res in partials {
-        let map = res?;
-        for (cc, (v4s, v6s)) in map.into_iter() {
-            let entry = country_sets
-                .entry(cc)
-                .or_insert((BTreeSet::new(), BTreeSet::new()));
-            entry.0.extend(v4s);
-            entry.1.extend(v6s);
+/// RIR の1行が表す割り当ての区間（CIDR には分けない）
+#[derive(Debug, Clone, Copy)]
+enum RirRange {
+    V4(u32, u32),
+    V6(u128, u128),
+}
+
+impl RirRange {
+    fn parse(params: &[&str]) -> Result<Self, AppError> {
+        if params[2] == "ipv4" {
+            let (start, end) = crate::ipv4_utils::parse_ipv4_range(params[3], params[4])?;
+            Ok(Self::V4(start, end))
+        } else {
+            let net = parse_ipv6_net(params[3], params[4])?;
+            Ok(Self::V6(
+                u128::from(net.network()),
+                u128::from(net.broadcast()),
+            ))
         }
     }
 
-    // 集約してVecへ変換（最小CIDR化）— 国ごとに並列実行
-    let aggregated: Vec<(String, (Vec<IpNet>, Vec<IpNet>))> = country_sets
-        .into_iter()
-        .collect::<Vec<_>>()
-        .into_par_iter()
-        .map(|(cc, (v4set, v6set))| {
-            let v4_vec = v4set.iter().copied().collect::<Vec<_>>();
-            let v6_vec = v6set.iter().copied().collect::<Vec<_>>();
+    fn push_to(self, builder: &mut IpRangeBuilder) {
+        match self {
+            Self::V4(start, end) => builder.push_v4(start, end),
+            Self::V6(start, end) => builder.push_v6(start, end),
+        }
+    }
+}
 
-            let (agg_v4, agg_v6) = join(
-                || IpNet::aggregate(&v4_vec),
-                || IpNet::aggregate(&v6_vec),
-            );
+/// 残す国コード
+#[derive(Debug, Clone, Default)]
+pub enum CountryFilter {
+    /// すべての国（lookup / serve など全体のマップが必要な場合）
+    #[default]
+    All,
+    /// 指定した国だけ（大文字）
+    Only(HashSet<String>),
+}
 
-            (cc, (agg_v4, agg_v6))
-        })
-        .collect();
+impl CountryFilter {
+    /// 指定した国だけを残す
+    pub fn only<S: AsRef<str>>(codes: &[S]) -> Self {
+        Self::Only(
+            codes
+                .iter()
+                .map(|c| c.as_ref().to_ascii_uppercase())
+                .collect(),
+        )
+    }
 
-    let mut country_map: HashMap<String, (Vec<IpNet>, Vec<IpNet>)> = HashMap::new();
-    for (cc, pair) in aggregated {
-        country_map.insert(cc, pair);
+    pub fn wants(&self, country_code: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(codes) => codes.contains(country_code),
+        }
     }
-    Ok(country_map)
 }
 
-// 単一RIRテキストをパースし、国コード→(v4セット, v6セット)の部分結果を返す
-fn parse_one_rir_text_to_sets(
-    text: &str,
-) -> Result<HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)>, AppError> {
-    let mut country_sets: HashMap<String, (BTreeSet<IpNet>, BTreeSet<IpNet>)> = HashMap::new();
+/// RIR の delegated ファイルを1行ずつ解析する
+/// ダウンロード中の本文を任意の区切りのチャンクで `feed` でき、ファイル全体を保持しない。
+/// 割り当ては CIDR に分けずに区間のまま積み、対象外の国の行は読み飛ばす
+#[derive(Debug)]
+pub struct RirParser {
+    filter: CountryFilter,
+    /// RIR（registry 列）ごとの割り当てを統計に記録する（--stats）
+    collect_stats: bool,
+    /// チャンクの末尾で途切れた行
+    partial: Vec<u8>,
+    sets: CountrySets,
+    rir_space: BTreeMap<String, IpRangeBuilder>,
+    line_no: usize,
+}
 
-    for line in text.lines() {
+impl RirParser {
+    pub fn new(filter: CountryFilter) -> Self {
+        Self {
+            filter,
+            collect_stats: stats().is_enabled(),
+            partial: Vec::new(),
+            sets: HashMap::new(),
+            rir_space: BTreeMap::new(),
+            line_no: 0,
+        }
+    }
+
+    /// 本文の続きを与える（行の途中で区切られていてもよい）
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), AppError> {
+        let mut rest = chunk;
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            let (head, tail) = rest.split_at(pos);
+            rest = &tail[1..];
+            if self.partial.is_empty() {
+                self.feed_line_bytes(head)?;
+            } else {
+                let mut line = std::mem::take(&mut self.partial);
+                line.extend_from_slice(head);
+                self.feed_line_bytes(&line)?;
+            }
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(())
+    }
+
+    fn feed_line_bytes(&mut self, line: &[u8]) -> Result<(), AppError> {
+        self.line_no += 1;
+        let line = std::str::from_utf8(line).map_err(|e| {
+            AppError::ParseError(format!(
+                "RIR file line {}: invalid UTF-8: {}",
+                self.line_no, e
+            ))
+        })?;
+        self.parse_line(line.trim_end_matches('\r'))
+    }
+
+    /// 1行を解析する
+    pub fn parse_line(&mut self, line: &str) -> Result<(), AppError> {
         if line.starts_with('#') || line.contains('*') || line.contains("reserved") {
-            continue;
+            return Ok(());
         }
         let params: Vec<&str> = line.split('|').collect();
         if params.len() < 7 {
-            continue;
+            return Ok(());
         }
 
         let status = params[6].to_ascii_lowercase();
         if status != "allocated" && status != "assigned" {
-            continue;
+            return Ok(());
+        }
+        if params[2] != "ipv4" && params[2] != "ipv6" {
+            return Ok(());
         }
 
         let country_code = params[1].to_uppercase();
-        match params[2] {
-            "ipv4" | "ipv6" => {
-                let nets = parse_ip_params(&params)?;
-                let entry = country_sets
-                    .entry(country_code)
-                    .or_insert((BTreeSet::new(), BTreeSet::new()));
-                if params[2] == "ipv4" {
-                    for n in nets { entry.0.insert(n); }
-                } else {
-                    for n in nets { entry.1.insert(n); }
-                }
+        let wanted = self.filter.wants(&country_code);
+        if !wanted && !self.collect_stats {
+            return Ok(());
+        }
+        let range = RirRange::parse(&params)?;
+        if self.collect_stats {
+            range.push_to(
+                self.rir_space
+                    .entry(params[0].to_ascii_lowercase())
+                    .or_default(),
+            );
+        }
+        if wanted {
+            range.push_to(self.sets.entry(country_code).or_default());
+        }
+        Ok(())
+    }
+
+    /// 末尾の改行の無い行を解析し、国コード→区間の部分結果を返す
+    pub fn finish(mut self) -> Result<CountrySets, AppError> {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.feed_line_bytes(&line)?;
+        }
+        if self.collect_stats {
+            stats().record_rir_space(std::mem::take(&mut self.rir_space));
+        }
+        Ok(self.sets)
+    }
+}
+
+/// 取得済みの RIR テキストをすべての国について解析する
+/// `String` のほか、共有している `Arc<String>` もそのまま渡せる
+pub fn parse_all_country_codes<S>(rir_texts: &[S]) -> Result<CountryMap, AppError>
+where
+    S: Deref + Sync,
+    S::Target: AsRef<str>,
+{
+    let started = Instant::now();
+    // RIRファイル単位のパースをrayonで並列化し、結果を順次マージ
+    let partials: Vec<CountrySets> = rir_texts
+        .par_iter()
+        .map(|text| {
+            let mut parser = RirParser::new(CountryFilter::All);
+            for line in (**text).as_ref().lines() {
+                parser.parse_line(line)?;
+            }
+            parser.finish()
+        })
+        .collect::<Result<_, AppError>>()?;
+    let map = aggregate_country_sets(partials);
+    registry().record_parse(started.elapsed());
+    Ok(map)
+}
+
+/// ファイルごとの部分結果をマージし、国ごとに区間を正規化する
+pub fn aggregate_country_sets(partials: Vec<CountrySets>) -> CountryMap {
+    let mut partials = partials.into_iter();
+    let mut country_sets: CountrySets = partials.next().unwrap_or_default();
+    for map in partials {
+        for (cc, mut ranges) in map.into_iter() {
+            country_sets.entry(cc).or_default().append(&mut ranges);
+        }
+    }
+
+    {
+        // 集約前の数（--stats）。RIR の割り当てから求めた CIDR の数
+        let mut collector = stats();
+        if collector.is_enabled() {
+            for (cc, ranges) in &country_sets {
+                let (v4, v6) = ranges.distinct_cidr_len();
+                collector.record_source_prefixes("country", cc, IpFamily::V4, v4);
+                collector.record_source_prefixes("country", cc, IpFamily::V6, v6);
             }
-            _ => {}
         }
     }
 
-    Ok(country_sets)
+    // 国ごとに並列実行
+    country_sets
+        .into_iter()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(cc, ranges)| (cc, ranges.build()))
+        .collect()
 }
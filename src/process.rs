@@ -1,33 +1,33 @@
-use crate::common::OutputFormat;
 use crate::error::AppError;
-use crate::output::write_ip_lists_to_files;
-use ipnet::IpNet;
-use std::collections::{BTreeSet, HashMap};
+use crate::interval::IpRangeSet;
+use crate::output::{OutputOptions, write_ip_lists_to_files};
+use crate::parse::{CountryFilter, CountryMap, RirParser, aggregate_country_sets};
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use tokio::task::JoinHandle;
-use crate::common::debug_log;
+use tracing::{instrument, warn};
 
-/// 全RIRテキストから該当国コードのIP一覧を集約し、そのまま書き出し
+/// パース済みの国コードマップから該当国コードのIP一覧を書き出す
 pub async fn process_all_country_codes(
     country_codes: &[String],
-    rir_texts: &[String],
-    output_format: OutputFormat,
+    country_map: CountryMap,
+    output: &OutputOptions,
 ) -> Result<(), AppError> {
-    // 1回だけ全RIRテキストをパースして国コード→(IPv4,IPv6)のマップを作る（CPU重）
-    let rir_texts_owned = rir_texts.to_owned();
-    let country_map = tokio::task::spawn_blocking(move || {
-        crate::parse::parse_all_country_codes(&rir_texts_owned)
-    })
-    .await??;
     let country_map_arc = Arc::new(country_map);
+    // 正規化後に同じ国コード（jp と jpn など）は1度だけ書き出す（同じファイルへの同時書き込みを防ぐ）
+    let country_codes: BTreeSet<String> = country_codes
+        .iter()
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
 
     // 国コードごとに並列タスクを生成（事前パース結果を参照）
     let mut tasks: Vec<JoinHandle<Result<(), AppError>>> = Vec::new();
-    for code in country_codes {
+    for code in &country_codes {
         let code_cloned = code.clone();
         let map_cloned = Arc::clone(&country_map_arc);
+        let output_cloned = output.clone();
         tasks.push(tokio::spawn(async move {
-            crate::process::process_country_code_from_map(&code_cloned, &map_cloned, output_format)
+            crate::process::process_country_code_from_map(&code_cloned, &map_cloned, &output_cloned)
                 .await
         }));
     }
@@ -39,45 +39,41 @@ pub async fn process_all_country_codes(
     Ok(())
 }
 
-/// IPv4範囲をBTreeSetへ直接挿入
-/// 逐次集合化する
-// 旧実装の逐次パースヘルパは廃止（共通パーサへ統一）
-
-/// RIR テキストを1行ずつストリーミング解析し、重複排除しながら集合化
-/// 戻り値は **重複無し・昇順** の `BTreeSet`
+/// RIR テキストを1行ずつストリーミング解析し、対象国コードの区間集合にする
 pub fn parse_and_collect_ips(
     country_code: &str,
     rir_texts: &[String],
-) -> Result<(BTreeSet<IpNet>, BTreeSet<IpNet>), AppError> {
-    // 実装統一: 共通の全体パーサを用い、対象国コードのみ抽出
+) -> Result<IpRangeSet, AppError> {
+    // 共通のパーサで対象国コードの行だけを集合化する
     let cc_upper = country_code.to_ascii_uppercase();
-    let map = crate::parse::parse_all_country_codes(rir_texts)?;
-    let (v4_vec, v6_vec) = map.get(&cc_upper).cloned().unwrap_or_default();
-    let v4_set: BTreeSet<IpNet> = v4_vec.into_iter().collect();
-    let v6_set: BTreeSet<IpNet> = v6_vec.into_iter().collect();
-    Ok((v4_set, v6_set))
+    let mut parser = RirParser::new(CountryFilter::only(&[&cc_upper]));
+    for text in rir_texts {
+        for line in text.lines() {
+            parser.parse_line(line)?;
+        }
+    }
+    let mut map = aggregate_country_sets(vec![parser.finish()?]);
+    Ok(map.remove(&cc_upper).unwrap_or_default())
 }
 
+#[instrument(skip_all, fields(country = %country_code))]
 pub async fn process_country_code_from_map(
     country_code: &str,
-    country_map: &HashMap<String, (Vec<IpNet>, Vec<IpNet>)>,
-    output_format: OutputFormat,
+    country_map: &CountryMap,
+    output: &OutputOptions,
 ) -> Result<(), AppError> {
     let upper = country_code.to_ascii_uppercase();
-    let (v4_vec, v6_vec) = match country_map.get(&upper) {
-        Some(tup) => tup,
-        None => {
-            debug_log(format!("No IPs found for country code: {}", upper));
+    let set = match country_map.get(&upper) {
+        Some(set) if !set.is_empty() => set,
+        _ => {
+            // 既知の国コードでも RIR データに割り当てが無い場合は明示的に警告
+            warn!(
+                country = %upper,
+                "No IPs found for country code (no allocated/assigned prefixes in the RIR data)"
+            );
             return Ok(());
         }
     };
 
-    // CPU バウンドの aggregate を block_in_place で分離
-    let (ipv4_set, ipv6_set) = tokio::task::block_in_place(|| {
-        let v4_set = IpNet::aggregate(&v4_vec).into_iter().collect();
-        let v6_set = IpNet::aggregate(&v6_vec).into_iter().collect();
-        (v4_set, v6_set)
-    });
-
-    write_ip_lists_to_files(&upper, &ipv4_set, &ipv6_set, output_format).await
+    write_ip_lists_to_files(&upper, set, output).await
 }
@@ -0,0 +1,99 @@
+//! ホストごとのトークンバケットによる流量制限
+//!
+//! JSON API（RIPEstat / ARIN RDAP）への問い合わせを、並列数（`--concurrency`）とは別に
+//! 1秒あたりのリクエスト数で制限する。429 / Retry-After を受けたホストは、
+//! 指定時間が経つまで全タスクの問い合わせを止める。
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::{Instant, sleep_until};
+
+/// 1ホスト分のバケット
+#[derive(Debug)]
+struct Bucket {
+    /// 残りトークン（予約済みの分だけ負になる）
+    tokens: f64,
+    last: Instant,
+}
+
+/// ホストごとのトークンバケット
+/// `rate` 0 以下は無制限
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// `rate` リクエスト/秒、最大 `burst` 件まで連続で送れるリミッタ
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst: burst.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 制限なし
+    pub fn unlimited() -> Self {
+        Self::new(0.0, 1.0)
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.rate <= 0.0
+    }
+
+    /// トークンを1つ予約し、使えるようになるまで待つ
+    pub async fn acquire(&self, host: &str) {
+        if let Some(at) = self.reserve(host) {
+            sleep_until(at).await;
+        }
+    }
+
+    /// トークンを1つ予約し、待つ必要があれば送信可能になる時刻を返す
+    fn reserve(&self, host: &str) -> Option<Instant> {
+        if self.is_unlimited() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = self.refilled(&mut buckets, host, now);
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            None
+        } else {
+            Some(now + Duration::from_secs_f64(-bucket.tokens / self.rate))
+        }
+    }
+
+    /// ホストへの問い合わせを `wait` の間止める（429 / Retry-After 受信時）
+    pub fn defer(&self, host: &str, wait: Duration) {
+        if self.is_unlimited() {
+            return;
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let rate = self.rate;
+        let bucket = self.refilled(&mut buckets, host, now);
+        // トークンが0に戻るまでに `wait` かかるよう負債を積む
+        bucket.tokens = bucket.tokens.min(-wait.as_secs_f64() * rate);
+    }
+
+    fn refilled<'a>(
+        &self,
+        buckets: &'a mut HashMap<String, Bucket>,
+        host: &str,
+        now: Instant,
+    ) -> &'a mut Bucket {
+        let bucket = buckets.entry(host.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            last: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+        bucket
+    }
+}
@@ -0,0 +1,375 @@
+//! 機械可読な実行レポート（`--report <FILE>`）
+//!
+//! 1回の実行の入力、使ったデータソース、失敗、書き出したファイル（SHA-256 付き）、
+//! カスタマーコーンの各メンバーが寄与したプレフィックス、AS名、重複の要素ごとの起源AS と国コード、取り除いた bogon、
+//! エントリ数の調整と終了コードを JSON で書き出す
+//! （外部のジョブ管理から結果を判定するため）。
+//! 記録は `--report` 指定時のみ有効にする。常駐モード（watch / serve）では記録しない。
+
+use crate::asname::{AsNames, AttributedPrefix};
+use crate::bogon::BogonRemoval;
+use crate::cli::Command;
+use crate::common::{Asn, expand_asns};
+use crate::cone::CustomerCone;
+use crate::error::{AppError, exit_code};
+use crate::output_common::atomic_write;
+use crate::shape::ShapeReport;
+use chrono::{DateTime, Local, SecondsFormat};
+use ipnet::IpNet;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+static JOURNAL: Mutex<Journal> = Mutex::new(Journal::new());
+
+/// プロセス全体の記録
+pub fn journal() -> MutexGuard<'static, Journal> {
+    JOURNAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 取得を試みたデータソース
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceRecord {
+    pub url: String,
+    pub ok: bool,
+}
+
+/// 失敗したデータソースや問い合わせ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailureRecord {
+    /// 失敗の種類（rir / asn）
+    pub kind: String,
+    /// URL や AS番号
+    pub target: String,
+    pub reason: String,
+}
+
+/// 書き出したファイル
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutputRecord {
+    pub path: String,
+    /// 前回の内容から変化したか（生成日時の行は比較しない）
+    pub changed: bool,
+    pub entries: usize,
+    /// 書き出した内容の SHA-256（16進）
+    pub sha256: String,
+}
+
+/// カスタマーコーンの1メンバー（起点を含む）と、そのメンバーから得たプレフィックス
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConeMemberRecord {
+    pub asn: Asn,
+    /// 起点からの p2c の段数（起点は 0、ppdc-ases から求めた場合は `null`）
+    pub depth: Option<usize>,
+    /// たどった直上の provider
+    pub via: Option<Asn>,
+    pub prefixes: Vec<String>,
+}
+
+/// 展開したカスタマーコーン
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConeRecord {
+    pub root: Asn,
+    pub members: Vec<ConeMemberRecord>,
+    pub depth_limited: bool,
+    pub size_limited: bool,
+}
+
+/// 書き出す前のプレフィックス長の調整と過剰集約の結果（ファミリごと）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShapeRecord {
+    /// 出力ファイル名（拡張子とファミリを除く）
+    pub list: String,
+    #[serde(flatten)]
+    pub report: ShapeReport,
+}
+
+/// 出力から取り除いた bogon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BogonRecord {
+    /// 出力ファイル名（拡張子とファミリを除く）
+    pub list: String,
+    pub removed: Vec<BogonRemoval>,
+}
+
+/// 重複の出力の要素ごとの起源AS と国コード
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttributionRecord {
+    /// 出力ファイル名（拡張子とファミリを除く）
+    pub list: String,
+    pub elements: Vec<AttributedPrefix>,
+}
+
+/// 実行中の記録（有効化されるまでは何も記録しない）
+#[derive(Debug, Default)]
+pub struct Journal {
+    enabled: bool,
+    sources: Vec<SourceRecord>,
+    failures: Vec<FailureRecord>,
+    outputs: Vec<OutputRecord>,
+    cones: Vec<ConeRecord>,
+    as_names: BTreeMap<Asn, String>,
+    attribution: Vec<AttributionRecord>,
+    shaping: Vec<ShapeRecord>,
+    bogons: Vec<BogonRecord>,
+}
+
+impl Journal {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            sources: Vec::new(),
+            failures: Vec::new(),
+            outputs: Vec::new(),
+            cones: Vec::new(),
+            as_names: BTreeMap::new(),
+            attribution: Vec::new(),
+            shaping: Vec::new(),
+            bogons: Vec::new(),
+        }
+    }
+
+    /// 記録を有効にする
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// データソースの取得結果（同じURLは最後の結果で上書き）
+    pub fn record_source(&mut self, url: &str, ok: bool) {
+        if !self.enabled {
+            return;
+        }
+        match self.sources.iter_mut().find(|s| s.url == url) {
+            Some(s) => s.ok = ok,
+            None => self.sources.push(SourceRecord {
+                url: url.to_string(),
+                ok,
+            }),
+        }
+    }
+
+    pub fn record_failure(&mut self, kind: &str, target: &str, reason: &str) {
+        if !self.enabled {
+            return;
+        }
+        self.failures.push(FailureRecord {
+            kind: kind.to_string(),
+            target: target.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn record_output(&mut self, path: &Path, changed: bool, entries: usize, content: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+        self.outputs.push(OutputRecord {
+            path: path.display().to_string(),
+            changed,
+            entries,
+            sha256: sha256_hex(content),
+        });
+    }
+
+    /// 展開したカスタマーコーン（プレフィックスは `record_prefixes` で埋める）
+    pub fn record_cone(&mut self, cone: &CustomerCone) {
+        if !self.enabled {
+            return;
+        }
+        let root = ConeMemberRecord {
+            asn: cone.root,
+            depth: Some(0),
+            via: None,
+            prefixes: Vec::new(),
+        };
+        let members = cone.members.iter().map(|m| ConeMemberRecord {
+            asn: m.asn,
+            depth: m.depth,
+            via: m.via,
+            prefixes: Vec::new(),
+        });
+        self.cones.push(ConeRecord {
+            root: cone.root,
+            members: std::iter::once(root).chain(members).collect(),
+            depth_limited: cone.depth_limited,
+            size_limited: cone.size_limited,
+        });
+    }
+
+    /// 出力に付けた AS名
+    pub fn record_as_names(&mut self, names: &AsNames) {
+        if !self.enabled {
+            return;
+        }
+        self.as_names
+            .extend(names.iter().map(|(&asn, name)| (Asn(asn), name.clone())));
+    }
+
+    /// 重複の出力の要素ごとの起源AS と国コード
+    pub fn record_attribution(&mut self, list: &str, elements: Vec<AttributedPrefix>) {
+        if !self.enabled {
+            return;
+        }
+        self.attribution.push(AttributionRecord {
+            list: list.to_string(),
+            elements,
+        });
+    }
+
+    /// 書き出す前の調整の結果
+    pub fn record_shaping(&mut self, list: &str, reports: Vec<ShapeReport>) {
+        if !self.enabled {
+            return;
+        }
+        self.shaping
+            .extend(reports.into_iter().map(|report| ShapeRecord {
+                list: list.to_string(),
+                report,
+            }));
+    }
+
+    /// 出力から取り除いた bogon
+    pub fn record_bogons(&mut self, list: &str, removed: Vec<BogonRemoval>) {
+        if !self.enabled {
+            return;
+        }
+        self.bogons.push(BogonRecord {
+            list: list.to_string(),
+            removed,
+        });
+    }
+
+    /// AS から得たプレフィックスを、その AS を含むコーンの記録に付ける
+    pub fn record_prefixes<'a>(
+        &mut self,
+        asn: &str,
+        prefixes: impl IntoIterator<Item = &'a IpNet>,
+    ) {
+        if !self.enabled || self.cones.is_empty() {
+            return;
+        }
+        let Ok(asn) = asn.parse::<Asn>() else { return };
+        let prefixes: Vec<String> = prefixes.into_iter().map(IpNet::to_string).collect();
+        for member in self.cones.iter_mut().flat_map(|c| c.members.iter_mut()) {
+            if member.asn == asn {
+                member.prefixes = prefixes.clone();
+            }
+        }
+    }
+}
+
+/// 実行の入力（サブコマンドの引数）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunInputs {
+    pub countries: Vec<String>,
+    pub as_numbers: Vec<Asn>,
+    pub profiles: Vec<String>,
+}
+
+impl RunInputs {
+    pub fn from_command(command: &Command) -> Self {
+        match command {
+            Command::Country(a) => Self {
+                countries: a.codes.clone(),
+                ..Self::default()
+            },
+            Command::Asn(a) => Self {
+                as_numbers: expand_asns(&a.as_numbers),
+                ..Self::default()
+            },
+            Command::Overlap(a) => Self {
+                countries: a.country_codes.clone(),
+                as_numbers: expand_asns(&a.as_numbers),
+                ..Self::default()
+            },
+            Command::IrrCompare(a) => Self {
+                as_numbers: expand_asns(&a.as_numbers),
+                ..Self::default()
+            },
+            Command::Run(a) => Self {
+                profiles: a.profiles.clone(),
+                ..Self::default()
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// JSON で書き出すレポート
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    pub version: &'static str,
+    /// サブコマンド名（解決できなかった場合は `null`）
+    pub command: Option<&'static str>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub exit_code: u8,
+    pub error: Option<String>,
+    pub inputs: RunInputs,
+    pub sources: Vec<SourceRecord>,
+    pub failures: Vec<FailureRecord>,
+    pub outputs: Vec<OutputRecord>,
+    /// `--customer-cone` で展開したコーン（メンバーごとのプレフィックス付き）
+    pub cones: Vec<ConeRecord>,
+    /// `--as-names` で付けた AS名（AS番号 → 名前）
+    pub as_names: BTreeMap<Asn, String>,
+    /// `--attribute-overlaps` で重複の要素に付けた起源AS と国コード（リストごと）
+    pub attribution: Vec<AttributionRecord>,
+    /// `--drop-longer-than-*` / `--widen-to-*` / `--max-entries` による調整（リスト・ファミリごと）
+    pub shaping: Vec<ShapeRecord>,
+    /// 出力から取り除いた bogon（`--keep-bogons` では空）
+    pub bogons: Vec<BogonRecord>,
+}
+
+impl RunReport {
+    /// 実行結果と記録からレポートを作り、記録を空にする
+    pub fn finish(
+        started_at: DateTime<Local>,
+        command: Option<&Command>,
+        result: &Result<(), AppError>,
+    ) -> Self {
+        let mut journal = journal();
+        let (exit_code, error) = match result {
+            Ok(()) => (exit_code::SUCCESS, None),
+            Err(AppError::NoChange) => (exit_code::NO_CHANGE, None),
+            Err(e) => (e.exit_code(), Some(e.to_string())),
+        };
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            command: command.map(Command::name),
+            started_at: started_at.to_rfc3339_opts(SecondsFormat::Secs, false),
+            finished_at: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            exit_code,
+            error,
+            inputs: command.map(RunInputs::from_command).unwrap_or_default(),
+            sources: std::mem::take(&mut journal.sources),
+            failures: std::mem::take(&mut journal.failures),
+            outputs: std::mem::take(&mut journal.outputs),
+            cones: std::mem::take(&mut journal.cones),
+            as_names: std::mem::take(&mut journal.as_names),
+            attribution: std::mem::take(&mut journal.attribution),
+            shaping: std::mem::take(&mut journal.shaping),
+            bogons: std::mem::take(&mut journal.bogons),
+        }
+    }
+
+    /// レポートを原子的に書き出す
+    pub async fn write(&self, path: &Path) -> Result<(), AppError> {
+        let mut json = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::Other(format!("Failed to serialize run report: {}", e)))?;
+        json.push('\n');
+        atomic_write(path, json.as_bytes()).await
+    }
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    let digest = Sha256::digest(content);
+    let mut out = String::with_capacity(digest.len() * 2);
+    for b in digest.iter() {
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
@@ -0,0 +1,511 @@
+//! HTTPサーバ（serve）モード
+//!
+//! パース済みのRIRデータとAS番号ごとのプレフィックスをメモリに保持し、リストをHTTPで配信する。
+//! - `GET /country/{cc}/{v4|v6}.{txt|nft}`
+//! - `GET /as/{asn}/{v4|v6}.{txt|nft}`
+//! - `GET /overlap?cc=JP,KR&as=2497,4713[&family=v4|v6][&format=txt|nft]`
+//! - `GET /healthz`
+//! - `GET /metrics`（Prometheus テキスト形式）
+//!
+//! 応答には内容から計算した弱いETagを付け、`If-None-Match` が一致すれば 304 を返す。
+//! RIRファイルは条件付きGETで、ASは一度要求されたものだけをバックグラウンドで定期更新する。
+
+use crate::asn::get_prefixes_via_rdap;
+use crate::common::{Asn, IpFamily, OutputFormat};
+use crate::common_download::DownloadOptions;
+use crate::country::normalize_country_code;
+use crate::error::AppError;
+use crate::fetch::ApiPolicy;
+use crate::interval::IpRangeSet;
+use crate::metrics::registry;
+use crate::output::OutputOptions;
+use crate::output_common::{make_header, render_list_nft, render_list_txt, sanitize_identifier};
+use crate::parse::{CountryFilter, CountryMap};
+use crate::watch::{RefreshSummary, RirCache};
+use axum::Router;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use chrono::Local;
+use ipnet::IpNet;
+use reqwest::Client;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+/// メモリに保持するAS番号の上限（任意のAS番号の要求で際限なく増えないようにする）
+/// 超えた場合は最も長く要求されていないASを捨てる
+pub const MAX_CACHED_AS: usize = 1024;
+
+/// 容量を超えると最も長く使われていないキーから捨てるマップ
+#[derive(Debug)]
+pub struct LruMap<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+}
+
+impl<K: Eq + Hash + Copy, V: Clone> LruMap<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// 値を返し、使ったものとして記録する
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|(value, used)| {
+            *used = tick;
+            value.clone()
+        })
+    }
+
+    /// 値を入れて使ったものとして記録し、容量を超えた場合に捨てたキーを返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<K> {
+        self.tick += 1;
+        self.entries.insert(key, (value, self.tick));
+        if self.entries.len() <= self.capacity {
+            return None;
+        }
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (_, used))| *used)
+            .map(|(k, _)| *k)?;
+        self.entries.remove(&oldest);
+        Some(oldest)
+    }
+
+    /// 既にあるキーの値だけを置き換える（使った順は変えない）
+    pub fn replace(&mut self, key: &K, value: V) -> bool {
+        match self.entries.get_mut(key) {
+            Some((old, _)) => {
+                *old = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn keys(&self) -> Vec<K> {
+        self.entries.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// 取得時刻付きのデータ
+#[derive(Debug, Clone)]
+struct Snapshot<T> {
+    data: T,
+    updated_at: String,
+}
+
+/// サーバの共有状態
+pub struct ServeState {
+    client: Client,
+    download: DownloadOptions,
+    output: OutputOptions,
+    api: Arc<ApiPolicy>,
+    rir: Mutex<RirCache>,
+    countries: RwLock<Option<Snapshot<Arc<CountryMap>>>>,
+    as_cache: std::sync::Mutex<LruMap<u32, Snapshot<Arc<IpRangeSet>>>>,
+}
+
+impl ServeState {
+    /// `output` は除外CIDRのみを使う（形式はURLで指定する）
+    /// `api` は AS の問い合わせに使う JSON API の方針
+    pub fn new(
+        client: Client,
+        download: DownloadOptions,
+        output: OutputOptions,
+        api: Arc<ApiPolicy>,
+    ) -> Self {
+        Self {
+            client,
+            download,
+            output,
+            api,
+            // URL でどの国でも求められるため全体を保持する
+            rir: Mutex::new(RirCache::new(CountryFilter::All)),
+            countries: RwLock::new(None),
+            as_cache: std::sync::Mutex::new(LruMap::new(MAX_CACHED_AS)),
+        }
+    }
+
+    /// RIRデータとキャッシュ済みASを更新する
+    /// 失敗したものは前回の内容を残す（ASの失敗は警告のみ）
+    pub async fn refresh(&self) -> Result<RefreshSummary, AppError> {
+        let mut summary = RefreshSummary::default();
+        let map = self
+            .rir
+            .lock()
+            .await
+            .refresh(&self.client, &self.download, &mut summary)
+            .await?;
+        if summary.reparsed {
+            *self.countries.write().unwrap_or_else(|e| e.into_inner()) = Some(Snapshot {
+                data: map,
+                updated_at: now_str(),
+            });
+        }
+
+        let cached: Vec<u32> = self.lock_as_cache().keys();
+        for asn in cached {
+            match get_prefixes_via_rdap(&self.client, &asn.to_string(), &self.api).await {
+                // 更新中に捨てられたASは入れ直さない
+                Ok(sets) => {
+                    self.lock_as_cache().replace(&asn, snapshot_now(sets));
+                }
+                Err(e) => warn!(asn, error = %e, "AS refresh failed, keeping previous prefixes"),
+            }
+        }
+        Ok(summary)
+    }
+
+    /// `interval` ごとに `refresh` を繰り返す（初回は即時）
+    pub async fn refresh_loop(&self, interval: Duration) {
+        loop {
+            match self.refresh().await {
+                Ok(summary) => {
+                    registry().record_run_success();
+                    debug!(?summary, "Refresh finished");
+                }
+                Err(e) => warn!(error = %e, "Refresh failed, keeping previous data"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    fn countries(&self) -> Option<Snapshot<Arc<CountryMap>>> {
+        self.countries
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn lock_as_cache(&self) -> std::sync::MutexGuard<'_, LruMap<u32, Snapshot<Arc<IpRangeSet>>>> {
+        self.as_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// キャッシュ済みならそれを、無ければ取得してキャッシュしたASのプレフィックスを返す
+    async fn as_sets(&self, asn: u32) -> Result<Snapshot<Arc<IpRangeSet>>, AppError> {
+        if let Some(hit) = self.lock_as_cache().get(&asn) {
+            return Ok(hit);
+        }
+        let sets = get_prefixes_via_rdap(&self.client, &asn.to_string(), &self.api).await?;
+        let snapshot = snapshot_now(sets);
+        if let Some(evicted) = self.lock_as_cache().insert(asn, snapshot.clone()) {
+            debug!(
+                asn = evicted,
+                "AS cache is full, dropped least recently requested AS"
+            );
+        }
+        Ok(snapshot)
+    }
+}
+
+fn snapshot_now((v4, v6): (BTreeSet<IpNet>, BTreeSet<IpNet>)) -> Snapshot<Arc<IpRangeSet>> {
+    Snapshot {
+        data: Arc::new(IpRangeSet::from_nets(v4.iter().chain(&v6))),
+        updated_at: now_str(),
+    }
+}
+
+/// ルーティング
+pub fn router(state: Arc<ServeState>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .route("/country/{cc}/{file}", get(country_list))
+        .route("/as/{asn}/{file}", get(as_list))
+        .route("/overlap", get(overlap_list))
+        .with_state(state)
+}
+
+/// 待ち受けてリクエストを処理する（終了しない）
+pub async fn serve(listener: TcpListener, state: Arc<ServeState>) -> Result<(), AppError> {
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+async fn healthz(State(state): State<Arc<ServeState>>) -> Response {
+    match state.countries() {
+        Some(_) => (StatusCode::OK, "ok\n").into_response(),
+        None => error_response(StatusCode::SERVICE_UNAVAILABLE, "RIR data not loaded yet"),
+    }
+}
+
+async fn metrics() -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+        )],
+        registry().render(),
+    )
+        .into_response()
+}
+
+async fn country_list(
+    State(state): State<Arc<ServeState>>,
+    Path((cc, file)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some((family, format)) = parse_list_file(&file) else {
+        return not_found();
+    };
+    let code = match normalize_country_code(&cc) {
+        Ok(code) => code,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e),
+    };
+    let Some(snapshot) = state.countries() else {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "RIR data not loaded yet");
+    };
+
+    let set = snapshot
+        .data
+        .get(&code)
+        .map(|set| set.only(family))
+        .unwrap_or_default();
+    let header = make_header(&snapshot.updated_at, &code, "N/A");
+    let name = format!("{}_{}", family.as_str(), code);
+    list_response(
+        &headers,
+        format,
+        &name,
+        &state.output.prepare(&set),
+        &header,
+    )
+}
+
+async fn as_list(
+    State(state): State<Arc<ServeState>>,
+    Path((asn, file)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some((family, format)) = parse_list_file(&file) else {
+        return not_found();
+    };
+    let asn = match parse_as_number(&asn) {
+        Ok(n) => n,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e),
+    };
+    let snapshot = match state.as_sets(asn).await {
+        Ok(s) => s,
+        Err(e) => return error_response(StatusCode::BAD_GATEWAY, &e.to_string()),
+    };
+
+    let set = snapshot.data.only(family);
+    let header = make_header(&snapshot.updated_at, "N/A", &asn.to_string());
+    let name = format!("AS_{}_{}", asn, family.as_str());
+    list_response(
+        &headers,
+        format,
+        &name,
+        &state.output.prepare(&set),
+        &header,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct OverlapQuery {
+    cc: String,
+    #[serde(rename = "as")]
+    asn: String,
+    family: Option<String>,
+    format: Option<String>,
+}
+
+async fn overlap_list(
+    State(state): State<Arc<ServeState>>,
+    Query(q): Query<OverlapQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let codes = match split_list(&q.cc)
+        .map(normalize_country_code)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(c) if !c.is_empty() => c,
+        Ok(_) => return error_response(StatusCode::BAD_REQUEST, "cc is empty"),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e),
+    };
+    let as_numbers = match split_list(&q.asn)
+        .map(parse_as_number)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(a) if !a.is_empty() => a,
+        Ok(_) => return error_response(StatusCode::BAD_REQUEST, "as is empty"),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &e),
+    };
+    let family = match q.family.as_deref() {
+        None => None,
+        Some("v4") => Some(IpFamily::V4),
+        Some("v6") => Some(IpFamily::V6),
+        Some(other) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("invalid family '{}' (valid: v4, v6)", other),
+            );
+        }
+    };
+    let format = match q.format.as_deref().map(OutputFormat::from_str).transpose() {
+        Ok(f) => f.unwrap_or(OutputFormat::Txt),
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let Some(snapshot) = state.countries() else {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "RIR data not loaded yet");
+    };
+
+    let mut country = IpRangeSet::new();
+    for code in &codes {
+        if let Some(set) = snapshot.data.get(code) {
+            country = country.union(set);
+        }
+    }
+    let mut as_ips = IpRangeSet::new();
+    for asn in &as_numbers {
+        match state.as_sets(*asn).await {
+            Ok(s) => as_ips = as_ips.union(&s.data),
+            Err(e) => return error_response(StatusCode::BAD_GATEWAY, &e.to_string()),
+        }
+    }
+
+    let mut overlap = country.intersection(&as_ips);
+    if let Some(f) = family {
+        overlap = overlap.only(f);
+    }
+
+    let cc_label = codes.join("_");
+    let as_label = as_numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join("_");
+    let header = make_header(&snapshot.updated_at, &cc_label, &as_label);
+    let mut name = format!("overlap_{}_{}", cc_label, as_label);
+    if let Some(f) = family {
+        name.push('_');
+        name.push_str(f.as_str());
+    }
+    list_response(
+        &headers,
+        format,
+        &name,
+        &state.output.prepare(&overlap),
+        &header,
+    )
+}
+
+/// リスト本文の応答を作る（If-None-Match が一致すれば 304）
+fn list_response(
+    req_headers: &HeaderMap,
+    format: OutputFormat,
+    name: &str,
+    nets: &BTreeSet<IpNet>,
+    header: &str,
+) -> Response {
+    // 生成日時のヘッダ行は含めずに計算するため弱いETagとする
+    let etag = list_etag(format, name, nets);
+    let matched = req_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| {
+            let t = t.trim();
+            t == "*" || t.trim_start_matches("W/") == etag.trim_start_matches("W/")
+        });
+
+    let etag_value =
+        HeaderValue::from_str(&etag).unwrap_or_else(|_| HeaderValue::from_static("W/\"0\""));
+    if matched {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag_value)]).into_response();
+    }
+
+    let body = match format {
+        OutputFormat::Txt => render_list_txt(nets, header),
+        OutputFormat::Nft => render_list_nft(name, nets, header),
+    };
+    (
+        StatusCode::OK,
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            ),
+            (header::ETAG, etag_value),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// 形式・名前・CIDR から求めた SHA-256 の先頭128ビット（Rust のバージョンやプロセスに依らない）
+fn list_etag(format: OutputFormat, name: &str, nets: &BTreeSet<IpNet>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{format:?}"));
+    hasher.update([0]);
+    hasher.update(sanitize_identifier(name));
+    for net in nets {
+        hasher.update([0]);
+        hasher.update(net.to_string());
+    }
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    format!("W/\"{hex}\"")
+}
+
+/// `v4.txt` / `v6.nft` などをファミリと形式に分解
+fn parse_list_file(file: &str) -> Option<(IpFamily, OutputFormat)> {
+    let (fam, ext) = file.split_once('.').filter(|(_, ext)| !ext.is_empty())?;
+    let family = match fam {
+        "v4" => IpFamily::V4,
+        "v6" => IpFamily::V6,
+        _ => return None,
+    };
+    let format = OutputFormat::from_str(ext).ok()?;
+    Some((family, format))
+}
+
+/// `13335` / `AS13335` / asdot（`1.10`）形式のAS番号
+fn parse_as_number(s: &str) -> Result<u32, String> {
+    s.parse::<Asn>().map(|asn| asn.0)
+}
+
+fn split_list(s: &str) -> impl Iterator<Item = &str> {
+    s.split(',').map(str::trim).filter(|t| !t.is_empty())
+}
+
+fn now_str() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn not_found() -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "expected {v4|v6}.{txt|nft}, e.g. /country/JP/v4.txt",
+    )
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response {
+    (status, format!("{}\n", msg)).into_response()
+}
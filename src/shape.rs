@@ -0,0 +1,405 @@
+//! エントリ数に上限のある出力先向けのプレフィックス長の調整と過剰集約
+//!
+//! ハードウェア ACL（TCAM）やクラウドのセキュリティグループは、アドレス数ではなく
+//! エントリ数に上限がある。書き出す前にファミリごとに次の順で適用する。
+//! 1. `drop-longer-than`: /N より長いプレフィックスを取り除く
+//! 2. `widen-to`: /N より長いプレフィックスを /N へ広げる
+//! 3. `max-entries`: 隣り合うプレフィックスを、増えるアドレス空間が最も少ない組から
+//!    共通の上位プレフィックスへまとめ、K エントリ以下にする
+//!
+//! 2 と 3 は元の集合に無いアドレスを含めるため、増えたアドレス数を `ShapeReport` で報告する。
+//! 避ける範囲（bogon と除外CIDR）を指定した場合、2 ではそれに重なる /N へは広げずに元の長さのまま残し
+//! （`widen-to` より長いものが残るため、その数を報告する）、3 ではそれに重なる上位プレフィックスへはまとめない。
+
+use crate::common::IpFamily;
+use crate::interval::IpRangeSet;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// プレフィックス長の調整と過剰集約の設定（すべて `None` なら何もしない）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShapeOptions {
+    /// IPv4 でこれより長いプレフィックスを取り除く
+    pub drop_longer_than_v4: Option<u8>,
+    /// IPv6 でこれより長いプレフィックスを取り除く
+    pub drop_longer_than_v6: Option<u8>,
+    /// IPv4 でこれより長いプレフィックスをこの長さへ広げる
+    pub widen_to_v4: Option<u8>,
+    /// IPv6 でこれより長いプレフィックスをこの長さへ広げる
+    pub widen_to_v6: Option<u8>,
+    /// 1つのリスト（ファミリ）あたりの最大エントリ数
+    pub max_entries: Option<usize>,
+}
+
+impl ShapeOptions {
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn for_family(&self, family: IpFamily) -> (Option<u8>, Option<u8>) {
+        match family {
+            IpFamily::V4 => (self.drop_longer_than_v4, self.widen_to_v4),
+            IpFamily::V6 => (self.drop_longer_than_v6, self.widen_to_v6),
+        }
+    }
+}
+
+/// 1ファミリ分の調整結果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShapeReport {
+    /// `IPv4` / `IPv6`
+    pub family: &'static str,
+    pub entries_before: usize,
+    pub entries_after: usize,
+    /// `drop-longer-than` で取り除いたプレフィックス数
+    pub dropped: usize,
+    /// `widen-to` で広げたプレフィックス数
+    pub widened: usize,
+    /// 広げると避ける範囲に重なるため、元の長さのまま残したプレフィックス数
+    pub not_widened: usize,
+    /// 取り除いたアドレス数
+    pub removed_addresses: u128,
+    /// 広げた・まとめたことで増えたアドレス数
+    pub added_addresses: u128,
+    /// 調整前のアドレス数
+    pub addresses_before: u128,
+}
+
+impl ShapeReport {
+    /// 集約以外の変更があったか
+    pub fn changed(&self) -> bool {
+        self.dropped > 0 || self.widened > 0 || self.not_widened > 0 || self.added_addresses > 0
+    }
+
+    /// 人が読むための1行の集計
+    pub fn summary(&self, list: &str) -> String {
+        let mut out = format!(
+            "{} {}: {} -> {} entries",
+            list, self.family, self.entries_before, self.entries_after
+        );
+        if self.dropped > 0 {
+            out.push_str(&format!(
+                ", dropped {} prefix(es) ({} addresses)",
+                self.dropped, self.removed_addresses
+            ));
+        }
+        if self.widened > 0 {
+            out.push_str(&format!(", widened {} prefix(es)", self.widened));
+        }
+        if self.not_widened > 0 {
+            out.push_str(&format!(
+                ", kept {} prefix(es) longer than widen-to to avoid bogon or excluded ranges",
+                self.not_widened
+            ));
+        }
+        if self.added_addresses > 0 {
+            let pct = if self.addresses_before == 0 {
+                0.0
+            } else {
+                self.added_addresses as f64 / self.addresses_before as f64 * 100.0
+            };
+            out.push_str(&format!(
+                ", +{} addresses (+{:.2}%) not in the source data",
+                self.added_addresses, pct
+            ));
+        }
+        out
+    }
+}
+
+/// 集合に調整を適用する（無効なら集約もせずそのまま返す）
+/// `avoid` の範囲は広げたりまとめたりしても含めない
+/// 戻り値の報告は、要素のあるファミリごとに1つ
+pub fn shape_nets(
+    set: &BTreeSet<IpNet>,
+    opts: &ShapeOptions,
+    avoid: &[IpNet],
+) -> (BTreeSet<IpNet>, Vec<ShapeReport>) {
+    if opts.is_noop() {
+        return (set.clone(), Vec::new());
+    }
+    let mut out = BTreeSet::new();
+    let mut reports = Vec::new();
+    for family in [IpFamily::V4, IpFamily::V6] {
+        let nets: Vec<IpNet> = set
+            .iter()
+            .copied()
+            .filter(|n| IpFamily::of(n) == family)
+            .collect();
+        if nets.is_empty() {
+            continue;
+        }
+        let (shaped, report) = shape_family(&nets, family, opts, avoid);
+        out.extend(shaped);
+        reports.push(report);
+    }
+    (out, reports)
+}
+
+fn shape_family(
+    nets: &[IpNet],
+    family: IpFamily,
+    opts: &ShapeOptions,
+    avoid: &[IpNet],
+) -> (Vec<IpNet>, ShapeReport) {
+    let (drop_longer_than, widen_to) = opts.for_family(family);
+    let aggregated = IpNet::aggregate(&nets.to_vec());
+    let addresses_before = total_size(&aggregated);
+
+    let (kept, removed): (Vec<IpNet>, Vec<IpNet>) = aggregated
+        .into_iter()
+        .partition(|n| drop_longer_than.is_none_or(|len| n.prefix_len() <= len));
+    let removed_addresses = total_size(&removed);
+
+    let avoided = IpRangeSet::from_nets(avoid);
+    let (mut widened, mut not_widened) = (0, 0);
+    let kept: Vec<IpNet> = kept
+        .into_iter()
+        .map(|n| match widen_to {
+            Some(len) if n.prefix_len() > len => {
+                let wide = IpNet::new(n.addr(), len).map(|w| w.trunc()).unwrap_or(n);
+                if avoided.overlaps_net(&wide) {
+                    not_widened += 1;
+                    n
+                } else {
+                    widened += 1;
+                    wide
+                }
+            }
+            _ => n,
+        })
+        .collect();
+    let mut shaped = IpNet::aggregate(&kept);
+    if let Some(k) = opts.max_entries {
+        shaped = compress(&shaped, family, k, avoid);
+    }
+
+    let kept_addresses = addresses_before.saturating_sub(removed_addresses);
+    let report = ShapeReport {
+        family: family.as_str(),
+        entries_before: nets.len(),
+        entries_after: shaped.len(),
+        dropped: removed.len(),
+        widened,
+        not_widened,
+        removed_addresses,
+        added_addresses: total_size(&shaped).saturating_sub(kept_addresses),
+        addresses_before,
+    };
+    (shaped, report)
+}
+
+/// プレフィックスのアドレス数（IPv6 の /0 は u128 に収まらないため飽和させる）
+fn block_size(bits: u8, len: u8) -> u128 {
+    1u128
+        .checked_shl(u32::from(bits - len))
+        .unwrap_or(u128::MAX)
+}
+
+fn total_size(nets: &[IpNet]) -> u128 {
+    nets.iter()
+        .map(|n| block_size(n.max_prefix_len(), n.prefix_len()))
+        .fold(0, u128::saturating_add)
+}
+
+/// 整列済みで重ならないプレフィックスの連結リストの1要素
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    start: u128,
+    len: u8,
+    alive: bool,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// 隣り合う2つをまとめる候補
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Candidate {
+    /// 増えるアドレス数
+    cost: u128,
+    start: u128,
+    left: usize,
+    right: usize,
+}
+
+struct Compressor {
+    bits: u8,
+    blocks: Vec<Block>,
+    /// まとめ先に含めない範囲（整列済み、重ならない）
+    avoid: Vec<(u128, u128)>,
+}
+
+impl Compressor {
+    fn last(&self, b: &Block) -> u128 {
+        b.start + (block_size(self.bits, b.len) - 1)
+    }
+
+    /// `left` と `right` を含む最小のプレフィックスと、その中の既存の要素
+    fn supernet(&self, left: usize, right: usize) -> (u128, u8, Vec<usize>) {
+        let (l, r) = (&self.blocks[left], &self.blocks[right]);
+        let diff = l.start ^ self.last(r);
+        let len = self.bits - (128 - diff.leading_zeros()) as u8;
+        let start = if len == 0 {
+            0
+        } else {
+            l.start & !(block_size(self.bits, len) - 1)
+        };
+        let end = start + (block_size(self.bits, len) - 1);
+
+        // 重ならないプレフィックスは上位プレフィックスに完全に含まれるか、外にある
+        let mut members = vec![left];
+        let mut cur = l.prev;
+        while let Some(i) = cur
+            && self.blocks[i].start >= start
+        {
+            members.push(i);
+            cur = self.blocks[i].prev;
+        }
+        members.reverse();
+        let mut cur = Some(right);
+        while let Some(i) = cur
+            && self.blocks[i].start <= end
+        {
+            members.push(i);
+            cur = self.blocks[i].next;
+        }
+        (start, len, members)
+    }
+
+    fn overlaps_avoided(&self, start: u128, end: u128) -> bool {
+        let i = self.avoid.partition_point(|&(_, e)| e < start);
+        self.avoid.get(i).is_some_and(|&(s, _)| s <= end)
+    }
+
+    fn candidate(&self, left: usize) -> Option<Candidate> {
+        let right = self.blocks[left].next?;
+        let (start, len, members) = self.supernet(left, right);
+        if self.overlaps_avoided(start, start + (block_size(self.bits, len) - 1)) {
+            return None;
+        }
+        let covered = members
+            .iter()
+            .map(|&i| block_size(self.bits, self.blocks[i].len))
+            .fold(0u128, u128::saturating_add);
+        Some(Candidate {
+            cost: block_size(self.bits, len).saturating_sub(covered),
+            start,
+            left,
+            right,
+        })
+    }
+}
+
+/// 増えるアドレス空間が最も少ない隣接の組から順にまとめ、`max_entries` 以下にする
+/// `avoid` に重ならずにまとめられる組が尽きた場合は上限を超えたまま返す
+fn compress(nets: &[IpNet], family: IpFamily, max_entries: usize, avoid: &[IpNet]) -> Vec<IpNet> {
+    let max_entries = max_entries.max(1);
+    if nets.len() <= max_entries {
+        return nets.to_vec();
+    }
+    let bits = family.bits();
+    let mut sorted: Vec<(u128, u8)> = nets
+        .iter()
+        .map(|n| (net_start(n), n.prefix_len()))
+        .collect();
+    sorted.sort_unstable();
+    let count = sorted.len();
+    let mut avoid: Vec<(u128, u128)> = IpNet::aggregate(&avoid.to_vec())
+        .iter()
+        .filter(|n| IpFamily::of(n) == family)
+        .map(|n| {
+            (
+                net_start(n),
+                net_start(n) + (block_size(bits, n.prefix_len()) - 1),
+            )
+        })
+        .collect();
+    avoid.sort_unstable();
+    let mut c = Compressor {
+        bits,
+        avoid,
+        blocks: sorted
+            .into_iter()
+            .enumerate()
+            .map(|(i, (start, len))| Block {
+                start,
+                len,
+                alive: true,
+                prev: i.checked_sub(1),
+                next: (i + 1 < count).then_some(i + 1),
+            })
+            .collect(),
+    };
+
+    let mut heap: BinaryHeap<Reverse<Candidate>> = (0..count)
+        .filter_map(|i| c.candidate(i))
+        .map(Reverse)
+        .collect();
+    let mut remaining = count;
+    while remaining > max_entries {
+        let Some(Reverse(cand)) = heap.pop() else {
+            break;
+        };
+        let (l, r) = (&c.blocks[cand.left], &c.blocks[cand.right]);
+        if !l.alive || !r.alive || l.next != Some(cand.right) {
+            continue;
+        }
+        // 周囲がまとめられて費用が変わっていれば入れ直す
+        let Some(current) = c.candidate(cand.left) else {
+            continue;
+        };
+        if current != cand {
+            heap.push(Reverse(current));
+            continue;
+        }
+
+        let (start, len, members) = c.supernet(cand.left, cand.right);
+        let (first, last) = (members[0], members[members.len() - 1]);
+        let (prev, next) = (c.blocks[first].prev, c.blocks[last].next);
+        for &i in &members {
+            c.blocks[i].alive = false;
+        }
+        let merged = c.blocks.len();
+        c.blocks.push(Block {
+            start,
+            len,
+            alive: true,
+            prev,
+            next,
+        });
+        if let Some(p) = prev {
+            c.blocks[p].next = Some(merged);
+            heap.extend(c.candidate(p).map(Reverse));
+        }
+        if let Some(n) = next {
+            c.blocks[n].prev = Some(merged);
+        }
+        heap.extend(c.candidate(merged).map(Reverse));
+        remaining -= members.len() - 1;
+    }
+
+    c.blocks
+        .iter()
+        .filter(|b| b.alive)
+        .filter_map(|b| to_net(family, b.start, b.len))
+        .collect()
+}
+
+fn net_start(net: &IpNet) -> u128 {
+    match net {
+        IpNet::V4(n) => u128::from(u32::from(n.network())),
+        IpNet::V6(n) => u128::from(n.network()),
+    }
+}
+
+fn to_net(family: IpFamily, start: u128, len: u8) -> Option<IpNet> {
+    match family {
+        IpFamily::V4 => Ipv4Net::new(Ipv4Addr::from(u32::try_from(start).ok()?), len)
+            .ok()
+            .map(IpNet::V4),
+        IpFamily::V6 => Ipv6Net::new(Ipv6Addr::from(start), len).ok().map(IpNet::V6),
+    }
+}
@@ -0,0 +1,249 @@
+//! 書き出したリストの統計（`--stats <FILE>`）
+//!
+//! ファイアウォールのセットの大きさの見積もりや実行結果の確認のため、リスト（ファミリ）ごとに
+//! 集約前後のプレフィックス数、アドレス数（IPv6 は /48・/64 単位も）、プレフィックス長の分布と
+//! RIR ごとの内訳を JSON で書き出す。
+//! - 集約前の数は取得元の値（国コードは RIR の割り当てから求めたCIDR、AS は取得したプレフィックス、
+//!   いずれも重複を除く）。overlap / set は書き出す前の集合の数
+//! - RIR ごとの内訳は、その実行で RIR のファイルを読んだ場合のみ（各 RIR の割り当てに含まれる割合）
+//!
+//! 記録は `--stats` 指定時のみ有効にする。常駐モード（watch / serve）では記録しない。
+
+use crate::common::IpFamily;
+use crate::error::AppError;
+use crate::interval::{IpRangeBuilder, IpRangeSet};
+use crate::output_common::{address_count, atomic_write, exact_address_count};
+use chrono::{Local, SecondsFormat};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::AsyncWriteExt;
+
+static STATS: Mutex<StatsCollector> = Mutex::new(StatsCollector::new());
+
+/// プロセス全体の統計の記録
+pub fn stats() -> MutexGuard<'static, StatsCollector> {
+    STATS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// RIR ごとの内訳
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RirShare {
+    /// その RIR の割り当てに重なるプレフィックス数
+    pub prefixes: usize,
+    /// リストのアドレス空間のうち、その RIR の割り当てに含まれる割合（%）
+    pub percent: f64,
+}
+
+/// 1つのリスト（ファミリ）の統計
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListStats {
+    /// country / asn / overlap / set
+    pub kind: String,
+    pub name: String,
+    pub family: &'static str,
+    /// 集約前のプレフィックス数
+    pub prefixes_before: usize,
+    /// 書き出したプレフィックス数（集約、除外、調整の後）
+    pub prefixes_after: usize,
+    pub addresses: u128,
+    /// /48 単位の大きさ（IPv6 のみ）
+    pub ipv6_48s: Option<f64>,
+    /// /64 単位の大きさ（IPv6 のみ）
+    pub ipv6_64s: Option<f64>,
+    /// プレフィックス長 → 数
+    pub prefix_lengths: BTreeMap<u8, usize>,
+    /// RIR → 内訳（RIR のファイルを読まなかった実行では `null`）
+    pub rirs: Option<BTreeMap<String, RirShare>>,
+}
+
+impl ListStats {
+    /// 集合から統計を求める
+    pub fn compute(
+        kind: &str,
+        name: &str,
+        family: IpFamily,
+        prefixes_before: usize,
+        nets: &BTreeSet<IpNet>,
+        rir_space: Option<&BTreeMap<String, IpRangeSet>>,
+    ) -> Self {
+        let addresses = exact_address_count(nets);
+        let units = |len: i32| {
+            nets.iter()
+                .map(|n| 2f64.powi(len - i32::from(n.prefix_len())))
+                .sum::<f64>()
+        };
+        let (ipv6_48s, ipv6_64s) = match family {
+            IpFamily::V4 => (None, None),
+            IpFamily::V6 => (Some(units(48)), Some(units(64))),
+        };
+        let mut prefix_lengths = BTreeMap::new();
+        for n in nets {
+            *prefix_lengths.entry(n.prefix_len()).or_insert(0) += 1;
+        }
+        let rirs = rir_space.map(|space| {
+            let total = address_count(nets);
+            let list = IpRangeSet::from_nets(nets);
+            space
+                .iter()
+                .filter_map(|(rir, allocated)| {
+                    let inside = list.intersection(allocated);
+                    if inside.is_empty() {
+                        return None;
+                    }
+                    let prefixes = nets.iter().filter(|n| allocated.overlaps_net(n)).count();
+                    let percent = if total == 0.0 {
+                        0.0
+                    } else {
+                        inside.address_count() as f64 / total * 100.0
+                    };
+                    Some((rir.clone(), RirShare { prefixes, percent }))
+                })
+                .collect()
+        });
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            family: family.as_str(),
+            prefixes_before,
+            prefixes_after: nets.len(),
+            addresses,
+            ipv6_48s,
+            ipv6_64s,
+            prefix_lengths,
+            rirs,
+        }
+    }
+}
+
+/// 実行中の統計の記録（有効化されるまでは何も記録しない）
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    enabled: bool,
+    /// (kind, name, family) → 取得元のプレフィックス数（集約前、重複なし）
+    sources: Option<HashMap<(String, String, &'static str), usize>>,
+    /// RIR → 割り当て済みの範囲
+    rir_space: Option<Arc<BTreeMap<String, IpRangeSet>>>,
+    lists: Vec<ListStats>,
+}
+
+impl StatsCollector {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            sources: None,
+            rir_space: None,
+            lists: Vec::new(),
+        }
+    }
+
+    /// 記録を有効にする
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 取得元から得た集約前のプレフィックス数
+    pub fn record_source_prefixes(
+        &mut self,
+        kind: &str,
+        name: &str,
+        family: IpFamily,
+        count: usize,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        self.sources
+            .get_or_insert_with(HashMap::new)
+            .insert((kind.to_string(), name.to_string(), family.as_str()), count);
+    }
+
+    /// RIR ごとの割り当て済みの範囲（同じ RIR は合算する）
+    pub fn record_rir_space(&mut self, space: BTreeMap<String, IpRangeBuilder>) {
+        if !self.enabled {
+            return;
+        }
+        let mut merged = self
+            .rir_space
+            .take()
+            .map(Arc::unwrap_or_clone)
+            .unwrap_or_default();
+        for (rir, ranges) in space {
+            let entry = merged.entry(rir).or_default();
+            *entry = entry.union(&ranges.build());
+        }
+        self.rir_space = Some(Arc::new(merged));
+    }
+
+    /// 統計を取り出して空にする（取得元の数と RIR の範囲も破棄する）
+    pub fn take(&mut self) -> Vec<ListStats> {
+        self.sources = None;
+        self.rir_space = None;
+        std::mem::take(&mut self.lists)
+    }
+}
+
+/// 書き出したリストの統計を記録する（無効なら何もしない）
+/// `before` は書き出す前の集合の数で、取得元の数が記録されていればそちらを使う
+pub fn record_list_stats(
+    kind: &str,
+    name: &str,
+    family: IpFamily,
+    before: usize,
+    nets: &BTreeSet<IpNet>,
+) {
+    let (before, rir_space) = {
+        let collector = stats();
+        if !collector.enabled {
+            return;
+        }
+        let key = (kind.to_string(), name.to_string(), family.as_str());
+        let before = collector
+            .sources
+            .as_ref()
+            .and_then(|s| s.get(&key).copied())
+            .unwrap_or(before);
+        (before, collector.rir_space.clone())
+    };
+    // 重なりの計算はロックの外で行う
+    let list = ListStats::compute(kind, name, family, before, nets, rir_space.as_deref());
+    stats().lists.push(list);
+}
+
+/// JSON で書き出す統計
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    pub generated_at: String,
+    pub lists: Vec<ListStats>,
+}
+
+impl StatsReport {
+    /// 記録から作り、記録を空にする
+    pub fn finish() -> Self {
+        Self {
+            generated_at: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            lists: stats().take(),
+        }
+    }
+
+    /// ファイル（`-` なら標準出力）へ書き出す
+    pub async fn write(&self, path: &Path) -> Result<(), AppError> {
+        let mut json = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::Other(format!("Failed to serialize stats: {}", e)))?;
+        json.push('\n');
+        if path == Path::new("-") {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(json.as_bytes()).await?;
+            stdout.flush().await?;
+            Ok(())
+        } else {
+            atomic_write(path, json.as_bytes()).await
+        }
+    }
+}
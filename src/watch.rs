@@ -0,0 +1,304 @@
+//! 常駐（watch）モード
+//!
+//! 設定ファイルのプロファイルを一定間隔（+ ジッター）で再生成する。
+//! - HTTPクライアントは全リフレッシュで共有
+//! - RIRファイルは ETag / Last-Modified による条件付きGETで、未変更なら再ダウンロードも再パースもしない
+//! - RIRファイルはダウンロードしながら解析し、本文ではなくプロファイルの国の解析結果だけを保持する
+//! - リフレッシュは同時に1つしか走らない
+//! - リフレッシュが失敗した場合は書き出しを行わず、前回の出力をそのまま残す
+//! - 出力内容が変わったリフレッシュの後だけフックコマンドを実行する
+
+use crate::asn::AsLookupOptions;
+use crate::commands::handle_run::run_jobs;
+use crate::common_download::DownloadOptions;
+use crate::config::Job;
+use crate::error::AppError;
+use crate::fetch::{Conditional, Validators, fetch_parsed_conditional};
+use crate::hooks::run_hooks;
+use crate::metrics::{registry, write_metrics_file};
+use crate::parse::{CountryFilter, CountryMap, CountrySets, aggregate_country_sets};
+use futures::future::join_all;
+use rand::Rng;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+/// 待機処理の抽象化（テストでは実時間を待たずに差し替える）
+pub trait Clock: Send + Sync {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send;
+}
+
+/// tokio のタイマーを使う実時間の Clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep(duration)
+    }
+}
+
+/// 1回のリフレッシュの結果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshSummary {
+    /// 本文をダウンロードしたRIRファイル数
+    pub downloaded: usize,
+    /// 304 で再ダウンロードを省略したRIRファイル数
+    pub not_modified: usize,
+    /// 取得に失敗し、前回の内容で代用したRIRファイル数
+    pub stale: usize,
+    /// 国コードマップを再パースしたか
+    pub reparsed: bool,
+    /// 内容が変わった出力ファイル数
+    pub changed_files: usize,
+}
+
+/// URLごとの前回の解析結果
+#[derive(Debug, Default)]
+struct CachedRir {
+    validators: Validators,
+    sets: Option<CountrySets>,
+    parse_time: Duration,
+}
+
+/// RIRファイルの解析結果のキャッシュとパース済みの国コードマップ
+/// watch / serve モードでリフレッシュをまたいで保持する
+pub struct RirCache {
+    filter: CountryFilter,
+    rir: HashMap<String, CachedRir>,
+    country_map: Option<Arc<CountryMap>>,
+}
+
+/// 常駐モードの実行器
+pub struct Watcher<C: Clock> {
+    client: Client,
+    jobs: Vec<Job>,
+    download: DownloadOptions,
+    lookup: AsLookupOptions,
+    interval: Duration,
+    jitter: Duration,
+    clock: C,
+    post_hooks: Vec<String>,
+    metrics_file: Option<PathBuf>,
+    // リフレッシュ中はロックを保持し、同時実行を防ぐ
+    state: Mutex<RirCache>,
+}
+
+impl<C: Clock> Watcher<C> {
+    pub fn new(
+        client: Client,
+        jobs: Vec<Job>,
+        download: DownloadOptions,
+        lookup: AsLookupOptions,
+        interval: Duration,
+        jitter: Duration,
+        clock: C,
+    ) -> Self {
+        let countries: Vec<&String> = jobs.iter().flat_map(|j| &j.countries).collect();
+        let filter = CountryFilter::only(&countries);
+        Self {
+            client,
+            jobs,
+            download,
+            lookup,
+            interval,
+            jitter,
+            clock,
+            post_hooks: Vec::new(),
+            metrics_file: None,
+            state: Mutex::new(RirCache::new(filter)),
+        }
+    }
+
+    /// 全プロファイル共通のフックコマンドを設定する
+    pub fn with_post_hooks(mut self, hooks: Vec<String>) -> Self {
+        self.post_hooks = hooks;
+        self
+    }
+
+    /// リフレッシュごとにメトリクスを書き出すファイルを設定する
+    pub fn with_metrics_file(mut self, path: Option<PathBuf>) -> Self {
+        self.metrics_file = path;
+        self
+    }
+
+    /// リフレッシュを繰り返す
+    /// `max_refreshes` が `Some(n)` なら n 回で終了（テスト用）、`None` なら無期限
+    /// 失敗したリフレッシュは警告を出して次の周期へ進む
+    pub async fn run(&self, max_refreshes: Option<u64>) {
+        let mut count: u64 = 0;
+        loop {
+            let result = self.refresh_once().await;
+            if let Some(path) = &self.metrics_file {
+                if result.is_ok() {
+                    registry().record_run_success();
+                }
+                if let Err(e) = write_metrics_file(path).await {
+                    warn!(path = %path.display(), error = %e, "Failed to write metrics");
+                }
+            }
+            match result {
+                Ok(summary) => debug!(?summary, "Refresh finished"),
+                // 出力は書き出し済みなので「前回の出力を維持」とは報告しない
+                Err(e @ AppError::Hook(_)) => warn!(error = %e, "Hook failed"),
+                Err(e) => warn!(error = %e, "Refresh failed, keeping previous outputs"),
+            }
+            count += 1;
+            if max_refreshes.is_some_and(|max| count >= max) {
+                return;
+            }
+            self.clock.sleep(self.next_delay()).await;
+        }
+    }
+
+    /// 次のリフレッシュまでの待ち時間（interval + [0, jitter]）
+    pub fn next_delay(&self) -> Duration {
+        let jitter_secs = self.jitter.as_secs_f64();
+        let extra = if jitter_secs > 0.0 {
+            rand::rng().random::<f64>() * jitter_secs
+        } else {
+            0.0
+        };
+        self.interval + Duration::from_secs_f64(extra)
+    }
+
+    /// 1回分のリフレッシュ
+    /// すでに別のリフレッシュが実行中ならエラーを返す
+    pub async fn refresh_once(&self) -> Result<RefreshSummary, AppError> {
+        let mut state = self
+            .state
+            .try_lock()
+            .map_err(|_| AppError::Other("A refresh is already running".into()))?;
+
+        let mut summary = RefreshSummary::default();
+        let country_map = if self.jobs.iter().any(|j| !j.countries.is_empty()) {
+            state
+                .refresh(&self.client, &self.download, &mut summary)
+                .await?
+        } else {
+            Arc::new(CountryMap::new())
+        };
+
+        let written = run_jobs(&self.jobs, &country_map, &self.client, &self.lookup).await?;
+        summary.changed_files = written.iter().filter(|f| f.changed).count();
+        run_hooks(&self.post_hooks, &written, None).await?;
+        Ok(summary)
+    }
+}
+
+impl RirCache {
+    /// `filter` の国だけを保持するキャッシュ
+    pub fn new(filter: CountryFilter) -> Self {
+        Self {
+            filter,
+            rir: HashMap::new(),
+            country_map: None,
+        }
+    }
+
+    /// 条件付きGETでRIRファイルを更新し、変化があった場合のみ国コードマップを作り直す
+    pub async fn refresh(
+        &mut self,
+        client: &Client,
+        download: &DownloadOptions,
+        summary: &mut RefreshSummary,
+    ) -> Result<Arc<CountryMap>, AppError> {
+        let (rir, filter) = (&self.rir, &self.filter);
+        let requests = download.rir_urls.iter().map(|url| {
+            let validators = rir
+                .get(url)
+                .filter(|c| c.sets.is_some())
+                .map(|c| c.validators.clone())
+                .unwrap_or_default();
+            async move {
+                let res = fetch_parsed_conditional(
+                    client,
+                    url,
+                    filter,
+                    &validators,
+                    download.max_retries,
+                    download.max_backoff_sec,
+                )
+                .await;
+                (url.clone(), res)
+            }
+        });
+        let results = join_all(requests).await;
+
+        let mut failed = Vec::new();
+        let mut parse_error = None;
+        for (url, res) in results {
+            let entry = self.rir.entry(url.clone()).or_default();
+            match res {
+                Ok(Conditional::NotModified) => summary.not_modified += 1,
+                Ok(Conditional::Modified(parsed)) => {
+                    summary.downloaded += 1;
+                    entry.validators = parsed.validators;
+                    entry.sets = Some(parsed.sets);
+                    entry.parse_time = parsed.parse_time;
+                }
+                Err(e) => {
+                    warn!(url = %url, error = %e, "RIR download failed");
+                    if entry.sets.is_some() {
+                        // 前回取得分で代用（最後に成功した内容を維持）
+                        summary.stale += 1;
+                    } else {
+                        failed.push(url);
+                    }
+                    if matches!(e, AppError::ParseError(_)) && parse_error.is_none() {
+                        parse_error = Some(e);
+                    }
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            debug!(?failed, "Some RIR files failed to download");
+            if !download.continue_on_partial {
+                if let Some(e) = parse_error {
+                    return Err(e);
+                }
+                return Err(AppError::PartialData(
+                    "Some RIR downloads failed (use --continue-on-partial to proceed)".into(),
+                ));
+            }
+        }
+
+        if summary.downloaded == 0
+            && let Some(map) = &self.country_map
+        {
+            return Ok(Arc::clone(map));
+        }
+
+        // 集約で消費するため、保持している解析結果は複製して渡す
+        let cached: Vec<&CachedRir> = download
+            .rir_urls
+            .iter()
+            .filter_map(|u| self.rir.get(u).filter(|c| c.sets.is_some()))
+            .collect();
+        if cached.is_empty() {
+            return Err(parse_error
+                .unwrap_or_else(|| AppError::Fetch("No RIR files available to process".into())));
+        }
+        let parse_time = cached
+            .iter()
+            .map(|c| c.parse_time)
+            .max()
+            .unwrap_or_default();
+        let partials: Vec<CountrySets> =
+            cached.into_iter().filter_map(|c| c.sets.clone()).collect();
+
+        let started = Instant::now();
+        let map = tokio::task::spawn_blocking(move || aggregate_country_sets(partials)).await?;
+        registry().record_parse(parse_time + started.elapsed());
+        let map = Arc::new(map);
+        self.country_map = Some(Arc::clone(&map));
+        summary.reparsed = true;
+        Ok(map)
+    }
+}
@@ -0,0 +1,103 @@
+use fire_scope::asn::{AsOutcome, AsReport, extract_announced_prefixes, merges_arin_prefixes};
+use serde_json::json;
+use std::time::Duration;
+
+fn sample_report() -> AsReport {
+    let mut report = AsReport::default();
+    report.push("2497", AsOutcome::Success { v4: 12, v6: 3 });
+    report.push("64512", AsOutcome::Empty);
+    report.push("4713", AsOutcome::Failed("HTTP 503".into()));
+    report
+}
+
+#[test]
+fn report_counts_and_summary() {
+    let report = sample_report();
+    assert_eq!(report.succeeded(), 1);
+    assert_eq!(report.empty(), 1);
+    assert_eq!(report.failed(), 1);
+
+    let summary = report.summary();
+    let lines: Vec<&str> = summary.lines().collect();
+    assert_eq!(
+        lines[0],
+        "AS lookup summary: 1 succeeded, 1 empty, 1 failed"
+    );
+    assert_eq!(lines[1], "  AS2497: 12 IPv4, 3 IPv6 prefixes");
+    assert_eq!(lines[2], "  AS64512: no announced prefixes");
+    assert_eq!(lines[3], "  AS4713: FAILED: HTTP 503");
+}
+
+#[test]
+fn strict_mode_fails_on_any_failure() {
+    let report = sample_report();
+    let err = report.check(false).unwrap_err().to_string();
+    assert!(err.contains("1 of 3 AS lookups failed"), "{err}");
+    assert!(report.check(true).is_ok());
+
+    // 空の結果は失敗とみなさない
+    let mut ok = AsReport::default();
+    ok.push("64512", AsOutcome::Empty);
+    assert!(ok.check(false).is_ok());
+
+    // 続行モードでも全件失敗ならエラー
+    let mut all_failed = AsReport::default();
+    all_failed.push("4713", AsOutcome::Failed("timeout".into()));
+    assert!(all_failed.check(true).is_err());
+}
+
+#[test]
+fn announced_prefixes_respect_minimum_duration() {
+    let json = json!({
+        "data": {
+            "query_starttime": "2025-01-01T00:00:00",
+            "query_endtime": "2025-01-15T00:00:00",
+            "prefixes": [
+                // 期間全体
+                {"prefix": "192.0.2.0/24", "timelines": [
+                    {"starttime": "2024-12-01T00:00:00", "endtime": "2025-01-15T00:00:00"}
+                ]},
+                // 2時間だけ（一時的なハイジャック）
+                {"prefix": "198.51.100.0/24", "timelines": [
+                    {"starttime": "2025-01-10T00:00:00", "endtime": "2025-01-10T02:00:00"}
+                ]},
+                // 重なる区間は二重に数えない（合計 1日）
+                {"prefix": "203.0.113.0/24", "timelines": [
+                    {"starttime": "2025-01-02T00:00:00", "endtime": "2025-01-02T18:00:00"},
+                    {"starttime": "2025-01-02T12:00:00", "endtime": "2025-01-03T00:00:00"}
+                ]},
+                // 期間外は切り詰める（期間内は 6時間）
+                {"prefix": "2001:db8::/32", "timelines": [
+                    {"starttime": "2024-12-31T00:00:00", "endtime": "2025-01-01T06:00:00"}
+                ]},
+                {"prefix": "not-a-prefix", "timelines": []}
+            ]
+        }
+    });
+    let prefixes = |min_secs: u64| {
+        extract_announced_prefixes(&json, Duration::from_secs(min_secs))
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(prefixes(0).len(), 4);
+    assert_eq!(
+        prefixes(6 * 3600),
+        ["192.0.2.0/24", "203.0.113.0/24", "2001:db8::/32"]
+    );
+    assert_eq!(prefixes(86_400), ["192.0.2.0/24", "203.0.113.0/24"]);
+    assert_eq!(prefixes(86_401), ["192.0.2.0/24"]);
+}
+
+#[test]
+fn min_announced_skips_the_arin_merge() {
+    use fire_scope::fetch::ApiPolicy;
+    // 広報時間を確かめられない ARIN の分は、--min-announced 指定時には併合しない
+    assert!(merges_arin_prefixes(&ApiPolicy::default()));
+    let policy = ApiPolicy {
+        min_announced: Duration::from_secs(3600),
+        ..ApiPolicy::default()
+    };
+    assert!(!merges_arin_prefixes(&policy));
+}
@@ -0,0 +1,231 @@
+mod common;
+
+use common::{nets, temp_dir};
+use fire_scope::asn::AsMap;
+use fire_scope::asname::{
+    AsAnnotations, AsNameOptions, AsNames, extract_as_overview_name, load_as_names,
+    parse_as_name_line, read_as_names,
+};
+use fire_scope::common::{IpFamily, OutputFormat};
+use fire_scope::diff::parse_list_text;
+use fire_scope::interval::IpRangeSet;
+use fire_scope::output::{OutputOptions, write_as_ip_list_to_file, write_named_set_to_file};
+use serde_json::json;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+
+const NAMES: &str = "\
+# asnames
+AS13335  CLOUDFLARENET - Cloudflare, Inc., US
+2497 IIJ Internet Initiative Japan Inc., JP
+AS4713   OCN, JP
+AS64500  bad#name{}
+not-a-line
+";
+
+fn annotations() -> AsAnnotations {
+    let mut names = AsNames::new();
+    read_as_names(NAMES.as_bytes(), None, &mut names).unwrap();
+    let mut map = AsMap::new();
+    map.insert(
+        "13335".into(),
+        (nets(&["198.51.100.0/24"]), nets(&["2001:db8::/32"])),
+    );
+    map.insert(
+        "4713".into(),
+        (
+            nets(&["198.51.100.0/25", "203.0.113.0/24"]),
+            BTreeSet::new(),
+        ),
+    );
+    map.insert("64501".into(), (nets(&["192.0.2.0/24"]), BTreeSet::new()));
+    AsAnnotations::from_as_map(names, &map)
+}
+
+#[test]
+fn parses_as_name_lists() {
+    assert_eq!(
+        parse_as_name_line("AS13335  CLOUDFLARENET - Cloudflare, Inc., US"),
+        Some((13335, "CLOUDFLARENET".into()))
+    );
+    assert_eq!(
+        parse_as_name_line("4713\tOCN, JP"),
+        Some((4713, "OCN".into()))
+    );
+    // コメントに書けない文字は取り除く
+    assert_eq!(
+        parse_as_name_line("AS64500 bad#name{}"),
+        Some((64500, "badname".into()))
+    );
+    assert_eq!(parse_as_name_line("# comment"), None);
+    assert_eq!(parse_as_name_line("not-a-line"), None);
+
+    let mut names = AsNames::new();
+    let wanted = HashSet::from([2497]);
+    assert_eq!(
+        read_as_names(NAMES.as_bytes(), Some(&wanted), &mut names).unwrap(),
+        4
+    );
+    assert_eq!(names.len(), 1);
+    assert_eq!(names[&2497], "IIJ");
+
+    let overview =
+        json!({"data": {"holder": "CLOUDFLARENET - Cloudflare, Inc.", "announced": true}});
+    assert_eq!(
+        extract_as_overview_name(&overview).as_deref(),
+        Some("CLOUDFLARENET")
+    );
+    assert_eq!(
+        extract_as_overview_name(&json!({"data": {"holder": ""}})),
+        None
+    );
+}
+
+#[test]
+fn attributes_elements_to_origin_ases() {
+    let a = annotations();
+    assert_eq!(a.label(13335), "AS13335 CLOUDFLARENET");
+    assert_eq!(a.label(64501), "AS64501");
+    // 含む・含まれるのどちらも起源とみなす
+    assert_eq!(
+        a.origins_of(&"198.51.100.0/24".parse().unwrap()),
+        BTreeSet::from([4713, 13335])
+    );
+    assert_eq!(
+        a.origins_of(&"198.51.100.128/26".parse().unwrap()),
+        BTreeSet::from([13335])
+    );
+    assert_eq!(
+        a.origins_of(&"198.51.0.0/16".parse().unwrap()),
+        BTreeSet::from([4713, 13335])
+    );
+    assert!(a.element_comment(&"10.0.0.0/8".parse().unwrap()).is_none());
+    assert_eq!(
+        a.element_comment(&"198.51.100.0/25".parse().unwrap())
+            .as_deref(),
+        Some("AS4713 OCN, AS13335 CLOUDFLARENET")
+    );
+    assert_eq!(
+        a.header_lines(&nets(&["198.51.100.0/24", "192.0.2.0/24"])),
+        "# AS Name: AS4713 OCN\n# AS Name: AS13335 CLOUDFLARENET\n"
+    );
+    assert_eq!(
+        a.restricted_to(&[4713])
+            .origins_of(&"198.51.100.0/24".parse().unwrap()),
+        BTreeSet::from([4713])
+    );
+}
+
+#[tokio::test]
+async fn writes_names_into_headers_and_nft_comments() {
+    let mut output = OutputOptions::new(OutputFormat::Nft);
+    output.dir = temp_dir("asname");
+    output.annotations = Some(Arc::new(annotations()));
+
+    let list = nets(&["198.51.100.0/24", "203.0.113.0/24"]);
+    write_as_ip_list_to_file("4713", IpFamily::V4, &IpRangeSet::from_nets(&list), &output)
+        .await
+        .unwrap();
+    let text = tokio::fs::read_to_string(output.dir.join("AS_4713_IPv4.nft"))
+        .await
+        .unwrap();
+    assert!(text.contains(
+        "# AS Number: 4713\n# AS Name: AS4713 OCN\n# AS Name: AS13335 CLOUDFLARENET\n\n"
+    ));
+    assert!(text.contains(
+        "    198.51.100.0/24, # AS4713 OCN, AS13335 CLOUDFLARENET\n    203.0.113.0/24 # AS4713 OCN\n}\n"
+    ));
+    // コメント付きでも読み戻せる
+    assert_eq!(parse_list_text(&text), list);
+
+    // TXT はヘッダのみ
+    output.format = OutputFormat::Txt;
+    write_named_set_to_file(
+        "CARRIER",
+        "N/A",
+        "4713",
+        &IpRangeSet::from_nets(&list),
+        &output,
+    )
+    .await
+    .unwrap();
+    let text = tokio::fs::read_to_string(output.dir.join("CARRIER_IPv4.txt"))
+        .await
+        .unwrap();
+    assert!(text.contains("# AS Name: AS4713 OCN\n"));
+    assert!(text.ends_with("\n198.51.100.0/24\n203.0.113.0/24\n"));
+}
+
+#[tokio::test]
+async fn loads_names_from_a_local_file() {
+    let dir = temp_dir("asname");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("asnames.txt");
+    std::fs::write(&path, NAMES).unwrap();
+
+    let client = reqwest::Client::new();
+    let mut opts = AsNameOptions {
+        enabled: false,
+        file: Some(path),
+    };
+    assert!(
+        load_as_names(&client, &[13335], &opts, 2, &Default::default())
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    opts.enabled = true;
+    let names = load_as_names(
+        &client,
+        &[13335, 2497, 65000],
+        &opts,
+        2,
+        &Default::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(names.len(), 2);
+    assert_eq!(names[&13335], "CLOUDFLARENET");
+}
+
+#[tokio::test]
+async fn overlap_outputs_carry_origin_and_country_per_element() {
+    // --as-names なしでも起源AS と国コードを付ける
+    let mut attribution = AsAnnotations::default();
+    attribution.add_prefixes(2497, &nets(&["192.0.2.0/24"]));
+    attribution.add_prefixes(4713, &nets(&["198.51.100.0/25"]));
+    attribution.add_country_prefixes("jp", &nets(&["192.0.2.0/24"]));
+    attribution.add_country_prefixes("kr", &nets(&["198.51.100.0/24"]));
+    assert!(attribution.has_countries());
+    assert_eq!(
+        attribution
+            .element_comment(&"192.0.2.0/25".parse().unwrap())
+            .as_deref(),
+        Some("AS2497 (JP)")
+    );
+    assert_eq!(
+        attribution.countries_of(&"198.51.0.0/16".parse().unwrap()),
+        BTreeSet::from(["KR".to_string()])
+    );
+    // AS で絞っても国コードは残る
+    assert_eq!(
+        attribution
+            .restricted_to(&[4713])
+            .element_comment(&"198.51.100.0/25".parse().unwrap())
+            .as_deref(),
+        Some("AS4713 (KR)")
+    );
+
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("asname");
+    output.annotations = Some(Arc::new(attribution));
+    let overlap = IpRangeSet::from_nets(&nets(&["192.0.2.0/24", "198.51.100.0/25"]));
+    fire_scope::output::write_overlap_to_file("JP_KR", "2497_4713", &overlap, &output)
+        .await
+        .unwrap();
+    let text = tokio::fs::read_to_string(output.dir.join("overlap_JP_KR_2497_4713_IPv4.txt"))
+        .await
+        .unwrap();
+    assert!(text.ends_with("\n192.0.2.0/24 # AS2497 (JP)\n198.51.100.0/25 # AS4713 (KR)\n"));
+    assert_eq!(parse_list_text(&text), overlap.to_set());
+}
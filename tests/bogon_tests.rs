@@ -0,0 +1,124 @@
+mod common;
+
+use common::{nets, ranges, strings, temp_dir};
+use fire_scope::bogon::{BogonRemoval, BogonTable};
+use fire_scope::common::{IpFamily, OutputFormat};
+use fire_scope::output::{OutputOptions, write_as_ip_list_to_file};
+use fire_scope::report::{RunReport, journal};
+use fire_scope::shape::{ShapeOptions, shape_nets};
+use std::sync::Arc;
+
+fn removal(prefix: &str, reason: &str) -> BogonRemoval {
+    BogonRemoval {
+        prefix: prefix.into(),
+        reason: reason.into(),
+    }
+}
+
+#[test]
+fn removes_builtin_special_purpose_ranges() {
+    let table = BogonTable::builtin();
+    let set = ranges(&[
+        "8.8.8.0/24",
+        "10.1.0.0/16",
+        "100.0.0.0/9",
+        "192.0.2.0/24",
+        "2001:db8:1::/48",
+        "2400:cb00::/32",
+    ]);
+    let (kept, removed) = table.remove_from(&set);
+    assert_eq!(
+        strings(&kept.to_set()),
+        ["8.8.8.0/24", "100.0.0.0/10", "2400:cb00::/32"]
+    );
+    assert_eq!(
+        removed,
+        [
+            removal("10.1.0.0/16", "private-use (RFC 1918)"),
+            removal("100.64.0.0/10", "shared address space (RFC 6598)"),
+            removal("192.0.2.0/24", "documentation (RFC 5737)"),
+            removal("2001:db8:1::/48", "documentation (RFC 3849)"),
+        ]
+    );
+
+    // 該当しなければそのまま
+    let clean = ranges(&["8.8.8.0/24", "2400:cb00::/32"]);
+    assert_eq!(table.remove_from(&clean), (clean.clone(), Vec::new()));
+}
+
+#[test]
+fn reads_full_bogon_lists() {
+    let mut table = BogonTable::builtin();
+    let text = "# fullbogons\n41.0.0.0/8 # unallocated\n\n2c0f::/16\n";
+    assert_eq!(table.read(text.as_bytes(), "fullbogons.txt").unwrap(), 2);
+    let (kept, removed) = table.remove_from(&ranges(&["41.1.0.0/16", "8.8.8.0/24"]));
+    assert_eq!(strings(&kept.to_set()), ["8.8.8.0/24"]);
+    assert_eq!(removed, [removal("41.1.0.0/16", "fullbogons.txt")]);
+
+    assert!(table.read("41.0.0.0/33\n".as_bytes(), "bad.txt").is_err());
+}
+
+#[test]
+fn widening_and_compression_avoid_bogons() {
+    let table = BogonTable::builtin();
+    let widen = ShapeOptions {
+        widen_to_v4: Some(7),
+        ..ShapeOptions::default()
+    };
+    // 11.0.0.0/24 を /7 に広げると 10.0.0.0/8 を含むため広げず、その数を報告する
+    let (shaped, reports) = shape_nets(&nets(&["11.0.0.0/24"]), &widen, table.ranges());
+    assert_eq!(strings(&shaped), ["11.0.0.0/24"]);
+    assert_eq!((reports[0].widened, reports[0].not_widened), (0, 1));
+    assert_eq!(reports[0].added_addresses, 0);
+    assert!(
+        reports[0]
+            .summary("JP")
+            .contains("kept 1 prefix(es) longer than widen-to")
+    );
+    // 重ならない /N へは広げる
+    let (shaped, reports) = shape_nets(&nets(&["12.0.0.0/24"]), &widen, table.ranges());
+    assert_eq!(strings(&shaped), ["12.0.0.0/7"]);
+    assert_eq!((reports[0].widened, reports[0].not_widened), (1, 0));
+
+    // 9.255.255.0/24 と 11.0.0.0/24 をまとめると 10.0.0.0/8 を含むためまとめない
+    let compress = ShapeOptions {
+        max_entries: Some(1),
+        ..ShapeOptions::default()
+    };
+    let set = nets(&["9.255.255.0/24", "11.0.0.0/24"]);
+    assert_eq!(shape_nets(&set, &compress, table.ranges()).0, set);
+    assert_eq!(strings(&shape_nets(&set, &compress, &[]).0), ["8.0.0.0/6"]);
+}
+
+#[tokio::test]
+async fn outputs_drop_bogons_and_record_them() {
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("bogon");
+    output.bogons = Some(Arc::new(BogonTable::builtin()));
+
+    journal().enable();
+    let list = ranges(&["8.8.8.0/24", "10.0.0.0/24", "127.0.0.0/8"]);
+    write_as_ip_list_to_file("64500", IpFamily::V4, &list, &output)
+        .await
+        .unwrap();
+    let text = tokio::fs::read_to_string(output.dir.join("AS_64500_IPv4.txt"))
+        .await
+        .unwrap();
+    assert!(text.ends_with("\n8.8.8.0/24\n"));
+    // 既定（`OutputOptions::new`）では取り除かない
+    assert_eq!(
+        OutputOptions::new(OutputFormat::Txt).prepare(&list),
+        list.to_set()
+    );
+
+    let report = RunReport::finish(chrono::Local::now(), None, &Ok(()));
+    assert_eq!(report.bogons.len(), 1);
+    assert_eq!(report.bogons[0].list, "AS_64500_IPv4");
+    assert_eq!(
+        report.bogons[0].removed,
+        [
+            removal("10.0.0.0/24", "private-use (RFC 1918)"),
+            removal("127.0.0.0/8", "loopback (RFC 1122)"),
+        ]
+    );
+}
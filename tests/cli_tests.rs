@@ -1,24 +1,20 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use fire_scope::cli::Cli;
+use fire_scope::common::{Asn, AsnRange};
 
 #[test]
 fn cli_parses_country_and_format() {
     // 有効な最小引数（国コード + フォーマット）
-    let args = [
-        "fire-scope",
-        "-c",
-        "jp",
-        "-f",
-        "nft",
-        "--concurrency",
-        "3",
-    ];
+    let args = ["fire-scope", "-c", "jp", "-f", "nft", "--concurrency", "3"];
 
-    let cli = Cli::parse_from(&args);
+    let cli = Cli::parse_from(args);
     let cc = cli.country_codes.expect("country required");
     assert_eq!(cc, vec!["JP".to_string()]);
     assert_eq!(cli.output_format, "nft".to_string());
     assert_eq!(cli.concurrency, 3usize);
+    // AS の失敗は既定で厳格
+    assert!(!cli.continue_on_as_failure);
+    assert_eq!(cli.ripestat_sourceapp, "fire-scope");
     assert!(!cli.overlap);
     assert!(cli.as_numbers.is_none());
 }
@@ -36,10 +32,304 @@ fn cli_parses_as_numbers_and_overlap_requirements() {
         "--format",
         "txt",
     ];
-    let cli = Cli::parse_from(&args);
+    let cli = Cli::parse_from(args);
     assert!(cli.overlap);
     assert_eq!(cli.country_codes.unwrap(), vec!["US".to_string()]);
-    assert_eq!(cli.as_numbers.unwrap(), vec![65000]);
+    assert_eq!(cli.as_numbers.unwrap(), vec![AsnRange::from(Asn(65000))]);
     assert_eq!(cli.output_format, "txt".to_string());
 }
 
+#[test]
+fn cli_normalizes_and_rejects_country_codes() {
+    let cli = Cli::parse_from(["fire-scope", "-c", "jpn", "uk"]);
+    assert_eq!(
+        cli.country_codes.unwrap(),
+        vec!["JP".to_string(), "GB".to_string()]
+    );
+
+    // 未知の国コードはパース時点でエラー
+    let res = Cli::try_parse_from(["fire-scope", "-c", "zx"]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn cli_parses_subcommands_with_global_options() {
+    use fire_scope::cli::Command;
+
+    let cli = Cli::parse_from([
+        "fire-scope",
+        "country",
+        "jp",
+        "kr",
+        "-f",
+        "nft",
+        "--max-retries",
+        "2",
+    ]);
+    assert_eq!(cli.output_format, "nft");
+    assert_eq!(cli.max_retries, 2);
+    match cli
+        .resolve_command()
+        .unwrap_or_else(|e| panic!("resolve: {e}"))
+    {
+        Command::Country(a) => assert_eq!(a.codes, vec!["JP".to_string(), "KR".to_string()]),
+        other => panic!("unexpected command: {other:?}"),
+    }
+
+    let cli = Cli::parse_from(["fire-scope", "overlap", "-c", "jp", "-a", "2497", "4713"]);
+    match cli
+        .resolve_command()
+        .unwrap_or_else(|e| panic!("resolve: {e}"))
+    {
+        Command::Overlap(a) => {
+            assert_eq!(a.country_codes, vec!["JP".to_string()]);
+            assert_eq!(a.as_numbers, vec![Asn(2497).into(), Asn(4713).into()]);
+            assert!(!a.per_pair && a.matrix.is_none());
+        }
+        other => panic!("unexpected command: {other:?}"),
+    }
+    let cli = Cli::parse_from([
+        "fire-scope",
+        "overlap",
+        "-c",
+        "jp",
+        "-a",
+        "2497",
+        "--per-pair",
+        "--matrix",
+        "-",
+    ]);
+    match cli
+        .resolve_command()
+        .unwrap_or_else(|e| panic!("resolve: {e}"))
+    {
+        Command::Overlap(a) => {
+            assert!(a.per_pair);
+            assert_eq!(a.matrix.as_deref(), Some(std::path::Path::new("-")));
+        }
+        other => panic!("unexpected command: {other:?}"),
+    }
+
+    // 共通オプションはサブコマンドの前にも書ける
+    let cli = Cli::parse_from([
+        "fire-scope",
+        "-f",
+        "nft",
+        "--output-dir",
+        "out",
+        "country",
+        "jp",
+    ]);
+    assert_eq!(cli.output_format, "nft");
+    assert!(matches!(cli.command, Some(Command::Country(_))));
+
+    // サブコマンドが無い場合はすべてのサブコマンドを示す
+    let err = Cli::parse_from(["fire-scope"])
+        .resolve_command()
+        .unwrap_err()
+        .to_string();
+    for name in ["country", "irr-compare", "watch", "serve"] {
+        assert!(err.contains(name), "{name} missing from: {err}");
+    }
+
+    // サブコマンドと旧形式フラグの併用は不可
+    assert!(Cli::try_parse_from(["fire-scope", "-a", "1", "asn", "2"]).is_err());
+    let matches = Cli::command()
+        .try_get_matches_from(["fire-scope", "-c", "jp", "-f", "nft", "country", "kr"])
+        .unwrap();
+    assert!(Cli::reject_legacy_with_subcommand(&matches).is_err());
+    // 値が無いサブコマンドはエラー
+    let cli = Cli::parse_from(["fire-scope", "asn"]);
+    assert!(cli.resolve_command().is_err());
+}
+
+#[test]
+fn cli_legacy_flags_map_to_subcommands() {
+    use fire_scope::cli::Command;
+
+    let cli = Cli::parse_from(["fire-scope", "-o", "-c", "us", "-a", "65000"]);
+    assert!(matches!(cli.resolve_command(), Ok(Command::Overlap(_))));
+
+    // -c と -a の同時指定（-o なし）は従来どおり AS を優先
+    let cli = Cli::parse_from(["fire-scope", "-c", "us", "-a", "65000"]);
+    assert!(matches!(cli.resolve_command(), Ok(Command::Asn(_))));
+
+    let cli = Cli::parse_from(["fire-scope", "-c", "us"]);
+    assert!(matches!(cli.resolve_command(), Ok(Command::Country(_))));
+
+    let cli = Cli::parse_from(["fire-scope"]);
+    assert!(cli.resolve_command().is_err());
+}
+
+#[test]
+fn cli_log_level_and_format() {
+    let cli = Cli::try_parse_from(["fire-scope", "country", "jp"]).unwrap();
+    assert_eq!(cli.log_level(), "warn");
+    assert_eq!(cli.log_format, "text");
+
+    // -d は debug 相当、より詳細な指定はそのまま
+    let cli = Cli::try_parse_from(["fire-scope", "-d", "country", "jp"]).unwrap();
+    assert_eq!(cli.log_level(), "debug");
+    let cli =
+        Cli::try_parse_from(["fire-scope", "-d", "--log-level", "trace", "country", "jp"]).unwrap();
+    assert_eq!(cli.log_level(), "trace");
+
+    let cli = Cli::try_parse_from(["fire-scope", "country", "jp", "--log-format", "json"]).unwrap();
+    assert_eq!(cli.log_format, "json");
+    assert!(Cli::try_parse_from(["fire-scope", "country", "jp", "--log-level", "loud"]).is_err());
+}
+
+#[test]
+fn cli_validates_rate_limit_and_sourceapp() {
+    let cli = Cli::try_parse_from([
+        "fire-scope",
+        "--api-rate-limit",
+        "2.5",
+        "--ripestat-sourceapp",
+        "acme-fw",
+        "asn",
+        "2497",
+    ])
+    .unwrap();
+    assert_eq!(cli.api_rate_limit, 2.5);
+    assert_eq!(cli.api_policy().sourceapp, "acme-fw");
+    assert_eq!(cli.api_policy().announced_window, None);
+    assert!(cli.api_policy().min_announced.is_zero());
+    // AS の問い合わせ設定が方針を運び、IRR の whois と流量制限を共有する
+    let lookup = cli.as_lookup_options();
+    assert_eq!(lookup.api.sourceapp, "acme-fw");
+    assert!(std::sync::Arc::ptr_eq(&lookup.api, &lookup.irr.api));
+
+    let cli = Cli::try_parse_from([
+        "fire-scope",
+        "--announced-window",
+        "30d",
+        "--min-announced",
+        "12h",
+        "asn",
+        "2497",
+    ])
+    .unwrap();
+    let policy = cli.api_policy();
+    assert_eq!(
+        policy.announced_window,
+        Some(std::time::Duration::from_secs(30 * 86_400))
+    );
+    assert_eq!(
+        policy.min_announced,
+        std::time::Duration::from_secs(12 * 3600)
+    );
+
+    assert!(Cli::try_parse_from(["fire-scope", "--api-rate-limit", "-1", "asn", "2497"]).is_err());
+    assert!(
+        Cli::try_parse_from(["fire-scope", "--ripestat-sourceapp", "a&b=c", "asn", "2497"])
+            .is_err()
+    );
+}
+
+#[test]
+fn cli_accepts_as_sets_without_numbers() {
+    let cli = Cli::try_parse_from([
+        "fire-scope",
+        "--irr-dump",
+        "radb.db.gz",
+        "--as-set-max-depth",
+        "3",
+        "asn",
+        "--as-set",
+        "as-example",
+    ])
+    .unwrap();
+    let opts = cli.as_lookup_options();
+    assert_eq!(opts.irr.max_depth, 3);
+    assert_eq!(opts.irr.dumps.len(), 1);
+    assert!(cli.resolve_command().is_ok());
+
+    // AS番号も AS-SET も無ければエラー、AS-SET名でないものは拒否
+    let cli = Cli::try_parse_from(["fire-scope", "asn"]);
+    assert!(cli.is_err() || cli.unwrap().resolve_command().is_err());
+    assert!(Cli::try_parse_from(["fire-scope", "asn", "--as-set", "2497"]).is_err());
+}
+
+#[test]
+fn cli_parses_irr_compare_and_as_source() {
+    let cli = Cli::try_parse_from([
+        "fire-scope",
+        "--as-source",
+        "irr",
+        "--irr-whois",
+        "whois.radb.net",
+        "irr-compare",
+        "2497",
+    ])
+    .unwrap();
+    assert_eq!(
+        cli.as_lookup_options().source,
+        fire_scope::asn::AsSource::Irr
+    );
+    let command = cli.resolve_command().unwrap();
+    assert_eq!(command.name(), "irr-compare");
+
+    assert!(Cli::try_parse_from(["fire-scope", "--as-source", "whois", "asn", "2497"]).is_err());
+}
+
+#[test]
+fn parses_asn_forms_and_ranges() {
+    for s in ["13335", "AS13335", "as13335", " AS13335 "] {
+        assert_eq!(s.parse::<Asn>(), Ok(Asn(13335)), "{s}");
+    }
+    // asdot
+    assert_eq!("1.10".parse::<Asn>(), Ok(Asn(65546)));
+    assert_eq!("AS1.10".parse::<Asn>(), Ok(Asn(65546)));
+    for bad in ["", "AS", "AS-FOO", "1.70000", "4294967296", "-1", "12a"] {
+        assert!(bad.parse::<Asn>().is_err(), "{bad}");
+    }
+
+    let range: AsnRange = "AS64512-AS64515".parse().unwrap();
+    assert_eq!(
+        range.iter().collect::<Vec<_>>(),
+        [Asn(64512), Asn(64513), Asn(64514), Asn(64515)]
+    );
+    assert_eq!(range.to_string(), "64512-64515");
+    assert_eq!("2497".parse::<AsnRange>().unwrap().to_string(), "2497");
+    assert!("65000-64512".parse::<AsnRange>().is_err());
+    assert!("1-100000".parse::<AsnRange>().is_err());
+
+    let cli = Cli::parse_from(["fire-scope", "asn", "AS2497", "1.10", "64512-64513", "2497"]);
+    let ranges = match cli.resolve_command().unwrap() {
+        fire_scope::cli::Command::Asn(a) => a.as_numbers,
+        other => panic!("unexpected command: {other:?}"),
+    };
+    assert_eq!(
+        fire_scope::common::expand_asns(&ranges),
+        [Asn(2497), Asn(65546), Asn(64512), Asn(64513)]
+    );
+    assert!(Cli::try_parse_from(["fire-scope", "asn", "AS-FOO"]).is_err());
+}
+
+#[test]
+fn warns_about_reserved_asns() {
+    use fire_scope::common::reserved_asn_warnings;
+
+    assert!(reserved_asn_warnings(&[Asn(2497), Asn(13335), Asn(394_000)]).is_empty());
+    let mut asns: Vec<Asn> = (64512..=64520).map(Asn).collect();
+    asns.extend([
+        Asn(65000),
+        Asn(23456),
+        Asn(64496),
+        Asn(4_200_000_000),
+        Asn(2497),
+    ]);
+    let warnings = reserved_asn_warnings(&asns);
+    assert_eq!(
+        warnings,
+        [
+            "11 AS number(s) for private use (RFC 6996) will not appear in RIPEstat: AS64512-AS64520, AS65000, AS4200000000",
+            "1 AS number(s) AS_TRANS (RFC 6793) will not appear in RIPEstat: AS23456",
+            "1 AS number(s) for documentation (RFC 5398) will not appear in RIPEstat: AS64496",
+        ]
+    );
+    assert_eq!(Asn(0).reserved(), Some("reserved (RFC 7607)"));
+    assert_eq!(Asn(65535).reserved(), Some("reserved (RFC 7300)"));
+    assert_eq!(Asn(u32::MAX).reserved(), Some("reserved (RFC 7300)"));
+}
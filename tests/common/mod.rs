@@ -0,0 +1,33 @@
+//! テスト間で共有するヘルパ（テストごとに使う分だけ使う）
+#![allow(dead_code)]
+
+use fire_scope::interval::IpRangeSet;
+use ipnet::IpNet;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// `target/test-output` 配下の、テストごとに異なる出力ディレクトリ
+pub fn temp_dir(prefix: &str) -> PathBuf {
+    PathBuf::from(format!(
+        "target/test-output/{}_{}",
+        prefix,
+        rand::random::<u64>()
+    ))
+}
+
+pub fn nets(list: &[&str]) -> BTreeSet<IpNet> {
+    list.iter()
+        .map(|s| {
+            s.parse()
+                .unwrap_or_else(|e| panic!("failed to parse {s}: {e}"))
+        })
+        .collect()
+}
+
+pub fn ranges(list: &[&str]) -> IpRangeSet {
+    IpRangeSet::from_nets(&nets(list))
+}
+
+pub fn strings(set: &BTreeSet<IpNet>) -> Vec<String> {
+    set.iter().map(IpNet::to_string).collect()
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::temp_dir;
+use fire_scope::common::Asn;
+use fire_scope::cone::{
+    AsRelationships, ConeMember, ConeOptions, add_customer_cones, read_as_relationships,
+};
+use fire_scope::report::{RunReport, journal};
+use ipnet::IpNet;
+
+// 64500 ─┬─ 64501 ── 64503 ── 64505
+//        └─ 64502 ──┘
+// 64503 と 64504 は peer、64501 は 64500 の provider でもある（ループ）
+const AS_REL: &str = "\
+# source:topology|BGP
+64500|64502|-1
+64500|64501|-1
+64501|64503|-1
+64502|64503|-1|bgp
+64503|64505|-1
+64503|64504|0
+64501|64500|-1
+bad|line|-1
+";
+
+const PPDC: &str = "\
+# cone for each AS
+64600 64600 64602 64601 64601
+64700 64700 64701
+";
+
+fn relationships(text: &str) -> AsRelationships {
+    let mut data = AsRelationships::default();
+    read_as_relationships(text.as_bytes(), None, &mut data).unwrap();
+    data
+}
+
+fn member(asn: u32, depth: usize, via: u32) -> ConeMember {
+    ConeMember {
+        asn: Asn(asn),
+        depth: Some(depth),
+        via: Some(Asn(via)),
+    }
+}
+
+#[test]
+fn expands_as_rel_breadth_first_with_limits() {
+    let data = relationships(AS_REL);
+    let cone = data.cone(Asn(64500), 8, 100);
+    assert_eq!(
+        cone.members,
+        [
+            member(64501, 1, 64500),
+            member(64502, 1, 64500),
+            member(64503, 2, 64501),
+            member(64505, 3, 64503)
+        ]
+    );
+    assert!(!cone.depth_limited && !cone.size_limited);
+    assert_eq!(
+        cone.summary(),
+        "AS64500 customer cone: 4 customer AS(es), depth 3"
+    );
+
+    let shallow = data.cone(Asn(64500), 2, 100);
+    assert_eq!(shallow.members.len(), 3);
+    assert!(shallow.depth_limited);
+
+    let small = data.cone(Asn(64500), 8, 2);
+    assert_eq!(
+        small.asns().collect::<Vec<_>>(),
+        [Asn(64500), Asn(64501), Asn(64502)]
+    );
+    assert!(small.size_limited);
+    assert!(small.summary().ends_with("truncated at the size limit"));
+
+    // 顧客の無い AS は自分だけ
+    assert!(data.cone(Asn(64505), 8, 100).members.is_empty());
+}
+
+#[test]
+fn falls_back_to_ppdc_ases_cones() {
+    let mut data = AsRelationships::default();
+    let wanted = [64600].into_iter().collect();
+    read_as_relationships(PPDC.as_bytes(), Some(&wanted), &mut data).unwrap();
+    let cone = data.cone(Asn(64600), 1, 100);
+    assert_eq!(
+        cone.asns().collect::<Vec<_>>(),
+        [Asn(64600), Asn(64601), Asn(64602)]
+    );
+    assert_eq!(cone.members[0].depth, None);
+    // 対象外の行は保持しない
+    assert!(data.cone(Asn(64700), 8, 100).members.is_empty());
+}
+
+#[tokio::test]
+async fn adds_cones_and_records_attribution() {
+    let dir = temp_dir("cone");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("as-rel.txt");
+    std::fs::write(&path, AS_REL).unwrap();
+
+    let mut opts = ConeOptions {
+        files: vec![path],
+        max_depth: 1,
+        ..ConeOptions::default()
+    };
+    // 無効なら変えない
+    let asns = add_customer_cones(&[Asn(64501), Asn(64500)], &opts)
+        .await
+        .unwrap();
+    assert_eq!(asns, [Asn(64501), Asn(64500)]);
+
+    journal().enable();
+    opts.enabled = true;
+    let asns = add_customer_cones(&[Asn(64501), Asn(64500)], &opts)
+        .await
+        .unwrap();
+    assert_eq!(asns, [Asn(64501), Asn(64500), Asn(64503), Asn(64502)]);
+
+    let net: IpNet = "198.51.100.0/24".parse().unwrap();
+    journal().record_prefixes("64503", [&net]);
+    let report = RunReport::finish(chrono::Local::now(), None, &Ok(()));
+    assert_eq!(report.cones.len(), 2);
+    let root = &report.cones[0];
+    assert_eq!(root.root, Asn(64501));
+    assert_eq!(root.members[0].depth, Some(0));
+    // 64501 → 64500 の p2c（ループ）も1段としてたどる
+    assert_eq!(root.members[1].asn, Asn(64500));
+    assert_eq!(root.members[2].asn, Asn(64503));
+    assert_eq!(root.members[2].via, Some(Asn(64501)));
+    assert_eq!(root.members[2].prefixes, ["198.51.100.0/24"]);
+    assert!(report.cones[1].depth_limited);
+
+    // 取得元が無ければエラー
+    opts.files.clear();
+    assert!(add_customer_cones(&[Asn(64500)], &opts).await.is_err());
+}
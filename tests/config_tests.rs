@@ -0,0 +1,341 @@
+mod common;
+
+use clap::{CommandFactory, FromArgMatches};
+use common::temp_dir;
+use fire_scope::asn::AsLookupOptions;
+use fire_scope::cli::Cli;
+use fire_scope::commands::handle_run::{run_job, run_jobs};
+use fire_scope::common::{Asn, OutputFormat};
+use fire_scope::config::{Config, Defaults};
+use fire_scope::interval::IpRangeSet;
+use fire_scope::output::OutputOptions;
+use fire_scope::parse::CountryMap;
+use ipnet::IpNet;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::fs;
+
+fn ipnet(s: &str) -> IpNet {
+    IpNet::from_str(s).unwrap_or_else(|e| panic!("failed to parse {s}: {e}"))
+}
+
+const SAMPLE: &str = r#"
+[defaults]
+format = "nft"
+max-retries = 3
+continue-on-partial = true
+continue-on-as-failure = true
+exclude = ["10.0.0.0/24"]
+
+[profiles.jp_allow]
+country = ["jpn", "kr"]
+as-number = [2497]
+set-name = "JP_ALLOW"
+exclude = ["10.0.1.0/25"]
+
+[profiles.carrier]
+country = ["jp"]
+as-number = [2497, "AS4713"]
+overlap = true
+format = "txt"
+output-dir = "out/carrier"
+"#;
+
+#[test]
+fn cli_defaults_apply_unless_given_on_command_line() {
+    let config = Config::from_toml_str(SAMPLE).unwrap_or_else(|e| panic!("config: {e}"));
+    let matches =
+        Cli::command().get_matches_from(["fire-scope", "--config", "x.toml", "--max-retries", "9"]);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| panic!("cli: {e}"));
+    config
+        .defaults
+        .apply(&mut cli, &matches)
+        .unwrap_or_else(|e| panic!("apply: {e}"));
+
+    // コマンドライン指定が優先
+    assert_eq!(cli.max_retries, 9);
+    // 未指定項目は設定ファイルの値
+    assert_eq!(cli.output_format, "nft");
+    assert!(cli.continue_on_partial);
+    assert!(cli.as_lookup_options().continue_on_failure);
+    assert_eq!(cli.exclude.unwrap(), vec![ipnet("10.0.0.0/24")]);
+}
+
+/// `[defaults]` のすべてのキーと、CLI の既定値と異なる値
+const EVERY_DEFAULT: &[(&str, &str)] = &[
+    ("country", r#"["jp"]"#),
+    ("as-number", "[2497]"),
+    ("overlap", "true"),
+    ("format", r#""nft""#),
+    ("max-retries", "2"),
+    ("max-backoff-sec", "4"),
+    ("http-timeout-secs", "30"),
+    ("connect-timeout-secs", "3"),
+    ("concurrency", "2"),
+    ("continue-on-partial", "true"),
+    ("continue-on-as-failure", "true"),
+    ("api-rate-limit", "1.5"),
+    ("ripestat-sourceapp", r#""example-org""#),
+    ("announced-window", r#""7d""#),
+    ("min-announced", r#""1d""#),
+    ("as-source", r#""mrt""#),
+    ("mrt-file", r#"["rib.mrt"]"#),
+    ("mrt-min-peers", "3"),
+    ("irr-dump", r#"["radb.db"]"#),
+    ("irr-whois", r#""whois.radb.net""#),
+    ("as-set-max-depth", "2"),
+    ("customer-cone", "true"),
+    ("as-rel-file", r#"["as-rel.txt"]"#),
+    ("cone-max-depth", "2"),
+    ("cone-max-size", "10"),
+    ("as-names", "true"),
+    ("as-names-file", r#""names.txt""#),
+    ("attribute-overlaps", "true"),
+    ("debug", "true"),
+    ("output-dir", r#""out""#),
+    ("exclude", r#"["10.0.0.0/8"]"#),
+    ("rir-url", r#"["https://example.net/rir"]"#),
+    ("post-hook", r#"["true"]"#),
+    ("metrics-file", r#""metrics.prom""#),
+    ("report", r#""report.json""#),
+    ("stats", r#""stats.json""#),
+    ("max-shrink-percent", "20.0"),
+    ("keep-bogons", "true"),
+    ("bogon-file", r#"["bogons.txt"]"#),
+    ("drop-longer-than-v4", "24"),
+    ("drop-longer-than-v6", "48"),
+    ("widen-to-v4", "24"),
+    ("widen-to-v6", "48"),
+    ("max-entries", "100"),
+    ("no-change-exit-code", "true"),
+    ("log-level", r#""info""#),
+    ("log-format", r#""json""#),
+];
+
+#[test]
+fn every_default_key_maps_to_a_cli_argument_and_is_applied() {
+    // 表のキーが上の一覧と一致する（キーを追加したら一覧にも値を追加する）
+    let keys: Vec<String> = Defaults::FIELDS
+        .iter()
+        .map(|(k, _)| k.replace('_', "-"))
+        .collect();
+    let expected: Vec<&str> = EVERY_DEFAULT.iter().map(|(k, _)| *k).collect();
+    assert_eq!(keys, expected);
+
+    let command = Cli::command();
+    for ((_, id), (key, value)) in Defaults::FIELDS.iter().zip(EVERY_DEFAULT) {
+        // 適用先の CLI 引数が存在する
+        assert!(
+            command.get_arguments().any(|a| a.get_id() == *id),
+            "no CLI argument '{id}' for '{key}'"
+        );
+
+        let config = Config::from_toml_str(&format!("[defaults]\n{key} = {value}\n"))
+            .unwrap_or_else(|e| panic!("{key}: {e}"));
+        let matches = Cli::command().get_matches_from(["fire-scope"]);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| panic!("cli: {e}"));
+        let before = format!("{cli:?}");
+        config
+            .defaults
+            .apply(&mut cli, &matches)
+            .unwrap_or_else(|e| panic!("{key}: {e}"));
+        assert_ne!(format!("{cli:?}"), before, "'{key}' was not applied");
+    }
+}
+
+#[test]
+fn resolve_jobs_validates_and_merges_profile_settings() {
+    let config = Config::from_toml_str(SAMPLE).unwrap_or_else(|e| panic!("config: {e}"));
+    let mut base = OutputOptions::new(OutputFormat::Nft);
+    base.exclude.push(ipnet("10.0.0.0/24"));
+
+    let jobs = config
+        .resolve_jobs(&[], true, &base)
+        .unwrap_or_else(|e| panic!("resolve: {e}"));
+    assert_eq!(jobs.len(), 2);
+
+    // 名前順（BTreeMap）
+    let carrier = &jobs[0];
+    assert_eq!(carrier.name, "carrier");
+    assert!(carrier.overlap);
+    assert_eq!(carrier.output.format, OutputFormat::Txt);
+    assert_eq!(carrier.output.dir, PathBuf::from("out/carrier"));
+    assert_eq!(carrier.all_as_numbers(), [Asn(2497), Asn(4713)]);
+
+    let jp = &jobs[1];
+    assert_eq!(jp.countries, vec!["JP".to_string(), "KR".to_string()]);
+    assert_eq!(jp.set_name.as_deref(), Some("JP_ALLOW"));
+    assert_eq!(
+        jp.output.exclude,
+        vec![ipnet("10.0.0.0/24"), ipnet("10.0.1.0/25")]
+    );
+
+    // 未知のプロファイル
+    assert!(
+        config
+            .resolve_jobs(&["nope".to_string()], false, &base)
+            .is_err()
+    );
+
+    // 不正な設定
+    let bad = Config::from_toml_str("[profiles.x]\ncountry = [\"zx\"]\n").unwrap();
+    assert!(bad.resolve_jobs(&[], true, &base).is_err());
+    assert!(Config::from_toml_str("[defaults]\nunknown-key = 1\n").is_err());
+    assert!(Config::from_toml_str("[profiles.x]\nas-number = [\"AS-FOO\"]\n").is_err());
+}
+
+#[test]
+fn profiles_accept_asn_ranges() {
+    let config = Config::from_toml_str(
+        "[profiles.lab]\nas-number = [\"64512-64514\", \"1.10\", 64513]\ncustomer-cone = true\n",
+    )
+    .unwrap();
+    let jobs = config
+        .resolve_jobs(&[], true, &OutputOptions::new(OutputFormat::Txt))
+        .unwrap();
+    // 範囲は展開して重複を除き、ラベルは指定のまま
+    assert_eq!(
+        jobs[0].all_as_numbers(),
+        [Asn(64512), Asn(64513), Asn(64514), Asn(65546)]
+    );
+    assert_eq!(jobs[0].as_labels(), ["64512-64514", "65546", "64513"]);
+    assert_eq!(jobs[0].customer_cone, Some(true));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_job_writes_named_set_with_exclusions() {
+    let dir = temp_dir("run");
+    let config = Config::from_toml_str(&format!(
+        "[profiles.allow]\ncountry = [\"jp\"]\nas-number = [64500]\nset-name = \"ALLOW\"\nexclude = [\"198.51.100.0/25\"]\noutput-dir = \"{}\"\n",
+        dir.display()
+    ))
+    .unwrap_or_else(|e| panic!("config: {e}"));
+    let jobs = config
+        .resolve_jobs(
+            &["allow".to_string()],
+            false,
+            &OutputOptions::new(OutputFormat::Txt),
+        )
+        .unwrap_or_else(|e| panic!("resolve: {e}"));
+
+    let mut country_map: CountryMap = HashMap::new();
+    country_map.insert(
+        "JP".into(),
+        IpRangeSet::from_nets(&[ipnet("198.51.100.0/24")]),
+    );
+    let mut as_map = HashMap::new();
+    as_map.insert(
+        "64500".to_string(),
+        (
+            BTreeSet::from([ipnet("203.0.113.0/24")]),
+            BTreeSet::from([ipnet("2001:db8::/48")]),
+        ),
+    );
+
+    run_job(&jobs[0], &country_map, &as_map)
+        .await
+        .unwrap_or_else(|e| panic!("run_job: {e}"));
+
+    let v4 = fs::read_to_string(dir.join("ALLOW_IPv4.txt"))
+        .await
+        .unwrap_or_else(|e| panic!("read v4: {e}"));
+    let v6 = fs::read_to_string(dir.join("ALLOW_IPv6.txt"))
+        .await
+        .unwrap_or_else(|e| panic!("read v6: {e}"));
+    assert!(v4.contains("198.51.100.128/25"));
+    assert!(!v4.contains("198.51.100.0/24"));
+    assert!(v4.contains("203.0.113.0/24"));
+    assert!(v6.contains("2001:db8::/48"));
+
+    let _ = fs::remove_dir_all(&dir).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_jobs_writes_nothing_when_a_later_profile_fails() {
+    let dir = temp_dir("run");
+    let config = Config::from_toml_str(&format!(
+        "[profiles.a]\ncountry = [\"jp\"]\noutput-dir = \"{0}/a\"\n\n[profiles.b]\ncountry = [\"kr\"]\noutput-dir = \"{0}/b\"\n",
+        dir.display()
+    ))
+    .unwrap_or_else(|e| panic!("config: {e}"));
+    let mut base = OutputOptions::new(OutputFormat::Txt);
+    base.max_shrink_percent = Some(30.0);
+    let jobs = config
+        .resolve_jobs(&[], true, &base)
+        .unwrap_or_else(|e| panic!("resolve: {e}"));
+
+    // b の前回の出力から半分に縮小するので b は失敗する
+    fs::create_dir_all(dir.join("b")).await.unwrap();
+    fs::write(dir.join("b/IPv4_KR.txt"), "198.18.0.0/24\n198.18.1.0/24\n")
+        .await
+        .unwrap();
+    let mut country_map: CountryMap = HashMap::new();
+    country_map.insert(
+        "JP".into(),
+        IpRangeSet::from_nets(&[ipnet("198.51.100.0/24")]),
+    );
+    country_map.insert(
+        "KR".into(),
+        IpRangeSet::from_nets(&[ipnet("198.18.0.0/24")]),
+    );
+
+    let err = run_jobs(
+        &jobs,
+        &country_map,
+        &reqwest::Client::new(),
+        &AsLookupOptions::new(1, false),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err.exit_code(),
+        fire_scope::error::exit_code::SAFETY_THRESHOLD
+    );
+    // 先に計算した a も書き出さない
+    assert!(!dir.join("a/IPv4_JP.txt").exists());
+    let kept = fs::read_to_string(dir.join("b/IPv4_KR.txt")).await.unwrap();
+    assert!(kept.contains("198.18.1.0/24"));
+
+    let _ = fs::remove_dir_all(&dir).await;
+}
+
+#[test]
+fn shaping_options_come_from_defaults_and_profiles() {
+    let config = Config::from_toml_str(
+        "[defaults]\ndrop-longer-than-v4 = 24\nmax-entries = 500\nkeep-bogons = true\nstats = \"stats.json\"\n\n[profiles.tcam]\ncountry = [\"jp\"]\nmax-entries = 64\nwiden-to-v6 = 48\n",
+    )
+    .unwrap();
+    let matches = Cli::command().get_matches_from([
+        "fire-scope",
+        "--config",
+        "x.toml",
+        "--max-entries",
+        "100",
+    ]);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap();
+    config.defaults.apply(&mut cli, &matches).unwrap();
+    let shape = cli.shape_options();
+    assert!(cli.keep_bogons);
+    assert_eq!(
+        cli.stats.as_deref(),
+        Some(std::path::Path::new("stats.json"))
+    );
+    assert_eq!(shape.drop_longer_than_v4, Some(24));
+    assert_eq!(shape.max_entries, Some(100));
+
+    let mut base = OutputOptions::new(OutputFormat::Txt);
+    base.shape = shape;
+    let jobs = config.resolve_jobs(&[], true, &base).unwrap();
+    assert_eq!(jobs[0].output.shape.max_entries, Some(64));
+    assert_eq!(jobs[0].output.shape.widen_to_v6, Some(48));
+    assert_eq!(jobs[0].output.shape.drop_longer_than_v4, Some(24));
+
+    // 範囲外の値
+    let bad =
+        Config::from_toml_str("[profiles.x]\ncountry = [\"jp\"]\nwiden-to-v4 = 33\n").unwrap();
+    assert!(bad.resolve_jobs(&[], true, &base).is_err());
+    let bad = Config::from_toml_str("[defaults]\nmax-entries = 0\n").unwrap();
+    let matches = Cli::command().get_matches_from(["fire-scope", "--config", "x.toml"]);
+    assert!(bad.defaults.apply(&mut cli, &matches).is_err());
+}
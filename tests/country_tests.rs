@@ -0,0 +1,30 @@
+use fire_scope::country::{is_known_alpha2, normalize_country_code};
+
+#[test]
+fn normalizes_alpha2_alpha3_and_aliases() {
+    assert_eq!(normalize_country_code("jp").unwrap(), "JP");
+    // alpha-3 → alpha-2
+    assert_eq!(normalize_country_code("jpn").unwrap(), "JP");
+    assert_eq!(normalize_country_code("USA").unwrap(), "US");
+    // 別名
+    assert_eq!(normalize_country_code("uk").unwrap(), "GB");
+    // RIR独自コード
+    assert_eq!(normalize_country_code("eu").unwrap(), "EU");
+    assert!(is_known_alpha2("AP"));
+}
+
+#[test]
+fn rejects_unknown_codes_with_suggestion() {
+    // 未知コード → 候補付きエラー
+    let e = normalize_country_code("JX").unwrap_err();
+    assert!(e.contains("Unknown country code 'JX'"));
+    assert!(e.contains("did you mean"));
+
+    // 隣接入れ替えの typo（NPJ → JPN → JP）
+    let e = normalize_country_code("jnp").unwrap_err();
+    assert!(e.contains("JP (JPN)"));
+
+    // 形式エラー
+    assert!(normalize_country_code("j1").is_err());
+    assert!(normalize_country_code("japan").is_err());
+}
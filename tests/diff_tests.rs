@@ -0,0 +1,59 @@
+use fire_scope::commands::handle_lookup::lookup_country;
+use fire_scope::diff::{diff_lists, parse_list_text};
+use fire_scope::interval::IpRangeSet;
+use fire_scope::parse::CountryMap;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+fn ipnet(s: &str) -> IpNet {
+    IpNet::from_str(s).unwrap_or_else(|e| panic!("failed to parse {s}: {e}"))
+}
+
+#[test]
+fn parses_txt_and_nft_lists() {
+    let nft = "# Generated at: x\ndefine IPv4_JP = {\n    10.0.0.0/25,\n    10.0.0.128/25, # comment\n    2001:db8::1\n}\n";
+    let got: Vec<String> = parse_list_text(nft)
+        .into_iter()
+        .map(|n| n.to_string())
+        .collect();
+    assert_eq!(got, vec!["10.0.0.0/25", "10.0.0.128/25", "2001:db8::1/128"]);
+}
+
+#[test]
+fn diff_is_address_based() {
+    let old = parse_list_text("10.0.0.0/25\n10.0.0.128/25\n192.0.2.0/24\n");
+    let new = parse_list_text("10.0.0.0/24\n198.51.100.0/24\n");
+    let diff = diff_lists(&old, &new);
+    let added: Vec<String> = diff.added.iter().map(|n| n.to_string()).collect();
+    let removed: Vec<String> = diff.removed.iter().map(|n| n.to_string()).collect();
+    // 集約の違い（/25 x2 → /24）は差分にならない
+    assert_eq!(added, vec!["198.51.100.0/24"]);
+    assert_eq!(removed, vec!["192.0.2.0/24"]);
+
+    assert!(diff_lists(&old, &old).is_empty());
+}
+
+#[test]
+fn lookup_country_finds_containing_allocation() {
+    let mut map: CountryMap = HashMap::new();
+    map.insert(
+        "JP".into(),
+        IpRangeSet::from_nets(&[ipnet("10.0.0.0/16"), ipnet("2001:db8::/32")]),
+    );
+    map.insert("KR".into(), IpRangeSet::from_nets(&[ipnet("10.1.0.0/16")]));
+
+    let ip = IpAddr::from_str("10.1.2.3").unwrap();
+    assert_eq!(
+        lookup_country(&map, ip),
+        Some(("KR".to_string(), ipnet("10.1.0.0/16")))
+    );
+    let ip = IpAddr::from_str("2001:db8::1").unwrap();
+    assert_eq!(
+        lookup_country(&map, ip).map(|(cc, _)| cc),
+        Some("JP".to_string())
+    );
+    let ip = IpAddr::from_str("192.0.2.1").unwrap();
+    assert!(lookup_country(&map, ip).is_none());
+}
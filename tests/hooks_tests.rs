@@ -0,0 +1,83 @@
+mod common;
+
+use common::{ranges, temp_dir};
+use fire_scope::common::OutputFormat;
+use fire_scope::error::AppError;
+use fire_scope::hooks::run_hooks;
+use fire_scope::output::{OutputOptions, write_ip_lists_to_files};
+
+#[tokio::test]
+async fn write_log_marks_only_changed_files() {
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("hooks");
+    let set = ranges(&["198.51.100.0/24", "2001:db8::/32"]);
+
+    write_ip_lists_to_files("JP", &set, &output).await.unwrap();
+    let first = output.log.take();
+    assert_eq!(first.len(), 2);
+    assert!(first.iter().all(|f| f.changed));
+
+    // 生成日時以外が同じなら未変更
+    write_ip_lists_to_files("JP", &set, &output).await.unwrap();
+    let second = output.log.take();
+    assert_eq!(second.len(), 2);
+    assert!(second.iter().all(|f| !f.changed));
+
+    let set_new = ranges(&["198.51.100.0/24", "203.0.113.0/24", "2001:db8::/32"]);
+    write_ip_lists_to_files("JP", &set_new, &output)
+        .await
+        .unwrap();
+    let third = output.log.take();
+    let changed: Vec<_> = third.iter().filter(|f| f.changed).collect();
+    assert_eq!(changed.len(), 1);
+    assert!(changed[0].path.ends_with("IPv4_JP.txt"));
+    assert_eq!(changed[0].entries, 2);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn hooks_run_only_on_change_and_receive_env() {
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("hooks");
+    let marker = output.dir.join("hook.env");
+    let hook = format!(
+        "printf '%s|%s|%s|%s' \"$FIRE_SCOPE_CHANGED_COUNT\" \"$FIRE_SCOPE_WRITTEN_COUNT\" \"$FIRE_SCOPE_PREFIX_COUNT\" \"$FIRE_SCOPE_PROFILE\" > {}",
+        marker.display()
+    );
+    let hooks = vec![hook];
+
+    write_ip_lists_to_files("JP", &ranges(&["198.51.100.0/24"]), &output)
+        .await
+        .unwrap();
+    run_hooks(&hooks, &output.log.take(), Some("edge"))
+        .await
+        .unwrap();
+    let env = std::fs::read_to_string(&marker).unwrap();
+    assert_eq!(env, "2|2|1|edge");
+
+    // 内容が変わらなければフックは実行されない
+    std::fs::remove_file(&marker).unwrap();
+    write_ip_lists_to_files("JP", &ranges(&["198.51.100.0/24"]), &output)
+        .await
+        .unwrap();
+    run_hooks(&hooks, &output.log.take(), Some("edge"))
+        .await
+        .unwrap();
+    assert!(!marker.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn failing_hook_is_reported_as_error() {
+    let mut output = OutputOptions::new(OutputFormat::Nft);
+    output.dir = temp_dir("hooks");
+    write_ip_lists_to_files("JP", &ranges(&["198.51.100.0/24"]), &output)
+        .await
+        .unwrap();
+
+    let res = run_hooks(&["exit 3".to_string()], &output.log.take(), None).await;
+    match res {
+        Err(AppError::Hook(msg)) => assert!(msg.contains("status 3"), "{msg}"),
+        other => panic!("expected hook error, got {other:?}"),
+    }
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::{nets, strings};
+use fire_scope::interval::{IpRangeBuilder, IpRangeSet, Ranges};
+use ipnet::IpNet;
+use std::collections::BTreeSet;
+
+#[test]
+fn ranges_merge_and_set_operations() {
+    // 重なり・隣接はまとまり、start > end は捨てる
+    let a = Ranges::<u32>::from_unsorted(vec![(20, 29), (0, 9), (10, 14), (5, 7), (40, 30)]);
+    assert_eq!(a.ranges(), &[(0, 14), (20, 29)]);
+    let b = Ranges::<u32>::from_unsorted(vec![(12, 22), (28, u32::MAX)]);
+
+    assert_eq!(a.union(&b).ranges(), &[(0, u32::MAX)]);
+    assert_eq!(a.intersection(&b).ranges(), &[(12, 14), (20, 22), (28, 29)]);
+    assert_eq!(a.difference(&b).ranges(), &[(0, 11), (23, 27)]);
+    assert_eq!(b.difference(&a).ranges(), &[(15, 19), (30, u32::MAX)]);
+    assert_eq!(a.address_count(), 25);
+    assert!(a.overlaps(14, 19) && !a.overlaps(15, 19));
+
+    // 空間全体でも桁あふれしない
+    let all = Ranges::<u128>::from_unsorted(vec![(0, u128::MAX), (5, 6)]);
+    assert_eq!(all.ranges(), &[(0, u128::MAX)]);
+    assert_eq!(all.cidrs(), vec![(0, 0)]);
+    assert_eq!(all.address_count(), u128::MAX);
+    assert!(all.difference(&all).is_empty());
+}
+
+#[test]
+fn converts_to_minimal_cidrs_like_aggregate() {
+    let mut builder = IpRangeBuilder::new();
+    // RIR の割り当て（192.0.2.0 から 384 個、重複と隣接を含む）
+    builder.push_v4(0xC000_0200, 0xC000_0200 + 383);
+    builder.push_v4(0xC000_0300, 0xC000_03FF);
+    builder.push_net(&"2001:db8::/33".parse().unwrap());
+    builder.push_net(&"2001:db8:8000::/33".parse().unwrap());
+    assert_eq!(builder.distinct_cidr_len(), (3, 2));
+    let set = builder.build();
+    assert_eq!(strings(&set.to_set()), ["192.0.2.0/23", "2001:db8::/32"]);
+
+    // CIDR 境界にかからない区間は IpNet::aggregate と同じ最小の列になる
+    let odd = nets(&[
+        "10.0.0.1/32",
+        "10.0.0.2/31",
+        "10.0.0.4/30",
+        "10.0.0.8/32",
+        "::1/128",
+    ]);
+    let expected: BTreeSet<IpNet> = IpNet::aggregate(&odd.iter().copied().collect::<Vec<_>>())
+        .into_iter()
+        .collect();
+    assert_eq!(IpRangeSet::from_nets(&odd).to_set(), expected);
+}
+
+#[test]
+fn ip_range_sets_keep_families_apart() {
+    let a = IpRangeSet::from_nets(&nets(&["10.0.0.0/8", "2001:db8::/32"]));
+    let b = IpRangeSet::from_nets(&nets(&["10.1.0.0/16", "::/0"]));
+    assert_eq!(
+        strings(&a.intersection(&b).to_set()),
+        ["10.1.0.0/16", "2001:db8::/32"]
+    );
+    assert_eq!(a.difference(&b).v4_nets().len(), 8);
+    assert!(a.difference(&b).v6.is_empty());
+    assert!(a.union(&b).overlaps_net(&"::1/128".parse().unwrap()));
+    assert!(!a.overlaps_net(&"11.0.0.0/8".parse().unwrap()));
+    assert_eq!(a.address_count(), (1 << 24) + (1 << 96));
+}
@@ -0,0 +1,323 @@
+mod common;
+
+use common::temp_dir;
+use fire_scope::asn::{AsLookupOptions, AsOutcome, AsSource, fetch_prefixes_for_many};
+use fire_scope::common::{Asn, OutputFormat};
+use fire_scope::config::Config;
+use fire_scope::irr::{
+    AsSetResolver, IrrOptions, IrrRoutes, Member, RouteMap, compare_routes, parse_member,
+    read_rpsl_as_sets, read_rpsl_dump, read_rpsl_routes, resolve_as_numbers,
+};
+use fire_scope::output::OutputOptions;
+use ipnet::IpNet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const DUMP: &str = "\
+# comment line
+as-set:         AS-ROOT
+descr:          members: AS999 (not a members attribute)
+members:        AS1, AS2 # trailing comment
+                AS-CHILD,
++               as-diamond
+mp-members:     AS1.4464
+source:         TEST
+
+as-set:         AS-CHILD
+members:        AS3, AS-DIAMOND, AS-ROOT, AS-MISSING
+source:         TEST
+
+as-set:         AS-DIAMOND
+members:        AS4, AS-DEEP
+source:         TEST
+
+as-set:         AS-DEEP
+members:        AS5
+source:         TEST
+
+aut-num:        AS1
+members:        AS6
+source:         TEST
+
+as-set:         AS-CHILD
+members:        AS7
+source:         OTHER
+";
+
+fn sets_from(text: &str) -> HashMap<String, Vec<String>> {
+    let mut sets = HashMap::new();
+    read_rpsl_as_sets(text.as_bytes(), &mut sets).unwrap();
+    sets
+}
+
+fn resolver(max_depth: usize) -> AsSetResolver {
+    let opts = IrrOptions {
+        max_depth,
+        ..IrrOptions::default()
+    };
+    AsSetResolver::from_sets(sets_from(DUMP), &opts)
+}
+
+#[test]
+fn parses_members() {
+    assert_eq!(parse_member("AS2497"), Some(Member::Asn(2497)));
+    assert_eq!(parse_member("as1.10"), Some(Member::Asn(65546)));
+    assert_eq!(parse_member("AS-Foo"), Some(Member::Set("AS-FOO".into())));
+    assert_eq!(
+        parse_member("AS2497:AS-CUSTOMERS"),
+        Some(Member::Set("AS2497:AS-CUSTOMERS".into()))
+    );
+    assert_eq!(parse_member("ANY"), None);
+    assert_eq!(parse_member("AS99999999999"), None);
+}
+
+#[test]
+fn reads_as_set_objects_with_continuations() {
+    let sets = sets_from(DUMP);
+    assert_eq!(
+        sets["AS-ROOT"],
+        ["AS1", "AS2", "AS-CHILD", "AS-DIAMOND", "AS1.4464"]
+    );
+    // 同名のオブジェクトは合算、aut-num の members は無視
+    assert_eq!(
+        sets["AS-CHILD"],
+        ["AS3", "AS-DIAMOND", "AS-ROOT", "AS-MISSING", "AS7"]
+    );
+    assert!(!sets.contains_key("AS1"));
+}
+
+#[test]
+fn reads_gzip_dumps() {
+    let dir = temp_dir("irr");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("test.db.gz");
+    let mut enc = flate2::write::GzEncoder::new(
+        std::fs::File::create(&path).unwrap(),
+        flate2::Compression::default(),
+    );
+    enc.write_all(DUMP.as_bytes()).unwrap();
+    enc.finish().unwrap();
+
+    let mut sets = HashMap::new();
+    read_rpsl_dump(&path, &mut sets).unwrap();
+    assert_eq!(sets.len(), 4);
+}
+
+#[tokio::test]
+async fn expands_recursively_with_loop_and_depth_limits() {
+    let expansion = resolver(8).expand("as-root").await.unwrap();
+    assert_eq!(expansion.name, "AS-ROOT");
+    assert_eq!(expansion.asns, BTreeSet::from([1, 2, 3, 4, 5, 7, 70000]));
+    assert_eq!(expansion.sets, 4);
+    assert_eq!(expansion.loops, ["AS-CHILD -> AS-ROOT"]);
+    assert_eq!(expansion.missing, ["AS-MISSING"]);
+    assert!(expansion.truncated.is_empty());
+
+    // 深さ1: AS-ROOT 直下の AS-SET までは展開し、その先は展開しない
+    let shallow = resolver(1).expand("AS-ROOT").await.unwrap();
+    assert_eq!(shallow.asns, BTreeSet::from([1, 2, 3, 4, 7, 70000]));
+    assert_eq!(shallow.truncated, ["AS-DEEP", "AS-MISSING"]);
+    assert!(
+        shallow
+            .summary()
+            .contains("depth limit: AS-DEEP, AS-MISSING")
+    );
+
+    assert!(resolver(8).expand("AS-NOPE").await.is_err());
+}
+
+#[tokio::test]
+async fn expands_via_whois() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut sock, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 256];
+                while !buf.ends_with(b"\r\n") {
+                    match sock.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                let resp = match String::from_utf8_lossy(&buf).trim() {
+                    "AS-REMOTE" => "as-set: AS-REMOTE\nmembers: AS10, AS-NESTED\n\n",
+                    "AS-NESTED" => "% comment\nas-set: AS-NESTED\nmembers: AS11\n",
+                    "-i origin AS11" => {
+                        "route: 198.51.100.0/24\norigin: AS11\n\nroute6: 2001:db8::/32\norigin: AS11\n"
+                    }
+                    _ => "%  No entries found for the selected source(s).\n",
+                };
+                let _ = sock.write_all(resp.as_bytes()).await;
+            });
+        }
+    });
+
+    let opts = IrrOptions {
+        whois: Some(addr.to_string()),
+        ..IrrOptions::default()
+    };
+    let asns = resolve_as_numbers(&[Asn(10), Asn(2497)], &["AS-REMOTE".into()], &opts)
+        .await
+        .unwrap();
+    assert_eq!(asns, [Asn(10), Asn(2497), Asn(11)]);
+
+    // route / route6 はダンプに無ければ whois の `-i origin` で引く
+    let routes = IrrRoutes::from_routes(RouteMap::new(), &opts);
+    let (v4, v6) = routes.prefixes(11).await.unwrap();
+    assert_eq!(v4, BTreeSet::from([net("198.51.100.0/24")]));
+    assert_eq!(v6, BTreeSet::from([net("2001:db8::/32")]));
+    assert!(routes.prefixes(12).await.unwrap().0.is_empty());
+
+    // 取得元が無ければエラー
+    assert!(
+        resolve_as_numbers(&[], &["AS-REMOTE".into()], &IrrOptions::default())
+            .await
+            .is_err()
+    );
+}
+
+#[test]
+fn profiles_accept_as_sets() {
+    let config = Config::from_toml_str(
+        r#"
+[profiles.peers]
+country = ["jp"]
+as-set = ["as-example"]
+overlap = true
+
+[profiles.bad]
+as-set = ["2497"]
+"#,
+    )
+    .unwrap();
+    let base = OutputOptions::new(OutputFormat::Txt);
+    let jobs = config
+        .resolve_jobs(&["peers".into()], false, &base)
+        .unwrap();
+    assert_eq!(jobs[0].as_sets, ["AS-EXAMPLE"]);
+    assert_eq!(jobs[0].as_labels(), ["AS-EXAMPLE"]);
+    assert!(config.resolve_jobs(&["bad".into()], false, &base).is_err());
+}
+
+const ROUTES: &str = "\
+route:          192.0.2.0/24
+descr:          example
+origin:         AS64500
+source:         TEST
+
+route:          198.51.100.0/25
+origin:         as64500 # comment
+source:         TEST
+
+route:          198.51.100.128/25
+origin:         AS64500
+source:         TEST
+
+route6:         2001:db8::/32
+origin:         AS64500
+source:         TEST
+
+route:          2001:db8:1::/48
+origin:         AS64500
+source:         TEST
+
+route:          203.0.113.0/24
+origin:         AS64501
+source:         TEST
+
+route:          not-a-prefix
+origin:         AS64500
+source:         TEST
+";
+
+fn net(s: &str) -> IpNet {
+    s.parse().unwrap()
+}
+
+#[test]
+fn reads_route_objects_by_origin() {
+    let mut routes = RouteMap::new();
+    read_rpsl_routes(ROUTES.as_bytes(), None, &mut routes).unwrap();
+    let (v4, v6) = &routes[&64500];
+    assert_eq!(v4.len(), 3);
+    // 族の合わない route（IPv6 の route:）と壊れたプレフィックスは無視
+    assert_eq!(v6, &BTreeSet::from([net("2001:db8::/32")]));
+    assert_eq!(routes[&64501].0, BTreeSet::from([net("203.0.113.0/24")]));
+
+    let mut only = RouteMap::new();
+    read_rpsl_routes(ROUTES.as_bytes(), Some(&HashSet::from([64501])), &mut only).unwrap();
+    assert_eq!(only.keys().collect::<Vec<_>>(), [&64501]);
+}
+
+#[test]
+fn compares_irr_with_bgp() {
+    let irr = BTreeSet::from([
+        net("192.0.2.0/24"),
+        net("198.51.100.0/22"),
+        net("203.0.113.0/24"),
+    ]);
+    let bgp = BTreeSet::from([
+        net("203.0.113.0/24"),
+        net("198.51.100.0/24"),
+        net("100.64.0.0/24"),
+    ]);
+    let cmp = compare_routes(&irr, &bgp);
+    assert_eq!(cmp.both, 1);
+    assert_eq!(cmp.irr_only, [net("192.0.2.0/24"), net("198.51.100.0/22")]);
+    assert_eq!(
+        cmp.bgp_only,
+        [
+            (net("100.64.0.0/24"), None),
+            (net("198.51.100.0/24"), Some(net("198.51.100.0/22")))
+        ]
+    );
+    assert_eq!(
+        cmp.report(64500),
+        "AS64500: 3 in IRR, 3 in BGP, 1 in both\n\
+         \x20 irr-only 192.0.2.0/24\n\
+         \x20 irr-only 198.51.100.0/22\n\
+         \x20 bgp-only 100.64.0.0/24\n\
+         \x20 bgp-only 198.51.100.0/24 (covered by route 198.51.100.0/22)\n"
+    );
+}
+
+#[tokio::test]
+async fn irr_source_feeds_the_as_pipeline() {
+    let dir = temp_dir("irr");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("routes.db");
+    std::fs::write(&path, ROUTES).unwrap();
+
+    let mut opts = AsLookupOptions::new(2, false);
+    opts.source = AsSource::Irr;
+    opts.irr.dumps = vec![path];
+    let client = reqwest::Client::new();
+    let as_numbers = ["64500".to_string(), "64502".to_string()];
+    let (map, report) = fetch_prefixes_for_many(&client, &as_numbers, &opts)
+        .await
+        .unwrap();
+
+    // 隣接する /25 は集約される
+    let (v4, v6) = &map["64500"];
+    assert_eq!(
+        v4,
+        &BTreeSet::from([net("192.0.2.0/24"), net("198.51.100.0/24")])
+    );
+    assert_eq!(v6.len(), 1);
+    assert_eq!(report.outcomes[1], ("64502".to_string(), AsOutcome::Empty));
+
+    // 取得元が無ければエラー
+    opts.irr.dumps.clear();
+    assert!(
+        fetch_prefixes_for_many(&client, &as_numbers, &opts)
+            .await
+            .is_err()
+    );
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::nets;
+use fire_scope::common::IpFamily;
+use fire_scope::metrics::Registry;
+use std::time::Duration;
+
+#[test]
+fn renders_download_and_list_metrics() {
+    let mut reg = Registry::new();
+    reg.record_download(
+        "https://example.net/a",
+        Duration::from_millis(1500),
+        Some(1234),
+        2,
+    );
+    reg.record_download_failure("https://example.net/b", 5);
+    reg.record_parse(Duration::from_millis(250));
+    reg.record_list(
+        "country",
+        "JP",
+        IpFamily::V4,
+        &nets(&["198.51.100.0/24", "203.0.113.0/25"]),
+    );
+    reg.record_list("asn", "64500", IpFamily::V6, &nets(&["2001:db8::/64"]));
+
+    let text = reg.render();
+    assert!(text.contains("# TYPE fire_scope_download_duration_seconds gauge"));
+    assert!(
+        text.contains("fire_scope_download_duration_seconds{url=\"https://example.net/a\"} 1.5")
+    );
+    assert!(text.contains("fire_scope_download_bytes{url=\"https://example.net/a\"} 1234"));
+    assert!(text.contains("fire_scope_download_retries_total{url=\"https://example.net/a\"} 2"));
+    assert!(text.contains("fire_scope_download_failures_total{url=\"https://example.net/b\"} 1"));
+    assert!(text.contains("fire_scope_parse_duration_seconds 0.25"));
+    assert!(
+        text.contains("fire_scope_list_prefixes{kind=\"country\",name=\"JP\",family=\"IPv4\"} 2")
+    );
+    assert!(
+        text.contains(
+            "fire_scope_list_addresses{kind=\"country\",name=\"JP\",family=\"IPv4\"} 384"
+        )
+    );
+    assert!(text.contains("fire_scope_list_addresses{kind=\"asn\",name=\"64500\",family=\"IPv6\"} 18446744073709552000"));
+    assert!(text.contains("fire_scope_list_last_success_timestamp_seconds{kind=\"country\",name=\"JP\",family=\"IPv4\"} "));
+    // 失敗したURLには成功時刻が無い
+    assert!(!text.contains(
+        "fire_scope_download_last_success_timestamp_seconds{url=\"https://example.net/b\"}"
+    ));
+}
+
+#[test]
+fn last_success_is_carried_over_from_previous_file() {
+    let previous = "\
+# TYPE fire_scope_list_last_success_timestamp_seconds gauge
+fire_scope_list_last_success_timestamp_seconds{kind=\"country\",name=\"KR\",family=\"IPv4\"} 1700000000
+fire_scope_list_last_success_timestamp_seconds{kind=\"country\",name=\"JP\",family=\"IPv4\"} 1600000000
+fire_scope_run_last_success_timestamp_seconds 1700000000
+fire_scope_list_prefixes{kind=\"country\",name=\"KR\",family=\"IPv4\"} 10
+";
+    let mut reg = Registry::new();
+    reg.record_list("country", "JP", IpFamily::V4, &nets(&["198.51.100.0/24"]));
+    reg.carry_over_last_success(previous);
+
+    let text = reg.render();
+    assert!(text.contains("fire_scope_list_last_success_timestamp_seconds{kind=\"country\",name=\"KR\",family=\"IPv4\"} 1700000000"));
+    assert!(text.contains("fire_scope_run_last_success_timestamp_seconds 1700000000"));
+    // 今回書き出したリストは新しい時刻のまま
+    assert!(!text.contains("name=\"JP\",family=\"IPv4\"} 1600000000"));
+    // 成功時刻以外は引き継がない
+    assert!(!text.contains("fire_scope_list_prefixes{kind=\"country\",name=\"KR\""));
+}
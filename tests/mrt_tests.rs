@@ -0,0 +1,215 @@
+mod common;
+
+use common::temp_dir;
+use fire_scope::asn::{AsLookupOptions, AsSource, fetch_prefixes_for_many};
+use fire_scope::mrt::{MrtRoutes, OriginCounts, origins_from_as_path, read_mrt, read_mrt_file};
+use ipnet::IpNet;
+use std::collections::{BTreeSet, HashSet};
+use std::io::Write;
+
+fn net(s: &str) -> IpNet {
+    s.parse().unwrap()
+}
+
+/// AS_PATH 属性の値（(セグメント種別, AS) の並び）
+fn as_path(segments: &[(u8, &[u32])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (seg_type, asns) in segments {
+        out.push(*seg_type);
+        out.push(asns.len() as u8);
+        for asn in *asns {
+            out.extend_from_slice(&asn.to_be_bytes());
+        }
+    }
+    out
+}
+
+/// ORIGIN と AS_PATH（拡張長）を持つパス属性
+fn attrs(path: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x40, 1, 1, 0];
+    out.extend_from_slice(&[0x50, 2]);
+    out.extend_from_slice(&(path.len() as u16).to_be_bytes());
+    out.extend_from_slice(path);
+    out
+}
+
+fn record(subtype: u16, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+    out.extend_from_slice(&13u16.to_be_bytes());
+    out.extend_from_slice(&subtype.to_be_bytes());
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(body);
+    out
+}
+
+/// RIB_IPV4/IPV6_UNICAST（`add_path` なら ADD-PATH 版）の1レコード
+fn rib(prefix: &str, entries: &[(u16, Vec<u8>)], add_path: bool) -> Vec<u8> {
+    let net = net(prefix);
+    let mut body = 7u32.to_be_bytes().to_vec();
+    body.push(net.prefix_len());
+    let addr = match net {
+        IpNet::V4(n) => n.addr().octets().to_vec(),
+        IpNet::V6(n) => n.addr().octets().to_vec(),
+    };
+    body.extend_from_slice(&addr[..usize::from(net.prefix_len()).div_ceil(8)]);
+    body.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for (i, (peer, path)) in entries.iter().enumerate() {
+        body.extend_from_slice(&peer.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        if add_path {
+            body.extend_from_slice(&(i as u32).to_be_bytes());
+        }
+        let a = attrs(path);
+        body.extend_from_slice(&(a.len() as u16).to_be_bytes());
+        body.extend_from_slice(&a);
+    }
+    let subtype = match (net, add_path) {
+        (IpNet::V4(_), false) => 2,
+        (IpNet::V6(_), false) => 4,
+        (IpNet::V4(_), true) => 8,
+        (IpNet::V6(_), true) => 10,
+    };
+    record(subtype, &body)
+}
+
+fn sample_dump() -> Vec<u8> {
+    let seq = |asns: &[u32]| as_path(&[(2, asns)]);
+    let mut dump = record(1, &[0u8; 8]); // PEER_INDEX_TABLE（中身は読まない）
+    dump.extend(rib(
+        "192.0.2.0/24",
+        &[
+            (0, seq(&[65000, 64500])),
+            (1, seq(&[65001, 64500])),
+            (2, seq(&[65002, 64501])),
+        ],
+        false,
+    ));
+    // 末尾が AS_SET（集約経路）: 集合の各 AS を起源とする
+    dump.extend(rib(
+        "198.51.100.0/24",
+        &[(0, as_path(&[(2, &[65000]), (1, &[64500, 64502])]))],
+        false,
+    ));
+    // ADD-PATH: 同じピアの2経路は1ピアと数える
+    dump.extend(rib(
+        "2001:db8::/32",
+        &[
+            (0, seq(&[65000, 64500])),
+            (0, seq(&[65003, 64500])),
+            (1, seq(&[64500])),
+        ],
+        true,
+    ));
+    // 空の AS_PATH（コレクタ自身の経路）は起源なし
+    dump.extend(rib("203.0.113.0/24", &[(0, Vec::new())], false));
+    dump
+}
+
+#[test]
+fn origins_from_as_path_segments() {
+    assert_eq!(
+        origins_from_as_path(&as_path(&[(2, &[1, 2, 3])])).unwrap(),
+        [3]
+    );
+    assert_eq!(
+        origins_from_as_path(&as_path(&[(2, &[1]), (1, &[9, 4, 9])])).unwrap(),
+        [4, 9]
+    );
+    // 末尾の AS_CONFED_SEQUENCE は無視
+    assert_eq!(
+        origins_from_as_path(&as_path(&[(2, &[1, 2]), (3, &[7])])).unwrap(),
+        [2]
+    );
+    assert!(origins_from_as_path(&[]).unwrap().is_empty());
+    assert!(origins_from_as_path(&[2, 2, 0, 0]).is_err());
+}
+
+#[test]
+fn reads_rib_records_with_visibility() {
+    let mut counts = OriginCounts::new();
+    let ribs = read_mrt(sample_dump().as_slice(), None, &mut counts).unwrap();
+    assert_eq!(ribs, 4);
+    assert_eq!(counts[&(net("192.0.2.0/24"), 64500)], 2);
+    assert_eq!(counts[&(net("192.0.2.0/24"), 64501)], 1);
+    assert_eq!(counts[&(net("198.51.100.0/24"), 64502)], 1);
+    assert_eq!(counts[&(net("2001:db8::/32"), 64500)], 2);
+    assert_eq!(counts.len(), 5);
+
+    let routes = MrtRoutes::from_counts(&counts, 2);
+    let (v4, v6) = routes.prefixes(64500);
+    assert_eq!(v4, BTreeSet::from([net("192.0.2.0/24")]));
+    assert_eq!(v6, BTreeSet::from([net("2001:db8::/32")]));
+    assert!(routes.prefixes(64501).0.is_empty());
+
+    // 対象の起源だけを数える
+    let mut only = OriginCounts::new();
+    read_mrt(
+        sample_dump().as_slice(),
+        Some(&HashSet::from([64502])),
+        &mut only,
+    )
+    .unwrap();
+    assert_eq!(only.len(), 1);
+
+    // 途中で切れたファイルはエラー
+    let dump = sample_dump();
+    assert!(read_mrt(&dump[..dump.len() - 3], None, &mut OriginCounts::new()).is_err());
+}
+
+#[test]
+fn reads_compressed_files() {
+    let dir = temp_dir("mrt");
+    std::fs::create_dir_all(&dir).unwrap();
+    let gz = dir.join("bview.gz");
+    let mut enc = flate2::write::GzEncoder::new(
+        std::fs::File::create(&gz).unwrap(),
+        flate2::Compression::default(),
+    );
+    enc.write_all(&sample_dump()).unwrap();
+    enc.finish().unwrap();
+    let bz = dir.join("rib.bz2");
+    let mut enc = bzip2::write::BzEncoder::new(
+        std::fs::File::create(&bz).unwrap(),
+        bzip2::Compression::default(),
+    );
+    enc.write_all(&sample_dump()).unwrap();
+    enc.finish().unwrap();
+
+    for path in [gz, bz] {
+        let mut counts = OriginCounts::new();
+        assert_eq!(read_mrt_file(&path, None, &mut counts).unwrap(), 4);
+        assert_eq!(counts.len(), 5);
+    }
+}
+
+#[tokio::test]
+async fn mrt_source_feeds_the_as_pipeline() {
+    let dir = temp_dir("mrt");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("rib.mrt");
+    std::fs::write(&path, sample_dump()).unwrap();
+
+    let mut opts = AsLookupOptions::new(2, false);
+    opts.source = AsSource::Mrt;
+    opts.mrt.files = vec![path.clone(), path];
+    opts.mrt.min_peers = 3;
+    let client = reqwest::Client::new();
+    let as_numbers = ["64500".to_string(), "64502".to_string()];
+    let (map, report) = fetch_prefixes_for_many(&client, &as_numbers, &opts)
+        .await
+        .unwrap();
+
+    // 2ファイル分のピア数を合算してしきい値を判定する
+    assert_eq!(map["64500"].0, BTreeSet::from([net("192.0.2.0/24")]));
+    assert_eq!(map["64500"].1.len(), 1);
+    assert!(map["64502"].0.is_empty());
+    assert_eq!(report.empty(), 1);
+
+    opts.mrt.files.clear();
+    assert!(
+        fetch_prefixes_for_many(&client, &as_numbers, &opts)
+            .await
+            .is_err()
+    );
+}
@@ -1,3 +1,5 @@
+mod common;
+
 use fire_scope::overlap::find_overlaps;
 use ipnet::IpNet;
 use std::collections::BTreeSet;
@@ -29,3 +31,172 @@ fn find_overlaps_ipv4_and_ipv6_basic() {
     assert!(got.contains(&"2001:db8:8000::/33".to_string()));
 }
 
+#[test]
+fn subtract_nets_splits_partially_excluded_ranges() {
+    let mut base = BTreeSet::new();
+    base.insert(ipnet("10.0.0.0/24"));
+    base.insert(ipnet("10.0.2.0/24"));
+    base.insert(ipnet("2001:db8::/32"));
+
+    let exclude = vec![
+        ipnet("10.0.0.0/26"),
+        ipnet("10.0.0.128/25"),
+        ipnet("10.0.2.0/23"),
+        ipnet("2001:db8::/33"),
+    ];
+
+    let got: Vec<String> = fire_scope::overlap::subtract_nets(&base, &exclude)
+        .into_iter()
+        .map(|n| n.to_string())
+        .collect();
+    assert_eq!(got, vec!["10.0.0.64/26", "2001:db8:8000::/33"]);
+}
+
+#[test]
+fn pair_overlaps_are_computed_per_country_and_as() {
+    use fire_scope::asn::AsMap;
+    use fire_scope::commands::handle_overlap::{pair_overlaps, render_overlap_matrix};
+    use fire_scope::interval::IpRangeSet;
+    use fire_scope::parse::CountryMap;
+
+    let mut countries = CountryMap::new();
+    countries.insert(
+        "JP".into(),
+        IpRangeSet::from_nets(&[ipnet("192.0.2.0/24"), ipnet("2001:db8::/32")]),
+    );
+    countries.insert(
+        "KR".into(),
+        IpRangeSet::from_nets(&[ipnet("192.0.2.192/26"), ipnet("198.51.100.0/24")]),
+    );
+    let mut as_map = AsMap::new();
+    as_map.insert(
+        "2497".into(),
+        (
+            [ipnet("192.0.2.0/25"), ipnet("198.51.100.0/26")].into(),
+            [ipnet("2001:db8::/48")].into(),
+        ),
+    );
+    as_map.insert(
+        "4713".into(),
+        ([ipnet("192.0.2.128/26")].into(), BTreeSet::new()),
+    );
+
+    let codes = ["JP".to_string(), "KR".to_string()];
+    // 取得できなかった AS（64500）は空
+    let asns = ["2497".to_string(), "4713".to_string(), "64500".to_string()];
+    let pairs = pair_overlaps(&codes, &countries, &asns, &as_map);
+    assert_eq!(pairs.len(), 6);
+    assert_eq!(
+        (pairs[0].country.as_str(), pairs[0].asn.as_str()),
+        ("JP", "2497")
+    );
+    assert_eq!(
+        pairs[0].overlap.nets,
+        IpRangeSet::from_nets(&[ipnet("192.0.2.0/25"), ipnet("2001:db8::/48")])
+    );
+    assert_eq!(
+        pairs[1].overlap.nets,
+        IpRangeSet::from_nets(&[ipnet("192.0.2.128/26")])
+    );
+    assert!(pairs[2].overlap.nets.is_empty());
+    assert_eq!(
+        pairs[3].overlap.nets,
+        IpRangeSet::from_nets(&[ipnet("198.51.100.0/26")])
+    );
+    assert!(pairs[4].overlap.nets.is_empty());
+    // 組の内訳はその国とその AS だけ
+    assert_eq!(
+        pairs[3].overlap.countries.keys().collect::<Vec<_>>(),
+        ["KR"]
+    );
+    assert_eq!(
+        pairs[3].overlap.origins.keys().collect::<Vec<_>>(),
+        ["2497"]
+    );
+
+    // 国同士の列は共通部分、対角はその国の総数
+    assert_eq!(
+        render_overlap_matrix(&codes, &countries, &asns, &pairs),
+        "country,family,AS2497,AS4713,AS64500,JP,KR\n\
+         JP,IPv4,128,64,0,256,64\n\
+         JP,IPv6,1208925819614629174706176,0,0,79228162514264337593543950336,0\n\
+         KR,IPv4,64,0,0,64,320\n\
+         KR,IPv6,0,0,0,0,0\n"
+    );
+}
+
+#[tokio::test]
+async fn overlaps_keep_origin_and_country_per_element() {
+    use fire_scope::commands::handle_overlap::{calculate_overlaps, overlap_annotations};
+    use fire_scope::common::OutputFormat;
+    use fire_scope::interval::IpRangeSet;
+    use fire_scope::output::{OutputOptions, write_overlap_to_file};
+    use fire_scope::report::{RunReport, journal};
+    use std::sync::Arc;
+
+    let countries = [
+        (
+            "JP".to_string(),
+            IpRangeSet::from_nets(&[ipnet("192.0.2.0/24")]),
+        ),
+        (
+            "KR".to_string(),
+            IpRangeSet::from_nets(&[ipnet("198.51.100.0/24")]),
+        ),
+    ];
+    let as_sets = [
+        (
+            "2497".to_string(),
+            IpRangeSet::from_nets(&[ipnet("192.0.0.0/16")]),
+        ),
+        (
+            "4713".to_string(),
+            IpRangeSet::from_nets(&[ipnet("192.0.2.128/25"), ipnet("198.51.100.0/25")]),
+        ),
+        (
+            "64500".to_string(),
+            IpRangeSet::from_nets(&[ipnet("203.0.113.0/24")]),
+        ),
+    ];
+    let overlap = calculate_overlaps(&countries, &as_sets);
+    assert_eq!(
+        overlap.nets,
+        IpRangeSet::from_nets(&[ipnet("192.0.2.0/24"), ipnet("198.51.100.0/25")])
+    );
+    // AS のプレフィックス全体ではなく、重なった部分を内訳に持つ（重ならない AS は含めない）
+    assert_eq!(
+        overlap.origins["2497"],
+        IpRangeSet::from_nets(&[ipnet("192.0.2.0/24")])
+    );
+    assert_eq!(overlap.origins.len(), 2);
+    assert_eq!(
+        overlap.countries["KR"],
+        IpRangeSet::from_nets(&[ipnet("198.51.100.0/25")])
+    );
+
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = common::temp_dir("overlap");
+    output.annotations = Some(Arc::new(overlap_annotations(None, &overlap)));
+    journal().enable();
+    write_overlap_to_file("JP_KR", "2497_4713", &overlap.nets, &output)
+        .await
+        .unwrap();
+    let text = tokio::fs::read_to_string(output.dir.join("overlap_JP_KR_2497_4713_IPv4.txt"))
+        .await
+        .unwrap();
+    assert!(
+        text.ends_with("\n192.0.2.0/24 # AS2497, AS4713 (JP)\n198.51.100.0/25 # AS4713 (KR)\n"),
+        "{text}"
+    );
+
+    let report = RunReport::finish(chrono::Local::now(), None, &Ok(()));
+    assert_eq!(report.attribution.len(), 1);
+    assert_eq!(report.attribution[0].list, "overlap_JP_KR_2497_4713");
+    let first = &report.attribution[0].elements[0];
+    assert_eq!(first.prefix, "192.0.2.0/24");
+    assert_eq!(
+        first.origins.iter().map(|a| a.0).collect::<Vec<_>>(),
+        [2497, 4713]
+    );
+    assert_eq!(first.countries, ["JP"]);
+}
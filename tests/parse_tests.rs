@@ -31,8 +31,141 @@ fn parse_all_country_codes_aggregates_per_country() {
     let rir2 = "apnic|JP|ipv4|10.0.0.128|128|20200101|allocated\n";
     let vec = vec![rir1.to_string(), rir2.to_string()];
     let map = parse_all_country_codes(&vec).unwrap_or_else(|e| panic!("parse err: {e}"));
-    let (v4, v6) = map.get("JP").cloned().unwrap_or_default();
-    assert!(v6.is_empty());
-    let v4s: Vec<String> = v4.iter().map(|n| n.to_string()).collect();
+    let set = map.get("JP").cloned().unwrap_or_default();
+    assert!(set.v6.is_empty());
+    let v4s: Vec<String> = set.v4_nets().iter().map(|n| n.to_string()).collect();
     assert_eq!(v4s, vec!["10.0.0.0/24".to_string()]);
 }
+
+#[test]
+fn rir_parser_streams_chunks_and_keeps_only_requested_countries() {
+    use fire_scope::parse::{CountryFilter, RirParser, aggregate_country_sets};
+
+    let text = lines_sample();
+    // 行の途中で区切って与えても結果は同じ（末尾の改行が無い行も読む）
+    let mut parser = RirParser::new(CountryFilter::only(&["jp"]));
+    for chunk in text.trim_end().as_bytes().chunks(7) {
+        parser.feed(chunk).unwrap();
+    }
+    let map = aggregate_country_sets(vec![parser.finish().unwrap()]);
+    assert_eq!(map.keys().collect::<Vec<_>>(), ["JP"]);
+    assert_eq!(
+        map["JP"].v4_nets(),
+        vec!["1.2.3.0/24".parse::<ipnet::IpNet>().unwrap()]
+    );
+    assert_eq!(
+        map["JP"].v6_nets(),
+        vec!["2001:db8::/32".parse::<ipnet::IpNet>().unwrap()]
+    );
+
+    let mut parser = RirParser::new(CountryFilter::All);
+    parser.feed(text.replace('\n', "\r\n").as_bytes()).unwrap();
+    let map = aggregate_country_sets(vec![parser.finish().unwrap()]);
+    assert_eq!(map.len(), 2);
+
+    let mut parser = RirParser::new(CountryFilter::All);
+    assert!(
+        parser
+            .feed(b"apnic|JP|ipv4|1.2.3.0|256|20200101|allocated\n\xff\n")
+            .is_err()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn download_country_map_parses_while_downloading() {
+    use fire_scope::common_download::{DownloadOptions, download_country_map};
+    use fire_scope::parse::CountryFilter;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // 本文を分けて送り、接続を閉じて終端を示す最小のHTTPスタブ
+    // （/missing は 404、/broken は不正な UTF-8 を返して要求数を数える）
+    let broken_requests = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = std::sync::Arc::clone(&broken_requests);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut sock, _)) = listener.accept().await else {
+                return;
+            };
+            let counter = std::sync::Arc::clone(&counter);
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match sock.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                if String::from_utf8_lossy(&buf).contains("/missing") {
+                    let _ = sock
+                        .write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                        .await;
+                    return;
+                }
+                if String::from_utf8_lossy(&buf).contains("/broken") {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let _ = sock
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nconnection: close\r\n\r\napnic|JP|ipv4|\xff\n",
+                        )
+                        .await;
+                    return;
+                }
+                let _ = sock
+                    .write_all(b"HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n")
+                    .await;
+                for part in lines_sample().as_bytes().chunks(20) {
+                    let _ = sock.write_all(part).await;
+                    let _ = sock.flush().await;
+                }
+                let _ = sock.shutdown().await;
+            });
+        }
+    });
+
+    let mut download = DownloadOptions::new(1, 1, false);
+    download.rir_urls = vec![format!("http://{}/delegated", addr)];
+    let map = download_country_map(
+        &reqwest::Client::new(),
+        &download,
+        &CountryFilter::only(&["us"]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(
+        map["US"].v4_nets(),
+        vec!["203.0.113.0/24".parse::<ipnet::IpNet>().unwrap()]
+    );
+
+    // 部分失敗は --continue-on-partial の有無に従う
+    download.rir_urls.push(format!("http://{}/missing", addr));
+    let filter = CountryFilter::All;
+    assert!(
+        download_country_map(&reqwest::Client::new(), &download, &filter)
+            .await
+            .is_err()
+    );
+    download.continue_on_partial = true;
+    let map = download_country_map(&reqwest::Client::new(), &download, &filter)
+        .await
+        .unwrap();
+    assert_eq!(map.len(), 2);
+
+    // 解析エラーは再試行せず、URL を含む解析エラーとして返す
+    let url = format!("http://{}/broken", addr);
+    let err =
+        fire_scope::fetch::fetch_parsed_with_retry(&reqwest::Client::new(), &url, &filter, 3, 1)
+            .await
+            .unwrap_err();
+    assert_eq!(err.exit_code(), fire_scope::error::exit_code::PARSE);
+    assert!(err.to_string().contains(&url), "{err}");
+    assert_eq!(broken_requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+    download.rir_urls = vec![url];
+    let err = download_country_map(&reqwest::Client::new(), &download, &filter)
+        .await
+        .unwrap_err();
+    assert_eq!(err.exit_code(), fire_scope::error::exit_code::PARSE);
+}
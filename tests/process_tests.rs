@@ -1,7 +1,11 @@
-use fire_scope::process::{parse_and_collect_ips, process_country_code_from_map};
 use fire_scope::common::OutputFormat;
+use fire_scope::interval::IpRangeSet;
+use fire_scope::output::OutputOptions;
+use fire_scope::parse::CountryMap;
+use fire_scope::process::{
+    parse_and_collect_ips, process_all_country_codes, process_country_code_from_map,
+};
 use ipnet::IpNet;
-use std::collections::HashMap;
 use std::str::FromStr;
 use tokio::fs;
 
@@ -15,11 +19,16 @@ fn parse_and_collect_ips_aggregates_and_filters() {
     let rir2 = "apnic|JP|ipv4|10.0.0.128|128|20200101|allocated\n"; // /25
     let rir3 = "apnic|JP|ipv6|2001:db8::|32|20200101|assigned\n"; // v6
     let rir4 = "apnic|JP|ipv4|10.0.1.0|256|20200101|available\n"; // skip
-    let texts = vec![rir1.to_string(), rir2.to_string(), rir3.to_string(), rir4.to_string()];
+    let texts = vec![
+        rir1.to_string(),
+        rir2.to_string(),
+        rir3.to_string(),
+        rir4.to_string(),
+    ];
 
-    let (v4, v6) = parse_and_collect_ips("JP", &texts).unwrap();
-    let v4s: Vec<String> = v4.iter().map(|n| n.to_string()).collect();
-    let v6s: Vec<String> = v6.iter().map(|n| n.to_string()).collect();
+    let set = parse_and_collect_ips("JP", &texts).unwrap();
+    let v4s: Vec<String> = set.v4_nets().iter().map(|n| n.to_string()).collect();
+    let v6s: Vec<String> = set.v6_nets().iter().map(|n| n.to_string()).collect();
 
     assert!(v4s.contains(&"10.0.0.0/24".to_string()));
     assert!(!v4s.contains(&"10.0.0.0/25".to_string()));
@@ -31,24 +40,29 @@ fn parse_and_collect_ips_aggregates_and_filters() {
 async fn process_country_code_from_map_writes_files() {
     // 一意な国コード名（ファイル名重複回避）
     let cc = format!("ZZTEST{}", rand::random::<u32>());
-    let mut map: HashMap<String, (Vec<IpNet>, Vec<IpNet>)> = HashMap::new();
+    let mut map = CountryMap::new();
     map.insert(
         cc.clone(),
-        (
-            vec![ipnet("203.0.113.0/25"), ipnet("203.0.113.128/25")], // aggregate→/24
-            vec![ipnet("2001:db8::/32")],
-        ),
+        IpRangeSet::from_nets(&[
+            ipnet("203.0.113.0/25"),
+            ipnet("203.0.113.128/25"), // aggregate→/24
+            ipnet("2001:db8::/32"),
+        ]),
     );
 
     // 実行（TXT出力）
-    process_country_code_from_map(&cc, &map, OutputFormat::Txt)
+    process_country_code_from_map(&cc, &map, &OutputOptions::new(OutputFormat::Txt))
         .await
         .unwrap_or_else(|e| panic!("process failed: {e}"));
 
     let v4_path = format!("IPv4_{}.txt", cc);
     let v6_path = format!("IPv6_{}.txt", cc);
-    let v4 = fs::read_to_string(&v4_path).await.unwrap_or_else(|e| panic!("read v4: {e}"));
-    let v6 = fs::read_to_string(&v6_path).await.unwrap_or_else(|e| panic!("read v6: {e}"));
+    let v4 = fs::read_to_string(&v4_path)
+        .await
+        .unwrap_or_else(|e| panic!("read v4: {e}"));
+    let v6 = fs::read_to_string(&v6_path)
+        .await
+        .unwrap_or_else(|e| panic!("read v6: {e}"));
 
     assert!(v4.contains("203.0.113.0/24"));
     assert!(v6.contains("2001:db8::/32"));
@@ -57,3 +71,26 @@ async fn process_country_code_from_map_writes_files() {
     let _ = fs::remove_file(&v4_path).await;
     let _ = fs::remove_file(&v6_path).await;
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn process_all_country_codes_writes_each_code_once() {
+    let cc = format!("ZZDUP{}", rand::random::<u32>());
+    let mut map = CountryMap::new();
+    map.insert(
+        cc.clone(),
+        IpRangeSet::from_nets(&[ipnet("198.51.100.0/24")]),
+    );
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = format!("target/test-output/dup_{}", rand::random::<u64>()).into();
+
+    // 正規化後に同じ国コードが重なっても、書き出しは1回だけ
+    let codes = vec![cc.clone(), cc.to_ascii_lowercase(), cc.clone()];
+    process_all_country_codes(&codes, map, &output)
+        .await
+        .unwrap();
+
+    let v4 = fs::read_to_string(output.dir.join(format!("IPv4_{}.txt", cc)))
+        .await
+        .unwrap();
+    assert_eq!(v4.matches("198.51.100.0/24").count(), 1);
+}
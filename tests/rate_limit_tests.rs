@@ -0,0 +1,124 @@
+use fire_scope::fetch::{ApiPolicy, fetch_json_with_policy, parse_retry_after};
+use fire_scope::rate_limit::RateLimiter;
+use serde_json::Value;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// 用意した応答を順に返す最小HTTPスタブ（最後の応答を繰り返す）
+async fn start_stub(responses: Vec<&'static str>) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hits = Arc::new(AtomicUsize::new(0));
+    let hits_c = Arc::clone(&hits);
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut sock, _)) = listener.accept().await else {
+                return;
+            };
+            let n = hits_c.fetch_add(1, Ordering::SeqCst);
+            let resp = responses[n.min(responses.len() - 1)];
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match sock.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                let _ = sock.write_all(resp.as_bytes()).await;
+                let _ = sock.shutdown().await;
+            });
+        }
+    });
+    (format!("http://{}/data.json", addr), hits)
+}
+
+const OK: &str = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 11\r\nconnection: close\r\n\r\n{\"ok\":true}";
+const TOO_MANY: &str = "HTTP/1.1 429 Too Many Requests\r\nretry-after: 1\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+const NOT_FOUND: &str = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+fn policy(limiter: RateLimiter) -> ApiPolicy {
+    ApiPolicy {
+        retry_attempts: 3,
+        max_backoff_secs: 1,
+        limiter,
+        sourceapp: "test".into(),
+        ..ApiPolicy::default()
+    }
+}
+
+#[tokio::test]
+async fn limiter_spaces_requests_per_host() {
+    let limiter = RateLimiter::new(20.0, 1.0);
+    let started = Instant::now();
+    for _ in 0..5 {
+        limiter.acquire("a.example").await;
+    }
+    // 1件目はバースト、残り4件は 50ms 間隔
+    assert!(
+        started.elapsed() >= Duration::from_millis(180),
+        "{:?}",
+        started.elapsed()
+    );
+
+    // 別ホストは影響を受けない
+    let started = Instant::now();
+    limiter.acquire("b.example").await;
+    assert!(started.elapsed() < Duration::from_millis(40));
+
+    // defer したホストは指定時間待つ
+    limiter.defer("b.example", Duration::from_millis(300));
+    let started = Instant::now();
+    limiter.acquire("b.example").await;
+    assert!(
+        started.elapsed() >= Duration::from_millis(250),
+        "{:?}",
+        started.elapsed()
+    );
+
+    assert!(RateLimiter::unlimited().is_unlimited());
+}
+
+#[tokio::test]
+async fn json_fetch_honors_retry_after() {
+    let (url, hits) = start_stub(vec![TOO_MANY, OK]).await;
+    let client = reqwest::Client::new();
+    let started = Instant::now();
+    let value: Value =
+        fetch_json_with_policy(&client, &url, 1024, &policy(RateLimiter::new(100.0, 10.0)))
+            .await
+            .unwrap();
+    assert_eq!(value["ok"], true);
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+    assert!(
+        started.elapsed() >= Duration::from_millis(900),
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn json_fetch_does_not_retry_client_errors() {
+    let (url, hits) = start_stub(vec![NOT_FOUND]).await;
+    let client = reqwest::Client::new();
+    let res: Result<Value, _> =
+        fetch_json_with_policy(&client, &url, 1024, &policy(RateLimiter::unlimited())).await;
+    assert!(res.is_err());
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn retry_after_parsing() {
+    assert_eq!(parse_retry_after("5"), Some(Duration::from_secs(5)));
+    // 上限で切り詰める
+    assert_eq!(parse_retry_after("86400"), Some(Duration::from_secs(120)));
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon"), None);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use clap::Parser;
+use common::{ranges, temp_dir};
+use fire_scope::cli::Cli;
+use fire_scope::common::OutputFormat;
+use fire_scope::error::{AppError, exit_code};
+use fire_scope::output::{OutputOptions, write_ip_lists_to_files};
+use fire_scope::report::{RunInputs, RunReport, journal};
+
+#[test]
+fn errors_map_to_documented_exit_codes() {
+    assert_eq!(
+        AppError::InvalidInput("x".into()).exit_code(),
+        exit_code::INVALID_INPUT
+    );
+    assert_eq!(AppError::Fetch("x".into()).exit_code(), exit_code::NETWORK);
+    assert_eq!(
+        AppError::ParseError("x".into()).exit_code(),
+        exit_code::PARSE
+    );
+    assert_eq!(
+        AppError::PartialData("x".into()).exit_code(),
+        exit_code::PARTIAL_DATA
+    );
+    assert_eq!(
+        AppError::SafetyThreshold("x".into()).exit_code(),
+        exit_code::SAFETY_THRESHOLD
+    );
+    assert_eq!(AppError::NoChange.exit_code(), exit_code::NO_CHANGE);
+    assert_eq!(AppError::Hook("x".into()).exit_code(), exit_code::FAILURE);
+}
+
+#[tokio::test]
+async fn shrink_threshold_keeps_previous_file() {
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("report");
+    output.max_shrink_percent = Some(30.0);
+    write_ip_lists_to_files(
+        "JP",
+        &ranges(&["198.51.100.0/24", "203.0.113.0/24", "2001:db8::/32"]),
+        &output,
+    )
+    .await
+    .unwrap();
+
+    // 半分に縮小 → 50% > 30% なので書き出さない
+    let err = write_ip_lists_to_files(
+        "JP",
+        &ranges(&["198.51.100.0/24", "2001:db8::/32"]),
+        &output,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.exit_code(), exit_code::SAFETY_THRESHOLD);
+    let kept = tokio::fs::read_to_string(output.dir.join("IPv4_JP.txt"))
+        .await
+        .unwrap();
+    assert!(kept.contains("203.0.113.0/24"));
+
+    // 集約の仕方が変わるだけなら縮小ではない
+    write_ip_lists_to_files(
+        "JP",
+        &ranges(&[
+            "198.51.100.0/25",
+            "198.51.100.128/25",
+            "203.0.113.0/24",
+            "2001:db8::/32",
+        ]),
+        &output,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn report_lists_outputs_with_hashes() {
+    journal().enable();
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("report");
+    write_ip_lists_to_files(
+        "JP",
+        &ranges(&["198.51.100.0/24", "2001:db8::/32"]),
+        &output,
+    )
+    .await
+    .unwrap();
+
+    let cli = Cli::parse_from(["fire-scope", "country", "jp"]);
+    let command = cli.resolve_command().unwrap();
+    let report = RunReport::finish(chrono::Local::now(), Some(&command), &Ok(()));
+    assert_eq!(report.command, Some("country"));
+    assert_eq!(report.exit_code, exit_code::SUCCESS);
+    assert_eq!(
+        report.inputs,
+        RunInputs {
+            countries: vec!["JP".into()],
+            ..RunInputs::default()
+        }
+    );
+
+    let path = output.dir.join("IPv4_JP.txt");
+    let entry = report
+        .outputs
+        .iter()
+        .find(|o| o.path == path.display().to_string())
+        .unwrap_or_else(|| panic!("missing output in {:?}", report.outputs));
+    assert!(entry.changed);
+    assert_eq!(entry.entries, 1);
+    assert_eq!(entry.sha256.len(), 64);
+
+    let report_path = output.dir.join("report.json");
+    report.write(&report_path).await.unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&tokio::fs::read_to_string(&report_path).await.unwrap()).unwrap();
+    assert_eq!(json["exit_code"], 0);
+    assert!(json["error"].is_null());
+
+    // 失敗時は分類された終了コードとメッセージを記録
+    let failed = RunReport::finish(
+        chrono::Local::now(),
+        Some(&command),
+        &Err(AppError::PartialData("Some RIR downloads failed".into())),
+    );
+    assert_eq!(failed.exit_code, exit_code::PARTIAL_DATA);
+    assert!(failed.error.unwrap().contains("Some RIR downloads failed"));
+}
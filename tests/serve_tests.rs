@@ -0,0 +1,207 @@
+use fire_scope::common::OutputFormat;
+use fire_scope::common_download::DownloadOptions;
+use fire_scope::output::OutputOptions;
+use fire_scope::serve::{LruMap, ServeState, serve};
+use reqwest::StatusCode;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const RIR_BODY: &str = "apnic|JP|ipv4|198.51.100.0|256|20200101|allocated\n\
+apnic|JP|ipv6|2001:db8::|32|20200101|allocated\n";
+
+/// RIRファイルを返すだけの最小HTTPスタブ
+async fn start_rir_stub() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut sock, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match sock.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    RIR_BODY.len(),
+                    RIR_BODY
+                );
+                let _ = sock.write_all(resp.as_bytes()).await;
+                let _ = sock.shutdown().await;
+            });
+        }
+    });
+    format!("http://{}/delegated-test-latest", addr)
+}
+
+/// サーバを起動してベースURLと状態を返す（RIRデータの取得は呼び出し側で行う）
+async fn start_server() -> (String, Arc<ServeState>) {
+    let (base, state, _) = start_server_with_rir_url().await;
+    (base, state)
+}
+
+async fn start_server_with_rir_url() -> (String, Arc<ServeState>, String) {
+    let rir_url = start_rir_stub().await;
+    let mut download = DownloadOptions::new(1, 1, false);
+    download.rir_urls = vec![rir_url.clone()];
+    let state = Arc::new(ServeState::new(
+        reqwest::Client::new(),
+        download,
+        OutputOptions::new(OutputFormat::Txt),
+        Default::default(),
+    ));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(serve(listener, Arc::clone(&state)));
+    (base, state, rir_url)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_country_lists_after_refresh() {
+    let (base, state) = start_server().await;
+    let client = reqwest::Client::new();
+
+    // 初回取得前は 503
+    let resp = client
+        .get(format!("{base}/country/JP/v4.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    state.refresh().await.unwrap();
+
+    let resp = client
+        .get(format!("{base}/country/jp/v4.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.text().await.unwrap();
+    assert!(body.starts_with("# Generated at:"));
+    assert!(body.contains("198.51.100.0/24"));
+    assert!(!body.contains("2001:db8::/32"));
+
+    let resp = client
+        .get(format!("{base}/country/JPN/v6.nft"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.text().await.unwrap();
+    assert!(body.contains("define IPv6_JP = {"));
+    assert!(body.contains("2001:db8::/32"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn etag_returns_not_modified() {
+    let (base, state) = start_server().await;
+    state.refresh().await.unwrap();
+    let client = reqwest::Client::new();
+    let url = format!("{base}/country/JP/v4.txt");
+
+    let resp = client.get(&url).send().await.unwrap();
+    let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+    // SHA-256 の先頭128ビット
+    assert!(
+        etag.starts_with("W/\"") && etag.len() == "W/\"\"".len() + 32,
+        "{etag}"
+    );
+
+    let resp = client
+        .get(&url)
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    // 形式が違えば別のETag
+    let resp = client
+        .get(format!("{base}/country/JP/v4.nft"))
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(resp.headers()["etag"].to_str().unwrap(), etag);
+
+    // 再取得で内容が変わらなければETagも変わらない
+    state.refresh().await.unwrap();
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.headers()["etag"].to_str().unwrap(), etag);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invalid_requests() {
+    let (base, state) = start_server().await;
+    state.refresh().await.unwrap();
+    let client = reqwest::Client::new();
+
+    let status = |path: &'static str| {
+        let client = client.clone();
+        let base = base.clone();
+        async move {
+            client
+                .get(format!("{base}{path}"))
+                .send()
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+    assert_eq!(status("/country/XX/v4.txt").await, StatusCode::BAD_REQUEST);
+    assert_eq!(status("/country/JP/v5.txt").await, StatusCode::NOT_FOUND);
+    assert_eq!(status("/country/JP/v4.json").await, StatusCode::NOT_FOUND);
+    assert_eq!(
+        status("/as/notanumber/v4.txt").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(status("/overlap?cc=JP&as=x").await, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        status("/overlap?cc=JP&as=2497&family=v5").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(status("/healthz").await, StatusCode::OK);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exposes_download_metrics() {
+    let (base, state, rir_url) = start_server_with_rir_url().await;
+    state.refresh().await.unwrap();
+
+    let resp = reqwest::get(format!("{base}/metrics")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.text().await.unwrap();
+    assert!(body.contains(&format!(
+        "fire_scope_download_bytes{{url=\"{}\"}} {}",
+        rir_url,
+        RIR_BODY.len()
+    )));
+    assert!(body.contains("fire_scope_parse_duration_seconds "));
+}
+
+#[test]
+fn as_cache_evicts_least_recently_requested() {
+    let mut cache = LruMap::new(2);
+    assert_eq!(cache.insert(1u32, "a"), None);
+    assert_eq!(cache.insert(2, "b"), None);
+    // 1 を要求したので 2 が最も古い
+    assert_eq!(cache.get(&1), Some("a"));
+    assert_eq!(cache.insert(3, "c"), Some(2));
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.len(), 2);
+
+    // 更新は使った順を変えず、捨てられたキーは入れ直さない
+    assert!(cache.replace(&1, "a2"));
+    assert!(!cache.replace(&2, "b2"));
+    assert_eq!(cache.insert(4, "d"), Some(1));
+    let mut keys = cache.keys();
+    keys.sort();
+    assert_eq!(keys, [3, 4]);
+}
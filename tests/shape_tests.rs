@@ -0,0 +1,134 @@
+mod common;
+
+use common::{nets, strings, temp_dir};
+use fire_scope::common::{IpFamily, OutputFormat};
+use fire_scope::interval::IpRangeSet;
+use fire_scope::output::{OutputOptions, write_as_ip_list_to_file};
+use fire_scope::report::{RunReport, journal};
+use fire_scope::shape::{ShapeOptions, shape_nets};
+
+#[test]
+fn drops_and_widens_long_prefixes() {
+    let set = nets(&[
+        "10.0.0.0/24",
+        "10.0.1.0/24",
+        "10.1.0.0/28",
+        "10.2.0.0/30",
+        "2001:db8::/48",
+        "2001:db8:1::/64",
+    ]);
+    let opts = ShapeOptions {
+        drop_longer_than_v4: Some(28),
+        widen_to_v4: Some(24),
+        ..ShapeOptions::default()
+    };
+    let (shaped, reports) = shape_nets(&set, &opts, &[]);
+    assert_eq!(
+        strings(&shaped),
+        [
+            "10.0.0.0/23",
+            "10.1.0.0/24",
+            "2001:db8::/48",
+            "2001:db8:1::/64"
+        ]
+    );
+
+    let v4 = &reports[0];
+    assert_eq!((v4.entries_before, v4.entries_after), (4, 2));
+    assert_eq!((v4.dropped, v4.removed_addresses), (1, 4));
+    assert_eq!((v4.widened, v4.added_addresses), (1, 240));
+    assert_eq!(
+        v4.summary("AS_64500"),
+        "AS_64500 IPv4: 4 -> 2 entries, dropped 1 prefix(es) (4 addresses), widened 1 prefix(es), +240 addresses (+45.11%) not in the source data"
+    );
+    // IPv6 の設定が無ければ変えない
+    assert!(!reports[1].changed());
+
+    // 無効なら集約もしない
+    let (same, reports) = shape_nets(&set, &ShapeOptions::default(), &[]);
+    assert_eq!(same, set);
+    assert!(reports.is_empty());
+}
+
+#[test]
+fn compresses_with_the_least_added_address_space() {
+    let set = nets(&[
+        "10.0.0.0/25",
+        "10.0.0.192/26",
+        "10.0.2.0/24",
+        "10.0.8.0/24",
+        "172.16.0.0/24",
+    ]);
+    let compress = |k| {
+        shape_nets(
+            &set,
+            &ShapeOptions {
+                max_entries: Some(k),
+                ..ShapeOptions::default()
+            },
+            &[],
+        )
+    };
+
+    let (shaped, reports) = compress(4);
+    assert_eq!(
+        strings(&shaped),
+        ["10.0.0.0/24", "10.0.2.0/24", "10.0.8.0/24", "172.16.0.0/24"]
+    );
+    assert_eq!(reports[0].added_addresses, 64);
+
+    let (shaped, reports) = compress(3);
+    assert_eq!(
+        strings(&shaped),
+        ["10.0.0.0/22", "10.0.8.0/24", "172.16.0.0/24"]
+    );
+    assert_eq!(reports[0].added_addresses, 64 + 512);
+
+    let (shaped, _) = compress(1);
+    assert_eq!(strings(&shaped), ["0.0.0.0/0"]);
+
+    // 上限以内なら集約のみ
+    let (shaped, reports) = compress(5);
+    assert_eq!(shaped, set);
+    assert!(!reports[0].changed());
+}
+
+#[tokio::test]
+async fn writes_compressed_lists_and_records_the_added_space() {
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("shape");
+    output.shape.max_entries = Some(1);
+
+    journal().enable();
+    let list = nets(&["198.51.100.0/25", "198.51.100.128/26"]);
+    write_as_ip_list_to_file(
+        "64500",
+        IpFamily::V4,
+        &IpRangeSet::from_nets(&list),
+        &output,
+    )
+    .await
+    .unwrap();
+    let text = tokio::fs::read_to_string(output.dir.join("AS_64500_IPv4.txt"))
+        .await
+        .unwrap();
+    assert!(text.ends_with("\n198.51.100.0/24\n"));
+
+    let report = RunReport::finish(chrono::Local::now(), None, &Ok(()));
+    assert_eq!(report.shaping.len(), 1);
+    assert_eq!(report.shaping[0].list, "AS_64500_IPv4");
+    assert_eq!(report.shaping[0].report.added_addresses, 64);
+    assert_eq!(report.outputs[0].entries, 1);
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["shaping"][0]["added_addresses"], 64);
+}
+
+#[test]
+fn compression_never_merges_over_excluded_ranges() {
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.exclude = vec!["8.8.1.0/24".parse().unwrap()];
+    output.shape.max_entries = Some(1);
+    // 8.8.0.0/24 と 8.8.2.0/24 をまとめると除外した 8.8.1.0/24 を含むため、上限を超えたまま残す
+    let set = IpRangeSet::from_nets(&nets(&["8.8.0.0/24", "8.8.1.0/24", "8.8.2.0/24"]));
+    assert_eq!(strings(&output.prepare(&set)), ["8.8.0.0/24", "8.8.2.0/24"]);
+}
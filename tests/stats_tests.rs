@@ -0,0 +1,109 @@
+mod common;
+
+use common::{nets, ranges, temp_dir};
+use fire_scope::common::{IpFamily, OutputFormat};
+use fire_scope::interval::IpRangeSet;
+use fire_scope::output::{OutputOptions, write_ip_lists_to_files, write_overlap_to_file};
+use fire_scope::parse::parse_all_country_codes;
+use fire_scope::stats::{ListStats, StatsReport, stats};
+use std::collections::BTreeMap;
+
+#[test]
+fn computes_sizes_and_prefix_lengths() {
+    let v6 = nets(&["2001:db8::/32", "2001:db9::/48", "2001:dba::/64"]);
+    let s = ListStats::compute("asn", "64500", IpFamily::V6, 5, &v6, None);
+    assert_eq!((s.prefixes_before, s.prefixes_after), (5, 3));
+    assert_eq!(s.addresses, (1u128 << 96) + (1 << 80) + (1 << 64));
+    assert_eq!(s.ipv6_48s, Some(65536.0 + 1.0 + 1.0 / 65536.0));
+    assert_eq!(s.ipv6_64s, Some(4294967296.0 + 65536.0 + 1.0));
+    assert_eq!(
+        s.prefix_lengths,
+        BTreeMap::from([(32, 1), (48, 1), (64, 1)])
+    );
+    assert_eq!(s.rirs, None);
+
+    let v4 = nets(&["192.0.2.0/24", "198.51.100.0/25"]);
+    let space = BTreeMap::from([
+        (
+            "apnic".to_string(),
+            IpRangeSet::from_nets(&nets(&["192.0.2.0/24"])),
+        ),
+        (
+            "ripencc".to_string(),
+            IpRangeSet::from_nets(&nets(&["198.51.100.0/26"])),
+        ),
+        (
+            "arin".to_string(),
+            IpRangeSet::from_nets(&nets(&["203.0.113.0/24"])),
+        ),
+    ]);
+    let s = ListStats::compute("country", "JP", IpFamily::V4, 2, &v4, Some(&space));
+    assert_eq!(s.addresses, 384);
+    assert_eq!(s.ipv6_48s, None);
+    let rirs = s.rirs.unwrap();
+    assert_eq!(rirs.keys().collect::<Vec<_>>(), ["apnic", "ripencc"]);
+    assert_eq!(rirs["apnic"].prefixes, 1);
+    assert!((rirs["apnic"].percent - 66.666).abs() < 0.01);
+    assert!((rirs["ripencc"].percent - 16.666).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn records_lists_written_in_a_run() {
+    let rir = "\
+2|apnic|20240101|3|19700101|20240101|+0900
+apnic|JP|ipv4|192.0.2.0|128|20100101|allocated
+apnic|JP|ipv4|192.0.2.128|128|20100101|allocated
+apnic|JP|ipv6|2001:db8::|32|20100101|allocated
+ripencc|JP|ipv4|198.51.100.0|256|20100101|assigned
+"
+    .to_string();
+    let mut output = OutputOptions::new(OutputFormat::Txt);
+    output.dir = temp_dir("stats");
+
+    // 有効にするまでは記録しない
+    write_overlap_to_file("JP", "64500", &ranges(&["192.0.2.0/24"]), &output)
+        .await
+        .unwrap();
+    assert!(StatsReport::finish().lists.is_empty());
+
+    stats().enable();
+    let map = parse_all_country_codes(&[rir]).unwrap();
+    write_ip_lists_to_files("JP", &map["JP"], &output)
+        .await
+        .unwrap();
+    write_overlap_to_file(
+        "JP",
+        "64500",
+        &ranges(&["192.0.2.0/25", "198.51.100.128/25"]),
+        &output,
+    )
+    .await
+    .unwrap();
+
+    let path = output.dir.join("stats.json");
+    StatsReport::finish().write(&path).await.unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&tokio::fs::read_to_string(&path).await.unwrap()).unwrap();
+    let lists = json["lists"].as_array().unwrap();
+    assert_eq!(lists.len(), 3);
+
+    // 集約前の数は RIR の割り当てから求めた CIDR の数
+    assert_eq!(lists[0]["kind"], "country");
+    assert_eq!(lists[0]["family"], "IPv4");
+    assert_eq!(lists[0]["prefixes_before"], 3);
+    assert_eq!(lists[0]["prefixes_after"], 2);
+    assert_eq!(lists[0]["addresses"], 512);
+    assert_eq!(lists[0]["prefix_lengths"]["24"], 2);
+    assert_eq!(lists[0]["rirs"]["apnic"]["percent"], 50.0);
+    assert_eq!(lists[0]["rirs"]["ripencc"]["prefixes"], 1);
+
+    assert_eq!(lists[1]["family"], "IPv6");
+    assert_eq!(lists[1]["ipv6_48s"], 65536.0);
+    assert_eq!(lists[1]["rirs"]["apnic"]["percent"], 100.0);
+
+    // overlap は書き出す前の集合の数
+    assert_eq!(lists[2]["kind"], "overlap");
+    assert_eq!(lists[2]["name"], "overlap_JP_64500");
+    assert_eq!(lists[2]["prefixes_before"], 2);
+    assert_eq!(lists[2]["prefix_lengths"]["25"], 2);
+}
@@ -0,0 +1,218 @@
+mod common;
+
+use common::temp_dir;
+use fire_scope::asn::AsLookupOptions;
+use fire_scope::common::OutputFormat;
+use fire_scope::common_download::DownloadOptions;
+use fire_scope::config::{Config, Job};
+use fire_scope::output::OutputOptions;
+use fire_scope::watch::{Clock, Watcher};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const RIR_BODY: &str = "apnic|JP|ipv4|198.51.100.0|256|20200101|allocated\n";
+const ETAG: &str = "\"v1\"";
+
+/// 実時間を待たずに待機時間だけを記録する Clock
+#[derive(Default, Clone)]
+struct MockClock {
+    sleeps: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl Clock for MockClock {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        self.sleeps.lock().unwrap().push(duration);
+        tokio::task::yield_now()
+    }
+}
+
+/// ETag 対応の最小HTTPスタブ
+/// mode: 0 = 通常, 1 = 500 を返す, 2 = 応答前に200ms待つ
+#[derive(Default)]
+struct Stub {
+    full: AtomicUsize,
+    not_modified: AtomicUsize,
+    mode: AtomicUsize,
+}
+
+async fn start_stub() -> (String, Arc<Stub>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stub = Arc::new(Stub::default());
+    let stub_c = Arc::clone(&stub);
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut sock, _)) = listener.accept().await else {
+                return;
+            };
+            let stub = Arc::clone(&stub_c);
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 1024];
+                while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match sock.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                let req = String::from_utf8_lossy(&buf).to_ascii_lowercase();
+                let resp = match stub.mode.load(Ordering::SeqCst) {
+                    1 => "HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+                    mode => {
+                        if mode == 2 {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                        }
+                        if req.contains(&format!("if-none-match: {}", ETAG)) {
+                            stub.not_modified.fetch_add(1, Ordering::SeqCst);
+                            format!("HTTP/1.1 304 Not Modified\r\netag: {}\r\nconnection: close\r\n\r\n", ETAG)
+                        } else {
+                            stub.full.fetch_add(1, Ordering::SeqCst);
+                            format!(
+                                "HTTP/1.1 200 OK\r\netag: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                                ETAG,
+                                RIR_BODY.len(),
+                                RIR_BODY
+                            )
+                        }
+                    }
+                };
+                let _ = sock.write_all(resp.as_bytes()).await;
+                let _ = sock.shutdown().await;
+            });
+        }
+    });
+    (format!("http://{}/delegated-test-latest", addr), stub)
+}
+
+fn jobs_for(dir: &Path) -> Vec<Job> {
+    let config = Config::from_toml_str(&format!(
+        "[profiles.jp]\ncountry = [\"jp\"]\noutput-dir = \"{}\"\n",
+        dir.display()
+    ))
+    .unwrap_or_else(|e| panic!("config: {e}"));
+    config
+        .resolve_jobs(&[], true, &OutputOptions::new(OutputFormat::Txt))
+        .unwrap_or_else(|e| panic!("resolve: {e}"))
+}
+
+fn download_for(url: &str) -> DownloadOptions {
+    let mut d = DownloadOptions::new(1, 1, false);
+    d.rir_urls = vec![url.to_string()];
+    d
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watch_refreshes_on_interval_without_redownloading() {
+    let (url, stub) = start_stub().await;
+    let dir = temp_dir("watch");
+    let clock = MockClock::default();
+    let interval = Duration::from_secs(3600);
+    let jitter = Duration::from_secs(60);
+    let watcher = Watcher::new(
+        reqwest::Client::new(),
+        jobs_for(&dir),
+        download_for(&url),
+        AsLookupOptions::new(2, false),
+        interval,
+        jitter,
+        clock.clone(),
+    );
+
+    watcher.run(Some(3)).await;
+
+    // 本文の取得は初回のみ、以降は 304
+    assert_eq!(stub.full.load(Ordering::SeqCst), 1);
+    assert_eq!(stub.not_modified.load(Ordering::SeqCst), 2);
+
+    // 周期ごとの待機は interval + [0, jitter]
+    let sleeps = clock.sleeps.lock().unwrap().clone();
+    assert_eq!(sleeps.len(), 2);
+    assert!(
+        sleeps
+            .iter()
+            .all(|d| *d >= interval && *d <= interval + jitter)
+    );
+
+    let v4 = tokio::fs::read_to_string(dir.join("IPv4_JP.txt"))
+        .await
+        .unwrap_or_else(|e| panic!("read: {e}"));
+    assert!(v4.contains("198.51.100.0/24"));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_refresh_keeps_last_good_output() {
+    let (url, stub) = start_stub().await;
+    let dir = temp_dir("watch");
+    let watcher = Watcher::new(
+        reqwest::Client::new(),
+        jobs_for(&dir),
+        download_for(&url),
+        AsLookupOptions::new(2, false),
+        Duration::from_secs(60),
+        Duration::ZERO,
+        MockClock::default(),
+    );
+
+    let first = watcher
+        .refresh_once()
+        .await
+        .unwrap_or_else(|e| panic!("first: {e}"));
+    assert_eq!(first.downloaded, 1);
+    assert!(first.reparsed);
+
+    // 取得失敗時は前回の内容で代用し、出力は維持
+    stub.mode.store(1, Ordering::SeqCst);
+    let second = watcher
+        .refresh_once()
+        .await
+        .unwrap_or_else(|e| panic!("second: {e}"));
+    assert_eq!(second.stale, 1);
+    assert!(!second.reparsed);
+    let v4 = tokio::fs::read_to_string(dir.join("IPv4_JP.txt"))
+        .await
+        .unwrap_or_else(|e| panic!("read: {e}"));
+    assert!(v4.contains("198.51.100.0/24"));
+
+    // 前回の内容が無い状態で失敗した場合はエラーで、何も書き出さない
+    let dir2 = temp_dir("watch");
+    let fresh = Watcher::new(
+        reqwest::Client::new(),
+        jobs_for(&dir2),
+        download_for(&url),
+        AsLookupOptions::new(2, false),
+        Duration::from_secs(60),
+        Duration::ZERO,
+        MockClock::default(),
+    );
+    assert!(fresh.refresh_once().await.is_err());
+    assert!(!dir2.join("IPv4_JP.txt").exists());
+
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refreshes_never_run_concurrently() {
+    let (url, stub) = start_stub().await;
+    stub.mode.store(2, Ordering::SeqCst);
+    let dir = temp_dir("watch");
+    let watcher = Watcher::new(
+        reqwest::Client::new(),
+        jobs_for(&dir),
+        download_for(&url),
+        AsLookupOptions::new(2, false),
+        Duration::from_secs(60),
+        Duration::ZERO,
+        MockClock::default(),
+    );
+
+    let (a, b) = tokio::join!(watcher.refresh_once(), watcher.refresh_once());
+    assert!(a.is_ok() != b.is_ok());
+    assert_eq!(stub.full.load(Ordering::SeqCst), 1);
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+}